@@ -8,4 +8,147 @@ function saveAuthToken(token) {
 
 htmx.on("htmx:configRequest", (e) => {
     e.detail.headers["AUTH"] = getAuthToken()
-})
\ No newline at end of file
+})
+
+// Messages pushed over the websocket also reach the tab that sent them,
+// which already appended the bubble from the /htmx/chat-send response.
+// Edits and deletes target the bubble itself and always go through.
+htmx.on("htmx:oobBeforeSwap", (e) => {
+    const message = e.detail.fragment.querySelector("[data-message-id]");
+    if (message && e.detail.target.id !== message.id && document.getElementById(message.id)) {
+        e.detail.shouldSwap = false;
+    }
+})
+
+// Set by a search hit, the message to show once its chat is open.
+let pendingJump = null;
+
+// Open chats and threads at the newest message, older pages are prepended as the user scrolls up.
+htmx.onLoad((elt) => {
+    if (elt.id === "chat-window") {
+        elt.scrollTop = elt.scrollHeight;
+        if (pendingJump) {
+            jumpToMessage(pendingJump);
+            pendingJump = null;
+        }
+    }
+    if (elt.id === "thread-panel") {
+        const threadWindow = elt.querySelector("[data-thread-window]");
+        threadWindow.scrollTop = threadWindow.scrollHeight;
+    }
+})
+
+// Invite links are rendered as paths, make them shareable.
+htmx.onLoad((elt) => {
+    elt.querySelectorAll("[data-invite-link]").forEach((input) => {
+        if (input.value.startsWith("/")) {
+            input.value = window.location.origin + input.value;
+        }
+    });
+})
+
+// Some proxies kill websocket upgrades, fall back to the server-sent events stream.
+htmx.on("htmx:wsError", () => {
+    const realtime = document.getElementById("realtime");
+    const fallback = document.getElementById("realtime-sse-fallback");
+    if (!realtime || !fallback || realtime.getAttribute("hx-ext") !== "ws") {
+        return;
+    }
+    const sse = fallback.content.firstElementChild.cloneNode(true);
+    realtime.replaceWith(sse);
+    htmx.process(sse);
+})
+
+// Replies: the arrow under a bubble fills in the chat form, the quote inside a reply
+// jumps back to the original.
+function setReplyTo(messageId, snippet) {
+    const input = document.getElementById("reply-to-message-id");
+    const preview = document.getElementById("reply-preview");
+    if (!input || !preview) {
+        return;
+    }
+    input.value = messageId;
+    document.getElementById("reply-preview-snippet").textContent = snippet;
+    preview.classList.toggle("hidden", !messageId);
+    if (messageId) {
+        document.getElementById("message").focus();
+    }
+}
+
+document.addEventListener("click", (e) => {
+    const reply = e.target.closest("[data-reply-to]");
+    if (reply) {
+        setReplyTo(reply.dataset.replyTo, reply.dataset.replySnippet);
+        return;
+    }
+    if (e.target.closest("[data-reply-cancel]")) {
+        setReplyTo("", "");
+        return;
+    }
+    const quote = e.target.closest("[data-quote-of]");
+    if (!quote) {
+        return;
+    }
+    e.preventDefault();
+    if (!jumpToMessage(quote.dataset.quoteOf)) {
+        // Not loaded yet, scrolling up brings in the older pages
+        const chatWindow = document.getElementById("chat-window");
+        if (chatWindow) {
+            chatWindow.scrollTo({ top: 0, behavior: "smooth" });
+        }
+    }
+})
+
+// Scrolls to the message and flashes it, false when it is not loaded.
+function jumpToMessage(messageId) {
+    const message = document.getElementById("message-" + messageId);
+    if (!message) {
+        return false;
+    }
+    message.scrollIntoView({ behavior: "smooth", block: "center" });
+    message.animate(
+        [{ backgroundColor: "rgba(59, 130, 246, 0.2)" }, { backgroundColor: "transparent" }],
+        { duration: 1500 },
+    );
+    return true;
+}
+
+// Search hits open their chat, thread replies open their thread as well.
+document.addEventListener("click", (e) => {
+    const hit = e.target.closest("[data-jump-to]");
+    if (!hit) {
+        return;
+    }
+    pendingJump = hit.dataset.jumpTo;
+    if (hit.dataset.jumpThread) {
+        // After the chat window swap, which closes the thread left open in the previous chat
+        hit.addEventListener("htmx:afterRequest", (request) => {
+            if (request.detail.successful) {
+                htmx.ajax("GET", "/htmx/thread?root_message_id=" + hit.dataset.jumpThread, {
+                    target: "#thread-panel-slot",
+                    swap: "innerHTML",
+                });
+            }
+        }, { once: true });
+    }
+})
+
+// Attachments are never sent as replies, so only a sent text message clears the reply.
+htmx.on("htmx:afterRequest", (e) => {
+    if (e.detail.elt.id === "chatForm" && e.detail.successful) {
+        setReplyTo("", "");
+    }
+})
+
+// Sidebar menu, closes on any click outside the toggle button.
+document.addEventListener("click", (e) => {
+    const menu = document.getElementById("dropdownMenu");
+    if (!menu) {
+        return;
+    }
+    if (e.target.closest("#menuButton")) {
+        menu.classList.toggle("hidden");
+    } else {
+        menu.classList.add("hidden");
+    }
+})
@@ -1,16 +1,40 @@
 // pub const HOME_PAGE: &str = "/";
 pub const LOGIN_PAGE: &str = "/login";
 pub const SIGNUP_PAGE: &str = "/signup";
+pub const FORGOT_PASSWORD_PAGE: &str = "/forgot-password";
+pub const RESEND_ACTIVATION_PAGE: &str = "/resend-activation";
 pub const CALLBACK_ACTIVATE_PAGE: &str = "/callback/activate/*";
+pub const CALLBACK_RESET_PASSWORD_PAGE: &str = "/callback/reset-password/*";
+// The link may be opened on a device the user is not logged in on
+pub const CALLBACK_CONFIRM_EMAIL_PAGE: &str = "/callback/confirm-email/*";
 pub const HTMX_LOGIN_PAGE: &str = "/htmx/login";
+// The second login step, the password was checked but no session exists yet
+pub const HTMX_LOGIN_TWO_FACTOR_PAGE: &str = "/htmx/login-two-factor";
 pub const HTMX_REGISTER_PAGE: &str = "/htmx/register";
+pub const HTMX_FORGOT_PASSWORD_PAGE: &str = "/htmx/forgot-password";
+pub const HTMX_RESET_PASSWORD_PAGE: &str = "/htmx/reset-password";
+pub const HTMX_RESEND_ACTIVATION_PAGE: &str = "/htmx/resend-activation";
 
-pub const PUBLIC_PAGES: [&str; 5] = [
+pub const PUBLIC_PAGES: [&str; 13] = [
     LOGIN_PAGE,
     SIGNUP_PAGE,
+    FORGOT_PASSWORD_PAGE,
+    RESEND_ACTIVATION_PAGE,
     CALLBACK_ACTIVATE_PAGE,
+    CALLBACK_RESET_PASSWORD_PAGE,
+    CALLBACK_CONFIRM_EMAIL_PAGE,
     HTMX_LOGIN_PAGE,
+    HTMX_LOGIN_TWO_FACTOR_PAGE,
     HTMX_REGISTER_PAGE,
+    HTMX_FORGOT_PASSWORD_PAGE,
+    HTMX_RESET_PASSWORD_PAGE,
+    HTMX_RESEND_ACTIVATION_PAGE,
 ];
 
+// Signed URLs work without a session, so these pass through even when nobody is logged in
+pub const MEDIA_PAGES: &str = "/media/*";
+// Clears the cookie even when the session behind it is already gone
+pub const LOGOUT_PAGE: &str = "/logout";
+pub const OPTIONAL_AUTH_PAGES: [&str; 2] = [MEDIA_PAGES, LOGOUT_PAGE];
+
 pub const DEBUG_PAGES: [&str; 1] = ["/debug/active-link"];
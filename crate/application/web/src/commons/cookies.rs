@@ -0,0 +1,51 @@
+use axum::response::Response;
+use http::header::SET_COOKIE;
+use http::HeaderValue;
+use usecases::RefreshResponse;
+
+pub const ACCESS_TOKEN_COOKIE: &str = "token";
+pub const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+
+// Dropped with the browser session, the refresh token gets a new one on the next visit
+pub fn access_token_cookie(token: &str) -> HeaderValue {
+    format!("{}={}; httpOnly; path=/", ACCESS_TOKEN_COOKIE, token)
+        .parse()
+        .unwrap()
+}
+
+pub fn refresh_token_cookie(token: &str, max_age: i64) -> HeaderValue {
+    format!(
+        "{}={}; httpOnly; path=/; Max-Age={}",
+        REFRESH_TOKEN_COOKIE, token, max_age
+    )
+    .parse()
+    .unwrap()
+}
+
+pub fn expired_cookie(name: &str) -> HeaderValue {
+    format!("{}=; httpOnly; path=/; Max-Age=0", name)
+        .parse()
+        .unwrap()
+}
+
+/// Hands the renewed tokens to the browser, unless the handler already set
+/// the access token cookie itself, e.g. logging out clears it.
+pub fn with_refreshed_tokens(mut response: Response, refreshed: &RefreshResponse) -> Response {
+    let prefix = format!("{}=", ACCESS_TOKEN_COOKIE);
+    let headers = response.headers_mut();
+    let sets_access_token = headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .any(|cookie| cookie.as_bytes().starts_with(prefix.as_bytes()));
+    if sets_access_token {
+        return response;
+    }
+    headers.append(SET_COOKIE, access_token_cookie(&refreshed.token));
+    if let Some(refresh_token) = &refreshed.refresh_token {
+        headers.append(
+            SET_COOKIE,
+            refresh_token_cookie(refresh_token, refreshed.refresh_token_expires_in),
+        );
+    }
+    response
+}
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use chats::events::{ChatEvent, ChatEventBrokerInterface};
+use futures::Stream;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Live chat events addressed to `user_id`, shared by the websocket and SSE transports.
+pub fn user_chat_events(
+    chat_event_broker: Arc<dyn ChatEventBrokerInterface>,
+    user_id: Uuid,
+) -> impl Stream<Item = ChatEvent> {
+    let events = chat_event_broker.subscribe();
+    futures::stream::unfold(events, move |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) if event.is_recipient(&user_id) => return Some((event, events)),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Event stream for user {} skipped {} chat events", user_id, skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
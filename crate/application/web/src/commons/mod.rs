@@ -1,3 +1,5 @@
 pub mod constants;
+pub mod cookies;
+pub mod event_stream;
 pub mod templates;
 pub mod response_builder;
@@ -1,5 +1,5 @@
 use axum::response::{IntoResponse, Response};
-use http::StatusCode;
+use http::{HeaderValue, StatusCode};
 use log::error;
 
 use crate::utils::render_error_alert;
@@ -27,3 +27,11 @@ pub fn ok_builder(response: String) -> http::Response<axum::body::Body> {
         .unwrap()
         .into_response()
 }
+
+// Makes the sidebar reload the chat list, see chat.html.
+pub fn with_chat_list_refresh(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert("HX-Trigger", HeaderValue::from_static("chatListChanged"));
+    response
+}
@@ -1,9 +1,21 @@
-use chats::entity::{ChatMessages, MessageBox};
-use chrono::FixedOffset;
+use chats::entity::{
+    Attachment, ChatMessages, ChatPreview, ChatRole, Message, MessageBox, MessageCursor,
+    MessageReaction, MessageReadReceipt, MessageSearchResults, QuotedMessage, ReactionSummary,
+    ThreadMessages, ThreadSummary,
+};
+use chats::events::{ChatEvent, ChatEventKind};
+use chrono::{FixedOffset, NaiveDateTime};
 use chrono_humanize::HumanTime;
-use minijinja::{context, Environment};
+use minijinja::{context, AutoEscape, Environment};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use shaku::{Component, Interface};
+use usecases::chat_usecase::REACTION_PALETTE;
+use usecases::group_chat_usecase::GroupChat;
+use usecases::group_invite_usecase::{GroupInvite, InvitePreview};
+use usecases::session_usecase::ActiveSession;
+use usecases::two_factor_usecase::{TwoFactorEnrollment, TwoFactorStatus};
 use users::user::UserInfoDisplay;
+use uuid::Uuid;
 
 #[derive(Component)]
 #[shaku(interface = JinjaTemplate)]
@@ -14,6 +26,9 @@ pub struct JinjaTemplateImpl {
 impl Default for JinjaTemplateImpl {
     fn default() -> Self {
         let mut env = Environment::new();
+        // Templates are registered without an extension, so escaping has to be switched on
+        // explicitly; messages and names come straight from other users.
+        env.set_auto_escape_callback(|_| AutoEscape::Html);
         // Layout
         const LAYOUT: &str = include_str!("../../page/fragments/layout.html");
         const MODAL_CONFIRM: &str = include_str!("../../page/fragments/modal_confirm.html");
@@ -24,10 +39,23 @@ impl Default for JinjaTemplateImpl {
         const CHAT: &str = include_str!("../../page/chat.html");
         const SOMETHING_WENT_WRONG: &str = include_str!("../../page/500.html");
         const PROFILE: &str = include_str!("../../page/profile.html");
+        const JOIN: &str = include_str!("../../page/join.html");
+        const SESSIONS: &str = include_str!("../../page/sessions.html");
+        const RESET_PASSWORD: &str = include_str!("../../page/reset_password.html");
+        const CONFIRM_EMAIL: &str = include_str!("../../page/callback/confirm_email.html");
+        const RESEND_ACTIVATION: &str = include_str!("../../page/resend_activation.html");
         env.add_template("chat", CHAT).unwrap();
         env.add_template("something-went-wrong", SOMETHING_WENT_WRONG)
             .unwrap();
         env.add_template("profile", PROFILE).unwrap();
+        env.add_template("join", JOIN).unwrap();
+        env.add_template("sessions", SESSIONS).unwrap();
+        env.add_template("reset_password", RESET_PASSWORD).unwrap();
+        env.add_template("confirm_email", CONFIRM_EMAIL).unwrap();
+        env.add_template("resend_activation", RESEND_ACTIVATION)
+            .unwrap();
+        const TWO_FACTOR: &str = include_str!("../../page/two_factor.html");
+        env.add_template("two_factor", TWO_FACTOR).unwrap();
 
         // htmx
         const USER_INFO: &str = include_str!("../../page/htmx/user_info.html");
@@ -35,6 +63,10 @@ impl Default for JinjaTemplateImpl {
         env.add_template("htmx-user-info", USER_INFO).unwrap();
         env.add_template("htmx-chat-window", CHAT_WINDOW).unwrap();
 
+        const CHAT_MESSAGES: &str = include_str!("../../page/htmx/chat_messages.html");
+        env.add_template("htmx-chat-messages", CHAT_MESSAGES)
+            .unwrap();
+
         const CHAT_WINDOW_EMPTY: &str = include_str!("../../page/htmx/chat_window_empty.html");
         env.add_template("htmx-chat-window-empty", CHAT_WINDOW_EMPTY)
             .unwrap();
@@ -42,11 +74,66 @@ impl Default for JinjaTemplateImpl {
         const CHAT_HEADER: &str = include_str!("../../page/htmx/chat_header.html");
         env.add_template("htmx-chat-header", CHAT_HEADER).unwrap();
 
+        const MESSAGE_REACTIONS: &str = include_str!("../../page/htmx/message_reactions.html");
+        env.add_template("htmx-message-reactions", MESSAGE_REACTIONS)
+            .unwrap();
+
         const MESSAGE_BOX: &str = include_str!("../../page/htmx/message_box.html");
         env.add_template("htmx-message-box", MESSAGE_BOX).unwrap();
 
+        const THREAD_PANEL: &str = include_str!("../../page/htmx/thread_panel.html");
+        env.add_template("htmx-thread-panel", THREAD_PANEL).unwrap();
+
+        const THREAD_MESSAGES: &str = include_str!("../../page/htmx/thread_messages.html");
+        env.add_template("htmx-thread-messages", THREAD_MESSAGES)
+            .unwrap();
+
+        const THREAD_SUMMARY: &str = include_str!("../../page/htmx/thread_summary.html");
+        env.add_template("htmx-thread-summary", THREAD_SUMMARY)
+            .unwrap();
+
+        const MESSAGE_SEARCH: &str = include_str!("../../page/htmx/message_search.html");
+        env.add_template("htmx-message-search", MESSAGE_SEARCH)
+            .unwrap();
+
+        const MESSAGE_SEARCH_RESULTS: &str =
+            include_str!("../../page/htmx/message_search_results.html");
+        env.add_template("htmx-message-search-results", MESSAGE_SEARCH_RESULTS)
+            .unwrap();
+
         const CHAT_FORM_BOX: &str = include_str!("../../page/htmx/chat_form_box.html");
         env.add_template("chat-form-box", CHAT_FORM_BOX).unwrap();
+
+        const CHAT_EVENT: &str = include_str!("../../page/htmx/chat_event.html");
+        env.add_template("htmx-chat-event", CHAT_EVENT).unwrap();
+
+        const GROUP_HEADER: &str = include_str!("../../page/htmx/group_header.html");
+        env.add_template("htmx-group-header", GROUP_HEADER).unwrap();
+
+        const GROUP_FORM: &str = include_str!("../../page/htmx/group_form.html");
+        env.add_template("htmx-group-form", GROUP_FORM).unwrap();
+
+        const GROUP_MEMBERS: &str = include_str!("../../page/htmx/group_members.html");
+        env.add_template("htmx-group-members", GROUP_MEMBERS).unwrap();
+
+        const GROUP_INVITES: &str = include_str!("../../page/htmx/group_invites.html");
+        env.add_template("htmx-group-invites", GROUP_INVITES).unwrap();
+
+        const CHAT_LIST: &str = include_str!("../../page/htmx/chat_list.html");
+        env.add_template("htmx-chat-list", CHAT_LIST).unwrap();
+
+        const LOGIN_TWO_FACTOR: &str = include_str!("../../page/htmx/login_two_factor.html");
+        env.add_template("htmx-login-two-factor", LOGIN_TWO_FACTOR)
+            .unwrap();
+
+        const TWO_FACTOR_ENROLL: &str = include_str!("../../page/htmx/two_factor_enroll.html");
+        env.add_template("htmx-two-factor-enroll", TWO_FACTOR_ENROLL)
+            .unwrap();
+
+        const TWO_FACTOR_RECOVERY_CODES: &str =
+            include_str!("../../page/htmx/two_factor_recovery_codes.html");
+        env.add_template("htmx-two-factor-recovery-codes", TWO_FACTOR_RECOVERY_CODES)
+            .unwrap();
         JinjaTemplateImpl { env }
     }
 }
@@ -56,9 +143,193 @@ pub trait JinjaTemplate: Interface {
     fn something_went_wrong_page(&self) -> String;
     fn htmx_user_info(&self, user_id: &str, user_info: Box<dyn UserInfoDisplay>) -> String;
     fn htmx_chat_header(&self, user_id: &str, user_info: Box<dyn UserInfoDisplay>) -> String;
-    fn htmx_chat_box(&self, chat_messages: &Option<ChatMessages>) -> String;
-    fn htmx_message_box(&self, message: &MessageBox) -> String;
+    fn htmx_chat_box(
+        &self,
+        chat_id: &str,
+        chat_messages: &Option<ChatMessages>,
+        viewer_id: &str,
+    ) -> String;
+    fn htmx_chat_messages(
+        &self,
+        chat_messages: &ChatMessages,
+        cursor: MessageCursor,
+        viewer_id: &str,
+    ) -> String;
+    fn htmx_message_box(&self, message: &MessageBox, viewer_id: &str) -> String;
+    /// The side panel of a thread, opened at its newest replies.
+    fn htmx_thread_panel(&self, thread: &ThreadMessages, viewer_id: &str) -> String;
+    fn htmx_thread_messages(
+        &self,
+        thread: &ThreadMessages,
+        cursor: MessageCursor,
+        viewer_id: &str,
+    ) -> String;
+    /// With `oob` set the bar replaces the one already on the page, for live updates.
+    fn htmx_message_reactions(
+        &self,
+        message_id: &Uuid,
+        reactions: &[(String, MessageReaction)],
+        viewer_id: &str,
+        oob: bool,
+    ) -> String;
     fn htmx_chat_form_box(&self, chat_id: &str) -> String;
+    fn htmx_chat_event(&self, event: &ChatEvent, viewer_id: &str) -> String;
+    fn htmx_chat_list(&self, chats: &[ChatPreview]) -> String;
+    fn htmx_group_header(&self, group: &GroupChat) -> String;
+    fn htmx_message_search(&self) -> String;
+    /// A page of hits, `is_first_page` tells whether to say so when nothing matched.
+    fn htmx_message_search_results(
+        &self,
+        results: &MessageSearchResults,
+        is_first_page: bool,
+    ) -> String;
+    fn htmx_group_form(&self) -> String;
+    fn htmx_group_members(&self, group: &GroupChat, viewer_id: &str) -> String;
+    fn htmx_group_invites(&self, group: &GroupChat, invites: &[GroupInvite]) -> String;
+    /// The `/join/{token}` page, showing either the group behind the link or why it cannot be used.
+    fn join_page(&self, token: &str, preview: Result<&InvitePreview, String>) -> String;
+    fn sessions_page(&self, sessions: &[ActiveSession]) -> String;
+    fn reset_password_page(&self, token: &str, error: Option<String>) -> String;
+    fn confirm_email_page(&self, error: Option<String>) -> String;
+    fn resend_activation_page(&self, error: Option<String>) -> String;
+    fn htmx_login_two_factor(&self, challenge_token: &str) -> String;
+    fn two_factor_page(&self, status: &TwoFactorStatus) -> String;
+    fn htmx_two_factor_enroll(&self, enrollment: &TwoFactorEnrollment) -> String;
+    fn htmx_two_factor_recovery_codes(&self, codes: &[String]) -> String;
+}
+
+fn default_avatar(name: &str) -> String {
+    format!(
+        "https://ui-avatars.com/api/?name={}&background=random&rounded=true",
+        utf8_percent_encode(name, NON_ALPHANUMERIC)
+    )
+}
+
+fn group_avatar(group: &GroupChat) -> String {
+    group
+        .chat
+        .avatar
+        .clone()
+        .unwrap_or_else(|| default_avatar(&group.chat.name))
+}
+
+fn seen_by_context(name: &str, receipt: &MessageReadReceipt) -> minijinja::Value {
+    context! {
+        message_id => receipt.message_id.to_string(),
+        user_id => receipt.user_id.to_string(),
+        name => name,
+        read_at => receipt.read_at.map(humanize),
+    }
+}
+
+fn attachment_context(attachment: &Attachment) -> minijinja::Value {
+    context! {
+        url => attachment.file_url,
+        // Images sent before thumbnails existed are shown as they are
+        preview_url => attachment.thumbnail_url.as_ref().unwrap_or(&attachment.file_url),
+        name => attachment.file_name,
+        file_type => attachment.file_type,
+        size => format_file_size(attachment.file_size),
+        is_image => attachment.is_image(),
+    }
+}
+
+fn quote_context(quote: &QuotedMessage) -> minijinja::Value {
+    context! {
+        message_id => quote.message_id.to_string(),
+        sender_name => quote.sender_name,
+        snippet => quote.snippet,
+        is_attachment => quote.is_attachment,
+        is_deleted => quote.is_deleted,
+    }
+}
+
+fn format_file_size(size: i32) -> String {
+    let size = size as f64;
+    if size < 1024.0 {
+        format!("{} B", size)
+    } else if size < 1024.0 * 1024.0 {
+        format!("{:.1} KB", size / 1024.0)
+    } else {
+        format!("{:.1} MB", size / 1024.0 / 1024.0)
+    }
+}
+
+// Attachments without a caption have no content to preview
+fn message_preview(message: &Message) -> String {
+    if message.is_deleted() {
+        String::from("Message deleted")
+    } else if message.is_attachment() && message.content.is_empty() {
+        String::from("📎 Attachment")
+    } else {
+        message.content.clone()
+    }
+}
+
+fn humanize(time: NaiveDateTime) -> String {
+    let tz = FixedOffset::east_opt(7 * 3600).unwrap();
+    let time = time.and_local_timezone(tz).unwrap();
+    HumanTime::from(time).to_string()
+}
+
+impl JinjaTemplateImpl {
+    // With `oob` set the box replaces the one already on the page, for live edits and deletes
+    fn render_message_box(&self, message: &MessageBox, viewer_id: &str, oob: bool) -> String {
+        let sent_at = humanize(message.0.sent_at.unwrap());
+        let seen_by: Vec<_> = message
+            .1
+            .iter()
+            .filter(|(_, receipt)| receipt.user_id != message.0.sender_id)
+            .map(|(name, receipt)| seen_by_context(name, receipt))
+            .collect();
+        self.env
+            .get_template("htmx-message-box")
+            .unwrap()
+            .render(context! {
+                message => message.0.content,
+                sender_id => message.0.sender_id.to_string(),
+                message_id => message.0.id.to_string(),
+                message_type => message.0.message_type,
+                sent_at => sent_at,
+                is_mine => message.0.sender_id.to_string() == viewer_id,
+                is_edited => message.0.is_edited(),
+                edited_at => message.0.edited_at.map(humanize),
+                is_deleted => message.0.is_deleted(),
+                oob => oob,
+                seen_by => seen_by,
+                reactions => self.htmx_message_reactions(&message.0.id, &message.2, viewer_id, false),
+                attachments => message.3.iter().map(attachment_context).collect::<Vec<_>>(),
+                quote => message.4.as_ref().map(quote_context),
+                in_thread => message.0.is_thread_reply(),
+                // Always there for messages of the timeline, so the first reply has a place to go
+                thread_summary => (!message.0.is_thread_reply() && !message.0.is_deleted()).then(|| {
+                    let thread = message.5.as_ref();
+                    let has_unread = thread.is_some_and(|thread| thread.unread_count > 0);
+                    self.render_thread_summary(&message.0.id, thread, has_unread, false)
+                }),
+            })
+            .unwrap()
+    }
+
+    fn render_thread_summary(
+        &self,
+        root_id: &Uuid,
+        thread: Option<&ThreadSummary>,
+        has_unread: bool,
+        oob: bool,
+    ) -> String {
+        self.env
+            .get_template("htmx-thread-summary")
+            .unwrap()
+            .render(context! {
+                root_id => root_id.to_string(),
+                reply_count => thread.map(|thread| thread.reply_count),
+                last_reply_at => thread.and_then(|thread| thread.last_reply_at).map(humanize),
+                has_unread => has_unread,
+                oob => oob,
+            })
+            .unwrap()
+    }
 }
 
 impl JinjaTemplate for JinjaTemplateImpl {
@@ -103,36 +374,155 @@ impl JinjaTemplate for JinjaTemplateImpl {
             .unwrap();
     }
 
-    fn htmx_chat_box(&self, chat_messages: &Option<ChatMessages>) -> String {
-        if chat_messages.is_none() {
+    fn htmx_chat_box(
+        &self,
+        chat_id: &str,
+        chat_messages: &Option<ChatMessages>,
+        viewer_id: &str,
+    ) -> String {
+        let Some(chat_messages) = chat_messages.as_ref().filter(|c| !c.messages.is_empty()) else {
             return self
                 .env
                 .get_template("htmx-chat-window-empty")
                 .unwrap()
-                .render(context! {})
+                .render(context! {
+                    chat_id => chat_id,
+                })
                 .unwrap();
-        }
+        };
         self.env
             .get_template("htmx-chat-window")
             .unwrap()
-            .render(context! {})
+            .render(context! {
+                chat_id => chat_id,
+                messages => self.htmx_chat_messages(chat_messages, MessageCursor::Latest, viewer_id),
+            })
             .unwrap()
     }
 
-    fn htmx_message_box(&self, message: &MessageBox) -> String {
-        let sent_at = message.0.sent_at.unwrap();
-        let tz = FixedOffset::east_opt(7 * 3600).unwrap();
-        let sent_at = sent_at.and_local_timezone(tz).unwrap();
-        let sent_at = HumanTime::from(sent_at).to_string();
+    fn htmx_chat_messages(
+        &self,
+        chat_messages: &ChatMessages,
+        cursor: MessageCursor,
+        viewer_id: &str,
+    ) -> String {
+        let load_older_before = match cursor {
+            MessageCursor::After(_) => None,
+            _ if !chat_messages.has_more => None,
+            _ => chat_messages
+                .messages
+                .first()
+                .map(|message| message.0.id.to_string()),
+        };
+        // Older pages are already covered by the newest message, which reads everything before it
+        let mark_read_up_to = match cursor {
+            MessageCursor::Before(_) => None,
+            _ => chat_messages
+                .messages
+                .last()
+                .map(|message| message.0.id.to_string()),
+        };
+        let messages: Vec<String> = chat_messages
+            .messages
+            .iter()
+            .map(|message| self.htmx_message_box(message, viewer_id))
+            .collect();
         self.env
-            .get_template("htmx-message-box")
+            .get_template("htmx-chat-messages")
             .unwrap()
             .render(context! {
-                message => message.0.content,
-                sender_id => message.0.sender_id.to_string(),
-                message_id => message.0.id.to_string(),
-                message_type => message.0.message_type,
-                sent_at => sent_at,
+                chat_id => chat_messages.chat_id.to_string(),
+                load_older_before => load_older_before,
+                mark_read_up_to => mark_read_up_to,
+                messages => messages.join(""),
+            })
+            .unwrap()
+    }
+
+    fn htmx_message_box(&self, message: &MessageBox, viewer_id: &str) -> String {
+        self.render_message_box(message, viewer_id, false)
+    }
+
+    fn htmx_thread_panel(&self, thread: &ThreadMessages, viewer_id: &str) -> String {
+        let root = &thread.root.0;
+        self.env
+            .get_template("htmx-thread-panel")
+            .unwrap()
+            .render(context! {
+                root_id => root.id.to_string(),
+                root_message => root.content,
+                root_sent_at => root.sent_at.map(humanize),
+                root_is_deleted => root.is_deleted(),
+                messages => self.htmx_thread_messages(thread, MessageCursor::Latest, viewer_id),
+            })
+            .unwrap()
+    }
+
+    fn htmx_thread_messages(
+        &self,
+        thread: &ThreadMessages,
+        cursor: MessageCursor,
+        viewer_id: &str,
+    ) -> String {
+        // Same paging as the chat window, see htmx_chat_messages
+        let load_older_before = match cursor {
+            MessageCursor::After(_) => None,
+            _ if !thread.has_more => None,
+            _ => thread
+                .messages
+                .first()
+                .map(|message| message.0.id.to_string()),
+        };
+        let mark_read_up_to = match cursor {
+            MessageCursor::Before(_) => None,
+            _ => thread
+                .messages
+                .last()
+                .map(|message| message.0.id.to_string()),
+        };
+        let messages: Vec<String> = thread
+            .messages
+            .iter()
+            .map(|message| self.htmx_message_box(message, viewer_id))
+            .collect();
+        self.env
+            .get_template("htmx-thread-messages")
+            .unwrap()
+            .render(context! {
+                root_id => thread.root.0.id.to_string(),
+                load_older_before => load_older_before,
+                mark_read_up_to => mark_read_up_to,
+                messages => messages.join(""),
+            })
+            .unwrap()
+    }
+
+    fn htmx_message_reactions(
+        &self,
+        message_id: &Uuid,
+        reactions: &[(String, MessageReaction)],
+        viewer_id: &str,
+        oob: bool,
+    ) -> String {
+        let reactions: Vec<_> = ReactionSummary::summarize(reactions)
+            .into_iter()
+            .map(|summary| {
+                context! {
+                    mine => summary.user_ids.iter().any(|user_id| user_id.to_string() == viewer_id),
+                    reaction => summary.reaction,
+                    count => summary.count,
+                    names => summary.names,
+                }
+            })
+            .collect();
+        self.env
+            .get_template("htmx-message-reactions")
+            .unwrap()
+            .render(context! {
+                message_id => message_id.to_string(),
+                reactions => reactions,
+                palette => REACTION_PALETTE,
+                oob => oob,
             })
             .unwrap()
     }
@@ -146,4 +536,368 @@ impl JinjaTemplate for JinjaTemplateImpl {
             })
             .unwrap()
     }
+
+    fn htmx_chat_event(&self, event: &ChatEvent, viewer_id: &str) -> String {
+        let template = self.env.get_template("htmx-chat-event").unwrap();
+        match &event.kind {
+            ChatEventKind::NewMessage(message) => template
+                .render(context! {
+                    kind => "new_message",
+                    chat_id => event.chat_id.to_string(),
+                    message_id => message.0.id.to_string(),
+                    is_mine => message.0.sender_id.to_string() == viewer_id,
+                    message_box => self.htmx_message_box(message, viewer_id),
+                })
+                .unwrap(),
+            ChatEventKind::NewThreadReply(message, thread) => {
+                let root_id = message.0.thread_root_id.unwrap_or_default();
+                let is_mine = message.0.sender_id.to_string() == viewer_id;
+                template
+                    .render(context! {
+                        kind => "new_thread_reply",
+                        chat_id => event.chat_id.to_string(),
+                        root_id => root_id.to_string(),
+                        message_id => message.0.id.to_string(),
+                        is_mine => is_mine,
+                        message_box => self.htmx_message_box(message, viewer_id),
+                        // Cleared again right away when the thread is open, see chat_event.html
+                        thread_summary => self.render_thread_summary(&root_id, Some(thread), !is_mine, true),
+                    })
+                    .unwrap()
+            }
+            ChatEventKind::MessagesRead(receipts) => template
+                .render(context! {
+                    kind => "messages_read",
+                    chat_id => event.chat_id.to_string(),
+                    receipts => receipts
+                        .iter()
+                        .map(|(name, receipt)| seen_by_context(name, receipt))
+                        .collect::<Vec<_>>(),
+                })
+                .unwrap(),
+            ChatEventKind::ReactionsChanged(message_id, reactions) => template
+                .render(context! {
+                    kind => "reactions_changed",
+                    chat_id => event.chat_id.to_string(),
+                    reactions => self.htmx_message_reactions(message_id, reactions, viewer_id, true),
+                })
+                .unwrap(),
+            ChatEventKind::MessageEdited(message) => template
+                .render(context! {
+                    kind => "message_edited",
+                    chat_id => event.chat_id.to_string(),
+                    message_box => self.render_message_box(message, viewer_id, true),
+                })
+                .unwrap(),
+            ChatEventKind::MessageDeleted(message) => template
+                .render(context! {
+                    kind => "message_deleted",
+                    chat_id => event.chat_id.to_string(),
+                    message_box => self.render_message_box(
+                        &MessageBox(message.clone(), Vec::new(), Vec::new(), Vec::new(), None, None),
+                        viewer_id,
+                        true,
+                    ),
+                })
+                .unwrap(),
+            ChatEventKind::MessageHidden(message_id) => template
+                .render(context! {
+                    kind => "message_hidden",
+                    chat_id => event.chat_id.to_string(),
+                    message_id => message_id.to_string(),
+                })
+                .unwrap(),
+            ChatEventKind::MembersChanged(members) => template
+                .render(context! {
+                    kind => "members_changed",
+                    chat_id => event.chat_id.to_string(),
+                    is_member => members
+                        .iter()
+                        .any(|profile| profile.member.user_id.to_string() == viewer_id),
+                })
+                .unwrap(),
+        }
+    }
+
+    fn htmx_chat_list(&self, chats: &[ChatPreview]) -> String {
+        let chats: Vec<_> = chats
+            .iter()
+            .map(|chat| {
+                let profile_picture = chat
+                    .profile_picture
+                    .clone()
+                    .unwrap_or_else(|| default_avatar(&chat.name));
+                context! {
+                    chat_id => chat.chat_id.to_string(),
+                    name => chat.name,
+                    profile_picture => profile_picture,
+                    counterpart_username => chat.counterpart_username,
+                    last_message => chat.last_message.as_ref().map(message_preview),
+                    last_activity => chat.last_activity_at.map(humanize),
+                    unread_message_count => chat.unread_message_count,
+                }
+            })
+            .collect();
+        self.env
+            .get_template("htmx-chat-list")
+            .unwrap()
+            .render(context! { chats => chats })
+            .unwrap()
+    }
+
+    fn htmx_group_header(&self, group: &GroupChat) -> String {
+        self.env
+            .get_template("htmx-group-header")
+            .unwrap()
+            .render(context! {
+                chat_id => group.chat.id.to_string(),
+                name => group.chat.name,
+                profile_picture => group_avatar(group),
+                member_count => group.members.len(),
+            })
+            .unwrap()
+    }
+
+    fn htmx_message_search(&self) -> String {
+        self.env
+            .get_template("htmx-message-search")
+            .unwrap()
+            .render(context! {})
+            .unwrap()
+    }
+
+    fn htmx_message_search_results(
+        &self,
+        results: &MessageSearchResults,
+        is_first_page: bool,
+    ) -> String {
+        let hits: Vec<_> = results
+            .hits
+            .iter()
+            .map(|hit| {
+                let snippet: Vec<_> = hit
+                    .highlights()
+                    .into_iter()
+                    .map(|(text, is_match)| context! { text => text, is_match => is_match })
+                    .collect();
+                context! {
+                    chat_id => hit.message.chat_id.to_string(),
+                    chat_name => hit.chat_name,
+                    sender_name => hit.sender_name,
+                    sent_at => hit.message.sent_at.map(humanize),
+                    is_attachment => hit.message.is_attachment(),
+                    thread_root_id => hit.message.thread_root_id.map(|id| id.to_string()),
+                    // Thread replies are not in the chat window, their root is
+                    jump_to => hit.message.thread_root_id.unwrap_or(hit.message.id).to_string(),
+                    snippet => snippet,
+                }
+            })
+            .collect();
+        let load_more_before = results
+            .hits
+            .last()
+            .filter(|_| results.has_more)
+            .map(|hit| hit.message.id.to_string());
+        self.env
+            .get_template("htmx-message-search-results")
+            .unwrap()
+            .render(context! {
+                hits => hits,
+                is_first_page => is_first_page,
+                load_more_before => load_more_before,
+            })
+            .unwrap()
+    }
+
+    fn htmx_group_form(&self) -> String {
+        self.env
+            .get_template("htmx-group-form")
+            .unwrap()
+            .render(context! {})
+            .unwrap()
+    }
+
+    fn htmx_group_members(&self, group: &GroupChat, viewer_id: &str) -> String {
+        let viewer_role = viewer_id
+            .parse()
+            .ok()
+            .and_then(|viewer_id| group.role_of(&viewer_id))
+            .unwrap_or(ChatRole::Member);
+        let members: Vec<_> = group
+            .members
+            .iter()
+            .map(|profile| {
+                let user_id = profile.member.user_id.to_string();
+                let is_me = user_id == viewer_id;
+                context! {
+                    is_me => is_me,
+                    role => profile.member.role.as_str(),
+                    removable => !is_me
+                        && viewer_role >= ChatRole::Admin
+                        && viewer_role > profile.member.role,
+                    user_id => user_id,
+                    username => profile.username,
+                    name => profile.name,
+                    profile_picture => profile
+                        .profile_picture
+                        .clone()
+                        .unwrap_or_else(|| default_avatar(&profile.name)),
+                }
+            })
+            .collect();
+        self.env
+            .get_template("htmx-group-members")
+            .unwrap()
+            .render(context! {
+                chat_id => group.chat.id.to_string(),
+                name => group.chat.name,
+                avatar => group_avatar(group),
+                is_admin => viewer_role >= ChatRole::Admin,
+                is_owner => viewer_role == ChatRole::Owner,
+                members => members,
+            })
+            .unwrap()
+    }
+
+    fn htmx_group_invites(&self, group: &GroupChat, invites: &[GroupInvite]) -> String {
+        let invites: Vec<_> = invites
+            .iter()
+            .map(|group_invite| {
+                let invite = &group_invite.invite;
+                context! {
+                    id => invite.id.to_string(),
+                    link => format!("/join/{}", group_invite.token),
+                    expires => invite.expires_at.map(humanize),
+                    use_count => invite.use_count,
+                    max_uses => invite.max_uses,
+                }
+            })
+            .collect();
+        self.env
+            .get_template("htmx-group-invites")
+            .unwrap()
+            .render(context! {
+                chat_id => group.chat.id.to_string(),
+                name => group.chat.name,
+                invites => invites,
+            })
+            .unwrap()
+    }
+
+    fn join_page(&self, token: &str, preview: Result<&InvitePreview, String>) -> String {
+        let template = self.env.get_template("join").unwrap();
+        let preview = match preview {
+            Ok(preview) => preview,
+            Err(error) => {
+                return template
+                    .render(context! { title => "Invite", error => error })
+                    .unwrap()
+            }
+        };
+        let group = &preview.group;
+        let member_names: Vec<&str> = group
+            .members
+            .iter()
+            .take(5)
+            .map(|profile| profile.name.as_str())
+            .collect();
+        template
+            .render(context! {
+                title => group.chat.name,
+                token => token,
+                chat_id => group.chat.id.to_string(),
+                name => group.chat.name,
+                avatar => group_avatar(group),
+                member_count => group.members.len(),
+                member_names => member_names,
+                is_member => preview.is_member,
+                expires => preview.invite.expires_at.map(humanize),
+            })
+            .unwrap()
+    }
+
+    fn sessions_page(&self, sessions: &[ActiveSession]) -> String {
+        let sessions: Vec<_> = sessions
+            .iter()
+            .map(|session| {
+                context! {
+                    id => session.id.to_string(),
+                    browser => session.device.browser,
+                    os => session.device.os,
+                    ip_address => session.ip_address,
+                    first_seen_at => session.first_seen_at.map(humanize),
+                    last_active_at => session.last_active_at.map(humanize),
+                    is_current => session.is_current,
+                }
+            })
+            .collect();
+        self.env
+            .get_template("sessions")
+            .unwrap()
+            .render(context! { title => "Sessions", sessions => sessions })
+            .unwrap()
+    }
+
+    fn reset_password_page(&self, token: &str, error: Option<String>) -> String {
+        self.env
+            .get_template("reset_password")
+            .unwrap()
+            .render(context! { token => token, error => error })
+            .unwrap()
+    }
+
+    fn confirm_email_page(&self, error: Option<String>) -> String {
+        self.env
+            .get_template("confirm_email")
+            .unwrap()
+            .render(context! { error => error })
+            .unwrap()
+    }
+
+    fn resend_activation_page(&self, error: Option<String>) -> String {
+        self.env
+            .get_template("resend_activation")
+            .unwrap()
+            .render(context! { error => error })
+            .unwrap()
+    }
+
+    fn htmx_login_two_factor(&self, challenge_token: &str) -> String {
+        self.env
+            .get_template("htmx-login-two-factor")
+            .unwrap()
+            .render(context! { challenge_token => challenge_token })
+            .unwrap()
+    }
+
+    fn two_factor_page(&self, status: &TwoFactorStatus) -> String {
+        self.env
+            .get_template("two_factor")
+            .unwrap()
+            .render(context! {
+                title => "Two-Factor Authentication",
+                enabled => status.enabled,
+                recovery_codes_left => status.recovery_codes_left,
+            })
+            .unwrap()
+    }
+
+    fn htmx_two_factor_enroll(&self, enrollment: &TwoFactorEnrollment) -> String {
+        self.env
+            .get_template("htmx-two-factor-enroll")
+            .unwrap()
+            .render(context! {
+                secret => enrollment.secret,
+                otpauth_uri => enrollment.otpauth_uri,
+            })
+            .unwrap()
+    }
+
+    fn htmx_two_factor_recovery_codes(&self, codes: &[String]) -> String {
+        self.env
+            .get_template("htmx-two-factor-recovery-codes")
+            .unwrap()
+            .render(context! { codes => codes })
+            .unwrap()
+    }
 }
@@ -0,0 +1,55 @@
+use crate::commons::response_builder::error_builder;
+use crate::WebModule;
+use axum::extract::Extension;
+use axum::response::{Html, IntoResponse};
+use axum::Form;
+use jwt::AccessClaims;
+use serde::Deserialize;
+use shaku_axum::Inject;
+use usecases::account_usecase::AccountUsecase;
+
+#[derive(Deserialize)]
+pub struct ChangePasswordForm {
+    current_password: String,
+    new_password: String,
+    password_confirmation: String,
+}
+
+pub async fn change_password(
+    account_usecase: Inject<WebModule, dyn AccountUsecase>,
+    claim: Extension<AccessClaims>,
+    Form(form): Form<ChangePasswordForm>,
+) -> impl IntoResponse {
+    match account_usecase
+        .change_password(
+            &claim,
+            &form.current_password,
+            &form.new_password,
+            &form.password_confirmation,
+        )
+        .await
+    {
+        Ok(_) => Html(include_str!("../../page/htmx/change_password_success.html")).into_response(),
+        Err(e) => error_builder(e, "password change"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailForm {
+    new_email: String,
+    current_password: String,
+}
+
+pub async fn change_email(
+    account_usecase: Inject<WebModule, dyn AccountUsecase>,
+    claim: Extension<AccessClaims>,
+    Form(form): Form<ChangeEmailForm>,
+) -> impl IntoResponse {
+    match account_usecase
+        .request_email_change(&claim, &form.new_email, &form.current_password)
+        .await
+    {
+        Ok(_) => Html(include_str!("../../page/htmx/change_email_sent.html")).into_response(),
+        Err(e) => error_builder(e, "email change"),
+    }
+}
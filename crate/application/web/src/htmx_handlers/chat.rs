@@ -1,15 +1,22 @@
 use std::str::FromStr;
 
 use crate::commons::{
-    response_builder::{error_builder, ok_builder},
+    response_builder::{error_builder, ok_builder, with_chat_list_refresh},
     templates::JinjaTemplate,
 };
 use crate::WebModule;
 use axum::{extract::Query, response::IntoResponse, Extension, Form, Json};
+use axum_extra::extract::Multipart;
+use chats::entity::{DeleteScope, MessageBox, MessageCursor};
+use commons::generic_errors::GenericError;
 use jwt::AccessClaims;
 use shaku_axum::Inject;
 use usecases::InvitePrivateChatUsecaseInterface;
-use usecases::{chat_usecase::ChatUsecase, userdetail_usecase::UserDetailUsecase};
+use usecases::{
+    chat_usecase::{AttachmentUpload, ChatUsecase},
+    group_chat_usecase::GroupChatUsecase,
+    userdetail_usecase::UserDetailUsecase,
+};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -37,6 +44,66 @@ pub async fn find_user_info_list(
     }
 }
 
+pub async fn chat_list(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+) -> impl IntoResponse {
+    chat_usecase
+        .get_user_chat_list(&claim.user_id)
+        .await
+        .map_err(|e| error_builder(e, "chat_list"))
+        .map(|chats| ok_builder(template.htmx_chat_list(&chats)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct OpenChatRequest {
+    pub chat_id: String,
+}
+
+pub async fn open_chat(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    user_detail_usecase: Inject<WebModule, dyn UserDetailUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<OpenChatRequest>,
+) -> impl IntoResponse {
+    let chat_messages = match chat_usecase
+        .get_messages_of_chat(&payload.chat_id, &claim.user_id, MessageCursor::Latest, None)
+        .await
+    {
+        Ok(chat_messages) => chat_messages,
+        Err(e) => return error_builder(e, "open_chat"),
+    };
+
+    let htmx_chat_header = if chat_messages.is_group {
+        match group_chat_usecase
+            .get_group(&payload.chat_id, &claim.user_id)
+            .await
+        {
+            Ok(group) => template.htmx_group_header(&group),
+            Err(e) => return error_builder(e, "open_chat"),
+        }
+    } else {
+        let friend_id = chat_messages
+            .chat_members
+            .iter()
+            .map(|member| member.user_id.to_string())
+            .find(|user_id| user_id != &claim.user_id)
+            .unwrap_or_default();
+        match user_detail_usecase.get_user_info(&friend_id).await {
+            Ok(user_info) => template.htmx_chat_header(&friend_id, Box::new(user_info)),
+            Err(e) => return error_builder(e, "open_chat"),
+        }
+    };
+
+    let htmx_chat_box =
+        template.htmx_chat_box(&payload.chat_id, &Some(chat_messages), &claim.user_id);
+    let htmx_chat_form_box = template.htmx_chat_form_box(&payload.chat_id);
+    ok_builder([htmx_chat_box, htmx_chat_header, htmx_chat_form_box].join(""))
+}
+
 #[derive(serde::Deserialize, Debug)]
 pub struct InvitePrivateChatRequest {
     pub user_email_or_username: String,
@@ -60,7 +127,11 @@ pub async fn invite_private_chat_usecase(
             let user_info = Box::new(val.friend_user_info);
             let friend_id = val.friend_id.to_string();
             let htmx_chat_header = template.htmx_chat_header(&friend_id, user_info);
-            let htmx_chat_box = template.htmx_chat_box(&val.chat_messages);
+            let htmx_chat_box = template.htmx_chat_box(
+                &val.chat_id.to_string(),
+                &val.chat_messages,
+                &claim.user_id,
+            );
             let htmx_chat_form_box = template.htmx_chat_form_box(&val.chat_id.to_string());
             ok_builder([htmx_chat_box, htmx_chat_header, htmx_chat_form_box].join(""))
         })
@@ -86,10 +157,55 @@ pub async fn chat_header(
         })
 }
 
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct ChatMessagesRequest {
+    pub chat_id: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl ChatMessagesRequest {
+    fn cursor(&self) -> anyhow::Result<MessageCursor> {
+        let parse = |id: &str| {
+            Uuid::from_str(id)
+                .map_err(|_| GenericError::invalid_input(String::from("Invalid message cursor")))
+        };
+        match (&self.before, &self.after) {
+            (None, None) => Ok(MessageCursor::Latest),
+            (Some(before), None) => Ok(MessageCursor::Before(parse(before)?)),
+            (None, Some(after)) => Ok(MessageCursor::After(parse(after)?)),
+            (Some(_), Some(_)) => Err(GenericError::invalid_input(String::from(
+                "Use either before or after, not both",
+            ))),
+        }
+    }
+}
+
+pub async fn chat_messages(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<ChatMessagesRequest>,
+) -> impl IntoResponse {
+    let cursor = match payload.cursor() {
+        Ok(cursor) => cursor,
+        Err(e) => return error_builder(e, "chat_messages"),
+    };
+    chat_usecase
+        .get_messages_of_chat(&payload.chat_id, &claim.user_id, cursor, payload.limit)
+        .await
+        .map(|val| ok_builder(template.htmx_chat_messages(&val, cursor, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "chat_messages"))
+}
+
 #[derive(Default, Debug, serde::Deserialize)]
 pub struct ChatSendRequest {
     pub chat_id: String,
     pub message: String,
+    // The form always posts the field, left empty unless the user picked a message to reply to
+    #[serde(default)]
+    pub reply_to_message_id: String,
 }
 
 pub async fn chat_send(
@@ -103,11 +219,329 @@ pub async fn chat_send(
             payload.chat_id.as_str(),
             &claim.user_id,
             payload.message.as_str(),
+            Some(payload.reply_to_message_id.as_str()).filter(|id| !id.is_empty()),
         )
         .await
         .map_err(|e| error_builder(e, "chat_send"))
         .map(|val| {
-            let htmx_chat_box = template.htmx_message_box(&val);
+            let htmx_chat_box = template.htmx_message_box(&val, &claim.user_id);
             ok_builder(htmx_chat_box)
         })
 }
+
+// Mirrors the chat form, so the text box doubles as the caption of the files
+pub async fn chat_send_attachments(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let mut chat_id = None;
+    let mut caption = String::new();
+    let mut uploads = Vec::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(_) => {
+                return error_builder(
+                    GenericError::invalid_input(String::from("Files could not be uploaded")),
+                    "chat_send_attachments",
+                )
+            }
+        };
+        match field.name().unwrap_or_default() {
+            "chat_id" => chat_id = field.text().await.ok(),
+            "message" => caption = field.text().await.unwrap_or_default(),
+            "files" => {
+                let file_name = field.file_name().unwrap_or_default().to_string();
+                // Browsers send an empty part when no file was picked
+                match field.bytes().await {
+                    Ok(data) if !file_name.is_empty() => uploads.push(AttachmentUpload {
+                        file_name,
+                        data: data.to_vec(),
+                    }),
+                    Ok(_) => {}
+                    Err(_) => {
+                        return error_builder(
+                            GenericError::invalid_input(String::from(
+                                "Files could not be uploaded",
+                            )),
+                            "chat_send_attachments",
+                        )
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some(chat_id) = chat_id else {
+        return error_builder(
+            GenericError::invalid_input(String::from("No chat found")),
+            "chat_send_attachments",
+        );
+    };
+
+    chat_usecase
+        .send_attachments_to_chat(&chat_id, &claim.user_id, &caption, &uploads)
+        .await
+        .map(|val| ok_builder(template.htmx_message_box(&val, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "chat_send_attachments"))
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct ChatReadRequest {
+    pub chat_id: String,
+    pub message_id: String,
+}
+
+pub async fn chat_read(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    Form(payload): Form<ChatReadRequest>,
+) -> impl IntoResponse {
+    match chat_usecase
+        .mark_chat_read(&payload.chat_id, &claim.user_id, &payload.message_id)
+        .await
+    {
+        // Unread badges only need a refresh when something was actually read
+        Ok(0) => ok_builder(String::new()),
+        Ok(_) => with_chat_list_refresh(ok_builder(String::new())),
+        Err(e) => error_builder(e, "chat_read"),
+    }
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct ThreadRequest {
+    pub root_message_id: String,
+    pub before: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl ThreadRequest {
+    // New replies arrive over SSE, so a thread only ever pages backwards
+    fn cursor(&self) -> anyhow::Result<MessageCursor> {
+        match &self.before {
+            None => Ok(MessageCursor::Latest),
+            Some(before) => Uuid::from_str(before)
+                .map(MessageCursor::Before)
+                .map_err(|_| GenericError::invalid_input(String::from("Invalid message cursor"))),
+        }
+    }
+}
+
+pub async fn open_thread(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<ThreadRequest>,
+) -> impl IntoResponse {
+    chat_usecase
+        .get_thread_messages(
+            &payload.root_message_id,
+            &claim.user_id,
+            MessageCursor::Latest,
+            payload.limit,
+        )
+        .await
+        .map(|val| ok_builder(template.htmx_thread_panel(&val, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "open_thread"))
+}
+
+pub async fn thread_messages(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<ThreadRequest>,
+) -> impl IntoResponse {
+    let cursor = match payload.cursor() {
+        Ok(cursor) => cursor,
+        Err(e) => return error_builder(e, "thread_messages"),
+    };
+    chat_usecase
+        .get_thread_messages(
+            &payload.root_message_id,
+            &claim.user_id,
+            cursor,
+            payload.limit,
+        )
+        .await
+        .map(|val| ok_builder(template.htmx_thread_messages(&val, cursor, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "thread_messages"))
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct ThreadSendRequest {
+    pub root_message_id: String,
+    pub message: String,
+}
+
+pub async fn thread_send(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<ThreadSendRequest>,
+) -> impl IntoResponse {
+    chat_usecase
+        .send_thread_reply(&payload.root_message_id, &claim.user_id, &payload.message)
+        .await
+        .map(|val| ok_builder(template.htmx_message_box(&val, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "thread_send"))
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct ThreadReadRequest {
+    pub root_message_id: String,
+    pub message_id: String,
+}
+
+pub async fn thread_read(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    Form(payload): Form<ThreadReadRequest>,
+) -> impl IntoResponse {
+    // Thread replies never count towards the chat list badges, nothing to refresh
+    chat_usecase
+        .mark_thread_read(
+            &payload.root_message_id,
+            &claim.user_id,
+            &payload.message_id,
+        )
+        .await
+        .map(|_| ok_builder(String::new()))
+        .unwrap_or_else(|e| error_builder(e, "thread_read"))
+}
+
+pub async fn message_search(template: Inject<WebModule, dyn JinjaTemplate>) -> impl IntoResponse {
+    ok_builder(template.htmx_message_search())
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct MessageSearchRequest {
+    #[serde(default)]
+    pub q: String,
+    pub before: Option<String>,
+    pub limit: Option<i64>,
+}
+
+pub async fn message_search_results(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<MessageSearchRequest>,
+) -> impl IntoResponse {
+    // Clearing the search box clears the results instead of complaining
+    if payload.q.trim().is_empty() {
+        return ok_builder(String::new());
+    }
+    let before = match payload.before.as_deref().map(Uuid::from_str).transpose() {
+        Ok(before) => before,
+        Err(_) => {
+            return error_builder(
+                GenericError::invalid_input(String::from("Invalid message cursor")),
+                "message_search_results",
+            )
+        }
+    };
+    chat_usecase
+        .search_messages(&claim.user_id, &payload.q, before, payload.limit)
+        .await
+        .map(|val| ok_builder(template.htmx_message_search_results(&val, before.is_none())))
+        .unwrap_or_else(|e| error_builder(e, "message_search_results"))
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct MessageReactionRequest {
+    pub message_id: String,
+    pub reaction: String,
+}
+
+pub async fn toggle_message_reaction(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<MessageReactionRequest>,
+) -> impl IntoResponse {
+    let message_id = match Uuid::from_str(&payload.message_id) {
+        Ok(message_id) => message_id,
+        Err(_) => {
+            return error_builder(
+                GenericError::invalid_input(String::from("Invalid message id")),
+                "toggle_message_reaction",
+            )
+        }
+    };
+    chat_usecase
+        .toggle_reaction(&payload.message_id, &claim.user_id, &payload.reaction)
+        .await
+        .map(|reactions| {
+            ok_builder(template.htmx_message_reactions(
+                &message_id,
+                &reactions,
+                &claim.user_id,
+                false,
+            ))
+        })
+        .unwrap_or_else(|e| error_builder(e, "toggle_message_reaction"))
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct MessageEditRequest {
+    pub message_id: String,
+    pub message: String,
+}
+
+pub async fn edit_message(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<MessageEditRequest>,
+) -> impl IntoResponse {
+    chat_usecase
+        .edit_message(&payload.message_id, &claim.user_id, &payload.message)
+        .await
+        .map(|val| {
+            with_chat_list_refresh(ok_builder(template.htmx_message_box(&val, &claim.user_id)))
+        })
+        .unwrap_or_else(|e| error_builder(e, "edit_message"))
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+pub struct MessageDeleteRequest {
+    pub message_id: String,
+    // "me" or "everyone"
+    pub scope: String,
+}
+
+pub async fn delete_message(
+    chat_usecase: Inject<WebModule, dyn ChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<MessageDeleteRequest>,
+) -> impl IntoResponse {
+    let scope = match payload.scope.as_str() {
+        "me" => DeleteScope::ForMe,
+        "everyone" => DeleteScope::ForEveryone,
+        _ => {
+            return error_builder(
+                GenericError::invalid_input(String::from("Unknown delete scope")),
+                "delete_message",
+            )
+        }
+    };
+    chat_usecase
+        .delete_message(&payload.message_id, &claim.user_id, scope)
+        .await
+        .map(|message| {
+            // Hidden messages simply disappear, the empty response swaps the bubble away
+            let html = match scope {
+                DeleteScope::ForMe => String::new(),
+                DeleteScope::ForEveryone => template.htmx_message_box(
+                    &MessageBox(message, Vec::new(), Vec::new(), Vec::new(), None, None),
+                    &claim.user_id,
+                ),
+            };
+            with_chat_list_refresh(ok_builder(html))
+        })
+        .unwrap_or_else(|e| error_builder(e, "delete_message"))
+}
@@ -0,0 +1,87 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension,
+};
+use chats::events::{ChatEvent, ChatEventBrokerInterface, ChatEventKind};
+use futures::StreamExt;
+use http::{HeaderMap, StatusCode};
+use jwt::AccessClaims;
+use shaku::HasComponent;
+use tracing::{error, info};
+use usecases::chat_usecase::ChatUsecase;
+use uuid::Uuid;
+
+use crate::commons::event_stream::user_chat_events;
+use crate::commons::templates::JinjaTemplate;
+use crate::WebModule;
+
+const LAST_EVENT_ID: &str = "last-event-id";
+
+pub async fn chat_events(
+    State(module): State<Arc<WebModule>>,
+    claim: Extension<AccessClaims>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Ok(user_id) = claim.user_id.parse::<Uuid>() else {
+        error!("Invalid user id in claims: {}", claim.user_id);
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let chat_event_broker: Arc<dyn ChatEventBrokerInterface> = module.resolve();
+    let chat_usecase: Arc<dyn ChatUsecase> = module.resolve();
+    let template: Arc<dyn JinjaTemplate> = module.resolve();
+
+    // Subscribe before replaying so nothing sent in between is lost; the client drops duplicates.
+    let live_events = user_chat_events(chat_event_broker, user_id);
+
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|value| value.to_str().ok());
+    let missed_events = match last_event_id {
+        Some(last_event_id) => {
+            info!("Resuming chat events for user {} after {}", user_id, last_event_id);
+            chat_usecase
+                .get_missed_chat_events(&claim.user_id, last_event_id)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to load missed chat events: {}", e);
+                    Vec::new()
+                })
+        }
+        None => Vec::new(),
+    };
+
+    let viewer_id = user_id.to_string();
+    let stream = futures::stream::iter(missed_events)
+        .chain(live_events)
+        .map(move |event| Ok::<_, Infallible>(to_sse_event(&event, &viewer_id, template.as_ref())));
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn to_sse_event(event: &ChatEvent, viewer_id: &str, template: &dyn JinjaTemplate) -> Event {
+    let sse_event = Event::default()
+        .event(event.kind.name())
+        .data(template.htmx_chat_event(event, viewer_id));
+
+    // Only messages can be replayed from the messages table, so only they carry a resumable id.
+    match &event.kind {
+        ChatEventKind::NewMessage(message) => sse_event.id(message.0.id.to_string()),
+        ChatEventKind::MessagesRead(_)
+        | ChatEventKind::ReactionsChanged(_, _)
+        | ChatEventKind::MessageEdited(_)
+        | ChatEventKind::MessageDeleted(_)
+        | ChatEventKind::MessageHidden(_)
+        | ChatEventKind::MembersChanged(_)
+        | ChatEventKind::NewThreadReply(_, _) => sse_event,
+    }
+}
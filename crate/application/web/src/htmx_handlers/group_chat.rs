@@ -0,0 +1,237 @@
+use crate::commons::{
+    response_builder::{error_builder, ok_builder, with_chat_list_refresh},
+    templates::JinjaTemplate,
+};
+use crate::WebModule;
+use axum::{
+    extract::Query,
+    response::IntoResponse,
+    Extension, Form,
+};
+use axum_extra::extract::Multipart;
+use chats::entity::ChatRole;
+use commons::generic_errors::GenericError;
+use jwt::AccessClaims;
+use shaku_axum::Inject;
+use usecases::group_chat_usecase::GroupChatUsecase;
+
+// Rendered out-of-band to close whatever dialog is open in the group panel.
+const CLOSE_GROUP_PANEL: &str = r#"<div id="group-panel-slot" hx-swap-oob="true"></div>"#;
+
+pub async fn group_form(template: Inject<WebModule, dyn JinjaTemplate>) -> impl IntoResponse {
+    ok_builder(template.htmx_group_form())
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct CreateGroupRequest {
+    pub name: String,
+    #[serde(default)]
+    pub members: String,
+}
+
+pub async fn create_group(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<CreateGroupRequest>,
+) -> impl IntoResponse {
+    let member_usernames: Vec<String> = payload
+        .members
+        .split(',')
+        .map(str::trim)
+        .filter(|username| !username.is_empty())
+        .map(String::from)
+        .collect();
+
+    group_chat_usecase
+        .create_group(&claim.user_id, &payload.name, &member_usernames)
+        .await
+        .map(|group| {
+            let chat_id = group.chat.id.to_string();
+            let htmx_chat_box = template.htmx_chat_box(&chat_id, &None, &claim.user_id);
+            let htmx_group_header = template.htmx_group_header(&group);
+            let htmx_chat_form_box = template.htmx_chat_form_box(&chat_id);
+            with_chat_list_refresh(ok_builder(
+                [
+                    htmx_chat_box,
+                    htmx_group_header,
+                    htmx_chat_form_box,
+                    CLOSE_GROUP_PANEL.to_string(),
+                ]
+                .join(""),
+            ))
+        })
+        .unwrap_or_else(|e| error_builder(e, "create_group"))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupMembersRequest {
+    pub chat_id: String,
+}
+
+pub async fn group_members(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<GroupMembersRequest>,
+) -> impl IntoResponse {
+    group_chat_usecase
+        .get_group(&payload.chat_id, &claim.user_id)
+        .await
+        .map(|group| ok_builder(template.htmx_group_members(&group, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "group_members"))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct AddGroupMemberRequest {
+    pub chat_id: String,
+    pub username: String,
+}
+
+pub async fn add_group_member(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<AddGroupMemberRequest>,
+) -> impl IntoResponse {
+    group_chat_usecase
+        .add_member(&payload.chat_id, &claim.user_id, &payload.username)
+        .await
+        .map(|group| ok_builder(template.htmx_group_members(&group, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "add_group_member"))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupMemberRequest {
+    pub chat_id: String,
+    pub user_id: String,
+}
+
+pub async fn remove_group_member(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<GroupMemberRequest>,
+) -> impl IntoResponse {
+    group_chat_usecase
+        .remove_member(&payload.chat_id, &claim.user_id, &payload.user_id)
+        .await
+        .map(|group| ok_builder(template.htmx_group_members(&group, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "remove_group_member"))
+}
+
+pub async fn leave_group(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<GroupMembersRequest>,
+) -> impl IntoResponse {
+    group_chat_usecase
+        .leave_group(&payload.chat_id, &claim.user_id)
+        .await
+        .map(|_| {
+            let htmx_chat_box = template.htmx_chat_box("", &None, &claim.user_id);
+            with_chat_list_refresh(ok_builder(
+                [htmx_chat_box, CLOSE_GROUP_PANEL.to_string()].join(""),
+            ))
+        })
+        .unwrap_or_else(|e| error_builder(e, "leave_group"))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct RenameGroupRequest {
+    pub chat_id: String,
+    pub name: String,
+}
+
+pub async fn rename_group(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<RenameGroupRequest>,
+) -> impl IntoResponse {
+    group_chat_usecase
+        .rename_group(&payload.chat_id, &claim.user_id, &payload.name)
+        .await
+        .map(|group| {
+            let htmx_group_members = template.htmx_group_members(&group, &claim.user_id);
+            let htmx_group_header = template.htmx_group_header(&group);
+            with_chat_list_refresh(ok_builder(htmx_group_members + &htmx_group_header))
+        })
+        .unwrap_or_else(|e| error_builder(e, "rename_group"))
+}
+
+pub async fn update_group_avatar(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let mut chat_id = None;
+    let mut avatar = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name().unwrap_or_default() {
+            "chat_id" => chat_id = field.text().await.ok(),
+            "avatar" => avatar = field.bytes().await.ok(),
+            _ => {}
+        }
+    }
+    let (Some(chat_id), Some(avatar)) = (chat_id, avatar) else {
+        return error_builder(
+            GenericError::invalid_input(String::from("No file found")),
+            "update_group_avatar",
+        );
+    };
+
+    group_chat_usecase
+        .update_group_avatar(&chat_id, &claim.user_id, &avatar)
+        .await
+        .map(|group| {
+            let htmx_group_members = template.htmx_group_members(&group, &claim.user_id);
+            let htmx_group_header = template.htmx_group_header(&group);
+            with_chat_list_refresh(ok_builder(htmx_group_members + &htmx_group_header))
+        })
+        .unwrap_or_else(|e| error_builder(e, "update_group_avatar"))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupRoleRequest {
+    pub chat_id: String,
+    pub user_id: String,
+    pub role: String,
+}
+
+pub async fn set_member_role(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<GroupRoleRequest>,
+) -> impl IntoResponse {
+    let role: ChatRole = match payload.role.parse() {
+        Ok(role) => role,
+        Err(_) => {
+            return error_builder(
+                GenericError::invalid_input(String::from("Unknown role")),
+                "set_member_role",
+            )
+        }
+    };
+    group_chat_usecase
+        .set_member_role(&payload.chat_id, &claim.user_id, &payload.user_id, role)
+        .await
+        .map(|group| ok_builder(template.htmx_group_members(&group, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "set_member_role"))
+}
+
+pub async fn transfer_ownership(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<GroupMemberRequest>,
+) -> impl IntoResponse {
+    group_chat_usecase
+        .transfer_ownership(&payload.chat_id, &claim.user_id, &payload.user_id)
+        .await
+        .map(|group| ok_builder(template.htmx_group_members(&group, &claim.user_id)))
+        .unwrap_or_else(|e| error_builder(e, "transfer_ownership"))
+}
@@ -0,0 +1,154 @@
+use crate::commons::{
+    response_builder::{error_builder, ok_builder},
+    templates::JinjaTemplate,
+};
+use crate::WebModule;
+use axum::{
+    extract::{Path, Query},
+    response::IntoResponse,
+    Extension, Form,
+};
+use commons::generic_errors::GenericError;
+use http::HeaderValue;
+use jwt::AccessClaims;
+use shaku_axum::Inject;
+use usecases::{group_chat_usecase::GroupChatUsecase, group_invite_usecase::GroupInviteUsecase};
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupInvitesRequest {
+    pub chat_id: String,
+}
+
+async fn render_invites(
+    group_chat_usecase: &dyn GroupChatUsecase,
+    group_invite_usecase: &dyn GroupInviteUsecase,
+    template: &dyn JinjaTemplate,
+    chat_id: &str,
+    user_id: &str,
+) -> anyhow::Result<String> {
+    let group = group_chat_usecase.get_group(chat_id, user_id).await?;
+    let invites = group_invite_usecase.list_invites(chat_id, user_id).await?;
+    Ok(template.htmx_group_invites(&group, &invites))
+}
+
+pub async fn group_invites(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    group_invite_usecase: Inject<WebModule, dyn GroupInviteUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<GroupInvitesRequest>,
+) -> impl IntoResponse {
+    render_invites(
+        &*group_chat_usecase,
+        &*group_invite_usecase,
+        &*template,
+        &payload.chat_id,
+        &claim.user_id,
+    )
+    .await
+    .map(ok_builder)
+    .unwrap_or_else(|e| error_builder(e, "group_invites"))
+}
+
+// Both limits are optional form fields, an empty value means no limit.
+#[derive(serde::Deserialize, Debug)]
+pub struct CreateGroupInviteRequest {
+    pub chat_id: String,
+    #[serde(default)]
+    pub expires_in_hours: String,
+    #[serde(default)]
+    pub max_uses: String,
+}
+
+fn parse_limit<T: std::str::FromStr>(value: &str, name: &str) -> anyhow::Result<Option<T>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| GenericError::invalid_input(format!("Invalid {}", name)))
+}
+
+pub async fn create_group_invite(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    group_invite_usecase: Inject<WebModule, dyn GroupInviteUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Form(payload): Form<CreateGroupInviteRequest>,
+) -> impl IntoResponse {
+    let limits = parse_limit(&payload.expires_in_hours, "expiry")
+        .and_then(|expires| Ok((expires, parse_limit(&payload.max_uses, "max uses")?)));
+    let (expires_in_hours, max_uses) = match limits {
+        Ok(limits) => limits,
+        Err(e) => return error_builder(e, "create_group_invite"),
+    };
+
+    if let Err(e) = group_invite_usecase
+        .create_invite(&payload.chat_id, &claim.user_id, expires_in_hours, max_uses)
+        .await
+    {
+        return error_builder(e, "create_group_invite");
+    }
+    render_invites(
+        &*group_chat_usecase,
+        &*group_invite_usecase,
+        &*template,
+        &payload.chat_id,
+        &claim.user_id,
+    )
+    .await
+    .map(ok_builder)
+    .unwrap_or_else(|e| error_builder(e, "create_group_invite"))
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct RevokeGroupInviteRequest {
+    pub chat_id: String,
+    pub invite_id: String,
+}
+
+pub async fn revoke_group_invite(
+    group_chat_usecase: Inject<WebModule, dyn GroupChatUsecase>,
+    group_invite_usecase: Inject<WebModule, dyn GroupInviteUsecase>,
+    claim: Extension<AccessClaims>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Query(payload): Query<RevokeGroupInviteRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = group_invite_usecase
+        .revoke_invite(&payload.invite_id, &claim.user_id)
+        .await
+    {
+        return error_builder(e, "revoke_group_invite");
+    }
+    render_invites(
+        &*group_chat_usecase,
+        &*group_invite_usecase,
+        &*template,
+        &payload.chat_id,
+        &claim.user_id,
+    )
+    .await
+    .map(ok_builder)
+    .unwrap_or_else(|e| error_builder(e, "revoke_group_invite"))
+}
+
+pub async fn join_group(
+    group_invite_usecase: Inject<WebModule, dyn GroupInviteUsecase>,
+    claim: Extension<AccessClaims>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    group_invite_usecase
+        .join_group(&token, &claim.user_id)
+        .await
+        .map(|group| {
+            let mut response = ok_builder(String::new());
+            let location = format!("/?chat_id={}", group.chat.id);
+            response
+                .headers_mut()
+                .insert("HX-Redirect", HeaderValue::from_str(&location).unwrap());
+            response
+        })
+        .unwrap_or_else(|e| error_builder(e, "join_group"))
+}
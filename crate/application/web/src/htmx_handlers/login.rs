@@ -1,17 +1,25 @@
+use crate::commons::cookies::{
+    access_token_cookie, expired_cookie, refresh_token_cookie, ACCESS_TOKEN_COOKIE,
+    REFRESH_TOKEN_COOKIE,
+};
+use crate::commons::templates::JinjaTemplate;
 use crate::utils::render_error_alert;
 use crate::WebModule;
 use axum::http::{HeaderMap, StatusCode};
-use axum::response::{IntoResponse, Response};
-use axum::Form;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::{Extension, Form};
 use axum_client_ip::SecureClientIp;
 use axum_extra::headers::UserAgent;
 use axum_extra::TypedHeader;
 use commons::generic_errors::GenericError;
-use http::header::SET_COOKIE;
+use http::header::{RETRY_AFTER, SET_COOKIE};
+use jwt::AccessClaims;
 use serde::Deserialize;
 use shaku_axum::Inject;
 use tracing::log::{error, info};
-use usecases::{LoginRequest, LoginUseCaseInterface};
+use usecases::{
+    LoginOutcome, LoginRequest, LoginResponse, LoginUseCaseInterface, TwoFactorLoginRequest,
+};
 
 #[derive(Deserialize)]
 pub struct LoginForm {
@@ -38,6 +46,7 @@ pub async fn login(
     user_agent: Option<TypedHeader<UserAgent>>,
     SecureClientIp(ip): SecureClientIp,
     login_usecase: Inject<WebModule, dyn LoginUseCaseInterface>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
     Form(form): Form<LoginForm>,
 ) -> impl IntoResponse {
     let user_agent = user_agent
@@ -51,30 +60,113 @@ pub async fn login(
     let login_request = form.to_login_request(user_agent.as_str(), ip.as_str());
 
     match login_usecase.login(login_request).await {
-        Ok(response) => {
+        Ok(LoginOutcome::LoggedIn(response)) => {
             info!("Login successful");
-            let mut headers = HeaderMap::new();
-            // set cookie
-            let cookie = format!("token={}; httpOnly; path=/", response.token)
-                .parse()
-                .unwrap();
-            headers.insert(SET_COOKIE, cookie);
-            headers.insert("hx-redirect", "/".parse().unwrap());
+            logged_in_response(&response)
+        }
+        Ok(LoginOutcome::TwoFactorRequired { challenge_token }) => {
+            info!("Login needs a second factor");
+            Html(template.htmx_login_two_factor(&challenge_token)).into_response()
+        }
+        Err(e) => login_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorLoginForm {
+    challenge_token: String,
+    code: String,
+}
+
+pub async fn login_two_factor(
+    user_agent: Option<TypedHeader<UserAgent>>,
+    SecureClientIp(ip): SecureClientIp,
+    login_usecase: Inject<WebModule, dyn LoginUseCaseInterface>,
+    Form(form): Form<TwoFactorLoginForm>,
+) -> impl IntoResponse {
+    let user_agent = user_agent
+        .map(|user_agent| user_agent.0.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let ip = ip.to_string();
+    let request = TwoFactorLoginRequest {
+        challenge_token: form.challenge_token.as_str(),
+        code: form.code.as_str(),
+        user_agent: user_agent.as_str(),
+        ip_address: ip.as_str(),
+    };
+
+    match login_usecase.complete_two_factor_login(request).await {
+        Ok(response) => {
+            info!("Two-factor login successful");
+            logged_in_response(&response)
+        }
+        Err(e) => login_error_response(e),
+    }
+}
+
+// Sets the session cookies and sends the browser to the chat
+fn logged_in_response(response: &LoginResponse) -> Response {
+    let mut headers = HeaderMap::new();
+    // set cookies
+    headers.append(SET_COOKIE, access_token_cookie(&response.token));
+    headers.append(
+        SET_COOKIE,
+        refresh_token_cookie(&response.refresh_token, response.refresh_token_expires_in),
+    );
+    headers.insert("hx-redirect", "/".parse().unwrap());
 
-            (headers, "").into_response()
+    (headers, "").into_response()
+}
+
+fn login_error_response(e: anyhow::Error) -> Response {
+    error!("Error occurred during login: {}", e);
+    let mut response = Response::builder().status(StatusCode::BAD_REQUEST);
+    let error_message = match e.downcast_ref::<GenericError>() {
+        // Still a 4xx, so the form shows it in place like any other login error
+        Some(generic_error @ GenericError::LoginLocked(retry_after, _)) => {
+            response = response
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(RETRY_AFTER, *retry_after);
+            generic_error.to_string()
         }
+        Some(generic_error) => generic_error.to_string(),
+        None => "An error occurred during registration.".to_string(),
+    };
+    let error_html = render_error_alert(error_message);
+    response.body(error_html).unwrap().into_response()
+}
+
+// Expires the cookies and sends the browser back to the login page
+fn logged_out_response() -> Response {
+    let mut headers = HeaderMap::new();
+    headers.append(SET_COOKIE, expired_cookie(ACCESS_TOKEN_COOKIE));
+    headers.append(SET_COOKIE, expired_cookie(REFRESH_TOKEN_COOKIE));
+    (headers, Redirect::to("/login")).into_response()
+}
+
+pub async fn logout(
+    login_usecase: Inject<WebModule, dyn LoginUseCaseInterface>,
+    claim: Option<Extension<AccessClaims>>,
+) -> impl IntoResponse {
+    if let Some(Extension(claim)) = claim {
+        info!("Logout for user {}", claim.user_id);
+        if let Err(e) = login_usecase.logout(&claim).await {
+            error!("Error occurred during logout: {}", e);
+        }
+    }
+    logged_out_response()
+}
+
+pub async fn logout_everywhere(
+    login_usecase: Inject<WebModule, dyn LoginUseCaseInterface>,
+    claim: Extension<AccessClaims>,
+) -> impl IntoResponse {
+    info!("Logout on every device for user {}", claim.user_id);
+    match login_usecase.logout_everywhere(&claim).await {
+        Ok(_) => logged_out_response(),
         Err(e) => {
-            error!("Error occurred during login: {}", e);
-            let error_message = match e.downcast_ref::<GenericError>() {
-                Some(generic_error) => generic_error.to_string(),
-                None => "An error occurred during registration.".to_string(),
-            };
-            let error_html = render_error_alert(error_message);
-            Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(error_html)
-                .unwrap()
-                .into_response()
+            error!("Error occurred during logout everywhere: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
 }
@@ -1,5 +1,12 @@
 pub mod register;
 pub mod login;
 pub mod chat;
+pub mod chat_events;
+pub mod group_chat;
+pub mod group_invite;
 pub mod user_detail;
 pub mod chat_box;
+pub mod session;
+pub mod password_reset;
+pub mod account;
+pub mod two_factor;
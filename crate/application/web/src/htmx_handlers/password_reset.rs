@@ -0,0 +1,47 @@
+use crate::commons::response_builder::error_builder;
+use crate::WebModule;
+use axum::response::{Html, IntoResponse};
+use axum::Form;
+use serde::Deserialize;
+use shaku_axum::Inject;
+use tracing::log::info;
+use usecases::password_reset_usecase::PasswordResetUsecase;
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordForm {
+    email: String,
+}
+
+// Answers the same whether the email has an account or not
+pub async fn forgot_password(
+    password_reset_usecase: Inject<WebModule, dyn PasswordResetUsecase>,
+    Form(form): Form<ForgotPasswordForm>,
+) -> impl IntoResponse {
+    match password_reset_usecase.request_reset(&form.email).await {
+        Ok(_) => Html(include_str!("../../page/htmx/forgot_password_sent.html")).into_response(),
+        Err(e) => error_builder(e, "password reset request"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordForm {
+    token: String,
+    password: String,
+    password_confirmation: String,
+}
+
+pub async fn reset_password(
+    password_reset_usecase: Inject<WebModule, dyn PasswordResetUsecase>,
+    Form(form): Form<ResetPasswordForm>,
+) -> impl IntoResponse {
+    match password_reset_usecase
+        .reset_password(&form.token, &form.password, &form.password_confirmation)
+        .await
+    {
+        Ok(_) => {
+            info!("Password reset successful");
+            Html(include_str!("../../page/htmx/reset_password_success.html")).into_response()
+        }
+        Err(e) => error_builder(e, "password reset"),
+    }
+}
@@ -9,6 +9,8 @@ use shaku_axum::Inject;
 use tracing::log::info;
 use usecases::{RegisterRequest, RegisterUseCaseInterface};
 
+use crate::commons::response_builder::error_builder;
+
 #[derive(Deserialize)]
 pub struct RegisterForm {
     username: String,
@@ -19,7 +21,7 @@ pub struct RegisterForm {
 }
 
 impl RegisterForm {
-    pub fn to_register_request(&self) -> RegisterRequest {
+    pub fn to_register_request(&self) -> RegisterRequest<'_> {
         RegisterRequest {
             username: &self.username,
             email: &self.email,
@@ -45,7 +47,7 @@ pub async fn register(
                 let mut debug_state = debug_state.write().await;
                 debug_state
                     .token
-                    .insert(form.username.clone(), response.encrypted_user_id.clone());
+                    .insert(form.username.clone(), response.activation_token.clone());
             }
 
             Html(
@@ -70,3 +72,19 @@ pub async fn register(
         }
     }
 }
+
+#[derive(Deserialize)]
+pub struct ResendActivationForm {
+    email: String,
+}
+
+// Answers the same whether the email waits for activation or not
+pub async fn resend_activation(
+    register_usecase: Inject<WebModule, dyn RegisterUseCaseInterface>,
+    Form(form): Form<ResendActivationForm>,
+) -> impl IntoResponse {
+    match register_usecase.resend_activation(&form.email).await {
+        Ok(_) => Html(include_str!("../../page/htmx/activation_sent.html")).into_response(),
+        Err(e) => error_builder(e, "resending activation"),
+    }
+}
@@ -0,0 +1,24 @@
+use crate::commons::response_builder::{error_builder, ok_builder};
+use crate::WebModule;
+use axum::{response::IntoResponse, Extension, Form};
+use jwt::AccessClaims;
+use shaku_axum::Inject;
+use usecases::session_usecase::SessionUsecase;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct RevokeSessionRequest {
+    pub session_id: String,
+}
+
+// The revoked row is swapped out with the empty body
+pub async fn revoke_session(
+    session_usecase: Inject<WebModule, dyn SessionUsecase>,
+    claim: Extension<AccessClaims>,
+    Form(payload): Form<RevokeSessionRequest>,
+) -> impl IntoResponse {
+    session_usecase
+        .revoke_session(&claim, &payload.session_id)
+        .await
+        .map(|_| ok_builder(String::new()))
+        .unwrap_or_else(|e| error_builder(e, "revoke_session"))
+}
@@ -0,0 +1,66 @@
+use crate::commons::response_builder::{error_builder, ok_builder};
+use crate::commons::templates::JinjaTemplate;
+use crate::WebModule;
+use axum::extract::Extension;
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::Form;
+use jwt::AccessClaims;
+use serde::Deserialize;
+use shaku_axum::Inject;
+use usecases::two_factor_usecase::TwoFactorUsecase;
+
+pub async fn enroll(
+    two_factor_usecase: Inject<WebModule, dyn TwoFactorUsecase>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    claim: Extension<AccessClaims>,
+) -> impl IntoResponse {
+    two_factor_usecase
+        .start_enrollment(&claim)
+        .await
+        .map(|enrollment| ok_builder(template.htmx_two_factor_enroll(&enrollment)))
+        .unwrap_or_else(|e| error_builder(e, "two-factor setup"))
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmTwoFactorForm {
+    code: String,
+}
+
+pub async fn confirm(
+    two_factor_usecase: Inject<WebModule, dyn TwoFactorUsecase>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    claim: Extension<AccessClaims>,
+    Form(form): Form<ConfirmTwoFactorForm>,
+) -> impl IntoResponse {
+    two_factor_usecase
+        .confirm_enrollment(&claim, &form.code)
+        .await
+        .map(|codes| ok_builder(template.htmx_two_factor_recovery_codes(&codes)))
+        .unwrap_or_else(|e| error_builder(e, "two-factor setup"))
+}
+
+#[derive(Deserialize)]
+pub struct DisableTwoFactorForm {
+    current_password: String,
+    code: String,
+}
+
+// Reloads the settings page, it shows the setup button again
+pub async fn disable(
+    two_factor_usecase: Inject<WebModule, dyn TwoFactorUsecase>,
+    claim: Extension<AccessClaims>,
+    Form(form): Form<DisableTwoFactorForm>,
+) -> impl IntoResponse {
+    match two_factor_usecase
+        .disable(&claim, &form.current_password, &form.code)
+        .await
+    {
+        Ok(_) => {
+            let mut headers = HeaderMap::new();
+            headers.insert("hx-redirect", "/two-factor".parse().unwrap());
+            (headers, "").into_response()
+        }
+        Err(e) => error_builder(e, "turning off two-factor"),
+    }
+}
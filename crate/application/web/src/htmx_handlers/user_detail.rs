@@ -51,6 +51,8 @@ impl UpdateProfileForm {
             date_of_birth,
             gender: self.gender.clone(),
             profile_picture: self.profile_picture.clone(),
+            profile_picture_medium: None,
+            profile_picture_small: None,
             created_at: Some(chrono::Local::now().naive_local()),
             updated_at: Some(chrono::Local::now().naive_local()),
         })
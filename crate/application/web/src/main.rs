@@ -1,22 +1,27 @@
 use crate::htmx_handlers::{login, register};
 use axum::body::Bytes;
-use axum::extract::MatchedPath;
-use axum::http::{HeaderMap, Request};
+use axum::extract::{DefaultBodyLimit, MatchedPath};
+use axum::http::{HeaderMap, Request, StatusCode};
 use axum::response::Response;
 use axum::routing::{get, post};
 use axum::{middleware, Router};
 use axum_client_ip::SecureClientIpSource;
 use chats::chat_services::ChatService;
+use chats::events::ChatEventBroker;
 use commons::templates::{JinjaTemplate, JinjaTemplateImpl};
 use credentials::credential_services::CredentialService;
 use crypto::Crypto;
 use fakers::{FakerImpl, FakerInnerImpl};
-use htmx_handlers::{chat, user_detail};
+use htmx_handlers::{
+    account, chat, chat_events, group_chat, group_invite, password_reset, session, two_factor,
+    user_detail,
+};
 use jwt::JWT;
 use log::{error, info};
 use mail::Mail;
 use middlewares::auth::auth;
-use persistence::{Env, DB};
+use persistence::env::myenv::STORAGE_BACKEND_S3;
+use persistence::{Env, EnvInterface, DB};
 use sessions::services::SessionService;
 use shaku::{module, HasComponent};
 use std::collections::HashMap;
@@ -24,6 +29,7 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
+use storage::{BlobStorage, LocalStorage, S3Storage};
 use tokio::net::TcpListener;
 use tokio::signal;
 use tokio::sync::RwLock;
@@ -33,7 +39,14 @@ use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
 use tracing::{debug, info_span, Span};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use usecases::chat_usecase::ChatUsecaseImpl;
+use usecases::account_usecase::AccountUsecaseImpl;
+use usecases::chat_usecase::{ChatUsecaseImpl, MAX_ATTACHMENTS_PER_MESSAGE, MAX_ATTACHMENT_SIZE};
+use usecases::group_chat_usecase::GroupChatUsecaseImpl;
+use usecases::group_invite_usecase::GroupInviteUsecaseImpl;
+use usecases::media_usecase::{MediaUsecase, MediaUsecaseImpl};
+use usecases::password_reset_usecase::PasswordResetUsecaseImpl;
+use usecases::session_usecase::SessionUsecaseImpl;
+use usecases::two_factor_usecase::TwoFactorUsecaseImpl;
 use usecases::userdetail_usecase::UserDetailUsecaseImpl;
 use usecases::{InvitePrivateChatUsecase, LoginUseCase, LoginUseCaseInterface, RegisterUseCase};
 use user_details::user_detail_service::UserDetailServiceImpl;
@@ -43,13 +56,19 @@ use users::user_services::UserService;
 mod commons;
 mod debug_handlers;
 mod htmx_handlers;
+mod media_handlers;
 mod middlewares;
 mod page_handlers;
 mod utils;
+mod ws_handlers;
+
+// Room for the chat id, the caption and the multipart boundaries around the files
+const ATTACHMENT_FORM_OVERHEAD: usize = 64 * 1024;
 
 module! {
      WebModule {
         components = [
+            ChatEventBroker,
             ChatService,
             CredentialService,
             Crypto ,
@@ -60,6 +79,7 @@ module! {
             InvitePrivateChatUsecase,
             JWT,
             JinjaTemplateImpl,
+            LocalStorage,
             LoginUseCase,
             Mail,
             RegisterUseCase,
@@ -68,6 +88,13 @@ module! {
             UserDetailUsecaseImpl,
             UserService,
             ChatUsecaseImpl,
+            GroupChatUsecaseImpl,
+            GroupInviteUsecaseImpl,
+            MediaUsecaseImpl,
+            SessionUsecaseImpl,
+            PasswordResetUsecaseImpl,
+            AccountUsecaseImpl,
+            TwoFactorUsecaseImpl,
         ],
 
         providers = []
@@ -86,10 +113,21 @@ async fn main() {
     // initialize tracing
     tracing_init();
     let env = Env::load();
-    let module_builder = WebModule::builder()
+    let mut module_builder = WebModule::builder()
         .with_component_override::<dyn JinjaTemplate>(Box::new(JinjaTemplateImpl::default()));
+    if env.get_storage_backend() == STORAGE_BACKEND_S3 {
+        module_builder = module_builder
+            .with_component_override::<dyn BlobStorage>(Box::new(S3Storage::new(Arc::new(env.clone()))));
+    }
     let module = usecases::utils::setup_module::<WebModule>(module_builder, env).await;
     let login_usecase: Arc<dyn LoginUseCaseInterface> = module.resolve();
+    let media_usecase: Arc<dyn MediaUsecase> = module.resolve();
+    if let Err(e) = media_usecase
+        .import_legacy_uploads(&assets_dir().join("uploads"))
+        .await
+    {
+        error!("Failed to move legacy uploads into the blob storage: {}", e);
+    }
     let arc_module = Arc::new(module);
     let debug_state = Arc::new(RwLock::new(DebugState {
         token: HashMap::new(),
@@ -100,22 +138,80 @@ async fn main() {
     let htmx_app = Router::new()
         .route("/register", post(register::register))
         .route("/find-users", get(chat::find_user_info_list))
+        .route("/chat-list", get(chat::chat_list))
         .route("/chat-header", get(chat::chat_header))
         .route("/chat-send", post(chat::chat_send))
+        .route("/chat-messages", get(chat::chat_messages))
+        .route(
+            "/chat-attachments",
+            post(chat::chat_send_attachments).layer(DefaultBodyLimit::max(
+                MAX_ATTACHMENT_SIZE * MAX_ATTACHMENTS_PER_MESSAGE + ATTACHMENT_FORM_OVERHEAD,
+            )),
+        )
+        .route("/chat-read", post(chat::chat_read))
+        .route("/thread", get(chat::open_thread))
+        .route("/thread-messages", get(chat::thread_messages))
+        .route("/thread-send", post(chat::thread_send))
+        .route("/thread-read", post(chat::thread_read))
+        .route("/message-search", get(chat::message_search))
+        .route("/message-search-results", get(chat::message_search_results))
+        .route("/message-reactions", post(chat::toggle_message_reaction))
+        .route("/message-edit", post(chat::edit_message))
+        .route("/message-delete", post(chat::delete_message))
+        .route("/chat", get(chat::open_chat))
+        .route("/group-form", get(group_chat::group_form))
+        .route("/groups", post(group_chat::create_group))
+        .route(
+            "/group-members",
+            get(group_chat::group_members)
+                .post(group_chat::add_group_member)
+                .delete(group_chat::remove_group_member),
+        )
+        .route("/group-leave", post(group_chat::leave_group))
+        .route("/group-rename", post(group_chat::rename_group))
+        .route("/group-avatar", post(group_chat::update_group_avatar))
+        .route("/group-role", post(group_chat::set_member_role))
+        .route("/group-transfer", post(group_chat::transfer_ownership))
+        .route(
+            "/group-invites",
+            get(group_invite::group_invites)
+                .post(group_invite::create_group_invite)
+                .delete(group_invite::revoke_group_invite),
+        )
+        .route("/join/{token}", post(group_invite::join_group))
+        .route("/events", get(chat_events::chat_events))
         .route(
             "/invite-private-chat",
             post(chat::invite_private_chat_usecase),
         )
+        .route("/session-revoke", post(session::revoke_session))
         .route("/update-profile", post(user_detail::update_profile))
         .route(
             "/upload-profile-picture",
             post(user_detail::upload_profile_picture),
         )
-        .route("/login", post(login::login));
+        .route("/change-password", post(account::change_password))
+        .route("/change-email", post(account::change_email))
+        .route("/two-factor-enroll", post(two_factor::enroll))
+        .route("/two-factor-confirm", post(two_factor::confirm))
+        .route("/two-factor-disable", post(two_factor::disable))
+        .route("/login", post(login::login))
+        .route("/login-two-factor", post(login::login_two_factor))
+        .route("/forgot-password", post(password_reset::forgot_password))
+        .route("/reset-password", post(password_reset::reset_password))
+        .route("/resend-activation", post(register::resend_activation));
 
     // This is callback nest routes
-    let callback_app =
-        Router::new().route("/activate/{token}", get(page_handlers::callback_activate));
+    let callback_app = Router::new()
+        .route("/activate/{token}", get(page_handlers::callback_activate))
+        .route(
+            "/reset-password/{token}",
+            get(page_handlers::callback_reset_password),
+        )
+        .route(
+            "/confirm-email/{token}",
+            get(page_handlers::callback_confirm_email),
+        );
 
     let debug_app = Router::new()
         .route("/create-dummy-user", get(debug_handlers::create_dummy_user))
@@ -125,7 +221,16 @@ async fn main() {
         .route("/", get(page_handlers::chat))
         .route("/login", get(page_handlers::login))
         .route("/signup", get(page_handlers::signup))
-        .route("/profile", get(page_handlers::profile));
+        .route("/forgot-password", get(page_handlers::forgot_password))
+        .route("/resend-activation", get(page_handlers::resend_activation))
+        .route("/profile", get(page_handlers::profile))
+        .route("/sessions", get(page_handlers::sessions))
+        .route("/two-factor", get(page_handlers::two_factor))
+        .route("/logout", post(login::logout))
+        .route("/logout-everywhere", post(login::logout_everywhere))
+        .route("/join/{token}", get(page_handlers::join))
+        .route("/media/{*path}", get(media_handlers::media))
+        .route("/ws", get(ws_handlers::ws));
 
     let app = app
         .nest("/htmx", htmx_app)
@@ -167,11 +272,19 @@ fn tracing_init() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 }
+fn assets_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets")
+}
 fn with_assets(router: Router) -> Router {
-    let assets_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
+    let assets_dir = assets_dir();
     let index_assets = assets_dir.join("index.html");
     let dir = ServeDir::new(assets_dir).not_found_service(ServeFile::new(index_assets));
+    // Uploads used to be written to `assets/uploads`, they are moved to the blob storage on
+    // start and only served through the access checked `/media` route
+    let no_uploads = get(|| async { StatusCode::NOT_FOUND });
     router
+        .route("/assets/uploads/{*path}", no_uploads.clone())
+        .route("/uploads/{*path}", no_uploads)
         .nest_service("/assets", dir.clone())
         .fallback_service(dir)
 }
@@ -0,0 +1,183 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use commons::generic_errors::GenericError;
+use http::{header, HeaderMap, StatusCode};
+use jwt::AccessClaims;
+use shaku_axum::Inject;
+use tracing::error;
+use usecases::media_usecase::{MediaFile, MediaUsecase};
+use usecases::utils::MEDIA_URL_PREFIX;
+
+use crate::WebModule;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct MediaRequest {
+    pub signature: Option<String>,
+}
+
+pub async fn media(
+    media_usecase: Inject<WebModule, dyn MediaUsecase>,
+    claim: Option<Extension<AccessClaims>>,
+    Path(path): Path<String>,
+    Query(payload): Query<MediaRequest>,
+    headers: HeaderMap,
+) -> Response {
+    let media_url = format!("{}{}", MEDIA_URL_PREFIX, path);
+    let viewer_id = claim.as_ref().map(|claim| claim.user_id.as_str());
+    let media = match media_usecase
+        .open_media(&media_url, viewer_id, payload.signature.as_deref())
+        .await
+    {
+        Ok(media) => media,
+        Err(e) => return media_error(e, &media_url),
+    };
+
+    serve_file(&*media_usecase, media, &headers)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Serving {} failed: {}", media_url, e);
+            StatusCode::NOT_FOUND.into_response()
+        })
+}
+
+// Plain status codes, these are loaded by <img> and download links rather than htmx
+fn media_error(e: anyhow::Error, media_url: &str) -> Response {
+    error!("Opening {} failed: {}", media_url, e);
+    let status = match e.downcast_ref::<GenericError>() {
+        Some(GenericError::Unauthorized()) => StatusCode::UNAUTHORIZED,
+        Some(GenericError::PermissionDenied(_, _)) => StatusCode::FORBIDDEN,
+        Some(GenericError::InvalidInput(_, _)) => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    status.into_response()
+}
+
+async fn serve_file(
+    media_usecase: &dyn MediaUsecase,
+    media: MediaFile,
+    headers: &HeaderMap,
+) -> anyhow::Result<Response> {
+    let length = media.size;
+
+    let range = match headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        .map(|range| parse_range(range, length))
+    {
+        Some(Err(())) => {
+            return Ok(Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", length))
+                .body(Body::empty())?);
+        }
+        Some(Ok(range)) => range,
+        None => None,
+    };
+
+    // Only media the browser renders itself is shown inline, everything else is downloaded
+    let is_inline = ["image/", "audio/", "video/"]
+        .iter()
+        .any(|prefix| media.content_type.starts_with(prefix));
+    let disposition = match (&media.file_name, is_inline) {
+        (Some(file_name), true) => {
+            format!("inline; filename*=UTF-8''{}", encode_filename(file_name))
+        }
+        (Some(file_name), false) => {
+            format!(
+                "attachment; filename*=UTF-8''{}",
+                encode_filename(file_name)
+            )
+        }
+        (None, true) => String::from("inline"),
+        (None, false) => String::from("attachment"),
+    };
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, &media.content_type)
+        .header(header::CONTENT_DISPOSITION, disposition)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "private, max-age=300")
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CONTENT_SECURITY_POLICY, "sandbox");
+
+    match range {
+        Some((start, end)) => {
+            let body = Body::from_stream(media_usecase.read_media(&media, range).await?);
+            Ok(response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, length),
+                )
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .body(body)?)
+        }
+        None => Ok(response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, length)
+            .body(Body::from_stream(
+                media_usecase.read_media(&media, None).await?,
+            ))?),
+    }
+}
+
+/// Parses a single `bytes=` range into inclusive offsets.
+/// `Ok(None)` means the header is ignored and the whole file is sent, as allowed for ranges
+/// we do not support; `Err` means the range lies outside the file.
+fn parse_range(range: &str, length: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(range) = range.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if range.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = range.split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        // The last `end` bytes
+        ("", end) => {
+            let Ok(suffix) = end.parse::<u64>() else {
+                return Ok(None);
+            };
+            if suffix == 0 || length == 0 {
+                return Err(());
+            }
+            (length.saturating_sub(suffix), length - 1)
+        }
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return Ok(None);
+            };
+            let end = match end {
+                "" => length.saturating_sub(1),
+                end => match end.parse::<u64>() {
+                    Ok(end) => end.min(length.saturating_sub(1)),
+                    Err(_) => return Ok(None),
+                },
+            };
+            (start, end)
+        }
+    };
+    if start >= length || start > end {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+// RFC 5987 encoding, uploads keep whatever name the user gave them
+fn encode_filename(file_name: &str) -> String {
+    file_name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -6,25 +6,60 @@ use axum::{
     response::Response,
 };
 use axum_extra::extract::CookieJar;
+use commons::generic_errors::GenericError;
 use http::StatusCode;
+use jwt::AccessClaims;
 use tracing::{error, trace};
-use usecases::LoginUseCaseInterface;
+use usecases::{LoginUseCaseInterface, RefreshResponse};
 
-use crate::commons::constants::{DEBUG_PAGES, PUBLIC_PAGES};
+use crate::commons::constants::{DEBUG_PAGES, OPTIONAL_AUTH_PAGES, PUBLIC_PAGES};
+use crate::commons::cookies::{with_refreshed_tokens, ACCESS_TOKEN_COOKIE, REFRESH_TOKEN_COOKIE};
 
-fn check_path(current_path: &str) -> bool {
-    let is_path = |path: &&str| {
-        if path.contains("*") {
-            let path = path.replace("*", "");
-            return current_path.starts_with(&path);
-        };
-        *path == current_path
+fn matches_path(current_path: &str, path: &str) -> bool {
+    if path.contains("*") {
+        let path = path.replace("*", "");
+        return current_path.starts_with(&path);
     };
+    path == current_path
+}
+
+fn check_path(current_path: &str) -> bool {
+    let is_path = |path: &&str| matches_path(current_path, path);
     let is_public = PUBLIC_PAGES.iter().any(is_path);
     let is_debug = DEBUG_PAGES.iter().any(is_path);
     is_debug || is_public
 }
 
+fn is_optional_auth_path(current_path: &str) -> bool {
+    OPTIONAL_AUTH_PAGES
+        .iter()
+        .any(|path| matches_path(current_path, path))
+}
+
+// An expired access token is renewed with the refresh token cookie, so does a missing one
+// after the browser was closed. The renewed tokens come back when the access token was refreshed.
+async fn authenticate(
+    login_usecase: &dyn LoginUseCaseInterface,
+    cookie_jar: &CookieJar,
+) -> anyhow::Result<(AccessClaims, Option<RefreshResponse>)> {
+    if let Some(token) = cookie_jar.get(ACCESS_TOKEN_COOKIE) {
+        trace!("Auth header: {}", token.value());
+        match login_usecase.authorize_current_user(token.value()).await {
+            Ok(claims) => return Ok((claims, None)),
+            Err(e) if !matches!(e.downcast_ref(), Some(GenericError::TokenExpired(_))) => {
+                return Err(e)
+            }
+            Err(_) => trace!("Access token expired, refreshing"),
+        }
+    }
+
+    let refresh_token = cookie_jar
+        .get(REFRESH_TOKEN_COOKIE)
+        .ok_or_else(|| anyhow::anyhow!("No auth token"))?;
+    let refreshed = login_usecase.refresh(refresh_token.value()).await?;
+    Ok((refreshed.claims.clone(), Some(refreshed)))
+}
+
 pub async fn auth(
     State(login_usecase): State<Arc<dyn LoginUseCaseInterface>>,
     cookie_jar: CookieJar,
@@ -37,27 +72,22 @@ pub async fn auth(
         return Ok(next.run(req).await);
     }
 
-    let auth_header = cookie_jar
-        .get("token")
-        .ok_or_else(|| {
-            error!("No auth token for");
-            StatusCode::UNAUTHORIZED
-        })
-        .map_err(|e1| {
-            error!("No auth token {}", e1);
-            StatusCode::UNAUTHORIZED
-        })?
-        .value();
-
-    trace!("Auth header: {}", auth_header);
-    let claims = login_usecase
-        .authorize_current_user(auth_header)
-        .await
-        .map_err(|e| {
+    // Claims are attached when the session is valid, the handler decides what anonymous users get
+    let is_optional_auth = is_optional_auth_path(current_path);
+
+    let (claims, refreshed) = match authenticate(&*login_usecase, &cookie_jar).await {
+        Ok(authenticated) => authenticated,
+        Err(_) if is_optional_auth => return Ok(next.run(req).await),
+        Err(e) => {
             error!("Error when authorizing current user: {}", e);
-            StatusCode::UNAUTHORIZED
-        })?;
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    };
 
     req.extensions_mut().insert(claims);
-    Ok(next.run(req).await)
+    let response = next.run(req).await;
+    Ok(match refreshed {
+        Some(refreshed) => with_refreshed_tokens(response, &refreshed),
+        None => response,
+    })
 }
@@ -1,27 +1,40 @@
-use axum::extract::{self, Path};
+use axum::extract::{self, Path, Query};
 use axum::http::StatusCode;
-use axum::response::{Html, IntoResponse, Response};
+use axum::response::{Html, IntoResponse};
 use commons::generic_errors::GenericError;
 use jwt::AccessClaims;
 use minijinja::context;
 use shaku_axum::Inject;
 use tracing::log::info;
+use usecases::account_usecase::AccountUsecase;
+use usecases::group_invite_usecase::GroupInviteUsecase;
+use usecases::media_usecase::MediaUsecase;
+use usecases::password_reset_usecase::PasswordResetUsecase;
+use usecases::session_usecase::SessionUsecase;
+use usecases::two_factor_usecase::TwoFactorUsecase;
 use usecases::userdetail_usecase::UserDetailUsecase;
 use usecases::RegisterUseCaseInterface;
 
 use crate::commons::templates::JinjaTemplate;
 use crate::WebModule;
 
+#[derive(serde::Deserialize, Debug)]
+pub struct ChatPageRequest {
+    // Opened right away, e.g. after joining a group through an invite link
+    pub chat_id: Option<String>,
+}
+
 pub async fn chat(
     user_detail_usecase: Inject<WebModule, dyn UserDetailUsecase>,
     template: Inject<WebModule, dyn crate::commons::templates::JinjaTemplate>,
     claim: extract::Extension<AccessClaims>,
+    Query(payload): Query<ChatPageRequest>,
 ) -> impl IntoResponse {
     let chat_page = template.env().get_template("chat").unwrap();
     if let Ok(user_info) = user_detail_usecase.get_user_info(&claim.user_id).await {
         let profile_picture = user_info
             .user_details
-            .and_then(|details| details.profile_picture)
+            .and_then(|details| details.profile_picture_small.or(details.profile_picture))
             .unwrap_or_else(|| {
                 format!(
                     "https://ui-avatars.com/api/?name={}&background=random&rounded=true",
@@ -30,7 +43,8 @@ pub async fn chat(
             });
         let render = chat_page
             .render(context! {
-                profile_picture => profile_picture
+                profile_picture => profile_picture,
+                open_chat_id => payload.chat_id,
             })
             .unwrap();
         return Html(render);
@@ -46,21 +60,66 @@ pub async fn chat(
     Html(sww_page)
 }
 
+pub async fn join(
+    group_invite_usecase: Inject<WebModule, dyn GroupInviteUsecase>,
+    media_usecase: Inject<WebModule, dyn MediaUsecase>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    claim: extract::Extension<AccessClaims>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    match group_invite_usecase
+        .preview_invite(&token, &claim.user_id)
+        .await
+    {
+        Ok(mut preview) => {
+            // Outsiders cannot load the group picture on their own, lend them a signed URL
+            if let Some(avatar) = &preview.group.chat.avatar {
+                if let Ok(signed_avatar) = media_usecase.sign_media_url(avatar).await {
+                    preview.group.chat.avatar = Some(signed_avatar);
+                }
+            }
+            Html(template.join_page(&token, Ok(&preview))).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Opening invite link failed: {}", e);
+            let error_message = match e.downcast_ref::<GenericError>() {
+                Some(generic_error) => generic_error.to_string(),
+                None => "This invite link cannot be opened right now".to_string(),
+            };
+            (
+                StatusCode::BAD_REQUEST,
+                Html(template.join_page(&token, Err(error_message))),
+            )
+                .into_response()
+        }
+    }
+}
+
 pub async fn login() -> Html<&'static str> {
     Html(include_str!("../../page/login.html"))
 }
 pub async fn signup() -> Html<&'static str> {
     Html(include_str!("../../page/signup.html"))
 }
+pub async fn forgot_password() -> Html<&'static str> {
+    Html(include_str!("../../page/forgot_password.html"))
+}
+pub async fn resend_activation(template: Inject<WebModule, dyn JinjaTemplate>) -> Html<String> {
+    Html(template.resend_activation_page(None))
+}
 
 pub async fn profile(
     user_detail_usecase: Inject<WebModule, dyn UserDetailUsecase>,
+    account_usecase: Inject<WebModule, dyn AccountUsecase>,
     template: Inject<WebModule, dyn JinjaTemplate>,
     claim: extract::Extension<AccessClaims>,
 ) -> Html<String> {
     let Ok(user_info) = user_detail_usecase.get_user_info(&claim.user_id).await else {
         return Html(template.something_went_wrong_page());
     };
+    let Ok(pending_email) = account_usecase.get_pending_email(&claim).await else {
+        return Html(template.something_went_wrong_page());
+    };
 
     //<img id="profile-preview" src="https://ui-avatars.com/api/?name={{username}}" alt="Profile Picture"
 
@@ -74,7 +133,8 @@ pub async fn profile(
     match user_info.user_details {
         Some(user_detail) => {
             let profile_picture = &user_detail
-                .profile_picture
+                .profile_picture_medium
+                .or(user_detail.profile_picture)
                 .map_or_else(|| profile_picture, |x| x.to_string());
 
             let dob = &user_detail
@@ -89,6 +149,7 @@ pub async fn profile(
                 .render(context! {
                     username =>  &user_info.username,
                     email =>  &user_info.email,
+                    pending_email => &pending_email,
                     profile_picture =>  profile_picture.as_str(),
                     last_name => &user_detail.last_name,
                     first_name=> &user_detail.first_name,
@@ -103,6 +164,7 @@ pub async fn profile(
                 .render(context! {
                     username =>  &user_info.username,
                     email =>  &user_info.email,
+                    pending_email => &pending_email,
                     profile_picture =>  profile_picture.as_str(),
                     last_name => "",
                     first_name=> "",
@@ -115,8 +177,37 @@ pub async fn profile(
     }
 }
 
+pub async fn sessions(
+    session_usecase: Inject<WebModule, dyn SessionUsecase>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    claim: extract::Extension<AccessClaims>,
+) -> Html<String> {
+    match session_usecase.get_active_sessions(&claim).await {
+        Ok(sessions) => Html(template.sessions_page(&sessions)),
+        Err(e) => {
+            tracing::error!("Listing sessions failed: {}", e);
+            Html(template.something_went_wrong_page())
+        }
+    }
+}
+
+pub async fn two_factor(
+    two_factor_usecase: Inject<WebModule, dyn TwoFactorUsecase>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    claim: extract::Extension<AccessClaims>,
+) -> Html<String> {
+    match two_factor_usecase.get_status(&claim).await {
+        Ok(status) => Html(template.two_factor_page(&status)),
+        Err(e) => {
+            tracing::error!("Loading two-factor settings failed: {}", e);
+            Html(template.something_went_wrong_page())
+        }
+    }
+}
+
 pub async fn callback_activate(
     register_usecase: Inject<WebModule, dyn RegisterUseCaseInterface>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
     Path(token): Path<String>,
 ) -> impl IntoResponse {
     info!("Activating user");
@@ -136,10 +227,58 @@ pub async fn callback_activate(
                 Some(generic_error) => generic_error.to_string(),
                 None => "An error during activation user".to_string(),
             };
-            Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(error_message)
-                .unwrap()
+            // Expired or not, the page offers a new link
+            (
+                StatusCode::BAD_REQUEST,
+                Html(template.resend_activation_page(Some(error_message))),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn callback_reset_password(
+    password_reset_usecase: Inject<WebModule, dyn PasswordResetUsecase>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    match password_reset_usecase.check_reset_token(&token).await {
+        Ok(_) => Html(template.reset_password_page(&token, None)).into_response(),
+        Err(e) => {
+            tracing::error!("Opening reset link failed: {}", e);
+            let error_message = match e.downcast_ref::<GenericError>() {
+                Some(generic_error) => generic_error.to_string(),
+                None => "This reset link cannot be opened right now".to_string(),
+            };
+            (
+                StatusCode::BAD_REQUEST,
+                Html(template.reset_password_page(&token, Some(error_message))),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn callback_confirm_email(
+    account_usecase: Inject<WebModule, dyn AccountUsecase>,
+    template: Inject<WebModule, dyn JinjaTemplate>,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    match account_usecase.confirm_email_change(&token).await {
+        Ok(_) => {
+            info!("Email change confirmed");
+            Html(template.confirm_email_page(None)).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Confirming email change failed: {}", e);
+            let error_message = match e.downcast_ref::<GenericError>() {
+                Some(generic_error) => generic_error.to_string(),
+                None => "This email change cannot be confirmed right now".to_string(),
+            };
+            (
+                StatusCode::BAD_REQUEST,
+                Html(template.confirm_email_page(Some(error_message))),
+            )
                 .into_response()
         }
     }
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::IntoResponse,
+    Extension,
+};
+use chats::events::ChatEventBrokerInterface;
+use futures::{SinkExt, StreamExt};
+use http::StatusCode;
+use jwt::AccessClaims;
+use shaku::HasComponent;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::commons::event_stream::user_chat_events;
+use crate::commons::templates::JinjaTemplate;
+use crate::WebModule;
+
+pub async fn ws(
+    ws: WebSocketUpgrade,
+    State(module): State<Arc<WebModule>>,
+    claim: Extension<AccessClaims>,
+) -> impl IntoResponse {
+    let Ok(user_id) = claim.user_id.parse::<Uuid>() else {
+        error!("Invalid user id in claims: {}", claim.user_id);
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let chat_event_broker: Arc<dyn ChatEventBrokerInterface> = module.resolve();
+    let template: Arc<dyn JinjaTemplate> = module.resolve();
+    ws.on_upgrade(move |socket| handle_socket(socket, user_id, chat_event_broker, template))
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    user_id: Uuid,
+    chat_event_broker: Arc<dyn ChatEventBrokerInterface>,
+    template: Arc<dyn JinjaTemplate>,
+) {
+    info!("Websocket connected for user {}", user_id);
+    let events = user_chat_events(chat_event_broker, user_id);
+    futures::pin_mut!(events);
+    let (mut sender, mut receiver) = socket.split();
+    let viewer_id = user_id.to_string();
+
+    loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(event) => {
+                    let frame = template.htmx_chat_event(&event, &viewer_id);
+                    if sender.send(Message::Text(frame.into())).await.is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            incoming = receiver.next() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    info!("Websocket disconnected for user {}", user_id);
+}
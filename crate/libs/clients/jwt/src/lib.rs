@@ -28,6 +28,19 @@ impl AccessClaims {
     pub fn new(user_id: String, role: Role) -> Self {
         Self::new_with_exp(user_id, role, generate_exp())
     }
+    /// Claims of a login session. Refreshing issues new claims with the same `jti`,
+    /// which is the `session_id` of the `sessions` row.
+    pub fn for_session(
+        user_id: String,
+        role: Role,
+        session_id: String,
+        exp_in_seconds: i64,
+    ) -> Self {
+        AccessClaims {
+            jti: session_id,
+            ..Self::new_with_exp(user_id, role, generate_exp_in(exp_in_seconds))
+        }
+    }
     fn new_with_exp(user_id: String, role: Role, exp: i64) -> Self {
         AccessClaims {
             exp,
@@ -122,10 +135,14 @@ impl JWTInterface for JWT {
 }
 
 fn generate_exp() -> i64 {
+    generate_exp_in(DEFAULT_EXP_IN_SECONDS)
+}
+
+fn generate_exp_in(seconds: i64) -> i64 {
     // Current time in UTC
     let now = chrono::Local::now();
     // Add the duration to current time
-    let expiration_time = now + Duration::seconds(DEFAULT_EXP_IN_SECONDS);
+    let expiration_time = now + Duration::seconds(seconds);
     // Return the expiration time as a UNIX timestamp
     expiration_time.timestamp()
 }
@@ -153,6 +170,15 @@ mod tests {
         assert!(exp > 0);
     }
 
+    #[test]
+    fn test_claims_for_session() {
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let claims = AccessClaims::for_session("user".into(), Role::User, session_id.clone(), 60);
+        assert_eq!(claims.jti, session_id);
+        assert!(claims.exp <= generate_exp_in(60));
+        assert!(claims.exp < generate_exp());
+    }
+
     #[test]
     fn test_generate_jti() {
         let jti = generate_jti();
@@ -104,7 +104,9 @@ impl SendEmail for Mail {
 mod tests {
     use crate::Mail;
     use crate::SendEmail;
-    use persistence::env::myenv::EnvInterface;
+    use persistence::env::myenv::{
+        EnvInterface, DEFAULT_ACCESS_TOKEN_TTL_SECONDS, DEFAULT_REFRESH_TOKEN_TTL_SECONDS,
+    };
     use persistence::Env;
     use std::sync::Arc;
 
@@ -121,6 +123,15 @@ mod tests {
             app_key_main: "testkey".to_string(),
             app_callback_url: "".to_string(),
             app_key_jwt: "".to_string(),
+            storage_backend: "local".to_string(),
+            storage_local_root: "".to_string(),
+            storage_s3_endpoint: "".to_string(),
+            storage_s3_bucket: "".to_string(),
+            storage_s3_region: "".to_string(),
+            storage_s3_access_key: "".to_string(),
+            storage_s3_secret_key: "".to_string(),
+            access_token_ttl_seconds: DEFAULT_ACCESS_TOKEN_TTL_SECONDS,
+            refresh_token_ttl_seconds: DEFAULT_REFRESH_TOKEN_TTL_SECONDS,
         });
         let mail = Mail::new(env);
         let result = mail
@@ -0,0 +1,25 @@
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use shaku::Interface;
+
+mod local;
+mod s3;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+/// The stored bytes, streamed so large attachments never sit in memory.
+pub type BlobStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// Where uploads are kept. Keys are relative paths such as `attachments/<file>`,
+/// callers are expected to only pass keys they generated themselves.
+#[async_trait::async_trait]
+pub trait BlobStorage: Interface {
+    async fn put(&self, key: &str, data: Vec<u8>, content_type: &str) -> anyhow::Result<()>;
+    /// Returns the size in bytes, or `None` when nothing is stored under the key.
+    async fn size(&self, key: &str) -> anyhow::Result<Option<u64>>;
+    /// Streams the whole object, or the inclusive byte `range` of it.
+    async fn get(&self, key: &str, range: Option<(u64, u64)>) -> anyhow::Result<BlobStream>;
+    /// Deleting a missing key is not an error.
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
@@ -0,0 +1,141 @@
+use std::io::ErrorKind;
+use std::path::{Component as PathComponent, Path, PathBuf};
+use std::sync::Arc;
+
+use futures::StreamExt;
+use persistence::env::myenv::EnvInterface;
+use shaku::Component;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{BlobStorage, BlobStream};
+
+/// Keeps uploads as plain files under `STORAGE_LOCAL_ROOT`.
+#[derive(Component)]
+#[shaku(interface = BlobStorage)]
+pub struct LocalStorage {
+    #[shaku(inject)]
+    env: Arc<dyn EnvInterface>,
+}
+
+impl LocalStorage {
+    pub fn new(env: Arc<dyn EnvInterface>) -> Self {
+        Self { env }
+    }
+
+    pub fn new_arc(env: Arc<dyn EnvInterface>) -> Arc<dyn BlobStorage> {
+        Arc::new(LocalStorage::new(env))
+    }
+
+    fn object_path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let key = Path::new(key);
+        // Never let a key climb out of the root
+        let is_relative = key
+            .components()
+            .all(|component| matches!(component, PathComponent::Normal(_)));
+        if key.as_os_str().is_empty() || !is_relative {
+            return Err(anyhow::anyhow!("Invalid storage key {}", key.display()));
+        }
+        Ok(Path::new(self.env.get_storage_local_root()).join(key))
+    }
+}
+
+#[async_trait::async_trait]
+impl BlobStorage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>, _content_type: &str) -> anyhow::Result<()> {
+        let path = self.object_path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, data).await?;
+        Ok(())
+    }
+
+    async fn size(&self, key: &str) -> anyhow::Result<Option<u64>> {
+        match tokio::fs::metadata(self.object_path(key)?).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get(&self, key: &str, range: Option<(u64, u64)>) -> anyhow::Result<BlobStream> {
+        let mut file = tokio::fs::File::open(self.object_path(key)?).await?;
+        match range {
+            Some((start, end)) => {
+                file.seek(std::io::SeekFrom::Start(start)).await?;
+                Ok(ReaderStream::new(file.take(end - start + 1)).boxed())
+            }
+            None => Ok(ReaderStream::new(file).boxed()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.object_path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use persistence::Env;
+
+    fn storage(root: &Path) -> LocalStorage {
+        let mut env = Env::load_test();
+        env.storage_local_root = root.to_string_lossy().to_string();
+        LocalStorage::new(Arc::new(env))
+    }
+
+    async fn read(storage: &LocalStorage, key: &str, range: Option<(u64, u64)>) -> Vec<u8> {
+        let chunks: Vec<_> = storage
+            .get(key, range)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat()
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_round_trip() {
+        let root = std::env::temp_dir().join(format!("storage-{}", std::process::id()));
+        let storage = storage(&root);
+
+        storage
+            .put(
+                "attachments/note.txt",
+                b"hello storage".to_vec(),
+                "text/plain",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.size("attachments/note.txt").await.unwrap(),
+            Some(13)
+        );
+        assert_eq!(
+            read(&storage, "attachments/note.txt", None).await,
+            b"hello storage"
+        );
+        assert_eq!(
+            read(&storage, "attachments/note.txt", Some((6, 12))).await,
+            b"storage"
+        );
+
+        storage.delete("attachments/note.txt").await.unwrap();
+        assert_eq!(storage.size("attachments/note.txt").await.unwrap(), None);
+        storage.delete("attachments/note.txt").await.unwrap();
+
+        for key in ["", "../escape.txt", "/etc/passwd", "attachments/../../x"] {
+            assert!(storage.put(key, Vec::new(), "text/plain").await.is_err());
+        }
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
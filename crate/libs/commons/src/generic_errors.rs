@@ -6,6 +6,8 @@ use thiserror::Error;
 pub const UNKNOWN_ERROR: u32 = 500;
 pub const INVALID_INPUT: u32 = 400;
 pub const UNAUTHORIZED: u32 = 401;
+pub const FORBIDDEN: u32 = 403;
+pub const TOO_MANY_REQUESTS: u32 = 429;
 
 #[derive(Debug, Serialize, Error)]
 pub enum GenericError {
@@ -32,6 +34,23 @@ pub enum GenericError {
 
     #[error("Unauthorized")]
     Unauthorized(),
+
+    #[error("{0}")]
+    PermissionDenied(String, u32),
+
+    /// Seconds until logging in is allowed again.
+    #[error("Too many failed login attempts, try again in {}", wait_time(*.0))]
+    LoginLocked(i64, u32),
+}
+
+// Rounded up, "in 0 minutes" would not help anyone
+fn wait_time(seconds: i64) -> String {
+    let minutes = (seconds + 59) / 60;
+    if minutes <= 1 {
+        "a minute".to_string()
+    } else {
+        format!("{} minutes", minutes)
+    }
 }
 
 impl GenericError {
@@ -76,4 +95,14 @@ impl GenericError {
         error!("unauthorized");
         GenericError::Unauthorized().into()
     }
+
+    pub fn permission_denied(message: String) -> anyhow::Error {
+        error!("permission denied: {}", message);
+        GenericError::PermissionDenied(message, FORBIDDEN).into()
+    }
+
+    pub fn login_locked(retry_after_seconds: i64) -> anyhow::Error {
+        error!("login locked for {} seconds", retry_after_seconds);
+        GenericError::LoginLocked(retry_after_seconds, TOO_MANY_REQUESTS).into()
+    }
 }
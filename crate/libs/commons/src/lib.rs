@@ -1 +1,3 @@
 pub mod generic_errors;
+pub mod secret_tokens;
+pub mod totp;
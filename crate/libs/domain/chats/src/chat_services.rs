@@ -1,7 +1,10 @@
 use crate::entity::{
-    Chat, ChatMember, ChatMessages, ChatPreview, Message, MessageBox, MessageReaction,
-    MessageReadReceipt,
+    Attachment, Chat, ChatInvite, ChatMember, ChatMemberProfile, ChatMessages, ChatPreview,
+    ChatRole, DeleteScope, Message, MessageBox, MessageCursor, MessageReaction, MessageReadReceipt,
+    MessageRevision, MessageSearch, MessageSearchHit, MessageSearchResults, QuotedMessage,
+    ThreadMessages, ThreadSummary, ATTACHMENT_MESSAGE_TYPE, HIGHLIGHT_END, HIGHLIGHT_START,
 };
+use commons::generic_errors::GenericError;
 use async_trait::async_trait;
 use log::info;
 use persistence::DatabaseInterface;
@@ -16,18 +19,203 @@ use uuid::Uuid;
 #[async_trait]
 pub trait ChatServiceInterface: Interface {
     async fn initiate_private_chat(&self, user1_id: &str, user2_id: &str) -> anyhow::Result<Uuid>;
+    async fn create_group_chat(
+        &self,
+        name: &str,
+        owner_id: &Uuid,
+        member_ids: &[Uuid],
+    ) -> anyhow::Result<Chat>;
+    async fn get_chat(&self, chat_id: &str) -> anyhow::Result<Option<Chat>>;
+    /// Fails with `GenericError::PermissionDenied` unless the user holds at least `role`.
+    async fn ensure_chat_role(
+        &self,
+        chat_id: &str,
+        user_id: &str,
+        role: ChatRole,
+    ) -> anyhow::Result<ChatRole>;
+    async fn add_chat_member(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<ChatMember>;
+    /// Removes `user_id`, or lets the actor leave when both ids are the same.
+    async fn remove_chat_member(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<()>;
+    async fn update_member_role(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        user_id: &str,
+        role: ChatRole,
+    ) -> anyhow::Result<()>;
+    async fn transfer_ownership(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        new_owner_id: &str,
+    ) -> anyhow::Result<()>;
+    async fn rename_chat(&self, chat_id: &str, actor_id: &str, name: &str) -> anyhow::Result<()>;
+    async fn update_chat_avatar(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        avatar: &str,
+    ) -> anyhow::Result<()>;
+    async fn get_chat_member_profiles(
+        &self,
+        chat_id: &str,
+    ) -> anyhow::Result<Vec<ChatMemberProfile>>;
+    /// Only admins can create invites, the creator is taken from `invite.created_by`.
+    async fn create_chat_invite(&self, invite: &ChatInvite) -> anyhow::Result<()>;
+    async fn get_chat_invite(&self, invite_id: &str) -> anyhow::Result<Option<ChatInvite>>;
+    /// Invites that can still be used, newest first.
+    async fn get_active_chat_invites(&self, chat_id: &str) -> anyhow::Result<Vec<ChatInvite>>;
+    async fn revoke_chat_invite(&self, invite_id: &str, actor_id: &str) -> anyhow::Result<()>;
+    /// Spends one use of the invite and adds the user to its chat, all or nothing.
+    async fn redeem_chat_invite(&self, invite_id: &str, user_id: &str)
+        -> anyhow::Result<ChatMember>;
     async fn get_user_chat_list(&self, user_id: &str) -> anyhow::Result<Vec<ChatPreview>>;
     async fn get_chat_members(&self, chat_id: &str) -> anyhow::Result<Vec<ChatMember>>;
     async fn is_chat_exist(&self, user1_id: &str, user2_id: &str) -> anyhow::Result<Option<Chat>>;
-    async fn get_messages_of_chat(&self, chat_id: &str) -> anyhow::Result<ChatMessages>;
+    /// Leaves out thread replies and the messages the viewer deleted for themselves.
+    async fn get_messages_of_chat(
+        &self,
+        chat_id: &str,
+        viewer_id: &str,
+        cursor: MessageCursor,
+        limit: i64,
+    ) -> anyhow::Result<ChatMessages>;
+    /// A reply has to quote a message of the same chat that was not deleted.
     async fn send_message_to_chat(
         &self,
         chat_id: &str,
         sender_id: &str,
         message: &str,
+        reply_to_message_id: Option<&str>,
     ) -> anyhow::Result<MessageBox>;
+    /// Stores an attachment message together with its files, all or nothing.
+    async fn send_attachments_to_chat(
+        &self,
+        message: &Message,
+        attachments: &[Attachment],
+    ) -> anyhow::Result<MessageBox>;
+    /// Threads hang under a message of the main timeline that was not deleted.
+    async fn send_thread_reply(
+        &self,
+        root_message_id: &str,
+        sender_id: &str,
+        message: &str,
+    ) -> anyhow::Result<MessageBox>;
+    /// A page of replies like `get_messages_of_chat`, the root comes with its summary
+    /// for the viewer.
+    async fn get_thread_messages(
+        &self,
+        root_message_id: &str,
+        viewer_id: &str,
+        cursor: MessageCursor,
+        limit: i64,
+    ) -> anyhow::Result<ThreadMessages>;
+    async fn get_thread_summary(
+        &self,
+        root_message_id: &str,
+    ) -> anyhow::Result<Option<ThreadSummary>>;
+    /// Only messages of the main timeline, thread replies are not replayed.
+    async fn get_messages_of_user_after(
+        &self,
+        user_id: &str,
+        last_message_id: &str,
+        limit: i64,
+    ) -> anyhow::Result<Vec<MessageBox>>;
+    /// Newest first, over every chat the user is a member of. Leaves out deleted messages and
+    /// the ones the user deleted for themselves.
+    async fn search_messages(
+        &self,
+        user_id: &str,
+        search: &MessageSearch,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> anyhow::Result<MessageSearchResults>;
+    /// Marks every message from others up to and including `message_id` as read by the user.
+    /// Returns only the receipts written now, each paired with the sender of its message.
+    /// Thread replies are left alone, see `mark_thread_read`.
+    async fn mark_chat_read(
+        &self,
+        chat_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<Vec<(Uuid, MessageReadReceipt)>>;
+    /// Like `mark_chat_read`, for the replies of one thread.
+    async fn mark_thread_read(
+        &self,
+        root_message_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<Vec<(Uuid, MessageReadReceipt)>>;
+    async fn get_message(&self, message_id: &str) -> anyhow::Result<Option<Message>>;
+    /// Only the sender can edit, the replaced content is kept as a `MessageRevision`.
+    /// Returns the message with everything needed to render it again.
+    async fn edit_message(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        content: &str,
+    ) -> anyhow::Result<MessageBox>;
+    /// Only the sender can delete. Deleting for everyone empties the message, drops its
+    /// reactions and revisions, and returns the attachments removed with it so their files
+    /// can be cleaned up.
+    async fn delete_message(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        scope: DeleteScope,
+    ) -> anyhow::Result<(Message, Vec<Attachment>)>;
+    /// Earlier versions of the message, oldest first.
+    async fn get_message_revisions(&self, message_id: &str)
+        -> anyhow::Result<Vec<MessageRevision>>;
+    /// Returns false when the user already reacted to the message with that emoji.
+    async fn add_reaction(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<bool>;
+    /// Returns false when there was no such reaction to remove.
+    async fn remove_reaction(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<bool>;
+    /// Returns whether the reaction is there after the toggle.
+    async fn toggle_reaction(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<bool>;
+    async fn get_message_reactions(
+        &self,
+        message_id: &str,
+    ) -> anyhow::Result<Vec<(String, MessageReaction)>>;
+    /// Finds an attachment by its file or thumbnail URL, together with the chat it was sent to.
+    async fn get_attachment_by_url(
+        &self,
+        file_url: &str,
+    ) -> anyhow::Result<Option<(Uuid, Attachment)>>;
+    async fn get_chat_id_by_avatar(&self, avatar: &str) -> anyhow::Result<Option<Uuid>>;
+    /// Points group pictures and attachments stored under `url` at `new_url`,
+    /// returns how many of them changed.
+    async fn replace_media_url(&self, url: &str, new_url: &str) -> anyhow::Result<u64>;
 }
 
+// Four bound parameters per row, well below SQLite's variable limit
+const READ_RECEIPTS_PER_INSERT: usize = 200;
+
 #[derive(Component)]
 #[shaku(interface = ChatServiceInterface)]
 pub struct ChatService {
@@ -45,16 +233,490 @@ impl ChatServiceInterface for ChatService {
         let mut pool = self.db.get_pool().begin().await?;
         let chat = Self::create_chat(user1_id, user2_id, &mut pool).await?;
         info!("Chat created: {}", chat.id);
-        Self::create_chat_member(user1_id, &mut pool, &chat).await?;
+        Self::create_chat_member(user1_id, &mut pool, &chat, ChatRole::Member).await?;
         info!("Chat member created for user1: {}", user1_id);
-        Self::create_chat_member(user2_id, &mut pool, &chat).await?;
+        Self::create_chat_member(user2_id, &mut pool, &chat, ChatRole::Member).await?;
         info!("Chat member created for user2: {}", user2_id);
         pool.commit().await?;
         Ok(chat.id)
     }
 
-    async fn get_user_chat_list(&self, _user_id: &str) -> anyhow::Result<Vec<ChatPreview>> {
-        todo!()
+    async fn create_group_chat(
+        &self,
+        name: &str,
+        owner_id: &Uuid,
+        member_ids: &[Uuid],
+    ) -> anyhow::Result<Chat> {
+        let chat = Chat::new_group(name);
+        info!("Creating group chat {} with {} members", chat.id, member_ids.len());
+        let mut pool = self.db.get_pool().begin().await?;
+        Self::insert_chat(&chat, &mut pool).await?;
+        Self::create_chat_member(&owner_id.to_string(), &mut pool, &chat, ChatRole::Owner).await?;
+        for member_id in member_ids.iter().filter(|id| *id != owner_id) {
+            Self::create_chat_member(&member_id.to_string(), &mut pool, &chat, ChatRole::Member)
+                .await?;
+        }
+        pool.commit().await?;
+        Ok(chat)
+    }
+
+    async fn get_chat(&self, chat_id: &str) -> anyhow::Result<Option<Chat>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            name,
+            is_group,
+            avatar,
+            created_at,
+            updated_at
+        FROM chats
+        WHERE id = ?"#;
+
+        let row = sqlx::query(query)
+            .bind(chat_id.to_string())
+            .fetch_optional(&mut *pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some(Chat {
+                id: row.try_get::<String, _>("id")?.parse()?,
+                name: row.try_get("name")?,
+                is_group: row.try_get("is_group")?,
+                avatar: row.try_get("avatar")?,
+                created_at: row.try_get("created_at")?,
+                updated_at: row.try_get("updated_at")?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn ensure_chat_role(
+        &self,
+        chat_id: &str,
+        user_id: &str,
+        role: ChatRole,
+    ) -> anyhow::Result<ChatRole> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        Self::require_role(&mut pool, chat_id, user_id, role).await
+    }
+
+    async fn add_chat_member(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<ChatMember> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        Self::require_role(&mut pool, chat_id, actor_id, ChatRole::Admin).await?;
+        let chat = Chat {
+            id: Uuid::from_str(chat_id)?,
+            ..Chat::default()
+        };
+        Self::create_chat_member(user_id, &mut pool, &chat, ChatRole::Member).await
+    }
+
+    async fn remove_chat_member(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<()> {
+        let mut pool = self.db.get_pool().begin().await?;
+        let target_role = Self::get_member_role(&mut pool, chat_id, user_id)
+            .await?
+            .ok_or_else(|| GenericError::invalid_input(String::from("User is not a member")))?;
+
+        if actor_id == user_id {
+            let query = r#"SELECT
+                COUNT(1) AS members,
+                SUM(CASE WHEN role = 'owner' THEN 1 ELSE 0 END) AS owners
+            FROM chat_members
+            WHERE chat_id = ?"#;
+            let row = sqlx::query(query)
+                .bind(chat_id.to_string())
+                .fetch_one(&mut *pool)
+                .await?;
+            let members: i64 = row.try_get("members")?;
+            let owners: i64 = row.try_get("owners")?;
+            if target_role == ChatRole::Owner && owners == 1 && members > 1 {
+                return Err(GenericError::permission_denied(String::from(
+                    "Transfer ownership before leaving the group",
+                )));
+            }
+        } else {
+            let actor_role =
+                Self::require_role(&mut pool, chat_id, actor_id, ChatRole::Admin).await?;
+            if actor_role <= target_role {
+                return Err(GenericError::permission_denied(format!(
+                    "Only the owner can remove an {}",
+                    target_role.as_str()
+                )));
+            }
+        }
+
+        let query = r#"DELETE FROM chat_members WHERE chat_id = ? AND user_id = ?"#;
+        sqlx::query(query)
+            .bind(chat_id.to_string())
+            .bind(user_id.to_string())
+            .execute(&mut *pool)
+            .await?;
+        pool.commit().await?;
+        Ok(())
+    }
+
+    async fn update_member_role(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        user_id: &str,
+        role: ChatRole,
+    ) -> anyhow::Result<()> {
+        let mut pool = self.db.get_pool().begin().await?;
+        Self::require_role(&mut pool, chat_id, actor_id, ChatRole::Owner).await?;
+        if role == ChatRole::Owner {
+            return Err(GenericError::invalid_input(String::from(
+                "Use an ownership transfer to make someone the owner",
+            )));
+        }
+        match Self::get_member_role(&mut pool, chat_id, user_id).await? {
+            None => {
+                return Err(GenericError::invalid_input(String::from(
+                    "User is not a member",
+                )))
+            }
+            Some(ChatRole::Owner) => {
+                return Err(GenericError::permission_denied(String::from(
+                    "The owner's role can only change through an ownership transfer",
+                )))
+            }
+            Some(_) => {}
+        }
+        Self::set_member_role(&mut pool, chat_id, user_id, role).await?;
+        pool.commit().await?;
+        Ok(())
+    }
+
+    async fn transfer_ownership(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        new_owner_id: &str,
+    ) -> anyhow::Result<()> {
+        let mut pool = self.db.get_pool().begin().await?;
+        Self::require_role(&mut pool, chat_id, actor_id, ChatRole::Owner).await?;
+        if new_owner_id == actor_id {
+            return Err(GenericError::invalid_input(String::from(
+                "You already own this group",
+            )));
+        }
+        if Self::get_member_role(&mut pool, chat_id, new_owner_id)
+            .await?
+            .is_none()
+        {
+            return Err(GenericError::invalid_input(String::from(
+                "User is not a member",
+            )));
+        }
+        Self::set_member_role(&mut pool, chat_id, new_owner_id, ChatRole::Owner).await?;
+        Self::set_member_role(&mut pool, chat_id, actor_id, ChatRole::Admin).await?;
+        pool.commit().await?;
+        info!(
+            "Ownership of {} transferred from {} to {}",
+            chat_id, actor_id, new_owner_id
+        );
+        Ok(())
+    }
+
+    async fn rename_chat(&self, chat_id: &str, actor_id: &str, name: &str) -> anyhow::Result<()> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        Self::require_role(&mut pool, chat_id, actor_id, ChatRole::Admin).await?;
+        let query = r#"UPDATE chats SET name = ?, updated_at = ? WHERE id = ?"#;
+        sqlx::query(query)
+            .bind(name.to_string())
+            .bind(chrono::Local::now().naive_local())
+            .bind(chat_id.to_string())
+            .execute(&mut *pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_chat_avatar(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        avatar: &str,
+    ) -> anyhow::Result<()> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        Self::require_role(&mut pool, chat_id, actor_id, ChatRole::Admin).await?;
+        let query = r#"UPDATE chats SET avatar = ?, updated_at = ? WHERE id = ?"#;
+        sqlx::query(query)
+            .bind(avatar.to_string())
+            .bind(chrono::Local::now().naive_local())
+            .bind(chat_id.to_string())
+            .execute(&mut *pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_chat_member_profiles(
+        &self,
+        chat_id: &str,
+    ) -> anyhow::Result<Vec<ChatMemberProfile>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            chat_members.id AS id,
+            chat_members.chat_id AS chat_id,
+            chat_members.user_id AS user_id,
+            chat_members.role AS role,
+            chat_members.joined_at AS joined_at,
+            users.username AS username,
+            user_details.first_name AS first_name,
+            user_details.last_name AS last_name,
+            COALESCE(user_details.profile_picture_small, user_details.profile_picture) AS profile_picture
+        FROM chat_members
+        JOIN users ON users.id = chat_members.user_id
+        LEFT JOIN user_details ON user_details.user_id = chat_members.user_id
+        WHERE chat_members.chat_id = ?
+        ORDER BY chat_members.joined_at ASC"#;
+
+        let rows = sqlx::query(query)
+            .bind(chat_id.to_string())
+            .fetch_all(&mut *pool)
+            .await?;
+
+        let mut profiles = Vec::new();
+        for row in rows {
+            profiles.push(ChatMemberProfile {
+                member: ChatMember {
+                    id: row.try_get::<String, _>("id")?.parse()?,
+                    chat_id: row.try_get::<String, _>("chat_id")?.parse()?,
+                    user_id: row.try_get::<String, _>("user_id")?.parse()?,
+                    role: row.try_get::<String, _>("role")?.parse()?,
+                    joined_at: row.try_get("joined_at")?,
+                },
+                username: row.try_get("username")?,
+                name: ChatService::decide_name(&row)?,
+                profile_picture: row.try_get("profile_picture")?,
+            });
+        }
+        Ok(profiles)
+    }
+
+    async fn create_chat_invite(&self, invite: &ChatInvite) -> anyhow::Result<()> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        Self::require_role(
+            &mut pool,
+            &invite.chat_id.to_string(),
+            &invite.created_by.to_string(),
+            ChatRole::Admin,
+        )
+        .await?;
+        let query = r#"INSERT INTO chat_invites (
+            id,
+            chat_id,
+            created_by,
+            expires_at,
+            max_uses,
+            use_count,
+            revoked_at,
+            created_at
+        ) VALUES (
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?
+        )"#;
+        sqlx::query(query)
+            .bind(invite.id.to_string())
+            .bind(invite.chat_id.to_string())
+            .bind(invite.created_by.to_string())
+            .bind(invite.expires_at)
+            .bind(invite.max_uses)
+            .bind(invite.use_count)
+            .bind(invite.revoked_at)
+            .bind(invite.created_at)
+            .execute(&mut *pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_chat_invite(&self, invite_id: &str) -> anyhow::Result<Option<ChatInvite>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            chat_id,
+            created_by,
+            expires_at,
+            max_uses,
+            use_count,
+            revoked_at,
+            created_at
+        FROM chat_invites
+        WHERE id = ?"#;
+        let row = sqlx::query(query)
+            .bind(invite_id.to_string())
+            .fetch_optional(&mut *pool)
+            .await?;
+        row.as_ref().map(Self::row_to_chat_invite).transpose()
+    }
+
+    async fn get_active_chat_invites(&self, chat_id: &str) -> anyhow::Result<Vec<ChatInvite>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            chat_id,
+            created_by,
+            expires_at,
+            max_uses,
+            use_count,
+            revoked_at,
+            created_at
+        FROM chat_invites
+        WHERE chat_id = ?
+          AND revoked_at IS NULL
+          AND (expires_at IS NULL OR expires_at > ?)
+          AND (max_uses IS NULL OR use_count < max_uses)
+        ORDER BY created_at DESC"#;
+        let rows = sqlx::query(query)
+            .bind(chat_id.to_string())
+            .bind(chrono::Local::now().naive_local())
+            .fetch_all(&mut *pool)
+            .await?;
+        rows.iter().map(Self::row_to_chat_invite).collect()
+    }
+
+    async fn revoke_chat_invite(&self, invite_id: &str, actor_id: &str) -> anyhow::Result<()> {
+        let mut pool = self.db.get_pool().begin().await?;
+        let chat_id: String =
+            sqlx::query(r#"SELECT chat_id FROM chat_invites WHERE id = ?"#)
+                .bind(invite_id.to_string())
+                .fetch_optional(&mut *pool)
+                .await?
+                .ok_or_else(|| GenericError::invalid_input(String::from("Invite not found")))?
+                .try_get("chat_id")?;
+        Self::require_role(&mut pool, &chat_id, actor_id, ChatRole::Admin).await?;
+
+        let query = r#"UPDATE chat_invites SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL"#;
+        sqlx::query(query)
+            .bind(chrono::Local::now().naive_local())
+            .bind(invite_id.to_string())
+            .execute(&mut *pool)
+            .await?;
+        pool.commit().await?;
+        info!("Invite {} of chat {} revoked by {}", invite_id, chat_id, actor_id);
+        Ok(())
+    }
+
+    async fn redeem_chat_invite(
+        &self,
+        invite_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<ChatMember> {
+        let mut pool = self.db.get_pool().begin().await?;
+        // The usability checks live in the UPDATE itself so concurrent joins cannot overspend it
+        let query = r#"UPDATE chat_invites
+        SET use_count = use_count + 1
+        WHERE id = ?
+          AND revoked_at IS NULL
+          AND (expires_at IS NULL OR expires_at > ?)
+          AND (max_uses IS NULL OR use_count < max_uses)"#;
+        let result = sqlx::query(query)
+            .bind(invite_id.to_string())
+            .bind(chrono::Local::now().naive_local())
+            .execute(&mut *pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(GenericError::invalid_input(String::from(
+                "This invite link is no longer valid",
+            )));
+        }
+
+        let chat_id: String = sqlx::query(r#"SELECT chat_id FROM chat_invites WHERE id = ?"#)
+            .bind(invite_id.to_string())
+            .fetch_one(&mut *pool)
+            .await?
+            .try_get("chat_id")?;
+        let chat = Chat {
+            id: Uuid::from_str(&chat_id)?,
+            ..Chat::default()
+        };
+        let member = Self::create_chat_member(user_id, &mut pool, &chat, ChatRole::Member).await?;
+        pool.commit().await?;
+        info!("{} joined chat {} with invite {}", user_id, chat_id, invite_id);
+        Ok(member)
+    }
+
+    async fn get_user_chat_list(&self, user_id: &str) -> anyhow::Result<Vec<ChatPreview>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            c.id AS chat_id,
+            c.name AS chat_name,
+            c.is_group,
+            c.created_at AS chat_created_at,
+            lm.id AS last_message_id,
+            lm.sender_id AS last_message_sender_id,
+            lm.content AS last_message_content,
+            lm.message_type AS last_message_type,
+            lm.message_key AS last_message_key,
+            lm.reply_to_message_id AS last_message_reply_to_message_id,
+            lm.sent_at AS last_message_sent_at,
+            lm.edited_at AS last_message_edited_at,
+            lm.deleted_at AS last_message_deleted_at,
+            counterpart.username AS username,
+            ud.first_name AS first_name,
+            ud.last_name AS last_name,
+            COALESCE(ud.profile_picture_small, ud.profile_picture, c.avatar) AS profile_picture,
+            (
+                SELECT COUNT(1)
+                FROM messages unread
+                WHERE unread.chat_id = c.id
+                    AND unread.thread_root_id IS NULL
+                    AND unread.sender_id != me.user_id
+                    AND unread.sent_at >= me.joined_at
+                    AND unread.deleted_at IS NULL
+                    AND NOT EXISTS (
+                        SELECT 1
+                        FROM message_read_receipts receipt
+                        WHERE receipt.message_id = unread.id AND receipt.user_id = me.user_id
+                    )
+                    AND NOT EXISTS (
+                        SELECT 1
+                        FROM hidden_messages hidden
+                        WHERE hidden.message_id = unread.id AND hidden.user_id = me.user_id
+                    )
+            ) AS unread_message_count
+        FROM chat_members me
+        JOIN chats c ON c.id = me.chat_id
+        LEFT JOIN messages lm ON lm.id = (
+            SELECT latest.id
+            FROM messages latest
+            WHERE latest.chat_id = c.id
+                AND latest.thread_root_id IS NULL
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM hidden_messages hidden
+                    WHERE hidden.message_id = latest.id AND hidden.user_id = me.user_id
+                )
+            ORDER BY latest.sent_at DESC, latest.id DESC
+            LIMIT 1
+        )
+        LEFT JOIN chat_members other_member
+            ON other_member.chat_id = c.id AND other_member.user_id != me.user_id AND c.is_group = FALSE
+        LEFT JOIN users counterpart ON counterpart.id = other_member.user_id
+        LEFT JOIN user_details ud ON ud.user_id = counterpart.id
+        WHERE me.user_id = ?
+        ORDER BY COALESCE(lm.sent_at, c.created_at) DESC"#;
+
+        let rows = sqlx::query(query)
+            .bind(user_id.to_string())
+            .fetch_all(&mut *pool)
+            .await?;
+
+        rows.iter().map(Self::row_to_chat_preview).collect()
     }
 
     async fn get_chat_members(&self, chat_id: &str) -> anyhow::Result<Vec<ChatMember>> {
@@ -63,6 +725,7 @@ impl ChatServiceInterface for ChatService {
             id,
             chat_id,
             user_id,
+            role,
             joined_at
         FROM chat_members
         WHERE chat_id = ?"#;
@@ -78,6 +741,7 @@ impl ChatServiceInterface for ChatService {
                 id: row.try_get::<String, _>("id")?.parse()?,
                 chat_id: row.try_get::<String, _>("chat_id")?.parse()?,
                 user_id: row.try_get::<String, _>("user_id")?.parse()?,
+                role: row.try_get::<String, _>("role")?.parse()?,
                 joined_at: row.get("joined_at"),
             });
         }
@@ -94,12 +758,12 @@ impl ChatServiceInterface for ChatService {
             is_group
         FROM chats
         WHERE
-            name in (?, ?) 
+            name in (?, ?) AND is_group = FALSE
         "#;
 
         let all_names = chat.get_all_possible_names();
         let rows = sqlx::query(query)
-            .bind(all_names.get(0).unwrap())
+            .bind(all_names.first().unwrap())
             .bind(all_names.get(1).unwrap())
             .fetch_optional(&mut *pool)
             .await?;
@@ -113,14 +777,762 @@ impl ChatServiceInterface for ChatService {
             id: rows.try_get::<String, _>("id")?.parse()?,
             name: rows.try_get::<String, _>("name")?,
             is_group: rows.try_get::<bool, _>("is_group")?,
+            avatar: None,
             created_at: None,
             updated_at: None,
         };
         Ok(Some(chat))
     }
 
-    async fn get_messages_of_chat(&self, chat_id: &str) -> anyhow::Result<ChatMessages> {
+    async fn get_messages_of_chat(
+        &self,
+        chat_id: &str,
+        viewer_id: &str,
+        cursor: MessageCursor,
+        limit: i64,
+    ) -> anyhow::Result<ChatMessages> {
+        let (messages, has_more) = self
+            .get_message_page(
+                "m.chat_id = ? AND m.thread_root_id IS NULL",
+                chat_id,
+                viewer_id,
+                cursor,
+                limit,
+            )
+            .await?;
+
+        Ok(ChatMessages {
+            chat_id: Uuid::from_str(chat_id)?,
+            chat_name: String::new(),
+            is_group: false,
+            messages,
+            chat_members: Vec::new(),
+            has_more,
+        })
+    }
+
+    async fn send_message_to_chat(
+        &self,
+        chat_id: &str,
+        sender_id: &str,
+        message: &str,
+        reply_to_message_id: Option<&str>,
+    ) -> anyhow::Result<MessageBox> {
+        let mut pool = self.db.get_pool().begin().await?;
+        let chat_id = Uuid::from_str(chat_id)?;
+        let sender_id = Uuid::from_str(sender_id)?;
+        log::info!("Sending message to chat: {}, from sender: {}", chat_id, sender_id);
+        let mut message = Message::new_private_message(chat_id, sender_id, message.to_owned());
+        if let Some(reply_to_message_id) = reply_to_message_id {
+            let quoted = Self::find_message(&mut pool, reply_to_message_id)
+                .await?
+                .filter(|quoted| quoted.chat_id == chat_id && !quoted.is_deleted())
+                .ok_or_else(|| {
+                    GenericError::invalid_input(String::from(
+                        "The message you replied to is no longer available",
+                    ))
+                })?;
+            message = message.with_reply_to(quoted.id);
+        }
+        Self::insert_message(&mut pool, &message).await?;
+        pool.commit().await?;
+
+        let quote = match message.reply_to_message_id {
+            Some(reply_to_message_id) => Self::get_quotes_of_message(
+                self.db.get_pool(),
+                Arc::new(vec![reply_to_message_id.to_string()]),
+            )
+            .await?
+            .remove(&reply_to_message_id),
+            None => None,
+        };
+        // A new message has neither read receipts nor reactions yet
+        let recipients = Vec::new();
+        let reactions = Vec::new();
+        Ok(MessageBox(
+            message,
+            recipients,
+            reactions,
+            Vec::new(),
+            quote,
+            None,
+        ))
+    }
+
+    async fn send_attachments_to_chat(
+        &self,
+        message: &Message,
+        attachments: &[Attachment],
+    ) -> anyhow::Result<MessageBox> {
+        let mut pool = self.db.get_pool().begin().await?;
+        log::info!(
+            "Sending {} attachments to chat: {}, from sender: {}",
+            attachments.len(),
+            message.chat_id,
+            message.sender_id
+        );
+        Self::insert_message(&mut pool, message).await?;
+        let query = r#"INSERT INTO attachments (
+            id,
+            message_id,
+            file_url,
+            file_name,
+            file_type,
+            file_size,
+            thumbnail_url,
+            uploaded_at
+        ) VALUES (
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?,
+            ?
+        )"#;
+        for attachment in attachments {
+            sqlx::query(query)
+                .bind(attachment.id.to_string())
+                .bind(message.id.to_string())
+                .bind(attachment.file_url.clone())
+                .bind(attachment.file_name.clone())
+                .bind(attachment.file_type.clone())
+                .bind(attachment.file_size)
+                .bind(attachment.thumbnail_url.clone())
+                .bind(attachment.uploaded_at)
+                .execute(&mut *pool)
+                .await?;
+        }
+        pool.commit().await?;
+
+        Ok(MessageBox(
+            message.clone(),
+            Vec::new(),
+            Vec::new(),
+            attachments.to_vec(),
+            None,
+            None,
+        ))
+    }
+
+    async fn send_thread_reply(
+        &self,
+        root_message_id: &str,
+        sender_id: &str,
+        message: &str,
+    ) -> anyhow::Result<MessageBox> {
+        let mut pool = self.db.get_pool().begin().await?;
+        let sender_id = Uuid::from_str(sender_id)?;
+        // Replies to a reply would need threads inside threads, they go under the same root
+        let root = Self::find_message(&mut pool, root_message_id)
+            .await?
+            .filter(|root| !root.is_thread_reply() && !root.is_deleted())
+            .ok_or_else(|| {
+                GenericError::invalid_input(String::from("This thread is no longer available"))
+            })?;
+        info!(
+            "Replying in thread {} of chat {}, from sender: {}",
+            root.id, root.chat_id, sender_id
+        );
+        let message = Message::new_private_message(root.chat_id, sender_id, message.to_owned())
+            .in_thread(root.id);
+        Self::insert_message(&mut pool, &message).await?;
+        pool.commit().await?;
+
+        Ok(MessageBox(
+            message,
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            None,
+            None,
+        ))
+    }
+
+    async fn get_thread_messages(
+        &self,
+        root_message_id: &str,
+        viewer_id: &str,
+        cursor: MessageCursor,
+        limit: i64,
+    ) -> anyhow::Result<ThreadMessages> {
+        let root = {
+            let mut pool = self.db.get_pool().acquire().await?;
+            Self::find_message(&mut pool, root_message_id)
+                .await?
+                .filter(|root| !root.is_thread_reply())
+                .ok_or_else(|| GenericError::invalid_input(String::from("Thread not found")))?
+        };
+        let (messages, has_more) = self
+            .get_message_page(
+                "m.thread_root_id = ?",
+                root_message_id,
+                viewer_id,
+                cursor,
+                limit,
+            )
+            .await?;
+        let root = self
+            .load_message_boxes(vec![root], Some(viewer_id))
+            .await?
+            .remove(0);
+
+        Ok(ThreadMessages {
+            root,
+            messages,
+            has_more,
+        })
+    }
+
+    async fn get_thread_summary(
+        &self,
+        root_message_id: &str,
+    ) -> anyhow::Result<Option<ThreadSummary>> {
+        let root_message_id = Uuid::from_str(root_message_id)?;
+        let mut threads = Self::get_threads_of_message(
+            self.db.get_pool(),
+            Arc::new(vec![root_message_id.to_string()]),
+            None,
+        )
+        .await?;
+        Ok(threads.remove(&root_message_id))
+    }
+
+    async fn get_messages_of_user_after(
+        &self,
+        user_id: &str,
+        last_message_id: &str,
+        limit: i64,
+    ) -> anyhow::Result<Vec<MessageBox>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            m.id,
+            m.chat_id,
+            m.sender_id,
+            m.content,
+            m.message_type,
+            m.message_key,
+            m.reply_to_message_id,
+            m.thread_root_id,
+            m.sent_at,
+            m.edited_at,
+            m.deleted_at
+        FROM messages m
+        JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = ?
+        JOIN messages last_message ON last_message.id = ?
+        LEFT JOIN hidden_messages hidden ON hidden.message_id = m.id AND hidden.user_id = cm.user_id
+        WHERE (m.sent_at > last_message.sent_at
+                OR (m.sent_at = last_message.sent_at AND m.id > last_message.id))
+            AND m.thread_root_id IS NULL
+            AND hidden.message_id IS NULL
+        ORDER BY m.sent_at ASC, m.id ASC
+        LIMIT ?"#;
+
+        let rows = sqlx::query(query)
+            .bind(user_id.to_string())
+            .bind(last_message_id.to_string())
+            .bind(limit)
+            .fetch_all(&mut *pool)
+            .await?;
+
+        let messages = rows
+            .iter()
+            .map(Self::row_to_message)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Replayed messages are rendered like live ones, so their files have to come along
+        let msg_ids = Arc::new(messages.iter().map(|message| message.id.to_string()).collect());
+        let quoted_ids = Arc::new(
+            messages
+                .iter()
+                .filter_map(|message| message.reply_to_message_id)
+                .map(|id| id.to_string())
+                .collect(),
+        );
+        let conn_pool = self.db.get_pool();
+        let (mut attachments, quotes) = tokio::try_join!(
+            Self::get_attachments_of_message(conn_pool.clone(), msg_ids),
+            Self::get_quotes_of_message(conn_pool, quoted_ids),
+        )?;
+
+        Ok(messages
+            .into_iter()
+            .map(|message| {
+                let files = attachments.remove(&message.id).unwrap_or_default();
+                let quote = message
+                    .reply_to_message_id
+                    .and_then(|id| quotes.get(&id).cloned());
+                MessageBox(message, Vec::new(), Vec::new(), files, quote, None)
+            })
+            .collect())
+    }
+
+    async fn search_messages(
+        &self,
+        user_id: &str,
+        search: &MessageSearch,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> anyhow::Result<MessageSearchResults> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        // Without free text every message of the user's chats is a candidate
+        let (snippet, source, text_filter) = match search.text {
+            Some(_) => (
+                "snippet(messages_fts, 0, ?, ?, '…', 16)",
+                "messages_fts JOIN messages m ON m.rowid = messages_fts.rowid",
+                "AND messages_fts MATCH ?",
+            ),
+            None => ("NULL", "messages m", ""),
+        };
+        let cursor_join = match before {
+            Some(_) => "JOIN messages cursor ON cursor.id = ?",
+            None => "",
+        };
+        let mut filters = String::new();
+        if before.is_some() {
+            filters.push_str(
+                " AND (m.sent_at < cursor.sent_at OR (m.sent_at = cursor.sent_at AND m.id < cursor.id))",
+            );
+        }
+        if search.from_username.is_some() {
+            filters.push_str(" AND sender.username = ? COLLATE NOCASE");
+        }
+        if search.in_chat.is_some() {
+            filters.push_str(
+                " AND ((c.is_group AND c.name = ? COLLATE NOCASE) OR (NOT c.is_group AND counterpart.username = ? COLLATE NOCASE))",
+            );
+        }
+        if search.before.is_some() {
+            filters.push_str(" AND m.sent_at < ?");
+        }
+        if search.after.is_some() {
+            filters.push_str(" AND m.sent_at >= ?");
+        }
+        if search.has_attachment {
+            filters.push_str(" AND m.message_type = ?");
+        }
+        let query = format!(
+            r#"SELECT
+            m.id,
+            m.chat_id,
+            m.sender_id,
+            m.content,
+            m.message_type,
+            m.message_key,
+            m.reply_to_message_id,
+            m.thread_root_id,
+            m.sent_at,
+            m.edited_at,
+            m.deleted_at,
+            sender.username AS username,
+            sender_details.first_name AS first_name,
+            sender_details.last_name AS last_name,
+            c.is_group,
+            CASE
+                WHEN c.is_group THEN c.name
+                ELSE COALESCE(
+                    counterpart_details.first_name || ' ' || counterpart_details.last_name,
+                    counterpart.username,
+                    c.name
+                )
+            END AS chat_name,
+            {snippet} AS snippet
+        FROM {source}
+        JOIN chat_members me ON me.chat_id = m.chat_id AND me.user_id = ?
+        JOIN chats c ON c.id = m.chat_id
+        JOIN users sender ON sender.id = m.sender_id
+        LEFT JOIN user_details sender_details ON sender_details.user_id = sender.id
+        LEFT JOIN chat_members other_member
+            ON other_member.chat_id = c.id AND other_member.user_id != me.user_id AND c.is_group = FALSE
+        LEFT JOIN users counterpart ON counterpart.id = other_member.user_id
+        LEFT JOIN user_details counterpart_details ON counterpart_details.user_id = counterpart.id
+        {cursor_join}
+        WHERE m.deleted_at IS NULL
+            {text_filter}
+            AND NOT EXISTS (
+                SELECT 1
+                FROM hidden_messages hidden
+                WHERE hidden.message_id = m.id AND hidden.user_id = me.user_id
+            )
+            {filters}
+        ORDER BY m.sent_at DESC, m.id DESC
+        LIMIT ?"#
+        );
+
+        // Bound in the order the placeholders appear above
+        let mut query = sqlx::query(&query);
+        if search.text.is_some() {
+            query = query
+                .bind(HIGHLIGHT_START.to_string())
+                .bind(HIGHLIGHT_END.to_string());
+        }
+        query = query.bind(user_id.to_string());
+        if let Some(before) = before {
+            query = query.bind(before.to_string());
+        }
+        if let Some(text) = &search.text {
+            query = query.bind(text.clone());
+        }
+        if let Some(from_username) = &search.from_username {
+            query = query.bind(from_username.clone());
+        }
+        if let Some(in_chat) = &search.in_chat {
+            query = query.bind(in_chat.clone()).bind(in_chat.clone());
+        }
+        if let Some(date) = search.before {
+            query = query.bind(date.and_time(chrono::NaiveTime::MIN));
+        }
+        if let Some(date) = search.after {
+            let day_after = date.succ_opt().unwrap_or(date);
+            query = query.bind(day_after.and_time(chrono::NaiveTime::MIN));
+        }
+        if search.has_attachment {
+            query = query.bind(ATTACHMENT_MESSAGE_TYPE);
+        }
+        // One extra row tells whether there is another page
+        let mut rows = query.bind(limit + 1).fetch_all(&mut *pool).await?;
+
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit.max(0) as usize);
+        let hits = rows
+            .iter()
+            .map(Self::row_to_search_hit)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(MessageSearchResults { hits, has_more })
+    }
+
+    async fn mark_chat_read(
+        &self,
+        chat_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<Vec<(Uuid, MessageReadReceipt)>> {
+        self.mark_read_up_to(
+            "m.chat_id = ? AND m.thread_root_id IS NULL",
+            chat_id,
+            user_id,
+            message_id,
+        )
+        .await
+    }
+
+    async fn mark_thread_read(
+        &self,
+        root_message_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<Vec<(Uuid, MessageReadReceipt)>> {
+        self.mark_read_up_to("m.thread_root_id = ?", root_message_id, user_id, message_id)
+            .await
+    }
+
+    async fn get_message(&self, message_id: &str) -> anyhow::Result<Option<Message>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        Self::find_message(&mut pool, message_id).await
+    }
+
+    async fn edit_message(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        content: &str,
+    ) -> anyhow::Result<MessageBox> {
+        let mut pool = self.db.get_pool().begin().await?;
+        let message = Self::find_own_message(&mut pool, message_id, user_id).await?;
+        if message.is_deleted() {
+            return Err(GenericError::invalid_input(String::from(
+                "Deleted messages cannot be edited",
+            )));
+        }
+        if message.content == content {
+            pool.commit().await?;
+            return self.get_message_box(message).await;
+        }
+
+        let revision = MessageRevision::new(message.id, message.content.clone());
+        let query = r#"INSERT INTO message_revisions (
+            id,
+            message_id,
+            content,
+            edited_at
+        ) VALUES (
+            ?,
+            ?,
+            ?,
+            ?
+        )"#;
+        sqlx::query(query)
+            .bind(revision.id.to_string())
+            .bind(revision.message_id.to_string())
+            .bind(revision.content.clone())
+            .bind(revision.edited_at)
+            .execute(&mut *pool)
+            .await?;
+        sqlx::query(r#"UPDATE messages SET content = ?, edited_at = ? WHERE id = ?"#)
+            .bind(content.to_string())
+            .bind(revision.edited_at)
+            .bind(message.id.to_string())
+            .execute(&mut *pool)
+            .await?;
+        pool.commit().await?;
+        info!("{} edited message {}", user_id, message.id);
+
+        let message = Message {
+            content: content.to_string(),
+            edited_at: revision.edited_at,
+            ..message
+        };
+        self.get_message_box(message).await
+    }
+
+    async fn delete_message(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        scope: DeleteScope,
+    ) -> anyhow::Result<(Message, Vec<Attachment>)> {
+        let mut pool = self.db.get_pool().begin().await?;
+        let message = Self::find_own_message(&mut pool, message_id, user_id).await?;
+        let message_id = message.id.to_string();
+
+        if scope == DeleteScope::ForMe {
+            let query = r#"INSERT OR IGNORE INTO hidden_messages (
+                message_id,
+                user_id,
+                hidden_at
+            ) VALUES (
+                ?,
+                ?,
+                ?
+            )"#;
+            sqlx::query(query)
+                .bind(message_id)
+                .bind(user_id.to_string())
+                .bind(chrono::Local::now().naive_local())
+                .execute(&mut *pool)
+                .await?;
+            pool.commit().await?;
+            return Ok((message, Vec::new()));
+        }
+        if message.is_deleted() {
+            pool.commit().await?;
+            return Ok((message, Vec::new()));
+        }
+
+        let query = r#"SELECT
+            id,
+            message_id,
+            file_url,
+            file_name,
+            file_type,
+            file_size,
+            thumbnail_url,
+            uploaded_at
+        FROM attachments
+        WHERE message_id = ?"#;
+        let attachments = sqlx::query(query)
+            .bind(message_id.clone())
+            .fetch_all(&mut *pool)
+            .await?
+            .iter()
+            .map(Self::row_to_attachment)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Read receipts stay, so the tombstone does not turn up as unread again
+        for query in [
+            r#"DELETE FROM attachments WHERE message_id = ?"#,
+            r#"DELETE FROM message_reactions WHERE message_id = ?"#,
+            r#"DELETE FROM message_revisions WHERE message_id = ?"#,
+        ] {
+            sqlx::query(query)
+                .bind(message_id.clone())
+                .execute(&mut *pool)
+                .await?;
+        }
+        let deleted_at = chrono::Local::now().naive_local();
+        sqlx::query(r#"UPDATE messages SET content = '', deleted_at = ? WHERE id = ?"#)
+            .bind(deleted_at)
+            .bind(message_id)
+            .execute(&mut *pool)
+            .await?;
+        pool.commit().await?;
+        info!("{} deleted message {} for everyone", user_id, message.id);
+
+        let message = Message {
+            content: String::new(),
+            deleted_at: Some(deleted_at),
+            ..message
+        };
+        Ok((message, attachments))
+    }
+
+    async fn get_message_revisions(
+        &self,
+        message_id: &str,
+    ) -> anyhow::Result<Vec<MessageRevision>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            message_id,
+            content,
+            edited_at
+        FROM message_revisions
+        WHERE message_id = ?
+        ORDER BY edited_at ASC, id ASC"#;
+        let rows = sqlx::query(query)
+            .bind(message_id.to_string())
+            .fetch_all(&mut *pool)
+            .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(MessageRevision {
+                    id: row.try_get::<String, _>("id")?.parse()?,
+                    message_id: row.try_get::<String, _>("message_id")?.parse()?,
+                    content: row.try_get("content")?,
+                    edited_at: row.try_get("edited_at")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn add_reaction(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<bool> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        Self::insert_reaction(&mut pool, message_id, user_id, reaction).await
+    }
+
+    async fn remove_reaction(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<bool> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        Self::delete_reaction(&mut pool, message_id, user_id, reaction).await
+    }
+
+    async fn toggle_reaction(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<bool> {
+        let mut pool = self.db.get_pool().begin().await?;
+        let added = if Self::delete_reaction(&mut pool, message_id, user_id, reaction).await? {
+            false
+        } else {
+            Self::insert_reaction(&mut pool, message_id, user_id, reaction).await?
+        };
+        pool.commit().await?;
+        Ok(added)
+    }
+
+    async fn get_message_reactions(
+        &self,
+        message_id: &str,
+    ) -> anyhow::Result<Vec<(String, MessageReaction)>> {
+        let message_uuid = Uuid::from_str(message_id)?;
+        let mut reactions = Self::get_reactions_of_message(
+            self.db.get_pool(),
+            Arc::new(vec![message_uuid.to_string()]),
+        )
+        .await?;
+        Ok(reactions.remove(&message_uuid).unwrap_or_default())
+    }
+
+    async fn get_attachment_by_url(
+        &self,
+        file_url: &str,
+    ) -> anyhow::Result<Option<(Uuid, Attachment)>> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            a.id,
+            a.message_id,
+            a.file_url,
+            a.file_name,
+            a.file_type,
+            a.file_size,
+            a.thumbnail_url,
+            a.uploaded_at,
+            m.chat_id
+        FROM attachments a
+        JOIN messages m ON m.id = a.message_id
+        WHERE a.file_url = ? OR a.thumbnail_url = ?"#;
+        let row = sqlx::query(query)
+            .bind(file_url.to_string())
+            .bind(file_url.to_string())
+            .fetch_optional(&mut *pool)
+            .await?;
+
+        match row {
+            Some(row) => Ok(Some((
+                row.try_get::<String, _>("chat_id")?.parse()?,
+                Self::row_to_attachment(&row)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_chat_id_by_avatar(&self, avatar: &str) -> anyhow::Result<Option<Uuid>> {
         let mut pool = self.db.get_pool().acquire().await?;
+        let row = sqlx::query(r#"SELECT id FROM chats WHERE avatar = ?"#)
+            .bind(avatar.to_string())
+            .fetch_optional(&mut *pool)
+            .await?;
+        row.map(|row| Ok(row.try_get::<String, _>("id")?.parse()?))
+            .transpose()
+    }
+
+    async fn replace_media_url(&self, url: &str, new_url: &str) -> anyhow::Result<u64> {
+        let mut pool = self.db.get_pool().begin().await?;
+        let mut replaced = 0;
+        for query in [
+            r#"UPDATE chats SET avatar = ? WHERE avatar = ?"#,
+            r#"UPDATE attachments SET file_url = ? WHERE file_url = ?"#,
+            r#"UPDATE attachments SET thumbnail_url = ? WHERE thumbnail_url = ?"#,
+        ] {
+            replaced += sqlx::query(query)
+                .bind(new_url.to_string())
+                .bind(url.to_string())
+                .execute(&mut *pool)
+                .await?
+                .rows_affected();
+        }
+        pool.commit().await?;
+        Ok(replaced)
+    }
+}
+
+impl ChatService {
+    fn row_to_message(row: &SqliteRow) -> anyhow::Result<Message> {
+        Ok(Message {
+            id: row.try_get::<String, _>("id")?.parse()?,
+            chat_id: row.try_get::<String, _>("chat_id")?.parse()?,
+            sender_id: row.try_get::<String, _>("sender_id")?.parse()?,
+            content: row.try_get("content")?,
+            message_type: row.try_get("message_type")?,
+            message_key: row.try_get("message_key")?,
+            reply_to_message_id: row
+                .try_get::<Option<String>, _>("reply_to_message_id")?
+                .map(|id| id.parse())
+                .transpose()?,
+            thread_root_id: row
+                .try_get::<Option<String>, _>("thread_root_id")?
+                .map(|id| id.parse())
+                .transpose()?,
+            sent_at: row.try_get("sent_at")?,
+            edited_at: row.try_get("edited_at")?,
+            deleted_at: row.try_get("deleted_at")?,
+        })
+    }
+
+    async fn find_message(
+        pool: &mut SqliteConnection,
+        message_id: &str,
+    ) -> anyhow::Result<Option<Message>> {
         let query = r#"SELECT
             id,
             chat_id,
@@ -128,37 +1540,139 @@ impl ChatServiceInterface for ChatService {
             content,
             message_type,
             message_key,
-            sent_at
+            reply_to_message_id,
+            thread_root_id,
+            sent_at,
+            edited_at,
+            deleted_at
         FROM messages
-        WHERE chat_id = ?
-        ORDER BY created_at ASC 
-        LIMIT 100"#;
+        WHERE id = ?"#;
+        let row = sqlx::query(query)
+            .bind(message_id.to_string())
+            .fetch_optional(pool)
+            .await?;
+        row.as_ref().map(Self::row_to_message).transpose()
+    }
 
-        let rows = sqlx::query(query)
-            .bind(chat_id.to_string())
+    async fn find_own_message(
+        pool: &mut SqliteConnection,
+        message_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<Message> {
+        let message = Self::find_message(pool, message_id)
+            .await?
+            .ok_or_else(|| GenericError::invalid_input(String::from("Message not found")))?;
+        if message.sender_id.to_string() != user_id {
+            return Err(GenericError::permission_denied(String::from(
+                "Only the sender can change this message",
+            )));
+        }
+        Ok(message)
+    }
+
+    async fn get_message_box(&self, message: Message) -> anyhow::Result<MessageBox> {
+        let mut message_boxes = self.load_message_boxes(vec![message], None).await?;
+        Ok(message_boxes.remove(0))
+    }
+
+    // A page of messages matching `scope`, a filter on `m` that binds `scope_id`.
+    // Returns the page oldest first and whether there is another one past it.
+    async fn get_message_page(
+        &self,
+        scope: &str,
+        scope_id: &str,
+        viewer_id: &str,
+        cursor: MessageCursor,
+        limit: i64,
+    ) -> anyhow::Result<(Vec<MessageBox>, bool)> {
+        let mut pool = self.db.get_pool().acquire().await?;
+        let (cursor_join, cursor_filter, order) = match cursor {
+            MessageCursor::Latest => ("", "", "DESC"),
+            MessageCursor::Before(_) => (
+                "JOIN messages cursor ON cursor.id = ? AND cursor.chat_id = m.chat_id",
+                "AND (m.sent_at < cursor.sent_at OR (m.sent_at = cursor.sent_at AND m.id < cursor.id))",
+                "DESC",
+            ),
+            MessageCursor::After(_) => (
+                "JOIN messages cursor ON cursor.id = ? AND cursor.chat_id = m.chat_id",
+                "AND (m.sent_at > cursor.sent_at OR (m.sent_at = cursor.sent_at AND m.id > cursor.id))",
+                "ASC",
+            ),
+        };
+        let query = format!(
+            r#"SELECT
+            m.id,
+            m.chat_id,
+            m.sender_id,
+            m.content,
+            m.message_type,
+            m.message_key,
+            m.reply_to_message_id,
+            m.thread_root_id,
+            m.sent_at,
+            m.edited_at,
+            m.deleted_at
+        FROM messages m
+        {cursor_join}
+        WHERE {scope}
+            AND NOT EXISTS (
+                SELECT 1
+                FROM hidden_messages hidden
+                WHERE hidden.message_id = m.id AND hidden.user_id = ?
+            )
+            {cursor_filter}
+        ORDER BY m.sent_at {order}, m.id {order}
+        LIMIT ?"#
+        );
+
+        let mut query = sqlx::query(&query);
+        if let MessageCursor::Before(id) | MessageCursor::After(id) = cursor {
+            query = query.bind(id.to_string());
+        }
+        // One extra row tells whether there is another page
+        let mut rows = query
+            .bind(scope_id.to_string())
+            .bind(viewer_id.to_string())
+            .bind(limit + 1)
             .fetch_all(&mut *pool)
             .await?;
 
-        let mut messages: Vec<MessageBox> = Vec::new();
+        let has_more = rows.len() as i64 > limit;
+        rows.truncate(limit.max(0) as usize);
+        if !matches!(cursor, MessageCursor::After(_)) {
+            rows.reverse();
+        }
+
+        let messages = rows
+            .iter()
+            .map(Self::row_to_message)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let messages = self.load_message_boxes(messages, Some(viewer_id)).await?;
+        Ok((messages, has_more))
+    }
+
+    // Everything shown along with the messages, thread summaries count unread replies for
+    // the viewer when there is one
+    async fn load_message_boxes(
+        &self,
+        messages: Vec<Message>,
+        viewer_id: Option<&str>,
+    ) -> anyhow::Result<Vec<MessageBox>> {
+        let mut message_boxes: Vec<MessageBox> = Vec::new();
         let mut msg_ids: Vec<String> = Vec::new();
+        let mut quoted_ids: Vec<String> = Vec::new();
 
-        for row in rows {
-            let id: Uuid = row.try_get::<String, _>("id")?.parse()?;
-            msg_ids.push(id.to_string());
-            let message = Message {
-                id,
-                chat_id: row.try_get::<String, _>("chat_id")?.parse()?,
-                sender_id: row.try_get::<String, _>("sender_id")?.parse()?,
-                content: row.try_get::<String, _>("content")?.parse()?,
-                message_type: row.try_get::<String, _>("message_type")?.parse()?,
-                message_key: row.try_get::<String, _>("message_key")?.parse()?,
-                sent_at: row.get("sent_at"),
-            };
+        for message in messages {
+            msg_ids.push(message.id.to_string());
+            if let Some(reply_to_message_id) = message.reply_to_message_id {
+                quoted_ids.push(reply_to_message_id.to_string());
+            }
 
             let recipients = Vec::new();
             let receipts = Vec::new();
-            let message_box = MessageBox(message, recipients, receipts);
-            messages.push(message_box);
+            let attachments = Vec::new();
+            let message_box = MessageBox(message, recipients, receipts, attachments, None, None);
+            message_boxes.push(message_box);
         }
 
         let msg_ids = Arc::new(msg_ids);
@@ -175,45 +1689,188 @@ impl ChatServiceInterface for ChatService {
             async move { Self::get_reactions_of_message(conn_pool, msg_ids).await }
         });
 
+        let attachment_handler = tokio::spawn({
+            let msg_ids = Arc::clone(&msg_ids);
+            let conn_pool = conn_pool.clone();
+            async move { Self::get_attachments_of_message(conn_pool, msg_ids).await }
+        });
+
+        let quote_handler = tokio::spawn({
+            let quoted_ids = Arc::new(quoted_ids);
+            let conn_pool = conn_pool.clone();
+            async move { Self::get_quotes_of_message(conn_pool, quoted_ids).await }
+        });
+
+        let thread_handler = tokio::spawn({
+            let msg_ids = Arc::clone(&msg_ids);
+            let conn_pool = conn_pool.clone();
+            let viewer_id = viewer_id.map(str::to_string);
+            async move { Self::get_threads_of_message(conn_pool, msg_ids, viewer_id).await }
+        });
+
         //let read_receipts = Self::get_recipients_of_message(&mut pool, &msg_ids).await?;
         //let reactions = Self::get_reactions_of_message(&mut pool, &msg_ids).await?;
 
-        let (read_receipts, reactions) = tokio::join!(receipt_handler, reaction_handler);
-        let read_receipts = read_receipts??;
-        let reactions = reactions??;
+        let (read_receipts, reactions, attachments, quotes, threads) = tokio::join!(
+            receipt_handler,
+            reaction_handler,
+            attachment_handler,
+            quote_handler,
+            thread_handler
+        );
+        let mut read_receipts = read_receipts??;
+        let mut reactions = reactions??;
+        let mut attachments = attachments??;
+        let quotes = quotes??;
+        let mut threads = threads??;
 
-        for message in &mut messages {
+        for message in &mut message_boxes {
             let message_id = message.0.id;
-            if reactions.contains_key(&message_id) {
-                message.2.push(reactions.get(&message_id).unwrap().clone());
+            if let Some(attachments) = attachments.remove(&message_id) {
+                message.3 = attachments;
             }
-            if read_receipts.contains_key(&message_id) {
-                message
-                    .1
-                    .push(read_receipts.get(&message_id).unwrap().clone());
+            if let Some(reactions) = reactions.remove(&message_id) {
+                message.2 = reactions;
             }
+            if let Some(receipts) = read_receipts.remove(&message_id) {
+                message.1 = receipts;
+            }
+            // Several replies can quote the same message, so the quote is cloned rather than taken
+            if let Some(reply_to_message_id) = message.0.reply_to_message_id {
+                message.4 = quotes.get(&reply_to_message_id).cloned();
+            }
+            message.5 = threads.remove(&message_id);
         }
 
-        Ok(ChatMessages {
-            chat_id: Uuid::from_str(chat_id)?,
-            chat_name: String::new(),
-            is_group: false,
-            messages,
-            chat_members: Vec::new(),
-        })
+        Ok(message_boxes)
     }
 
-    async fn send_message_to_chat(
+    // Receipts for the unread messages matching `scope` up to `message_id`, see mark_chat_read
+    async fn mark_read_up_to(
         &self,
-        chat_id: &str,
-        sender_id: &str,
-        message: &str,
-    ) -> anyhow::Result<MessageBox> {
-        let mut pool = self.db.get_pool().acquire().await?;
-        let chat_id = Uuid::from_str(chat_id)?;
-        let sender_id = Uuid::from_str(sender_id)?;
-        log::info!("Sending message to chat: {}, from sender: {}", chat_id, sender_id);
-        let message = Message::new_private_message(chat_id, sender_id, message.to_owned());
+        scope: &str,
+        scope_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<Vec<(Uuid, MessageReadReceipt)>> {
+        let mut pool = self.db.get_pool().begin().await?;
+        // Messages from before the user joined never count as unread, see get_user_chat_list
+        let query = format!(
+            r#"SELECT
+            m.id,
+            m.sender_id
+        FROM messages m
+        JOIN messages up_to ON up_to.id = ? AND up_to.chat_id = m.chat_id
+        JOIN chat_members me ON me.chat_id = m.chat_id AND me.user_id = ?
+        LEFT JOIN message_read_receipts r ON r.message_id = m.id AND r.user_id = me.user_id
+        WHERE {scope}
+            AND m.sender_id != me.user_id
+            AND m.sent_at >= me.joined_at
+            AND (m.sent_at < up_to.sent_at OR (m.sent_at = up_to.sent_at AND m.id <= up_to.id))
+            AND r.id IS NULL
+        ORDER BY m.sent_at ASC, m.id ASC"#
+        );
+        let rows = sqlx::query(&query)
+            .bind(message_id.to_string())
+            .bind(user_id.to_string())
+            .bind(scope_id.to_string())
+            .fetch_all(&mut *pool)
+            .await?;
+
+        let user_uuid = Uuid::from_str(user_id)?;
+        let read_at = chrono::Local::now().naive_local();
+        let mut receipts = Vec::with_capacity(rows.len());
+        for row in rows {
+            let receipt = MessageReadReceipt {
+                id: Uuid::new_v4(),
+                message_id: row.try_get::<String, _>("id")?.parse()?,
+                user_id: user_uuid,
+                read_at: Some(read_at),
+            };
+            let sender_id: Uuid = row.try_get::<String, _>("sender_id")?.parse()?;
+            receipts.push((sender_id, receipt));
+        }
+
+        for chunk in receipts.chunks(READ_RECEIPTS_PER_INSERT) {
+            let values = chunk.iter().map(|_| "(?, ?, ?, ?)").collect::<Vec<_>>().join(", ");
+            let query = format!(
+                r#"INSERT OR IGNORE INTO message_read_receipts (
+                    id,
+                    message_id,
+                    user_id,
+                    read_at
+                ) VALUES {}"#,
+                values
+            );
+            let mut qx = sqlx::query(&query);
+            for (_, receipt) in chunk {
+                qx = qx
+                    .bind(receipt.id.to_string())
+                    .bind(receipt.message_id.to_string())
+                    .bind(receipt.user_id.to_string())
+                    .bind(receipt.read_at);
+            }
+            qx.execute(&mut *pool).await?;
+        }
+        pool.commit().await?;
+
+        if !receipts.is_empty() {
+            info!("{} read {} messages of {}", user_id, receipts.len(), scope_id);
+        }
+        Ok(receipts)
+    }
+
+    fn row_to_chat_preview(row: &SqliteRow) -> anyhow::Result<ChatPreview> {
+        let chat_id: Uuid = row.try_get::<String, _>("chat_id")?.parse()?;
+        let is_group: bool = row.try_get("is_group")?;
+
+        let last_message = match row.try_get::<Option<String>, _>("last_message_id")? {
+            Some(id) => Some(Message {
+                id: id.parse()?,
+                chat_id,
+                sender_id: row
+                    .try_get::<String, _>("last_message_sender_id")?
+                    .parse()?,
+                content: row.try_get("last_message_content")?,
+                message_type: row.try_get("last_message_type")?,
+                message_key: row.try_get("last_message_key")?,
+                reply_to_message_id: row
+                    .try_get::<Option<String>, _>("last_message_reply_to_message_id")?
+                    .map(|id| id.parse())
+                    .transpose()?,
+                // The chat list only looks at the main timeline
+                thread_root_id: None,
+                sent_at: row.try_get("last_message_sent_at")?,
+                edited_at: row.try_get("last_message_edited_at")?,
+                deleted_at: row.try_get("last_message_deleted_at")?,
+            }),
+            None => None,
+        };
+
+        let counterpart_username: Option<String> = row.try_get("username")?;
+        let name = match counterpart_username {
+            Some(_) if !is_group => ChatService::decide_name(row)?,
+            _ => row.try_get("chat_name")?,
+        };
+
+        let last_activity_at = last_message
+            .as_ref()
+            .and_then(|message| message.sent_at)
+            .or(row.try_get("chat_created_at")?);
+
+        Ok(ChatPreview {
+            chat_id,
+            name,
+            is_group,
+            unread_message_count: row.try_get::<i64, _>("unread_message_count")? as i32,
+            last_message,
+            counterpart_username,
+            profile_picture: row.try_get("profile_picture")?,
+            last_activity_at,
+        })
+    }
+
+    async fn insert_message(pool: &mut SqliteConnection, message: &Message) -> anyhow::Result<()> {
         let query = r#"INSERT INTO messages (
             id,
             chat_id,
@@ -221,6 +1878,8 @@ impl ChatServiceInterface for ChatService {
             content,
             message_type,
             message_key,
+            reply_to_message_id,
+            thread_root_id,
             sent_at
         ) VALUES (
             ?,
@@ -229,6 +1888,8 @@ impl ChatServiceInterface for ChatService {
             ?,
             ?,
             ?,
+            ?,
+            ?,
             ?
         )"#;
 
@@ -239,46 +1900,159 @@ impl ChatServiceInterface for ChatService {
             .bind(message.content.clone())
             .bind(message.message_type.clone())
             .bind(message.message_key.clone())
+            .bind(message.reply_to_message_id.map(|id| id.to_string()))
+            .bind(message.thread_root_id.map(|id| id.to_string()))
             .bind(message.sent_at)
-            .execute(&mut *pool)
+            .execute(pool)
             .await?;
+        Ok(())
+    }
 
-        // TODO: implement reaction and read receipt
-        let recipients = Vec::new();
-        let reactions = Vec::new();
-        Ok(MessageBox(message, recipients, reactions))
+    async fn insert_reaction(
+        pool: &mut SqliteConnection,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<bool> {
+        let reaction =
+            MessageReaction::new(Uuid::from_str(message_id)?, Uuid::from_str(user_id)?, reaction);
+        let query = r#"INSERT OR IGNORE INTO message_reactions (
+            id,
+            message_id,
+            user_id,
+            reaction,
+            reacted_at
+        ) VALUES (
+            ?,
+            ?,
+            ?,
+            ?,
+            ?
+        )"#;
+        let result = sqlx::query(query)
+            .bind(reaction.id.to_string())
+            .bind(reaction.message_id.to_string())
+            .bind(reaction.user_id.to_string())
+            .bind(reaction.reaction.clone())
+            .bind(reaction.reacted_at)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_reaction(
+        pool: &mut SqliteConnection,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<bool> {
+        let query =
+            r#"DELETE FROM message_reactions WHERE message_id = ? AND user_id = ? AND reaction = ?"#;
+        let result = sqlx::query(query)
+            .bind(message_id.to_string())
+            .bind(user_id.to_string())
+            .bind(reaction.to_string())
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    fn row_to_chat_invite(row: &SqliteRow) -> anyhow::Result<ChatInvite> {
+        Ok(ChatInvite {
+            id: row.try_get::<String, _>("id")?.parse()?,
+            chat_id: row.try_get::<String, _>("chat_id")?.parse()?,
+            created_by: row.try_get::<String, _>("created_by")?.parse()?,
+            expires_at: row.try_get("expires_at")?,
+            max_uses: row.try_get("max_uses")?,
+            use_count: row.try_get("use_count")?,
+            revoked_at: row.try_get("revoked_at")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+
+    async fn get_member_role(
+        pool: &mut SqliteConnection,
+        chat_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<Option<ChatRole>> {
+        let query = r#"SELECT role FROM chat_members WHERE chat_id = ? AND user_id = ?"#;
+        let row = sqlx::query(query)
+            .bind(chat_id.to_string())
+            .bind(user_id.to_string())
+            .fetch_optional(pool)
+            .await?;
+        match row {
+            Some(row) => Ok(Some(row.try_get::<String, _>("role")?.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn require_role(
+        pool: &mut SqliteConnection,
+        chat_id: &str,
+        user_id: &str,
+        role: ChatRole,
+    ) -> anyhow::Result<ChatRole> {
+        match Self::get_member_role(pool, chat_id, user_id).await? {
+            Some(actual) if actual >= role => Ok(actual),
+            _ => Err(GenericError::permission_denied(format!(
+                "Only the group {} can do that",
+                match role {
+                    ChatRole::Owner => "owner",
+                    _ => "admins",
+                }
+            ))),
+        }
+    }
+
+    async fn set_member_role(
+        pool: &mut SqliteConnection,
+        chat_id: &str,
+        user_id: &str,
+        role: ChatRole,
+    ) -> anyhow::Result<()> {
+        let query = r#"UPDATE chat_members SET role = ? WHERE chat_id = ? AND user_id = ?"#;
+        sqlx::query(query)
+            .bind(role.as_str())
+            .bind(chat_id.to_string())
+            .bind(user_id.to_string())
+            .execute(pool)
+            .await?;
+        Ok(())
     }
-}
 
-impl ChatService {
     async fn create_chat_member(
         user1_id: &str,
         pool: &mut SqliteConnection,
         chat: &Chat,
-    ) -> anyhow::Result<()> {
+        role: ChatRole,
+    ) -> anyhow::Result<ChatMember> {
         let query = r#"INSERT INTO chat_members (
             id,
             chat_id,
             user_id,
+            role,
             joined_at
         ) VALUES (
             ?,
             ?,
             ?,
+            ?,
             ?
         )"#;
 
         let user1_id = Uuid::from_str(user1_id)?;
-        let member1 = ChatMember::new(chat.id, user1_id);
+        let member1 = ChatMember::new(chat.id, user1_id).with_role(role);
         sqlx::query(query)
             .bind(member1.id.to_string())
             .bind(member1.chat_id.to_string())
             .bind(member1.user_id.to_string())
+            .bind(member1.role.as_str())
             .bind(member1.joined_at)
             .execute(pool)
             .await?;
 
-        Ok(())
+        Ok(member1)
     }
 
     async fn create_chat(
@@ -287,10 +2061,16 @@ impl ChatService {
         pool: &mut SqliteConnection,
     ) -> Result<Chat, Error> {
         let chat = Chat::from_user1and2(user1_id, user2_id);
+        Self::insert_chat(&chat, pool).await?;
+        Ok(chat)
+    }
+
+    async fn insert_chat(chat: &Chat, pool: &mut SqliteConnection) -> Result<(), Error> {
         let query = r#"INSERT INTO chats (
             id,
             name,
             is_group,
+            avatar,
             created_at,
             updated_at
         ) VALUES (
@@ -298,27 +2078,41 @@ impl ChatService {
             ?,
             ?,
             ?,
+            ?,
             ?
         )"#;
         sqlx::query(query)
             .bind(chat.id.to_string())
             .bind(chat.name.clone())
             .bind(chat.is_group)
+            .bind(chat.avatar.clone())
             .bind(chat.created_at)
             .bind(chat.updated_at)
             .execute(pool)
             .await?;
-        Ok(chat)
+        Ok(())
     }
 
     async fn get_recipients_of_message(
         pool: Arc<Pool<Sqlite>>,
         msg_ids: Arc<Vec<String>>,
-    ) -> anyhow::Result<HashMap<Uuid, (String, MessageReadReceipt)>> {
+    ) -> anyhow::Result<HashMap<Uuid, Vec<(String, MessageReadReceipt)>>> {
         let mut pool = pool.acquire().await?;
         let placeholders = msg_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
         let query = format!(
-            r#"SELECT id, chat_id, sender_id, content, message_type, message_key, sent_at FROM messages WHERE id in ({}) "#,
+            r#"SELECT
+                    message_read_receipts.id as id,
+                    message_read_receipts.message_id as message_id,
+                    message_read_receipts.user_id as user_id,
+                    message_read_receipts.read_at as read_at,
+                    user_details.first_name as first_name,
+                    user_details.last_name as last_name,
+                    users.username as username
+             FROM message_read_receipts
+             JOIN users ON message_read_receipts.user_id = users.id
+             LEFT JOIN user_details ON message_read_receipts.user_id = user_details.user_id
+             WHERE message_read_receipts.message_id in ({})
+             ORDER BY message_read_receipts.read_at ASC"#,
             placeholders
         );
 
@@ -328,7 +2122,7 @@ impl ChatService {
         }
 
         let rows = qx.fetch_all(&mut *pool).await?;
-        let mut recipients = HashMap::new();
+        let mut recipients: HashMap<Uuid, Vec<_>> = HashMap::new();
         for row in rows {
             let message_id = row.try_get::<String, _>("message_id")?.parse()?;
             let receipt = (
@@ -340,7 +2134,7 @@ impl ChatService {
                     read_at: row.get("read_at"),
                 },
             );
-            recipients.insert(message_id, receipt);
+            recipients.entry(message_id).or_default().push(receipt);
         }
 
         Ok(recipients)
@@ -348,23 +2142,24 @@ impl ChatService {
     async fn get_reactions_of_message(
         pool: Arc<Pool<Sqlite>>,
         msg_ids: Arc<Vec<String>>,
-    ) -> anyhow::Result<HashMap<Uuid, (String, MessageReaction)>> {
+    ) -> anyhow::Result<HashMap<Uuid, Vec<(String, MessageReaction)>>> {
         let mut pool = pool.acquire().await?;
         let placeholders = msg_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
         let query = format!(
-            r#"SELECT 
-                    id,
-                    message_id,
-                    user_id,
-                    reaction,
-                    reacted_at,
+            r#"SELECT
+                    message_reactions.id as id,
+                    message_reactions.message_id as message_id,
+                    message_reactions.user_id as user_id,
+                    message_reactions.reaction as reaction,
+                    message_reactions.reacted_at as reacted_at,
                     user_details.first_name as first_name,
                     user_details.last_name as last_name,
                     users.username as username
-             FROM message_read_receipts 
-             LEFT JOIN user_details ON message_read_receipts.user_id = user_details.id
-             LEFT JOUN users ON message_read_receipts.user_id = users.id
-             WHERE id in ({}) "#,
+             FROM message_reactions
+             JOIN users ON message_reactions.user_id = users.id
+             LEFT JOIN user_details ON message_reactions.user_id = user_details.user_id
+             WHERE message_reactions.message_id in ({})
+             ORDER BY message_reactions.reacted_at ASC"#,
             placeholders
         );
 
@@ -373,37 +2168,202 @@ impl ChatService {
             qx = qx.bind(msg_id);
         }
         let rows = qx.fetch_all(&mut *pool).await?;
-        let mut recipients = HashMap::new();
+        let mut reactions: HashMap<Uuid, Vec<_>> = HashMap::new();
 
         for row in rows {
             let message_id = row.try_get::<String, _>("message_id")?.parse()?;
             let name = ChatService::decide_name(&row)?;
-            recipients.insert(
-                message_id,
-                (
-                    name,
-                    MessageReaction {
-                        id: row.try_get::<String, _>("id")?.parse()?,
-                        message_id,
-                        user_id: row.try_get::<String, _>("user_id")?.parse()?,
-                        reaction: row.try_get::<String, _>("reaction")?.parse()?,
-                        reacted_at: row.get("reacted_at"),
-                    },
-                ),
-            );
+            reactions.entry(message_id).or_default().push((
+                name,
+                MessageReaction {
+                    id: row.try_get::<String, _>("id")?.parse()?,
+                    message_id,
+                    user_id: row.try_get::<String, _>("user_id")?.parse()?,
+                    reaction: row.try_get("reaction")?,
+                    reacted_at: row.get("reacted_at"),
+                },
+            ));
         }
 
-        Ok(recipients)
+        Ok(reactions)
     }
 
-    fn decide_name(row: &SqliteRow) -> Result<String, anyhow::Error> {
-        let username = row.try_get::<String, _>("username")?.parse()?;
-        let first_name: Result<String, _> = row.try_get::<String, _>("first_name")?.parse();
-        let last_name: Result<String, _> = row.try_get::<String, _>("last_name")?.parse();
-        let mut name = username;
-        if first_name.is_ok() && last_name.is_ok() {
-            name = format!("{} {}", first_name.unwrap(), last_name.unwrap());
+    async fn get_attachments_of_message(
+        pool: Arc<Pool<Sqlite>>,
+        msg_ids: Arc<Vec<String>>,
+    ) -> anyhow::Result<HashMap<Uuid, Vec<Attachment>>> {
+        let mut pool = pool.acquire().await?;
+        let placeholders = msg_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+        let query = format!(
+            r#"SELECT
+                    id,
+                    message_id,
+                    file_url,
+                    file_name,
+                    file_type,
+                    file_size,
+                    thumbnail_url,
+                    uploaded_at
+             FROM attachments
+             WHERE message_id in ({})
+             ORDER BY uploaded_at ASC, id ASC"#,
+            placeholders
+        );
+
+        let mut qx = sqlx::query(&query);
+        for msg_id in msg_ids.iter() {
+            qx = qx.bind(msg_id);
+        }
+        let rows = qx.fetch_all(&mut *pool).await?;
+        let mut attachments: HashMap<Uuid, Vec<_>> = HashMap::new();
+
+        for row in rows {
+            let attachment = Self::row_to_attachment(&row)?;
+            attachments
+                .entry(attachment.message_id)
+                .or_default()
+                .push(attachment);
+        }
+
+        Ok(attachments)
+    }
+
+    // Keyed by the id of the quoted message, not by the replies quoting it
+    async fn get_quotes_of_message(
+        pool: Arc<Pool<Sqlite>>,
+        msg_ids: Arc<Vec<String>>,
+    ) -> anyhow::Result<HashMap<Uuid, QuotedMessage>> {
+        let mut pool = pool.acquire().await?;
+        let placeholders = msg_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+        let query = format!(
+            r#"SELECT
+                    m.id as id,
+                    m.chat_id as chat_id,
+                    m.sender_id as sender_id,
+                    m.content as content,
+                    m.message_type as message_type,
+                    m.message_key as message_key,
+                    m.reply_to_message_id as reply_to_message_id,
+                    m.thread_root_id as thread_root_id,
+                    m.sent_at as sent_at,
+                    m.edited_at as edited_at,
+                    m.deleted_at as deleted_at,
+                    user_details.first_name as first_name,
+                    user_details.last_name as last_name,
+                    users.username as username
+             FROM messages m
+             JOIN users ON m.sender_id = users.id
+             LEFT JOIN user_details ON m.sender_id = user_details.user_id
+             WHERE m.id in ({})"#,
+            placeholders
+        );
+
+        let mut qx = sqlx::query(&query);
+        for msg_id in msg_ids.iter() {
+            qx = qx.bind(msg_id);
+        }
+        let rows = qx.fetch_all(&mut *pool).await?;
+        let mut quotes = HashMap::new();
+
+        for row in rows {
+            let message = Self::row_to_message(&row)?;
+            let quote = QuotedMessage::new(&message, ChatService::decide_name(&row)?);
+            quotes.insert(message.id, quote);
+        }
+
+        Ok(quotes)
+    }
+
+    // Keyed by the root message, roots without replies are left out.
+    // Replies the viewer hid are not counted, just like in the thread itself
+    async fn get_threads_of_message(
+        pool: Arc<Pool<Sqlite>>,
+        msg_ids: Arc<Vec<String>>,
+        viewer_id: Option<String>,
+    ) -> anyhow::Result<HashMap<Uuid, ThreadSummary>> {
+        let mut pool = pool.acquire().await?;
+        let placeholders = msg_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+        // Without a viewer `me` stays NULL, so nothing is unread
+        let query = format!(
+            r#"SELECT
+                    r.thread_root_id as thread_root_id,
+                    COUNT(1) as reply_count,
+                    MAX(r.sent_at) as last_reply_at,
+                    COALESCE(SUM(
+                        r.sender_id != me.user_id
+                        AND r.sent_at >= me.joined_at
+                        AND r.deleted_at IS NULL
+                        AND receipt.id IS NULL
+                    ), 0) as unread_count
+             FROM messages r
+             LEFT JOIN chat_members me ON me.chat_id = r.chat_id AND me.user_id = ?
+             LEFT JOIN message_read_receipts receipt
+                ON receipt.message_id = r.id AND receipt.user_id = me.user_id
+             LEFT JOIN hidden_messages hidden
+                ON hidden.message_id = r.id AND hidden.user_id = me.user_id
+             WHERE r.thread_root_id in ({})
+                AND hidden.message_id IS NULL
+             GROUP BY r.thread_root_id"#,
+            placeholders
+        );
+
+        let mut qx = sqlx::query(&query).bind(viewer_id);
+        for msg_id in msg_ids.iter() {
+            qx = qx.bind(msg_id);
+        }
+        let rows = qx.fetch_all(&mut *pool).await?;
+        let mut threads = HashMap::new();
+
+        for row in rows {
+            let root_message_id = row.try_get::<String, _>("thread_root_id")?.parse()?;
+            let summary = ThreadSummary {
+                reply_count: row.try_get("reply_count")?,
+                last_reply_at: row.try_get("last_reply_at")?,
+                unread_count: row.try_get("unread_count")?,
+            };
+            threads.insert(root_message_id, summary);
         }
+
+        Ok(threads)
+    }
+
+    fn row_to_attachment(row: &SqliteRow) -> anyhow::Result<Attachment> {
+        Ok(Attachment {
+            id: row.try_get::<String, _>("id")?.parse()?,
+            message_id: row.try_get::<String, _>("message_id")?.parse()?,
+            file_url: row.try_get("file_url")?,
+            file_name: row.try_get("file_name")?,
+            file_type: row.try_get("file_type")?,
+            file_size: row.try_get("file_size")?,
+            thumbnail_url: row.try_get("thumbnail_url")?,
+            uploaded_at: row.try_get("uploaded_at")?,
+        })
+    }
+
+    fn row_to_search_hit(row: &SqliteRow) -> anyhow::Result<MessageSearchHit> {
+        let message = Self::row_to_message(row)?;
+        // Filter-only searches have nothing to highlight, they show the start of the message
+        let snippet = match row.try_get::<Option<String>, _>("snippet")? {
+            Some(snippet) => snippet,
+            None => message.snippet(),
+        };
+        Ok(MessageSearchHit {
+            sender_name: Self::decide_name(row)?,
+            chat_name: row.try_get("chat_name")?,
+            is_group: row.try_get("is_group")?,
+            snippet,
+            message,
+        })
+    }
+
+    fn decide_name(row: &SqliteRow) -> Result<String, anyhow::Error> {
+        let username = row.try_get::<String, _>("username")?;
+        let first_name = row.try_get::<Option<String>, _>("first_name")?;
+        let last_name = row.try_get::<Option<String>, _>("last_name")?;
+        let name = match (first_name, last_name) {
+            (Some(first_name), Some(last_name)) => format!("{} {}", first_name, last_name),
+            _ => username,
+        };
         Ok(name)
     }
 }
@@ -1,3 +1,4 @@
+use commons::generic_errors::GenericError;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -5,6 +6,7 @@ pub struct Chat {
     pub id: Uuid,
     pub name: String,
     pub is_group: bool,
+    pub avatar: Option<String>,
     pub created_at: Option<chrono::NaiveDateTime>,
     pub updated_at: Option<chrono::NaiveDateTime>,
 }
@@ -17,7 +19,14 @@ impl Chat {
             format!("{}_{}", second_name, first_name),
             format!("{}_{}", first_name, second_name),
         ];
-        return all_names;
+        all_names
+    }
+    pub fn new_group(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            is_group: true,
+            ..Chat::default()
+        }
     }
     pub fn from_user1and2(user_1_id: &str, user_2_id: &str) -> Self {
         let chat = Chat::default();
@@ -25,6 +34,7 @@ impl Chat {
             id: chat.id,
             name: format!("{}_{}", user_1_id, user_2_id),
             is_group: chat.is_group,
+            avatar: chat.avatar,
             created_at: chat.created_at,
             updated_at: chat.updated_at,
         }
@@ -37,6 +47,7 @@ impl Default for Chat {
             id: Uuid::new_v4(),
             name: String::new(),
             is_group: false,
+            avatar: None,
             created_at: Option::from(chrono::Local::now().naive_local()),
             updated_at: Option::from(chrono::Local::now().naive_local()),
         }
@@ -48,15 +59,87 @@ pub struct MessageBox(
     pub Message,
     pub Vec<(String, MessageReadReceipt)>,
     pub Vec<(String, MessageReaction)>,
+    pub Vec<Attachment>,
+    // The message this one replies to
+    pub Option<QuotedMessage>,
+    // Replies in the thread under this message, None until the first one
+    pub Option<ThreadSummary>,
 );
 
+impl MessageBox {
+    pub fn reaction_summaries(&self) -> Vec<ReactionSummary> {
+        ReactionSummary::summarize(&self.2)
+    }
+}
+
+/// Everyone who reacted to a message with the same emoji.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReactionSummary {
+    pub reaction: String,
+    pub count: usize,
+    pub user_ids: Vec<Uuid>,
+    pub names: Vec<String>,
+}
+
+impl ReactionSummary {
+    /// Groups reactions per emoji, in the order each emoji was first used.
+    pub fn summarize(reactions: &[(String, MessageReaction)]) -> Vec<ReactionSummary> {
+        let mut reactions: Vec<&(String, MessageReaction)> = reactions.iter().collect();
+        reactions.sort_by_key(|(_, reaction)| reaction.reacted_at);
+
+        let mut summaries: Vec<ReactionSummary> = Vec::new();
+        for (name, reaction) in reactions {
+            let index = match summaries
+                .iter()
+                .position(|summary| summary.reaction == reaction.reaction)
+            {
+                Some(index) => index,
+                None => {
+                    summaries.push(ReactionSummary {
+                        reaction: reaction.reaction.clone(),
+                        count: 0,
+                        user_ids: Vec::new(),
+                        names: Vec::new(),
+                    });
+                    summaries.len() - 1
+                }
+            };
+            let summary = &mut summaries[index];
+            summary.count += 1;
+            summary.user_ids.push(reaction.user_id);
+            summary.names.push(name.clone());
+        }
+        summaries
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChatMessages {
     pub chat_id: Uuid,
     pub chat_name: String,
     pub is_group: bool,
+    // Always oldest first, whatever the direction of the cursor
     pub messages: Vec<MessageBox>,
     pub chat_members: Vec<ChatMember>,
+    // Whether more messages exist past this page in the direction of the cursor
+    pub has_more: bool,
+}
+
+/// A page of replies in a thread, together with the message they hang under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadMessages {
+    pub root: MessageBox,
+    // Always oldest first, like `ChatMessages`
+    pub messages: Vec<MessageBox>,
+    pub has_more: bool,
+}
+
+/// Position in a chat history, keyed on the `(sent_at, id)` of an already loaded message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageCursor {
+    Latest,
+    Before(Uuid),
+    After(Uuid),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -67,7 +150,13 @@ pub struct Message {
     pub content: String,
     pub message_type: String,
     pub message_key: String,
+    pub reply_to_message_id: Option<Uuid>,
+    // Set for thread replies, which stay out of the main timeline
+    pub thread_root_id: Option<Uuid>,
     pub sent_at: Option<chrono::NaiveDateTime>,
+    pub edited_at: Option<chrono::NaiveDateTime>,
+    // Set once the sender deleted it for everyone, the row stays behind as a tombstone
+    pub deleted_at: Option<chrono::NaiveDateTime>,
 }
 
 impl Message {
@@ -85,7 +174,11 @@ impl Message {
             content,
             message_type,
             message_key,
+            reply_to_message_id: None,
+            thread_root_id: None,
             sent_at: Option::from(chrono::Local::now().naive_local()),
+            edited_at: None,
+            deleted_at: None,
         }
     }
 
@@ -98,6 +191,248 @@ impl Message {
             "dummy_key".to_string(),
         )
     }
+
+    /// The content is an optional caption, the files themselves are `Attachment` rows.
+    pub fn new_attachment_message(chat_id: Uuid, sender_id: Uuid, caption: String) -> Self {
+        Self::new(
+            chat_id,
+            sender_id,
+            caption,
+            ATTACHMENT_MESSAGE_TYPE.to_string(),
+            "dummy_key".to_string(),
+        )
+    }
+
+    pub fn with_reply_to(self, message_id: Uuid) -> Self {
+        Self {
+            reply_to_message_id: Some(message_id),
+            ..self
+        }
+    }
+
+    pub fn in_thread(self, root_message_id: Uuid) -> Self {
+        Self {
+            thread_root_id: Some(root_message_id),
+            ..self
+        }
+    }
+
+    pub fn is_thread_reply(&self) -> bool {
+        self.thread_root_id.is_some()
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        self.message_type == ATTACHMENT_MESSAGE_TYPE
+    }
+
+    pub fn is_edited(&self) -> bool {
+        self.edited_at.is_some()
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// The start of the content, for quotes and previews.
+    pub fn snippet(&self) -> String {
+        let mut snippet: String = self.content.chars().take(SNIPPET_LENGTH).collect();
+        if snippet.len() < self.content.len() {
+            snippet.push('…');
+        }
+        snippet
+    }
+}
+
+pub const ATTACHMENT_MESSAGE_TYPE: &str = "attachment";
+const SNIPPET_LENGTH: usize = 100;
+
+/// What a reply shows of the message it answers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotedMessage {
+    pub message_id: Uuid,
+    pub sender_id: Uuid,
+    pub sender_name: String,
+    // The start of the content, empty for deleted messages and attachments without a caption
+    pub snippet: String,
+    pub is_attachment: bool,
+    pub is_deleted: bool,
+}
+
+impl QuotedMessage {
+    pub fn new(message: &Message, sender_name: String) -> Self {
+        Self {
+            message_id: message.id,
+            sender_id: message.sender_id,
+            sender_name,
+            snippet: message.snippet(),
+            is_attachment: message.is_attachment(),
+            is_deleted: message.is_deleted(),
+        }
+    }
+}
+
+/// How busy the thread under a message is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadSummary {
+    pub reply_count: i64,
+    pub last_reply_at: Option<chrono::NaiveDateTime>,
+    // Replies the viewer has not read yet, always 0 when loaded without a viewer
+    pub unread_count: i64,
+}
+
+/// What the user typed in the message search box, free text plus filters such as
+/// `from:alice in:"Team Rocket" before:2025-03-01 after:2025-01-31 has:attachment`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageSearch {
+    // Free text as an FTS5 query, None when only filters were given
+    pub text: Option<String>,
+    pub from_username: Option<String>,
+    // A group name, or the username of the other member of a private chat
+    pub in_chat: Option<String>,
+    // Both exclude the day itself
+    pub before: Option<chrono::NaiveDate>,
+    pub after: Option<chrono::NaiveDate>,
+    pub has_attachment: bool,
+}
+
+impl MessageSearch {
+    pub fn parse(query: &str) -> anyhow::Result<Self> {
+        let mut search = Self::default();
+        let mut terms: Vec<String> = Vec::new();
+        for (token, quoted) in Self::tokenize(query) {
+            let filter = token.split_once(':').filter(|(_, value)| !value.is_empty());
+            match filter {
+                Some(("from", value)) => {
+                    search.from_username = Some(value.trim_start_matches('@').to_string())
+                }
+                Some(("in", value)) => search.in_chat = Some(value.to_string()),
+                Some(("before", value)) => search.before = Some(Self::parse_date(value)?),
+                Some(("after", value)) => search.after = Some(Self::parse_date(value)?),
+                Some(("has", "attachment")) => search.has_attachment = true,
+                Some(("has", _)) => {
+                    return Err(GenericError::invalid_input(String::from(
+                        "Only has:attachment is supported",
+                    )))
+                }
+                // Every term has to match, unquoted ones also match the start of a word
+                _ => {
+                    let phrase = format!("\"{}\"", token.replace('"', "\"\""));
+                    terms.push(if quoted { phrase } else { phrase + "*" });
+                }
+            }
+        }
+        if !terms.is_empty() {
+            search.text = Some(terms.join(" "));
+        }
+        if search == Self::default() {
+            return Err(GenericError::invalid_input(String::from(
+                "Type something to search for",
+            )));
+        }
+        Ok(search)
+    }
+
+    // Splits on whitespace outside of double quotes, which are dropped
+    fn tokenize(query: &str) -> Vec<(String, bool)> {
+        let mut tokens = Vec::new();
+        let mut token = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        for c in query.chars() {
+            match c {
+                '"' => {
+                    in_quotes = !in_quotes;
+                    quoted = true;
+                }
+                c if c.is_whitespace() && !in_quotes => {
+                    if !token.is_empty() {
+                        tokens.push((std::mem::take(&mut token), quoted));
+                    }
+                    quoted = false;
+                }
+                c => token.push(c),
+            }
+        }
+        if !token.is_empty() {
+            tokens.push((token, quoted));
+        }
+        tokens
+    }
+
+    fn parse_date(value: &str) -> anyhow::Result<chrono::NaiveDate> {
+        chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+            GenericError::invalid_input(String::from("Dates are written as YYYY-MM-DD"))
+        })
+    }
+}
+
+// Wrapped around the matched words of a search snippet
+pub const HIGHLIGHT_START: char = '\u{2}';
+pub const HIGHLIGHT_END: char = '\u{3}';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageSearchHit {
+    pub message: Message,
+    pub sender_name: String,
+    // The group name, or the name of the other member of a private chat
+    pub chat_name: String,
+    pub is_group: bool,
+    // Part of the content around the matches, see `highlights`
+    pub snippet: String,
+}
+
+impl MessageSearchHit {
+    /// The snippet in pieces, each flagged with whether it matched the search.
+    pub fn highlights(&self) -> Vec<(&str, bool)> {
+        let mut pieces = Vec::new();
+        for (i, piece) in self
+            .snippet
+            .split([HIGHLIGHT_START, HIGHLIGHT_END])
+            .enumerate()
+        {
+            if !piece.is_empty() {
+                pieces.push((piece, i % 2 == 1));
+            }
+        }
+        pieces
+    }
+}
+
+/// A page of search hits, newest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageSearchResults {
+    pub hits: Vec<MessageSearchHit>,
+    // Whether older hits exist past this page
+    pub has_more: bool,
+}
+
+/// The content a message had before one of its edits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageRevision {
+    pub id: Uuid,
+    pub message_id: Uuid,
+    pub content: String,
+    // When this content was replaced by the next one
+    pub edited_at: Option<chrono::NaiveDateTime>,
+}
+
+impl MessageRevision {
+    pub fn new(message_id: Uuid, content: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            message_id,
+            content,
+            edited_at: Option::from(chrono::Local::now().naive_local()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteScope {
+    /// Hides the message from the sender only, everyone else still sees it.
+    ForMe,
+    /// Replaces the message with a tombstone for every member.
+    ForEveryone,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -105,6 +440,7 @@ pub struct ChatMember {
     pub id: Uuid,
     pub chat_id: Uuid,
     pub user_id: Uuid,
+    pub role: ChatRole,
     pub joined_at: Option<chrono::NaiveDateTime>,
 }
 
@@ -114,9 +450,45 @@ impl ChatMember {
             id: Uuid::new_v4(),
             chat_id,
             user_id,
+            role: ChatRole::Member,
             joined_at: Option::from(chrono::Local::now().naive_local()),
         }
     }
+
+    pub fn with_role(self, role: ChatRole) -> Self {
+        Self { role, ..self }
+    }
+}
+
+/// Ordered by privilege, so roles can be compared with `>=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChatRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+impl ChatRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatRole::Member => "member",
+            ChatRole::Admin => "admin",
+            ChatRole::Owner => "owner",
+        }
+    }
+}
+
+impl std::str::FromStr for ChatRole {
+    type Err = anyhow::Error;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "member" => Ok(ChatRole::Member),
+            "admin" => Ok(ChatRole::Admin),
+            "owner" => Ok(ChatRole::Owner),
+            _ => Err(anyhow::anyhow!("Unknown chat role: {}", role)),
+        }
+    }
 }
 
 impl Default for ChatMember {
@@ -125,11 +497,66 @@ impl Default for ChatMember {
             id: Uuid::new_v4(),
             chat_id: Default::default(),
             user_id: Default::default(),
+            role: ChatRole::Member,
             joined_at: Option::from(chrono::Local::now().naive_local()),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMemberProfile {
+    pub member: ChatMember,
+    pub username: String,
+    pub name: String,
+    pub profile_picture: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatInvite {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub created_by: Uuid,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ChatInvite {
+    pub fn new(
+        chat_id: Uuid,
+        created_by: Uuid,
+        expires_at: Option<chrono::NaiveDateTime>,
+        max_uses: Option<i32>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            chat_id,
+            created_by,
+            expires_at,
+            max_uses,
+            use_count: 0,
+            revoked_at: None,
+            created_at: Option::from(chrono::Local::now().naive_local()),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= chrono::Local::now().naive_local())
+    }
+
+    pub fn is_used_up(&self) -> bool {
+        self.max_uses
+            .is_some_and(|max_uses| self.use_count >= max_uses)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && !self.is_expired() && !self.is_used_up()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MessageReadReceipt {
     pub id: Uuid,
@@ -146,20 +573,66 @@ pub struct MessageReaction {
     pub reaction: String,
     pub reacted_at: Option<chrono::NaiveDateTime>,
 }
+
+impl MessageReaction {
+    pub fn new(message_id: Uuid, user_id: Uuid, reaction: &str) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            message_id,
+            user_id,
+            reaction: reaction.to_string(),
+            reacted_at: Option::from(chrono::Local::now().naive_local()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Attachment {
     pub id: Uuid,
     pub message_id: Uuid,
     pub file_url: String,
+    pub file_name: String,
     pub file_type: String,
     pub file_size: i32,
+    // A smaller copy shown in the chat, only kept for images
+    pub thumbnail_url: Option<String>,
     pub uploaded_at: Option<chrono::NaiveDateTime>,
 }
 
+impl Attachment {
+    pub fn new(
+        message_id: Uuid,
+        file_url: String,
+        file_name: String,
+        file_type: String,
+        file_size: i32,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            message_id,
+            file_url,
+            file_name,
+            file_type,
+            file_size,
+            thumbnail_url: None,
+            uploaded_at: Option::from(chrono::Local::now().naive_local()),
+        }
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.file_type.starts_with("image/")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChatPreview {
     pub chat_id: Uuid,
     pub name: String,
     pub is_group: bool,
     pub unread_message_count: i32,
     pub last_message: Option<Message>,
+    // Only set for private chats, taken from the other member
+    pub counterpart_username: Option<String>,
+    pub profile_picture: Option<String>,
+    pub last_activity_at: Option<chrono::NaiveDateTime>,
 }
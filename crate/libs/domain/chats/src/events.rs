@@ -0,0 +1,181 @@
+use crate::entity::{
+    ChatMember, ChatMemberProfile, Message, MessageBox, MessageReaction, MessageReadReceipt,
+    ThreadSummary,
+};
+use log::debug;
+use shaku::{Component, Interface};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatEventKind {
+    NewMessage(MessageBox),
+    /// A reply in a thread, with the summary of the thread after it.
+    NewThreadReply(MessageBox, ThreadSummary),
+    /// Receipts of one reader, paired with the reader's display name like in `MessageBox`.
+    MessagesRead(Vec<(String, MessageReadReceipt)>),
+    /// Every reaction left on the message after the change.
+    ReactionsChanged(Uuid, Vec<(String, MessageReaction)>),
+    /// The message after the edit, rendered again in place of the old one.
+    MessageEdited(MessageBox),
+    /// The tombstone left behind by deleting a message for everyone.
+    MessageDeleted(Message),
+    /// A message the user deleted for themselves, only their own tabs drop it.
+    MessageHidden(Uuid),
+    /// The members of a group after someone joined, left or got another role.
+    MembersChanged(Vec<ChatMemberProfile>),
+}
+
+impl ChatEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatEventKind::NewMessage(_) => "new_message",
+            ChatEventKind::NewThreadReply(_, _) => "new_thread_reply",
+            ChatEventKind::MessagesRead(_) => "messages_read",
+            ChatEventKind::ReactionsChanged(_, _) => "reactions_changed",
+            ChatEventKind::MessageEdited(_) => "message_edited",
+            ChatEventKind::MessageDeleted(_) => "message_deleted",
+            ChatEventKind::MessageHidden(_) => "message_hidden",
+            ChatEventKind::MembersChanged(_) => "members_changed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatEvent {
+    pub chat_id: Uuid,
+    pub recipients: Vec<Uuid>,
+    pub kind: ChatEventKind,
+}
+
+impl ChatEvent {
+    pub fn new(chat_id: Uuid, members: &[ChatMember], kind: ChatEventKind) -> Self {
+        Self {
+            chat_id,
+            recipients: members.iter().map(|member| member.user_id).collect(),
+            kind,
+        }
+    }
+
+    pub fn new_message(members: &[ChatMember], message: MessageBox) -> Self {
+        Self::new(message.0.chat_id, members, ChatEventKind::NewMessage(message))
+    }
+
+    pub fn new_thread_reply(
+        members: &[ChatMember],
+        message: MessageBox,
+        thread: ThreadSummary,
+    ) -> Self {
+        Self::new(
+            message.0.chat_id,
+            members,
+            ChatEventKind::NewThreadReply(message, thread),
+        )
+    }
+
+    /// Only the senders of the messages care about them being read.
+    pub fn messages_read(
+        chat_id: Uuid,
+        senders: Vec<Uuid>,
+        receipts: Vec<(String, MessageReadReceipt)>,
+    ) -> Self {
+        Self {
+            chat_id,
+            recipients: senders,
+            kind: ChatEventKind::MessagesRead(receipts),
+        }
+    }
+
+    pub fn reactions_changed(
+        chat_id: Uuid,
+        members: &[ChatMember],
+        message_id: Uuid,
+        reactions: Vec<(String, MessageReaction)>,
+    ) -> Self {
+        Self::new(
+            chat_id,
+            members,
+            ChatEventKind::ReactionsChanged(message_id, reactions),
+        )
+    }
+
+    pub fn message_edited(members: &[ChatMember], message: MessageBox) -> Self {
+        Self::new(message.0.chat_id, members, ChatEventKind::MessageEdited(message))
+    }
+
+    pub fn message_deleted(members: &[ChatMember], message: Message) -> Self {
+        Self::new(message.chat_id, members, ChatEventKind::MessageDeleted(message))
+    }
+
+    pub fn message_hidden(user_id: Uuid, message: &Message) -> Self {
+        Self {
+            chat_id: message.chat_id,
+            recipients: vec![user_id],
+            kind: ChatEventKind::MessageHidden(message.id),
+        }
+    }
+
+    /// Sent to the members from before the change too, so removed members hear about it.
+    pub fn members_changed(
+        chat_id: Uuid,
+        previous_members: &[ChatMemberProfile],
+        members: Vec<ChatMemberProfile>,
+    ) -> Self {
+        let mut recipients: Vec<Uuid> = previous_members
+            .iter()
+            .chain(&members)
+            .map(|profile| profile.member.user_id)
+            .collect();
+        recipients.sort();
+        recipients.dedup();
+        Self {
+            chat_id,
+            recipients,
+            kind: ChatEventKind::MembersChanged(members),
+        }
+    }
+
+    /// A message replayed to a single user, e.g. after a dropped stream reconnects.
+    pub fn missed_message(user_id: Uuid, message: MessageBox) -> Self {
+        Self {
+            chat_id: message.0.chat_id,
+            recipients: vec![user_id],
+            kind: ChatEventKind::NewMessage(message),
+        }
+    }
+
+    pub fn is_recipient(&self, user_id: &Uuid) -> bool {
+        self.recipients.contains(user_id)
+    }
+}
+
+pub trait ChatEventBrokerInterface: Interface {
+    fn publish(&self, event: ChatEvent);
+    fn subscribe(&self) -> broadcast::Receiver<ChatEvent>;
+}
+
+/// In-process fan-out of chat events. Every open connection holds its own
+/// receiver and keeps only the events addressed to its user, so several
+/// tabs or devices of the same user all get a copy.
+#[derive(Component)]
+#[shaku(interface = ChatEventBrokerInterface)]
+pub struct ChatEventBroker {
+    #[shaku(default = broadcast::channel(EVENT_CHANNEL_CAPACITY).0)]
+    sender: broadcast::Sender<ChatEvent>,
+}
+
+impl ChatEventBrokerInterface for ChatEventBroker {
+    fn publish(&self, event: ChatEvent) {
+        let chat_id = event.chat_id;
+        match self.sender.send(event) {
+            Ok(receivers) => debug!("Chat event for {} sent to {} receivers", chat_id, receivers),
+            Err(_) => debug!("Chat event for {} dropped, nobody is listening", chat_id),
+        }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ChatEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -1,2 +1,3 @@
 pub mod chat_services;
 pub mod entity;
+pub mod events;
@@ -1,3 +1,4 @@
+use commons::secret_tokens::{generate_secret, hash_secret};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -23,4 +24,198 @@ impl Session {
             updated_at: Some(chrono::Local::now().naive_local()),
         }
     }
-}
\ No newline at end of file
+
+    pub fn device(&self) -> Device {
+        Device::parse(&self.user_agent)
+    }
+}
+
+/// One link of a session's refresh token chain, only the hash of the token is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl RefreshToken {
+    /// Creates the next token of the session, returns it with the secret for the cookie.
+    pub fn generate(session_id: Uuid, ttl_seconds: i64) -> (Self, String) {
+        let secret = generate_secret();
+        let now = chrono::Local::now().naive_local();
+        let token = Self {
+            id: Uuid::new_v4(),
+            session_id,
+            token_hash: Self::hash(&secret),
+            expires_at: now + chrono::Duration::seconds(ttl_seconds),
+            used_at: None,
+            created_at: Some(now),
+        };
+        (token, secret)
+    }
+
+    pub fn hash(secret: &str) -> String {
+        hash_secret(secret)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= chrono::Local::now().naive_local()
+    }
+}
+
+/// A password login waiting for its second factor, only the hash of the token is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    // Wrong codes entered so far
+    pub attempts: i64,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl LoginChallenge {
+    /// Returns the challenge with the token the login form sends back along with the code.
+    pub fn generate(user_id: Uuid, ttl_seconds: i64) -> (Self, String) {
+        let secret = generate_secret();
+        let now = chrono::Local::now().naive_local();
+        let challenge = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            token_hash: hash_secret(&secret),
+            attempts: 0,
+            expires_at: now + chrono::Duration::seconds(ttl_seconds),
+            created_at: Some(now),
+        };
+        (challenge, secret)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= chrono::Local::now().naive_local()
+    }
+}
+
+/// What failed logins are counted against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginScope {
+    Account,
+    Ip,
+}
+
+impl LoginScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginScope::Account => "account",
+            LoginScope::Ip => "ip",
+        }
+    }
+}
+
+/// Browser and operating system guessed from a user agent string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub browser: String,
+    pub os: String,
+}
+
+// Checked in order, most user agents also name the engines they are compatible with
+const BROWSERS: [(&str, &str); 10] = [
+    ("Edg", "Edge"),
+    ("OPR/", "Opera"),
+    ("Opera", "Opera"),
+    ("SamsungBrowser/", "Samsung Internet"),
+    ("Firefox/", "Firefox"),
+    ("FxiOS/", "Firefox"),
+    ("CriOS/", "Chrome"),
+    ("Chrome/", "Chrome"),
+    ("Safari/", "Safari"),
+    ("curl/", "curl"),
+];
+
+const OPERATING_SYSTEMS: [(&str, &str); 8] = [
+    ("Windows", "Windows"),
+    ("iPhone", "iOS"),
+    ("iPad", "iPadOS"),
+    ("Android", "Android"),
+    ("CrOS", "ChromeOS"),
+    ("Mac OS X", "macOS"),
+    ("Macintosh", "macOS"),
+    ("Linux", "Linux"),
+];
+
+impl Device {
+    pub fn parse(user_agent: &str) -> Self {
+        let find = |names: &[(&str, &str)], unknown: &str| {
+            names
+                .iter()
+                .find(|(token, _)| user_agent.contains(token))
+                .map_or(unknown, |(_, name)| name)
+                .to_string()
+        };
+        Self {
+            browser: find(&BROWSERS, "Unknown browser"),
+            os: find(&OPERATING_SYSTEMS, "Unknown OS"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Device, RefreshToken};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_generate_refresh_token() {
+        let session_id = Uuid::new_v4();
+        let (token, secret) = RefreshToken::generate(session_id, 60);
+        let (other, other_secret) = RefreshToken::generate(session_id, 60);
+        assert_eq!(token.session_id, session_id);
+        assert_eq!(token.token_hash, RefreshToken::hash(&secret));
+        assert_ne!(token.token_hash, secret);
+        assert_ne!(secret, other_secret);
+        assert_ne!(token.token_hash, other.token_hash);
+        assert!(!token.is_expired());
+        assert!(RefreshToken::generate(session_id, 0).0.is_expired());
+    }
+
+    #[test]
+    fn test_parse_device() {
+        let cases = [
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36 Edg/131.0.0.0",
+                "Edge",
+                "Windows",
+            ),
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.1 Safari/605.1.15",
+                "Safari",
+                "macOS",
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 18_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/131.0.6778.73 Mobile/15E148 Safari/604.1",
+                "Chrome",
+                "iOS",
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 14; SM-S918B) AppleWebKit/537.36 (KHTML, like Gecko) SamsungBrowser/26.0 Chrome/122.0.0.0 Mobile Safari/537.36",
+                "Samsung Internet",
+                "Android",
+            ),
+            (
+                "Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:133.0) Gecko/20100101 Firefox/133.0",
+                "Firefox",
+                "Linux",
+            ),
+            ("unknown", "Unknown browser", "Unknown OS"),
+        ];
+        for (user_agent, browser, os) in cases {
+            let device = Device::parse(user_agent);
+            assert_eq!(device.browser, browser, "{}", user_agent);
+            assert_eq!(device.os, os, "{}", user_agent);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::entity::Session;
+use crate::entity::{LoginChallenge, LoginScope, RefreshToken, Session};
 use log::error;
 use persistence::DatabaseInterface;
 use shaku::{Component, Interface};
@@ -6,6 +6,9 @@ use sqlx::Row;
 use std::sync::Arc;
 use uuid::Uuid;
 
+// Keeps the last active time accurate enough without writing on every request
+const ACTIVITY_UPDATE_INTERVAL_SECONDS: i64 = 60;
+
 #[derive(Component)]
 #[shaku(interface = SessionServiceInterface)]
 pub struct SessionService {
@@ -20,6 +23,49 @@ pub trait SessionServiceInterface: Interface {
     async fn get_session(&self, session_id: &str) -> anyhow::Result<Option<Session>>;
     async fn delete_session(&self, session_id: &str) -> anyhow::Result<()>;
     async fn delete_sessions_by_user(&self, user_id: Uuid) -> anyhow::Result<()>;
+    /// Like `delete_sessions_by_user`, but the given session stays.
+    async fn delete_other_sessions(&self, user_id: Uuid, session_id: &str) -> anyhow::Result<()>;
+    /// Most recently active first.
+    async fn get_sessions_by_user(&self, user_id: Uuid) -> anyhow::Result<Vec<Session>>;
+    /// Records activity on the session by moving its `updated_at` forward.
+    async fn touch_session(&self, session_id: &str) -> anyhow::Result<()>;
+    /// Deletes the session by its row id, only when it belongs to the user.
+    /// Returns whether there was such a session.
+    async fn delete_session_of_user(&self, id: Uuid, user_id: Uuid) -> anyhow::Result<bool>;
+    async fn create_refresh_token(&self, refresh_token: &RefreshToken) -> anyhow::Result<()>;
+    async fn get_refresh_token(&self, token_hash: &str) -> anyhow::Result<Option<RefreshToken>>;
+    /// Marks the token as exchanged. Returns false when it already was,
+    /// so only one of two concurrent refreshes gets to rotate it.
+    async fn use_refresh_token(&self, id: Uuid) -> anyhow::Result<bool>;
+    async fn create_login_challenge(&self, challenge: &LoginChallenge) -> anyhow::Result<()>;
+    async fn get_login_challenge(&self, token_hash: &str)
+        -> anyhow::Result<Option<LoginChallenge>>;
+    /// Counts a wrong code against the challenge, returns the attempts so far,
+    /// `None` when the challenge is already gone.
+    async fn record_login_challenge_attempt(&self, id: Uuid) -> anyhow::Result<Option<i64>>;
+    /// Returns false when it was already gone, so a challenge completes only once.
+    async fn delete_login_challenge(&self, id: Uuid) -> anyhow::Result<bool>;
+    /// When the subject is locked out, until when.
+    async fn get_login_lock(
+        &self,
+        scope: LoginScope,
+        subject: &str,
+    ) -> anyhow::Result<Option<chrono::NaiveDateTime>>;
+    /// Counts a failed login and returns the failures so far. Failures from before
+    /// `forget_before` are dropped first, the count starts over after a quiet period.
+    async fn record_login_failure(
+        &self,
+        scope: LoginScope,
+        subject: &str,
+        forget_before: chrono::NaiveDateTime,
+    ) -> anyhow::Result<i64>;
+    async fn lock_login(
+        &self,
+        scope: LoginScope,
+        subject: &str,
+        until: chrono::NaiveDateTime,
+    ) -> anyhow::Result<()>;
+    async fn clear_login_failures(&self, scope: LoginScope, subject: &str) -> anyhow::Result<()>;
 }
 
 #[async_trait::async_trait]
@@ -56,15 +102,16 @@ impl SessionServiceInterface for SessionService {
             WHERE session_id = ?
         "#;
 
+        // Logging out deletes the row, which simply makes the session invalid
         let session = sqlx::query(query)
             .bind(session_id.to_string())
-            .fetch_one(&mut *connection)
+            .fetch_optional(&mut *connection)
             .await
             .inspect_err(|e| {
                 error!("Error occurred while checking session: {}", e.to_string());
             })?;
 
-        Ok(!session.is_empty())
+        Ok(session.is_some())
     }
 
     async fn get_session(&self, session_id: &str) -> anyhow::Result<Option<Session>> {
@@ -77,15 +124,15 @@ impl SessionServiceInterface for SessionService {
 
         let session = sqlx::query(query)
             .bind(session_id.to_string())
-            .fetch_one(&mut *connection)
+            .fetch_optional(&mut *connection)
             .await
             .inspect_err(|e| {
                 error!("Error occurred while getting session: {}", e.to_string());
             })?;
 
-        if session.is_empty() {
+        let Some(session) = session else {
             return Ok(None);
-        }
+        };
 
         let session = Session {
             id: session.try_get::<String, _>("id")?.parse()?,
@@ -133,4 +180,391 @@ impl SessionServiceInterface for SessionService {
             })?;
         Ok(())
     }
+
+    async fn delete_other_sessions(&self, user_id: Uuid, session_id: &str) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            DELETE FROM sessions
+            WHERE user_id = ? AND session_id != ?
+        "#;
+        sqlx::query(query)
+            .bind(user_id.to_string())
+            .bind(session_id.to_string())
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while deleting other sessions: {}",
+                    e.to_string()
+                );
+            })?;
+        Ok(())
+    }
+
+    async fn get_sessions_by_user(&self, user_id: Uuid) -> anyhow::Result<Vec<Session>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            SELECT id, session_id, user_id, user_agent, ip_address, created_at, updated_at
+            FROM sessions
+            WHERE user_id = ?
+            ORDER BY updated_at DESC
+        "#;
+
+        let rows = sqlx::query(query)
+            .bind(user_id.to_string())
+            .fetch_all(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while getting user sessions: {}",
+                    e.to_string()
+                );
+            })?;
+
+        let mut sessions = Vec::new();
+        for session in rows {
+            sessions.push(Session {
+                id: session.try_get::<String, _>("id")?.parse()?,
+                session_id: session.try_get::<String, _>("session_id")?.parse()?,
+                user_id: session.try_get::<String, _>("user_id")?.parse()?,
+                user_agent: session.try_get("user_agent")?,
+                ip_address: session.try_get("ip_address")?,
+                created_at: session.try_get("created_at")?,
+                updated_at: session.try_get("updated_at")?,
+            });
+        }
+        Ok(sessions)
+    }
+
+    async fn touch_session(&self, session_id: &str) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let now = chrono::Local::now().naive_local();
+        let query = r#"
+            UPDATE sessions
+            SET updated_at = ?
+            WHERE session_id = ? AND (updated_at IS NULL OR updated_at < ?)
+        "#;
+        sqlx::query(query)
+            .bind(now)
+            .bind(session_id.to_string())
+            .bind(now - chrono::Duration::seconds(ACTIVITY_UPDATE_INTERVAL_SECONDS))
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!("Error occurred while touching session: {}", e.to_string());
+            })?;
+        Ok(())
+    }
+
+    async fn delete_session_of_user(&self, id: Uuid, user_id: Uuid) -> anyhow::Result<bool> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            DELETE FROM sessions
+            WHERE id = ? AND user_id = ?
+        "#;
+        let result = sqlx::query(query)
+            .bind(id.to_string())
+            .bind(user_id.to_string())
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while deleting user session: {}",
+                    e.to_string()
+                );
+            })?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn create_refresh_token(&self, refresh_token: &RefreshToken) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            INSERT INTO refresh_tokens (id, session_id, token_hash, expires_at, used_at, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+        "#;
+
+        sqlx::query(query)
+            .bind(refresh_token.id.to_string())
+            .bind(refresh_token.session_id.to_string())
+            .bind(&refresh_token.token_hash)
+            .bind(refresh_token.expires_at)
+            .bind(refresh_token.used_at)
+            .bind(refresh_token.created_at)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while creating refresh token: {}",
+                    e.to_string()
+                );
+            })?;
+
+        Ok(())
+    }
+
+    async fn get_refresh_token(&self, token_hash: &str) -> anyhow::Result<Option<RefreshToken>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            SELECT id, session_id, token_hash, expires_at, used_at, created_at
+            FROM refresh_tokens
+            WHERE token_hash = ?
+        "#;
+
+        let row = sqlx::query(query)
+            .bind(token_hash)
+            .fetch_optional(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while getting refresh token: {}",
+                    e.to_string()
+                );
+            })?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(RefreshToken {
+            id: row.try_get::<String, _>("id")?.parse()?,
+            session_id: row.try_get::<String, _>("session_id")?.parse()?,
+            token_hash: row.try_get("token_hash")?,
+            expires_at: row.try_get("expires_at")?,
+            used_at: row.try_get("used_at")?,
+            created_at: row.try_get("created_at")?,
+        }))
+    }
+
+    async fn use_refresh_token(&self, id: Uuid) -> anyhow::Result<bool> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            UPDATE refresh_tokens
+            SET used_at = ?
+            WHERE id = ? AND used_at IS NULL
+        "#;
+        let result = sqlx::query(query)
+            .bind(chrono::Local::now().naive_local())
+            .bind(id.to_string())
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while using refresh token: {}",
+                    e.to_string()
+                );
+            })?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn create_login_challenge(&self, challenge: &LoginChallenge) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            INSERT INTO login_challenges (id, user_id, token_hash, attempts, expires_at, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+        "#;
+
+        sqlx::query(query)
+            .bind(challenge.id.to_string())
+            .bind(challenge.user_id.to_string())
+            .bind(&challenge.token_hash)
+            .bind(challenge.attempts)
+            .bind(challenge.expires_at)
+            .bind(challenge.created_at)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while creating login challenge: {}",
+                    e.to_string()
+                );
+            })?;
+
+        Ok(())
+    }
+
+    async fn get_login_challenge(
+        &self,
+        token_hash: &str,
+    ) -> anyhow::Result<Option<LoginChallenge>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            SELECT id, user_id, token_hash, attempts, expires_at, created_at
+            FROM login_challenges
+            WHERE token_hash = ?
+        "#;
+
+        let row = sqlx::query(query)
+            .bind(token_hash)
+            .fetch_optional(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while getting login challenge: {}",
+                    e.to_string()
+                );
+            })?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(LoginChallenge {
+            id: row.try_get::<String, _>("id")?.parse()?,
+            user_id: row.try_get::<String, _>("user_id")?.parse()?,
+            token_hash: row.try_get("token_hash")?,
+            attempts: row.try_get("attempts")?,
+            expires_at: row.try_get("expires_at")?,
+            created_at: row.try_get("created_at")?,
+        }))
+    }
+
+    async fn record_login_challenge_attempt(&self, id: Uuid) -> anyhow::Result<Option<i64>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            UPDATE login_challenges
+            SET attempts = attempts + 1
+            WHERE id = ?
+            RETURNING attempts
+        "#;
+        let row = sqlx::query(query)
+            .bind(id.to_string())
+            .fetch_optional(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while counting login challenge attempt: {}",
+                    e.to_string()
+                );
+            })?;
+
+        row.map(|row| row.try_get("attempts"))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    async fn delete_login_challenge(&self, id: Uuid) -> anyhow::Result<bool> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            DELETE FROM login_challenges
+            WHERE id = ?
+        "#;
+        let result = sqlx::query(query)
+            .bind(id.to_string())
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while deleting login challenge: {}",
+                    e.to_string()
+                );
+            })?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_login_lock(
+        &self,
+        scope: LoginScope,
+        subject: &str,
+    ) -> anyhow::Result<Option<chrono::NaiveDateTime>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            SELECT locked_until
+            FROM login_failures
+            WHERE scope = ? AND subject = ? AND locked_until > ?
+        "#;
+        let row = sqlx::query(query)
+            .bind(scope.as_str())
+            .bind(subject)
+            .bind(chrono::Local::now().naive_local())
+            .fetch_optional(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!("Error occurred while getting login lock: {}", e.to_string());
+            })?;
+
+        row.map(|row| row.try_get("locked_until"))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    async fn record_login_failure(
+        &self,
+        scope: LoginScope,
+        subject: &str,
+        forget_before: chrono::NaiveDateTime,
+    ) -> anyhow::Result<i64> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        // One statement, so concurrent failures cannot overwrite each other's count
+        let query = r#"
+            INSERT INTO login_failures (scope, subject, failures, last_failed_at)
+            VALUES (?, ?, 1, ?)
+            ON CONFLICT (scope, subject) DO UPDATE
+            SET failures = CASE
+                    WHEN login_failures.last_failed_at < ? THEN 1
+                    ELSE login_failures.failures + 1
+                END,
+                last_failed_at = excluded.last_failed_at
+            RETURNING failures
+        "#;
+        let row = sqlx::query(query)
+            .bind(scope.as_str())
+            .bind(subject)
+            .bind(chrono::Local::now().naive_local())
+            .bind(forget_before)
+            .fetch_one(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while recording login failure: {}",
+                    e.to_string()
+                );
+            })?;
+
+        Ok(row.try_get("failures")?)
+    }
+
+    async fn lock_login(
+        &self,
+        scope: LoginScope,
+        subject: &str,
+        until: chrono::NaiveDateTime,
+    ) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            UPDATE login_failures
+            SET locked_until = ?
+            WHERE scope = ? AND subject = ?
+        "#;
+        sqlx::query(query)
+            .bind(until)
+            .bind(scope.as_str())
+            .bind(subject)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!("Error occurred while locking login: {}", e.to_string());
+            })?;
+
+        Ok(())
+    }
+
+    async fn clear_login_failures(&self, scope: LoginScope, subject: &str) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            DELETE FROM login_failures
+            WHERE scope = ? AND subject = ?
+        "#;
+        sqlx::query(query)
+            .bind(scope.as_str())
+            .bind(subject)
+            .execute(&mut *connection)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Error occurred while clearing login failures: {}",
+                    e.to_string()
+                );
+            })?;
+
+        Ok(())
+    }
 }
@@ -11,10 +11,20 @@ pub struct UserDetail {
     pub date_of_birth: Option<chrono::NaiveDate>,
     pub gender: Option<String>,
     pub profile_picture: Option<String>,
+    pub profile_picture_medium: Option<String>,
+    pub profile_picture_small: Option<String>,
     pub created_at: Option<chrono::NaiveDateTime>,
     pub updated_at: Option<chrono::NaiveDateTime>,
 }
 
+/// The stored sizes of one uploaded profile picture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfilePicture {
+    pub large: String,
+    pub medium: String,
+    pub small: String,
+}
+
 impl UserDetail {
     pub fn new(user_id: Uuid) -> Self {
         Self {
@@ -25,6 +35,8 @@ impl UserDetail {
             date_of_birth: None,
             gender: None,
             profile_picture: None,
+            profile_picture_medium: None,
+            profile_picture_small: None,
             created_at: Some(chrono::Local::now().naive_local()),
             updated_at: Some(chrono::Local::now().naive_local()),
         }
@@ -39,6 +51,8 @@ impl UserDetail {
             date_of_birth: row.try_get("date_of_birth")?,
             gender: row.try_get("gender")?,
             profile_picture: row.try_get("profile_picture")?,
+            profile_picture_medium: row.try_get("profile_picture_medium")?,
+            profile_picture_small: row.try_get("profile_picture_small")?,
             created_at: row.try_get("created_at")?,
             updated_at: row.try_get("updated_at")?,
         })
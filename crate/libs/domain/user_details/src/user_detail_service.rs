@@ -6,7 +6,7 @@ use persistence::DatabaseInterface;
 use shaku::{Component, Interface};
 use tracing::error;
 
-use crate::entity::UserDetail;
+use crate::entity::{ProfilePicture, UserDetail};
 
 #[derive(Component)]
 #[shaku(interface = UserDetailService)]
@@ -22,7 +22,20 @@ pub trait UserDetailService: Interface {
     async fn is_user_detail_exist(&self, user_id: &str) -> anyhow::Result<bool>;
     async fn upsert_user_detail(&self, user_detail: &UserDetail) -> anyhow::Result<()>;
     async fn get_user_detail_by_user_id(&self, user_id: &str) -> anyhow::Result<UserDetail>;
-    async fn update_profile_picture(&self, user_id: &str, file_path: &str) -> anyhow::Result<()>;
+    async fn update_profile_picture(
+        &self,
+        user_id: &str,
+        profile_picture: &ProfilePicture,
+    ) -> anyhow::Result<()>;
+    /// Whether some user currently has this file as one of the sizes of their profile picture.
+    async fn is_profile_picture_in_use(&self, file_path: &str) -> anyhow::Result<bool>;
+    /// Points every profile picture stored as `file_path` at `new_file_path`, returns how many
+    /// changed. Only the largest size, uploads from before `/media` have no smaller ones.
+    async fn replace_profile_picture_path(
+        &self,
+        file_path: &str,
+        new_file_path: &str,
+    ) -> anyhow::Result<u64>;
 }
 
 #[async_trait::async_trait]
@@ -132,11 +145,20 @@ impl UserDetailService for UserDetailServiceImpl {
     }
 
 
-    async fn update_profile_picture(&self, user_id: &str, file_path: &str) -> anyhow::Result<()> {
+    async fn update_profile_picture(
+        &self,
+        user_id: &str,
+        profile_picture: &ProfilePicture,
+    ) -> anyhow::Result<()> {
         let mut connection = self.db.get_pool().acquire().await?;
-        let query = r#"UPDATE user_details SET profile_picture = ? WHERE user_id = ?"#;
+        let query = r#"UPDATE user_details SET
+        profile_picture = ?,
+        profile_picture_medium = ?,
+        profile_picture_small = ? WHERE user_id = ?"#;
         sqlx::query(query)
-            .bind(file_path)
+            .bind(profile_picture.large.clone())
+            .bind(profile_picture.medium.clone())
+            .bind(profile_picture.small.clone())
             .bind(user_id)
             .execute(&mut *connection)
             .await
@@ -146,4 +168,42 @@ impl UserDetailService for UserDetailServiceImpl {
             })?;
         Ok(())
     }
+
+    async fn is_profile_picture_in_use(&self, file_path: &str) -> anyhow::Result<bool> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT count(1) as count FROM user_details
+        WHERE profile_picture = ? OR profile_picture_medium = ? OR profile_picture_small = ?"#;
+        let results = sqlx::query(query)
+            .bind(file_path)
+            .bind(file_path)
+            .bind(file_path)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to check profile_picture: {}", e);
+                anyhow!("Failed to check profile_picture")
+            })?;
+
+        let count: i64 = results.try_get::<i64, _>("count")?;
+        Ok(count > 0)
+    }
+
+    async fn replace_profile_picture_path(
+        &self,
+        file_path: &str,
+        new_file_path: &str,
+    ) -> anyhow::Result<u64> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"UPDATE user_details SET profile_picture = ? WHERE profile_picture = ?"#;
+        let result = sqlx::query(query)
+            .bind(new_file_path)
+            .bind(file_path)
+            .execute(&mut *connection)
+            .await
+            .map_err(|e| {
+                error!("Failed to replace profile_picture: {}", e);
+                anyhow!("Failed to replace profile_picture")
+            })?;
+        Ok(result.rows_affected())
+    }
 }
@@ -1,3 +1,4 @@
+use commons::secret_tokens::{generate_secret, hash_secret};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -19,7 +20,7 @@ impl User {
             id: Uuid::new_v4(),
             username,
             email,
-            password: bcrypt::hash(password, bcrypt::DEFAULT_COST)?,
+            password: Self::hash_password(&password)?,
             is_active: false, // User starts as inactive until explicitly activated
             created_at: Some(chrono::Local::now().naive_local()),
             updated_at: Some(chrono::Local::now().naive_local()),
@@ -37,11 +38,135 @@ impl User {
         self.is_active = true;
     }
 
+    pub fn hash_password(password: &str) -> anyhow::Result<String> {
+        Ok(bcrypt::hash(password, bcrypt::DEFAULT_COST)?)
+    }
+
     pub fn match_password(&self, password: &str) -> bool {
         bcrypt::verify(password, &self.password).unwrap_or(false)
     }
 }
 
+/// A pending "forgot password" link, only the hash of its token is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordReset {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl PasswordReset {
+    /// Returns the reset together with the token that goes into the link.
+    pub fn generate(user_id: Uuid, ttl: chrono::Duration) -> (Self, String) {
+        let token = generate_secret();
+        let now = chrono::Local::now().naive_local();
+        let reset = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            token_hash: hash_secret(&token),
+            expires_at: now + ttl,
+            created_at: Some(now),
+        };
+        (reset, token)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= chrono::Local::now().naive_local()
+    }
+}
+
+/// A new email address waiting for its owner to confirm it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailChange {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub new_email: String,
+    pub token_hash: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl EmailChange {
+    /// Returns the change together with the token that goes into the confirmation link.
+    pub fn generate(user_id: Uuid, new_email: String, ttl: chrono::Duration) -> (Self, String) {
+        let token = generate_secret();
+        let now = chrono::Local::now().naive_local();
+        let change = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            new_email,
+            token_hash: hash_secret(&token),
+            expires_at: now + ttl,
+            created_at: Some(now),
+        };
+        (change, token)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= chrono::Local::now().naive_local()
+    }
+}
+
+/// The authenticator app of a user, `secret` is stored encrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwoFactor {
+    pub user_id: Uuid,
+    pub secret: String,
+    // Unset while enrolling, until the first code is confirmed
+    pub enabled_at: Option<chrono::NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl TwoFactor {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled_at.is_some()
+    }
+}
+
+/// A one-time code to log in without the authenticator app, only its hash is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code_hash: String,
+    pub used_at: Option<chrono::NaiveDateTime>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl RecoveryCode {
+    /// Returns the codes together with what the user writes down, e.g. `3f9a1-c07be`.
+    pub fn generate(user_id: Uuid, count: usize) -> (Vec<Self>, Vec<String>) {
+        let now = chrono::Local::now().naive_local();
+        (0..count)
+            .map(|_| {
+                let secret = generate_secret();
+                let code = format!("{}-{}", &secret[..5], &secret[5..10]);
+                let recovery_code = Self {
+                    id: Uuid::new_v4(),
+                    user_id,
+                    code_hash: Self::hash(&code),
+                    used_at: None,
+                    created_at: Some(now),
+                };
+                (recovery_code, code)
+            })
+            .unzip()
+    }
+
+    /// Case, spaces and the dash do not matter when the code is typed back in.
+    pub fn hash(code: &str) -> String {
+        let normalized: String = code
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        hash_secret(&normalized)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UserInfo {
     pub id: Uuid,
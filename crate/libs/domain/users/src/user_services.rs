@@ -1,4 +1,4 @@
-use crate::user::{User, UserInfo};
+use crate::user::{EmailChange, PasswordReset, RecoveryCode, TwoFactor, User, UserInfo};
 use chrono::NaiveDateTime;
 use persistence::DatabaseInterface;
 use shaku::{Component, Interface};
@@ -21,6 +21,53 @@ pub trait UserServiceInterface: Interface + Send + Sync {
     async fn get_user_by_username(&self, username: &str) -> anyhow::Result<User>;
     async fn activate_user(&self, id: Uuid) -> anyhow::Result<()>;
     async fn find_user_info_list(&self, query: &str) -> anyhow::Result<Vec<UserInfo>>;
+    /// Active users only, like `get_user_by_username`.
+    async fn get_user_by_email(&self, email: &str) -> anyhow::Result<Option<User>>;
+    /// A signed up user still waiting for activation.
+    async fn get_inactive_user_by_email(&self, email: &str) -> anyhow::Result<Option<User>>;
+    /// Whether any account uses the address, active or not.
+    async fn is_email_taken(&self, email: &str) -> anyhow::Result<bool>;
+    /// Records an activation email for the user, unless one already went out after `not_since`.
+    /// Returns whether the email may be sent.
+    async fn mark_activation_sent(
+        &self,
+        id: Uuid,
+        not_since: chrono::NaiveDateTime,
+    ) -> anyhow::Result<bool>;
+    async fn update_password(&self, id: Uuid, password_hash: &str) -> anyhow::Result<()>;
+    async fn create_password_reset(&self, reset: &PasswordReset) -> anyhow::Result<()>;
+    async fn get_password_reset(&self, token_hash: &str) -> anyhow::Result<Option<PasswordReset>>;
+    /// Deletes the reset and returns it, `None` when it is unknown or was already used.
+    async fn consume_password_reset(
+        &self,
+        token_hash: &str,
+    ) -> anyhow::Result<Option<PasswordReset>>;
+    async fn delete_password_resets_by_user(&self, user_id: Uuid) -> anyhow::Result<()>;
+    async fn update_email(&self, id: Uuid, email: &str) -> anyhow::Result<()>;
+    async fn create_email_change(&self, change: &EmailChange) -> anyhow::Result<()>;
+    /// The latest change of the user that can still be confirmed.
+    async fn get_pending_email_change(&self, user_id: Uuid) -> anyhow::Result<Option<EmailChange>>;
+    /// Deletes the change and returns it, `None` when it is unknown or was already used.
+    async fn consume_email_change(&self, token_hash: &str) -> anyhow::Result<Option<EmailChange>>;
+    async fn delete_email_changes_by_user(&self, user_id: Uuid) -> anyhow::Result<()>;
+    async fn get_two_factor(&self, user_id: Uuid) -> anyhow::Result<Option<TwoFactor>>;
+    /// Starts over with a new secret, replacing an enrollment that was never confirmed.
+    async fn save_two_factor(&self, two_factor: &TwoFactor) -> anyhow::Result<()>;
+    /// Turns two-factor login on together with its recovery codes, replacing any older ones.
+    async fn enable_two_factor(
+        &self,
+        user_id: Uuid,
+        step: i64,
+        recovery_codes: &[RecoveryCode],
+    ) -> anyhow::Result<()>;
+    /// Records the step of an accepted code. Returns false when that step or a later one
+    /// was already used, so a code cannot be replayed.
+    async fn use_two_factor_step(&self, user_id: Uuid, step: i64) -> anyhow::Result<bool>;
+    /// Turns two-factor login off, the recovery codes go with it.
+    async fn delete_two_factor(&self, user_id: Uuid) -> anyhow::Result<()>;
+    /// Returns false when the code is unknown or was already used.
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> anyhow::Result<bool>;
+    async fn count_unused_recovery_codes(&self, user_id: Uuid) -> anyhow::Result<i64>;
 }
 
 impl UserService {
@@ -41,6 +88,25 @@ impl UserService {
 
         Ok(user)
     }
+    fn row_to_email_change(row: SqliteRow) -> anyhow::Result<EmailChange> {
+        Ok(EmailChange {
+            id: row.try_get::<String, _>("id")?.parse()?,
+            user_id: row.try_get::<String, _>("user_id")?.parse()?,
+            new_email: row.try_get("new_email")?,
+            token_hash: row.try_get("token_hash")?,
+            expires_at: row.try_get("expires_at")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+    fn row_to_password_reset(row: SqliteRow) -> anyhow::Result<PasswordReset> {
+        Ok(PasswordReset {
+            id: row.try_get::<String, _>("id")?.parse()?,
+            user_id: row.try_get::<String, _>("user_id")?.parse()?,
+            token_hash: row.try_get("token_hash")?,
+            expires_at: row.try_get("expires_at")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
 }
 
 #[async_trait::async_trait]
@@ -160,7 +226,7 @@ impl UserServiceInterface for UserService {
             u.username,
             ud.first_name,
             ud.last_name,
-            ud.profile_picture
+            COALESCE(ud.profile_picture_small, ud.profile_picture) AS profile_picture
         FROM users u
         LEFT JOIN user_details ud ON u.id = ud.user_id
         WHERE is_active = true and (lower(u.username) LIKE ? or lower(u.email) LIKE ?)"#;
@@ -185,4 +251,412 @@ impl UserServiceInterface for UserService {
         
     }
 
+    async fn get_user_by_email(&self, email: &str) -> anyhow::Result<Option<User>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            username,
+            email,
+            password,
+            is_active,
+            created_at,
+            updated_at,
+            deleted_at
+            FROM users
+            WHERE is_active = true and lower(email) = lower(?)"#;
+        let row = sqlx::query(query)
+            .bind(email.trim())
+            .fetch_optional(&mut *connection)
+            .await?;
+
+        row.map(Self::row_to_user).transpose()
+    }
+
+    async fn get_inactive_user_by_email(&self, email: &str) -> anyhow::Result<Option<User>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            username,
+            email,
+            password,
+            is_active,
+            created_at,
+            updated_at,
+            deleted_at
+            FROM users
+            WHERE is_active = false and deleted_at IS NULL and lower(email) = lower(?)"#;
+        let row = sqlx::query(query)
+            .bind(email.trim())
+            .fetch_optional(&mut *connection)
+            .await?;
+
+        row.map(Self::row_to_user).transpose()
+    }
+
+    async fn is_email_taken(&self, email: &str) -> anyhow::Result<bool> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT count(1) as count FROM users WHERE lower(email) = lower(?)"#;
+        let row = sqlx::query(query)
+            .bind(email.trim())
+            .fetch_one(&mut *connection)
+            .await?;
+
+        let count: i64 = row.try_get("count")?;
+        Ok(count > 0)
+    }
+
+    async fn mark_activation_sent(
+        &self,
+        id: Uuid,
+        not_since: chrono::NaiveDateTime,
+    ) -> anyhow::Result<bool> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        // One statement, so two requests at once cannot both send
+        let query = r#"
+            UPDATE users
+            SET activation_sent_at = ?
+            WHERE id = ? AND (activation_sent_at IS NULL OR activation_sent_at <= ?)"#;
+
+        let updated = sqlx::query(query)
+            .bind(chrono::Utc::now().naive_utc())
+            .bind(id.to_string())
+            .bind(not_since)
+            .execute(&mut *connection)
+            .await?
+            .rows_affected();
+        Ok(updated > 0)
+    }
+
+    async fn update_password(&self, id: Uuid, password_hash: &str) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            UPDATE users
+            SET password = ?,
+                updated_at = ?
+            WHERE id = ?"#;
+
+        sqlx::query(query)
+            .bind(password_hash)
+            .bind(chrono::Utc::now().naive_utc())
+            .bind(id.to_string())
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn create_password_reset(&self, reset: &PasswordReset) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"INSERT INTO password_resets (
+            id, user_id, token_hash, expires_at, created_at
+        ) VALUES (?, ?, ?, ?, ?)"#;
+
+        sqlx::query(query)
+            .bind(reset.id.to_string())
+            .bind(reset.user_id.to_string())
+            .bind(&reset.token_hash)
+            .bind(reset.expires_at)
+            .bind(reset.created_at)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_password_reset(&self, token_hash: &str) -> anyhow::Result<Option<PasswordReset>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            user_id,
+            token_hash,
+            expires_at,
+            created_at
+            FROM password_resets
+            WHERE token_hash = ?"#;
+        let row = sqlx::query(query)
+            .bind(token_hash)
+            .fetch_optional(&mut *connection)
+            .await?;
+
+        row.map(Self::row_to_password_reset).transpose()
+    }
+
+    async fn consume_password_reset(
+        &self,
+        token_hash: &str,
+    ) -> anyhow::Result<Option<PasswordReset>> {
+        let Some(reset) = self.get_password_reset(token_hash).await? else {
+            return Ok(None);
+        };
+
+        // Two submits of the same link race here, only the one that deletes it gets it
+        let mut connection = self.db.get_pool().acquire().await?;
+        let deleted = sqlx::query(r#"DELETE FROM password_resets WHERE id = ?"#)
+            .bind(reset.id.to_string())
+            .execute(&mut *connection)
+            .await?
+            .rows_affected();
+
+        Ok((deleted > 0).then_some(reset))
+    }
+
+    async fn delete_password_resets_by_user(&self, user_id: Uuid) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        sqlx::query(r#"DELETE FROM password_resets WHERE user_id = ?"#)
+            .bind(user_id.to_string())
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_email(&self, id: Uuid, email: &str) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            UPDATE users
+            SET email = ?,
+                updated_at = ?
+            WHERE id = ?"#;
+
+        sqlx::query(query)
+            .bind(email)
+            .bind(chrono::Utc::now().naive_utc())
+            .bind(id.to_string())
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn create_email_change(&self, change: &EmailChange) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"INSERT INTO email_changes (
+            id, user_id, new_email, token_hash, expires_at, created_at
+        ) VALUES (?, ?, ?, ?, ?, ?)"#;
+
+        sqlx::query(query)
+            .bind(change.id.to_string())
+            .bind(change.user_id.to_string())
+            .bind(&change.new_email)
+            .bind(&change.token_hash)
+            .bind(change.expires_at)
+            .bind(change.created_at)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_pending_email_change(&self, user_id: Uuid) -> anyhow::Result<Option<EmailChange>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            user_id,
+            new_email,
+            token_hash,
+            expires_at,
+            created_at
+            FROM email_changes
+            WHERE user_id = ? AND expires_at > ?
+            ORDER BY created_at DESC
+            LIMIT 1"#;
+        let row = sqlx::query(query)
+            .bind(user_id.to_string())
+            .bind(chrono::Local::now().naive_local())
+            .fetch_optional(&mut *connection)
+            .await?;
+
+        row.map(Self::row_to_email_change).transpose()
+    }
+
+    async fn consume_email_change(&self, token_hash: &str) -> anyhow::Result<Option<EmailChange>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            id,
+            user_id,
+            new_email,
+            token_hash,
+            expires_at,
+            created_at
+            FROM email_changes
+            WHERE token_hash = ?"#;
+        let Some(row) = sqlx::query(query)
+            .bind(token_hash)
+            .fetch_optional(&mut *connection)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let change = Self::row_to_email_change(row)?;
+
+        // Same as consume_password_reset, a link opened twice at once confirms once
+        let deleted = sqlx::query(r#"DELETE FROM email_changes WHERE id = ?"#)
+            .bind(change.id.to_string())
+            .execute(&mut *connection)
+            .await?
+            .rows_affected();
+
+        Ok((deleted > 0).then_some(change))
+    }
+
+    async fn delete_email_changes_by_user(&self, user_id: Uuid) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        sqlx::query(r#"DELETE FROM email_changes WHERE user_id = ?"#)
+            .bind(user_id.to_string())
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_two_factor(&self, user_id: Uuid) -> anyhow::Result<Option<TwoFactor>> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"SELECT
+            user_id,
+            secret,
+            enabled_at,
+            last_used_step,
+            created_at
+            FROM user_two_factor
+            WHERE user_id = ?"#;
+        let row = sqlx::query(query)
+            .bind(user_id.to_string())
+            .fetch_optional(&mut *connection)
+            .await?;
+
+        row.map(|row| -> anyhow::Result<TwoFactor> {
+            Ok(TwoFactor {
+                user_id: row.try_get::<String, _>("user_id")?.parse()?,
+                secret: row.try_get("secret")?,
+                enabled_at: row.try_get("enabled_at")?,
+                last_used_step: row.try_get("last_used_step")?,
+                created_at: row.try_get("created_at")?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn save_two_factor(&self, two_factor: &TwoFactor) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"INSERT INTO user_two_factor (
+            user_id, secret, enabled_at, last_used_step, created_at
+        ) VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT (user_id) DO UPDATE SET
+            secret = excluded.secret,
+            enabled_at = excluded.enabled_at,
+            last_used_step = excluded.last_used_step,
+            created_at = excluded.created_at"#;
+
+        sqlx::query(query)
+            .bind(two_factor.user_id.to_string())
+            .bind(&two_factor.secret)
+            .bind(two_factor.enabled_at)
+            .bind(two_factor.last_used_step)
+            .bind(two_factor.created_at)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn enable_two_factor(
+        &self,
+        user_id: Uuid,
+        step: i64,
+        recovery_codes: &[RecoveryCode],
+    ) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let mut tx = connection.begin().await?;
+
+        let query = r#"
+            UPDATE user_two_factor
+            SET enabled_at = ?,
+                last_used_step = ?
+            WHERE user_id = ?"#;
+        sqlx::query(query)
+            .bind(chrono::Local::now().naive_local())
+            .bind(step)
+            .bind(user_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(r#"DELETE FROM recovery_codes WHERE user_id = ?"#)
+            .bind(user_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        let query = r#"INSERT INTO recovery_codes (
+            id, user_id, code_hash, used_at, created_at
+        ) VALUES (?, ?, ?, ?, ?)"#;
+        for code in recovery_codes {
+            sqlx::query(query)
+                .bind(code.id.to_string())
+                .bind(code.user_id.to_string())
+                .bind(&code.code_hash)
+                .bind(code.used_at)
+                .bind(code.created_at)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_two_factor_step(&self, user_id: Uuid, step: i64) -> anyhow::Result<bool> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            UPDATE user_two_factor
+            SET last_used_step = ?
+            WHERE user_id = ? AND (last_used_step IS NULL OR last_used_step < ?)"#;
+
+        let updated = sqlx::query(query)
+            .bind(step)
+            .bind(user_id.to_string())
+            .bind(step)
+            .execute(&mut *connection)
+            .await?
+            .rows_affected();
+        Ok(updated > 0)
+    }
+
+    async fn delete_two_factor(&self, user_id: Uuid) -> anyhow::Result<()> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let mut tx = connection.begin().await?;
+
+        sqlx::query(r#"DELETE FROM recovery_codes WHERE user_id = ?"#)
+            .bind(user_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(r#"DELETE FROM user_two_factor WHERE user_id = ?"#)
+            .bind(user_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> anyhow::Result<bool> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            UPDATE recovery_codes
+            SET used_at = ?
+            WHERE user_id = ? AND code_hash = ? AND used_at IS NULL"#;
+
+        let updated = sqlx::query(query)
+            .bind(chrono::Local::now().naive_local())
+            .bind(user_id.to_string())
+            .bind(code_hash)
+            .execute(&mut *connection)
+            .await?
+            .rows_affected();
+        Ok(updated > 0)
+    }
+
+    async fn count_unused_recovery_codes(&self, user_id: Uuid) -> anyhow::Result<i64> {
+        let mut connection = self.db.get_pool().acquire().await?;
+        let query = r#"
+            SELECT count(1) as count
+            FROM recovery_codes
+            WHERE user_id = ? AND used_at IS NULL"#;
+
+        let row = sqlx::query(query)
+            .bind(user_id.to_string())
+            .fetch_one(&mut *connection)
+            .await?;
+        Ok(row.try_get("count")?)
+    }
 }
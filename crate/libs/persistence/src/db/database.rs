@@ -100,7 +100,9 @@ impl DatabaseInterface for DB {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::env::myenv::Env;
+    use crate::env::myenv::{
+        Env, DEFAULT_ACCESS_TOKEN_TTL_SECONDS, DEFAULT_REFRESH_TOKEN_TTL_SECONDS,
+    };
 
     #[tokio::test]
     async fn test_db_new() {
@@ -118,6 +120,15 @@ mod tests {
             app_key_main: "".to_string(),
             app_callback_url: "".to_string(),
             app_key_jwt: "".to_string(),
+            storage_backend: "local".to_string(),
+            storage_local_root: "".to_string(),
+            storage_s3_endpoint: "".to_string(),
+            storage_s3_bucket: "".to_string(),
+            storage_s3_region: "".to_string(),
+            storage_s3_access_key: "".to_string(),
+            storage_s3_secret_key: "".to_string(),
+            access_token_ttl_seconds: DEFAULT_ACCESS_TOKEN_TTL_SECONDS,
+            refresh_token_ttl_seconds: DEFAULT_REFRESH_TOKEN_TTL_SECONDS,
         };
         // Wrap it in an Arc and Box as required by the method signature
 
@@ -1,5 +1,14 @@
 use shaku::{Component, Interface};
 use std::env;
+use uuid::Uuid;
+
+pub const STORAGE_BACKEND_LOCAL: &str = "local";
+pub const STORAGE_BACKEND_S3: &str = "s3";
+// Kept out of the web `assets`, so uploads are only reachable through the access checked `/media` route
+pub const DEFAULT_STORAGE_LOCAL_ROOT: &str = "crate/application/web/uploads";
+// Access tokens are cheap to renew, the refresh token is what keeps a user logged in
+pub const DEFAULT_ACCESS_TOKEN_TTL_SECONDS: i64 = 15 * 60; // 15 minutes
+pub const DEFAULT_REFRESH_TOKEN_TTL_SECONDS: i64 = 30 * 24 * 60 * 60; // 30 days
 
 #[derive(Debug, Clone, Component)]
 #[shaku(interface = EnvInterface)]
@@ -14,6 +23,15 @@ pub struct Env {
     pub app_key_main: String,
     pub app_callback_url: String,
     pub app_key_jwt: String,
+    pub storage_backend: String,
+    pub storage_local_root: String,
+    pub storage_s3_endpoint: String,
+    pub storage_s3_bucket: String,
+    pub storage_s3_region: String,
+    pub storage_s3_access_key: String,
+    pub storage_s3_secret_key: String,
+    pub access_token_ttl_seconds: i64,
+    pub refresh_token_ttl_seconds: i64,
 }
 
 pub trait EnvInterface: Interface {
@@ -27,6 +45,15 @@ pub trait EnvInterface: Interface {
     fn get_app_key_main(&self) -> &str;
     fn get_app_callback_url(&self) -> &str;
     fn get_app_key_jwt(&self) -> &str;
+    fn get_storage_backend(&self) -> &str;
+    fn get_storage_local_root(&self) -> &str;
+    fn get_storage_s3_endpoint(&self) -> &str;
+    fn get_storage_s3_bucket(&self) -> &str;
+    fn get_storage_s3_region(&self) -> &str;
+    fn get_storage_s3_access_key(&self) -> &str;
+    fn get_storage_s3_secret_key(&self) -> &str;
+    fn get_access_token_ttl_seconds(&self) -> i64;
+    fn get_refresh_token_ttl_seconds(&self) -> i64;
 }
 
 impl EnvInterface for Env {
@@ -60,6 +87,33 @@ impl EnvInterface for Env {
     fn get_app_key_jwt(&self) -> &str {
         &self.app_key_jwt
     }
+    fn get_storage_backend(&self) -> &str {
+        &self.storage_backend
+    }
+    fn get_storage_local_root(&self) -> &str {
+        &self.storage_local_root
+    }
+    fn get_storage_s3_endpoint(&self) -> &str {
+        &self.storage_s3_endpoint
+    }
+    fn get_storage_s3_bucket(&self) -> &str {
+        &self.storage_s3_bucket
+    }
+    fn get_storage_s3_region(&self) -> &str {
+        &self.storage_s3_region
+    }
+    fn get_storage_s3_access_key(&self) -> &str {
+        &self.storage_s3_access_key
+    }
+    fn get_storage_s3_secret_key(&self) -> &str {
+        &self.storage_s3_secret_key
+    }
+    fn get_access_token_ttl_seconds(&self) -> i64 {
+        self.access_token_ttl_seconds
+    }
+    fn get_refresh_token_ttl_seconds(&self) -> i64 {
+        self.refresh_token_ttl_seconds
+    }
 }
 
 impl Default for Env {
@@ -81,6 +135,15 @@ impl Env {
             app_key_main: env::var("APP_KEY_MAIN").unwrap_or_else(|_| "".to_string()),
             app_callback_url: env::var("APP_CALLBACK_URL").unwrap_or_else(|_| "".to_string()),
             app_key_jwt: env::var("APP_KEY_JWT").unwrap_or_else(|_| "".to_string()),
+            storage_backend: env::var("STORAGE_BACKEND").unwrap_or_else(|_| STORAGE_BACKEND_LOCAL.to_string()),
+            storage_local_root: env::var("STORAGE_LOCAL_ROOT").unwrap_or_else(|_| DEFAULT_STORAGE_LOCAL_ROOT.to_string()),
+            storage_s3_endpoint: env::var("STORAGE_S3_ENDPOINT").unwrap_or_else(|_| "".to_string()),
+            storage_s3_bucket: env::var("STORAGE_S3_BUCKET").unwrap_or_else(|_| "".to_string()),
+            storage_s3_region: env::var("STORAGE_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            storage_s3_access_key: env::var("STORAGE_S3_ACCESS_KEY").unwrap_or_else(|_| "".to_string()),
+            storage_s3_secret_key: env::var("STORAGE_S3_SECRET_KEY").unwrap_or_else(|_| "".to_string()),
+            access_token_ttl_seconds: Self::seconds_var("ACCESS_TOKEN_TTL_SECONDS", DEFAULT_ACCESS_TOKEN_TTL_SECONDS),
+            refresh_token_ttl_seconds: Self::seconds_var("REFRESH_TOKEN_TTL_SECONDS", DEFAULT_REFRESH_TOKEN_TTL_SECONDS),
         };
         environment_variable.validate();
         environment_variable
@@ -94,7 +157,23 @@ impl Env {
     pub fn load_test() -> Env {
         dotenv::dotenv().ok();
         env::set_var("DATABASE_URL", "sqlite::memory:");
-        Self::new()
+        let mut environment_variable = Self::new();
+        // A fresh directory per call, so tests neither write into the tree nor share uploads.
+        // Removing it is up to the test
+        environment_variable.storage_local_root = env::temp_dir()
+            .join(format!("chaty-test-{}", Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        environment_variable
+    }
+
+    fn seconds_var(key: &str, default: i64) -> i64 {
+        match env::var(key) {
+            Ok(value) if !value.is_empty() => value
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a number of seconds", key)),
+            _ => default,
+        }
     }
 
     fn validate(&self) {
@@ -131,6 +210,35 @@ impl Env {
         if self.app_key_jwt.is_empty() {
             panic!("App jwt key is empty");
         }
+
+        // validate storage
+        match self.storage_backend.as_str() {
+            STORAGE_BACKEND_LOCAL => {
+                if self.storage_local_root.is_empty() {
+                    panic!("Storage local root is empty");
+                }
+            }
+            STORAGE_BACKEND_S3 => {
+                if self.storage_s3_endpoint.is_empty() {
+                    panic!("Storage s3 endpoint is empty");
+                }
+                if self.storage_s3_bucket.is_empty() {
+                    panic!("Storage s3 bucket is empty");
+                }
+                if self.storage_s3_access_key.is_empty() || self.storage_s3_secret_key.is_empty() {
+                    panic!("Storage s3 credentials are empty");
+                }
+            }
+            backend => panic!("Unknown storage backend {}", backend),
+        }
+
+        // validate token lifetimes
+        if self.access_token_ttl_seconds <= 0 {
+            panic!("Access token lifetime must be positive");
+        }
+        if self.refresh_token_ttl_seconds < self.access_token_ttl_seconds {
+            panic!("Refresh token lifetime must not be shorter than the access token lifetime");
+        }
     }
 }
 
@@ -164,6 +272,15 @@ mod tests {
             app_key_main: "".to_string(),
             app_callback_url: "".to_string(),
             app_key_jwt: "".to_string(),
+            storage_backend: "local".to_string(),
+            storage_local_root: "".to_string(),
+            storage_s3_endpoint: "".to_string(),
+            storage_s3_bucket: "".to_string(),
+            storage_s3_region: "".to_string(),
+            storage_s3_access_key: "".to_string(),
+            storage_s3_secret_key: "".to_string(),
+            access_token_ttl_seconds: DEFAULT_ACCESS_TOKEN_TTL_SECONDS,
+            refresh_token_ttl_seconds: DEFAULT_REFRESH_TOKEN_TTL_SECONDS,
         };
         env.validate();
     }
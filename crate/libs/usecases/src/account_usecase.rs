@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use commons::generic_errors::GenericError;
+use commons::secret_tokens::hash_secret;
+use jwt::AccessClaims;
+use log::info;
+use mail::SendEmail;
+use persistence::env::myenv::EnvInterface;
+use sessions::services::SessionServiceInterface;
+use shaku::{Component, Interface};
+use users::user::{EmailChange, User};
+use users::user_services::UserServiceInterface;
+use uuid::Uuid;
+
+use crate::register_usecase::{validate_email, validate_password};
+
+pub const EMAIL_CHANGE_TTL_HOURS: i64 = 24;
+
+const INVALID_CONFIRMATION_LINK: &str = "This confirmation link is invalid or has expired";
+
+#[derive(Component)]
+#[shaku(interface = AccountUsecase)]
+pub struct AccountUsecaseImpl {
+    #[shaku(inject)]
+    user_service: Arc<dyn UserServiceInterface>,
+    #[shaku(inject)]
+    session_service: Arc<dyn SessionServiceInterface>,
+    #[shaku(inject)]
+    env: Arc<dyn EnvInterface>,
+    #[shaku(inject)]
+    mail: Arc<dyn SendEmail>,
+}
+
+#[async_trait::async_trait]
+pub trait AccountUsecase: Interface {
+    /// Replaces the password after checking the current one.
+    /// The user's other sessions are logged out, this one stays.
+    async fn change_password(
+        &self,
+        claims: &AccessClaims,
+        current_password: &str,
+        new_password: &str,
+        password_confirmation: &str,
+    ) -> anyhow::Result<()>;
+    /// Keeps the new address as pending and emails it a confirmation link,
+    /// the current address is told about the change.
+    async fn request_email_change(
+        &self,
+        claims: &AccessClaims,
+        new_email: &str,
+        current_password: &str,
+    ) -> anyhow::Result<()>;
+    /// The address waiting for confirmation, if any.
+    async fn get_pending_email(&self, claims: &AccessClaims) -> anyhow::Result<Option<String>>;
+    /// Swaps in the pending address, the link stops working.
+    async fn confirm_email_change(&self, token: &str) -> anyhow::Result<()>;
+}
+
+#[async_trait::async_trait]
+impl AccountUsecase for AccountUsecaseImpl {
+    async fn change_password(
+        &self,
+        claims: &AccessClaims,
+        current_password: &str,
+        new_password: &str,
+        password_confirmation: &str,
+    ) -> anyhow::Result<()> {
+        let user = self.current_user(claims, current_password).await?;
+        validate_password(new_password)?;
+        if new_password != password_confirmation {
+            return Err(GenericError::invalid_input(
+                "Passwords do not match".to_string(),
+            ));
+        }
+
+        self.user_service
+            .update_password(user.id, &User::hash_password(new_password)?)
+            .await?;
+        // Whoever else knew the old password is logged out, reset links sent before die too
+        self.session_service
+            .delete_other_sessions(user.id, &claims.jti)
+            .await?;
+        self.user_service
+            .delete_password_resets_by_user(user.id)
+            .await?;
+        info!("Password of user {} was changed", user.id);
+        Ok(())
+    }
+
+    async fn request_email_change(
+        &self,
+        claims: &AccessClaims,
+        new_email: &str,
+        current_password: &str,
+    ) -> anyhow::Result<()> {
+        let user = self.current_user(claims, current_password).await?;
+        let new_email = new_email.trim();
+        validate_email(new_email)?;
+        if new_email.eq_ignore_ascii_case(&user.email) {
+            return Err(GenericError::invalid_input(
+                "This is already your email".to_string(),
+            ));
+        }
+        if self.user_service.is_email_taken(new_email).await? {
+            return Err(email_in_use());
+        }
+
+        // Only the latest request can be confirmed
+        self.user_service
+            .delete_email_changes_by_user(user.id)
+            .await?;
+        let (change, token) = EmailChange::generate(
+            user.id,
+            new_email.to_string(),
+            chrono::Duration::hours(EMAIL_CHANGE_TTL_HOURS),
+        );
+        self.user_service.create_email_change(&change).await?;
+
+        self.send_confirmation_email(&user, new_email, &token)
+            .await?;
+        self.send_change_notice(&user, new_email).await
+    }
+
+    async fn get_pending_email(&self, claims: &AccessClaims) -> anyhow::Result<Option<String>> {
+        let change = self
+            .user_service
+            .get_pending_email_change(claims.user_id.parse()?)
+            .await?;
+        Ok(change.map(|change| change.new_email))
+    }
+
+    async fn confirm_email_change(&self, token: &str) -> anyhow::Result<()> {
+        let change = match self
+            .user_service
+            .consume_email_change(&hash_secret(token))
+            .await?
+        {
+            Some(change) if !change.is_expired() => change,
+            _ => {
+                return Err(GenericError::invalid_input(
+                    INVALID_CONFIRMATION_LINK.to_string(),
+                ))
+            }
+        };
+
+        // Someone may have signed up with the address while the link was waiting
+        self.user_service
+            .update_email(change.user_id, &change.new_email)
+            .await
+            .map_err(|e| {
+                if e.to_string().contains("UNIQUE constraint failed") {
+                    email_in_use()
+                } else {
+                    GenericError::unknown(e)
+                }
+            })?;
+        info!("Email of user {} was changed", change.user_id);
+        Ok(())
+    }
+}
+
+impl AccountUsecaseImpl {
+    /// The user behind the claims, as long as they typed their password.
+    async fn current_user(&self, claims: &AccessClaims, password: &str) -> anyhow::Result<User> {
+        let user_id: Uuid = claims.user_id.parse()?;
+        let user = self.user_service.get_user_by_uuid(user_id).await?;
+        if !user.match_password(password) {
+            return Err(GenericError::invalid_input(
+                "Current password is incorrect".to_string(),
+            ));
+        }
+        Ok(user)
+    }
+
+    async fn send_confirmation_email(
+        &self,
+        user: &User,
+        new_email: &str,
+        token: &str,
+    ) -> anyhow::Result<()> {
+        let button = format!(
+            r#"<a href="{}/confirm-email/{}">Confirm email</a>"#,
+            self.env.get_app_callback_url(),
+            token
+        );
+        let message = format!(
+            r#"
+        You asked to use this address for your account {}.
+        Click the link below within {} hours to confirm it,
+        {}
+        Until then, your account keeps its current email. "#,
+            user.username, EMAIL_CHANGE_TTL_HOURS, button
+        );
+        self.mail
+            .send_email(
+                user.username.as_str(),
+                new_email,
+                "Confirm your new email",
+                &message,
+            )
+            .await
+    }
+
+    async fn send_change_notice(&self, user: &User, new_email: &str) -> anyhow::Result<()> {
+        let message = format!(
+            r#"
+        Someone asked to change the email of your account {} to {}.
+        The change only happens once the link sent to that address is opened.
+        If it was not you, change your password right away. "#,
+            user.username, new_email
+        );
+        self.mail
+            .send_email(
+                user.username.as_str(),
+                user.email.as_str(),
+                "Your email is being changed",
+                &message,
+            )
+            .await
+    }
+}
+
+fn email_in_use() -> anyhow::Error {
+    GenericError::invalid_input("Email is already in use".to_string())
+}
@@ -2,32 +2,295 @@ use std::sync::Arc;
 
 use chats::{
     chat_services::ChatServiceInterface,
-    entity::{ChatMessages, MessageBox},
+    entity::{
+        Attachment, ChatMember, ChatMemberProfile, ChatMessages, ChatPreview, DeleteScope, Message,
+        MessageBox, MessageCursor, MessageReaction, MessageReadReceipt, MessageRevision,
+        MessageSearch, MessageSearchResults, ThreadMessages,
+    },
+    events::{ChatEvent, ChatEventBrokerInterface},
 };
+use commons::generic_errors::GenericError;
+use log::warn;
 use shaku::{Component, Interface};
+use storage::BlobStorage;
+use uuid::Uuid;
+
+use crate::group_chat_usecase::map_permission_error;
+use crate::images::{self, ProcessedImage};
+use crate::utils::{self, MediaFolder};
+
+const MISSED_EVENTS_LIMIT: i64 = 500;
+pub const DEFAULT_MESSAGE_PAGE_SIZE: i64 = 30;
+pub const MAX_MESSAGE_PAGE_SIZE: i64 = 100;
+/// Offered in the reaction picker, and the only reactions accepted.
+pub const REACTION_PALETTE: [&str; 6] = ["👍", "❤️", "😂", "😮", "😢", "🙏"];
+pub const MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+pub const MAX_ATTACHMENTS_PER_MESSAGE: usize = 5;
+const MAX_FILE_NAME_LENGTH: usize = 255;
+// Fits the bubble width, the full image is one click away
+const ATTACHMENT_THUMBNAIL_SIZE: u32 = 320;
+
+/// A file as the client sent it, nothing about it is trusted yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentUpload {
+    pub file_name: String,
+    pub data: Vec<u8>,
+}
+
+// Executables, fonts and anything `infer` cannot recognize are refused
+fn validate_attachment(upload: &AttachmentUpload) -> anyhow::Result<infer::Type> {
+    if upload.data.is_empty() {
+        return Err(GenericError::invalid_input(format!(
+            "{} is empty",
+            upload.file_name
+        )));
+    }
+    if upload.data.len() > MAX_ATTACHMENT_SIZE {
+        return Err(GenericError::invalid_input(format!(
+            "{} is larger than {} MB",
+            upload.file_name,
+            MAX_ATTACHMENT_SIZE / 1024 / 1024
+        )));
+    }
+    infer::get(&upload.data)
+        .filter(|kind| {
+            matches!(
+                kind.matcher_type(),
+                infer::MatcherType::Image
+                    | infer::MatcherType::Audio
+                    | infer::MatcherType::Video
+                    | infer::MatcherType::Archive
+                    | infer::MatcherType::Doc
+                    | infer::MatcherType::Book
+                    | infer::MatcherType::Text
+            )
+        })
+        .ok_or_else(|| {
+            GenericError::invalid_input(format!(
+                "{} is not a supported file type",
+                upload.file_name
+            ))
+        })
+}
+
+/// An attachment ready to be stored, images are already stripped of their metadata.
+struct PreparedAttachment {
+    data: Vec<u8>,
+    extension: &'static str,
+    content_type: &'static str,
+    thumbnail: Option<ProcessedImage>,
+}
+
+async fn prepare_attachment(
+    upload: &AttachmentUpload,
+    kind: infer::Type,
+) -> anyhow::Result<PreparedAttachment> {
+    if kind.matcher_type() != infer::MatcherType::Image {
+        return Ok(PreparedAttachment {
+            data: upload.data.clone(),
+            extension: kind.extension(),
+            content_type: kind.mime_type(),
+            thumbnail: None,
+        });
+    }
+
+    let data = upload.data.clone();
+    let (image, thumbnail) = tokio::task::spawn_blocking(move || {
+        images::strip_and_thumbnail(&data, ATTACHMENT_THUMBNAIL_SIZE)
+    })
+    .await?
+    .map_err(|e| GenericError::invalid_input(format!("{}: {}", upload.file_name, e)))?;
+    Ok(PreparedAttachment {
+        extension: image.extension(),
+        content_type: image.content_type(),
+        data: image.data,
+        thumbnail: Some(thumbnail),
+    })
+}
+
+// Only the last path segment is kept, browsers may send full paths
+fn sanitize_file_name(file_name: &str, extension: &str) -> String {
+    let file_name = file_name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .trim();
+    if file_name.is_empty() {
+        return format!("file.{}", extension);
+    }
+    file_name.chars().take(MAX_FILE_NAME_LENGTH).collect()
+}
+
+fn validate_reaction(reaction: &str) -> anyhow::Result<&str> {
+    let reaction = reaction.trim();
+    if !REACTION_PALETTE.contains(&reaction) {
+        return Err(GenericError::invalid_input(String::from(
+            "Pick one of the offered reactions",
+        )));
+    }
+    Ok(reaction)
+}
 
 #[derive(Component)]
 #[shaku(interface = ChatUsecase)]
 pub struct ChatUsecaseImpl {
     #[shaku(inject)]
     chats_service: Arc<dyn ChatServiceInterface>,
+    #[shaku(inject)]
+    chat_event_broker: Arc<dyn ChatEventBrokerInterface>,
+    #[shaku(inject)]
+    storage: Arc<dyn BlobStorage>,
 }
 
 #[async_trait::async_trait]
 pub trait ChatUsecase: Interface {
-    async fn get_messages_of_chat(&self, chat_id: &str) -> anyhow::Result<ChatMessages>;
+    async fn get_messages_of_chat(
+        &self,
+        chat_id: &str,
+        user_id: &str,
+        cursor: MessageCursor,
+        page_size: Option<i64>,
+    ) -> anyhow::Result<ChatMessages>;
+    async fn get_user_chat_list(&self, user_id: &str) -> anyhow::Result<Vec<ChatPreview>>;
+    /// Sends a text message, optionally as a reply quoting another message of the chat.
     async fn send_message_to_chat(
         &self,
         chat_id: &str,
         sender_id: &str,
         message: &str,
+        reply_to_message_id: Option<&str>,
+    ) -> anyhow::Result<MessageBox>;
+    /// Sends the files as one message, the caption may be empty.
+    async fn send_attachments_to_chat(
+        &self,
+        chat_id: &str,
+        sender_id: &str,
+        caption: &str,
+        uploads: &[AttachmentUpload],
+    ) -> anyhow::Result<MessageBox>;
+    /// Replies in the thread under a message, shown to every member without reaching
+    /// the main timeline.
+    async fn send_thread_reply(
+        &self,
+        root_message_id: &str,
+        sender_id: &str,
+        message: &str,
+    ) -> anyhow::Result<MessageBox>;
+    async fn get_thread_messages(
+        &self,
+        root_message_id: &str,
+        user_id: &str,
+        cursor: MessageCursor,
+        page_size: Option<i64>,
+    ) -> anyhow::Result<ThreadMessages>;
+    /// Searches the messages of every chat the user is in, see `MessageSearch` for the
+    /// query syntax. Pages go back in time from the `before` message.
+    async fn search_messages(
+        &self,
+        user_id: &str,
+        query: &str,
+        before: Option<Uuid>,
+        page_size: Option<i64>,
+    ) -> anyhow::Result<MessageSearchResults>;
+    async fn get_missed_chat_events(
+        &self,
+        user_id: &str,
+        last_event_id: &str,
+    ) -> anyhow::Result<Vec<ChatEvent>>;
+    /// Marks the chat as read up to `message_id` and tells the senders, returns how many
+    /// messages were newly read.
+    async fn mark_chat_read(
+        &self,
+        chat_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<usize>;
+    /// Like `mark_chat_read`, up to `message_id` in the thread under the root.
+    async fn mark_thread_read(
+        &self,
+        root_message_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<usize>;
+    /// Adds the reaction, or takes it back when the user already reacted with it.
+    /// Returns every reaction on the message afterwards.
+    async fn toggle_reaction(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<Vec<(String, MessageReaction)>>;
+    /// Replaces the content of the user's own message and shows the edit to every member.
+    async fn edit_message(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        content: &str,
     ) -> anyhow::Result<MessageBox>;
+    /// Deletes the user's own message, returns the tombstone when it is gone for everyone.
+    async fn delete_message(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        scope: DeleteScope,
+    ) -> anyhow::Result<Message>;
+    /// Earlier versions of a message, for any member of its chat.
+    async fn get_message_revisions(
+        &self,
+        message_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<Vec<MessageRevision>>;
 }
 
 #[async_trait::async_trait]
 impl ChatUsecase for ChatUsecaseImpl {
-    async fn get_messages_of_chat(&self, chat_id: &str) -> anyhow::Result<ChatMessages> {
-        self.chats_service.get_messages_of_chat(chat_id).await
+    async fn get_messages_of_chat(
+        &self,
+        chat_id: &str,
+        user_id: &str,
+        cursor: MessageCursor,
+        page_size: Option<i64>,
+    ) -> anyhow::Result<ChatMessages> {
+        let members = self
+            .chats_service
+            .get_chat_members(chat_id)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        let user_uuid: Uuid = user_id.parse()?;
+        if !members.iter().any(|member| member.user_id == user_uuid) {
+            return Err(GenericError::unauthorized());
+        }
+
+        let page_size = page_size
+            .unwrap_or(DEFAULT_MESSAGE_PAGE_SIZE)
+            .clamp(1, MAX_MESSAGE_PAGE_SIZE);
+        let chat_messages = self
+            .chats_service
+            .get_messages_of_chat(chat_id, user_id, cursor, page_size)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        let chat = self
+            .chats_service
+            .get_chat(chat_id)
+            .await
+            .map_err(GenericError::unknown)?
+            .ok_or_else(|| GenericError::invalid_input(String::from("Chat not found")))?;
+
+        Ok(ChatMessages {
+            chat_name: chat.name,
+            is_group: chat.is_group,
+            chat_members: members,
+            ..chat_messages
+        })
+    }
+
+    async fn get_user_chat_list(&self, user_id: &str) -> anyhow::Result<Vec<ChatPreview>> {
+        self.chats_service
+            .get_user_chat_list(user_id)
+            .await
+            .map_err(GenericError::unknown)
     }
 
     async fn send_message_to_chat(
@@ -35,9 +298,418 @@ impl ChatUsecase for ChatUsecaseImpl {
         chat_id: &str,
         sender_id: &str,
         message: &str,
+        reply_to_message_id: Option<&str>,
+    ) -> anyhow::Result<MessageBox> {
+        let members = self
+            .chats_service
+            .get_chat_members(chat_id)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        let sender_uuid: Uuid = sender_id.parse()?;
+        if !members.iter().any(|member| member.user_id == sender_uuid) {
+            return Err(GenericError::unauthorized());
+        }
+
+        let message = self
+            .chats_service
+            .send_message_to_chat(chat_id, sender_id, message, reply_to_message_id)
+            .await?;
+
+        self.chat_event_broker
+            .publish(ChatEvent::new_message(&members, message.clone()));
+        Ok(message)
+    }
+
+    async fn send_attachments_to_chat(
+        &self,
+        chat_id: &str,
+        sender_id: &str,
+        caption: &str,
+        uploads: &[AttachmentUpload],
     ) -> anyhow::Result<MessageBox> {
+        if uploads.is_empty() || uploads.len() > MAX_ATTACHMENTS_PER_MESSAGE {
+            return Err(GenericError::invalid_input(format!(
+                "Attach between 1 and {} files",
+                MAX_ATTACHMENTS_PER_MESSAGE
+            )));
+        }
+        let kinds = uploads
+            .iter()
+            .map(validate_attachment)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let members = self
+            .chats_service
+            .get_chat_members(chat_id)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        let sender_uuid: Uuid = sender_id.parse()?;
+        if !members.iter().any(|member| member.user_id == sender_uuid) {
+            return Err(GenericError::unauthorized());
+        }
+
+        let message = Message::new_attachment_message(
+            chat_id.parse()?,
+            sender_uuid,
+            caption.trim().to_string(),
+        );
+        // Everything is processed before the first file is stored, so a bad image stores nothing
+        let mut prepared = Vec::with_capacity(uploads.len());
+        for (upload, kind) in uploads.iter().zip(kinds) {
+            prepared.push(prepare_attachment(upload, kind).await?);
+        }
+
+        let mut attachments = Vec::with_capacity(uploads.len());
+        for (upload, prepared) in uploads.iter().zip(prepared) {
+            let file_url = utils::save_uploaded_file(
+                self.storage.as_ref(),
+                MediaFolder::Attachments,
+                &format!("attachment_{}", message.id),
+                prepared.extension,
+                &prepared.data,
+            )
+            .await
+            .map_err(GenericError::unknown)?;
+            let mut attachment = Attachment::new(
+                message.id,
+                file_url,
+                sanitize_file_name(&upload.file_name, prepared.extension),
+                prepared.content_type.to_string(),
+                prepared.data.len() as i32,
+            );
+            if let Some(thumbnail) = &prepared.thumbnail {
+                let thumbnail_url = utils::save_processed_image(
+                    self.storage.as_ref(),
+                    MediaFolder::Attachments,
+                    &format!("thumbnail_{}", message.id),
+                    thumbnail,
+                )
+                .await
+                .map_err(GenericError::unknown)?;
+                attachment.thumbnail_url = Some(thumbnail_url);
+            }
+            attachments.push(attachment);
+        }
+
+        let message = self
+            .chats_service
+            .send_attachments_to_chat(&message, &attachments)
+            .await?;
+
+        self.chat_event_broker
+            .publish(ChatEvent::new_message(&members, message.clone()));
+        Ok(message)
+    }
+
+    async fn send_thread_reply(
+        &self,
+        root_message_id: &str,
+        sender_id: &str,
+        message: &str,
+    ) -> anyhow::Result<MessageBox> {
+        let (root, members) = self
+            .load_message_for_member(root_message_id, sender_id)
+            .await?;
+        let root_message_id = root.id.to_string();
+        let reply = self
+            .chats_service
+            .send_thread_reply(&root_message_id, sender_id, message)
+            .await
+            .map_err(map_permission_error)?;
+
+        let thread = self
+            .chats_service
+            .get_thread_summary(&root_message_id)
+            .await
+            .map_err(GenericError::unknown)?;
+        if let Some(thread) = thread {
+            self.chat_event_broker.publish(ChatEvent::new_thread_reply(
+                &members,
+                reply.clone(),
+                thread,
+            ));
+        }
+        Ok(reply)
+    }
+
+    async fn get_thread_messages(
+        &self,
+        root_message_id: &str,
+        user_id: &str,
+        cursor: MessageCursor,
+        page_size: Option<i64>,
+    ) -> anyhow::Result<ThreadMessages> {
+        let (root, _) = self
+            .load_message_for_member(root_message_id, user_id)
+            .await?;
+        let page_size = page_size
+            .unwrap_or(DEFAULT_MESSAGE_PAGE_SIZE)
+            .clamp(1, MAX_MESSAGE_PAGE_SIZE);
         self.chats_service
-            .send_message_to_chat(chat_id, sender_id, message)
+            .get_thread_messages(&root.id.to_string(), user_id, cursor, page_size)
+            .await
+            .map_err(map_permission_error)
+    }
+
+    async fn search_messages(
+        &self,
+        user_id: &str,
+        query: &str,
+        before: Option<Uuid>,
+        page_size: Option<i64>,
+    ) -> anyhow::Result<MessageSearchResults> {
+        let search = MessageSearch::parse(query)?;
+        let page_size = page_size
+            .unwrap_or(DEFAULT_MESSAGE_PAGE_SIZE)
+            .clamp(1, MAX_MESSAGE_PAGE_SIZE);
+        self.chats_service
+            .search_messages(user_id, &search, before, page_size)
+            .await
+            .map_err(GenericError::unknown)
+    }
+
+    async fn get_missed_chat_events(
+        &self,
+        user_id: &str,
+        last_event_id: &str,
+    ) -> anyhow::Result<Vec<ChatEvent>> {
+        let user_uuid: Uuid = user_id.parse()?;
+        let last_message_id: Uuid = last_event_id
+            .parse()
+            .map_err(|_| GenericError::invalid_input(String::from("Invalid last event id")))?;
+
+        let messages = self
+            .chats_service
+            .get_messages_of_user_after(user_id, &last_message_id.to_string(), MISSED_EVENTS_LIMIT)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        Ok(messages
+            .into_iter()
+            .map(|message| ChatEvent::missed_message(user_uuid, message))
+            .collect())
+    }
+
+    async fn mark_chat_read(
+        &self,
+        chat_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<usize> {
+        let user_uuid: Uuid = user_id.parse()?;
+        let message_uuid: Uuid = message_id
+            .parse()
+            .map_err(|_| GenericError::invalid_input(String::from("Invalid message id")))?;
+
+        let reader = self.find_reader(chat_id, &user_uuid).await?;
+        let receipts = self
+            .chats_service
+            .mark_chat_read(chat_id, user_id, &message_uuid.to_string())
             .await
+            .map_err(GenericError::unknown)?;
+        Ok(self.publish_read_receipts(&reader, receipts))
+    }
+
+    async fn mark_thread_read(
+        &self,
+        root_message_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> anyhow::Result<usize> {
+        let user_uuid: Uuid = user_id.parse()?;
+        let message_uuid: Uuid = message_id
+            .parse()
+            .map_err(|_| GenericError::invalid_input(String::from("Invalid message id")))?;
+        let root = self
+            .chats_service
+            .get_message(root_message_id)
+            .await
+            .map_err(GenericError::unknown)?
+            .ok_or_else(|| GenericError::invalid_input(String::from("Thread not found")))?;
+
+        let reader = self
+            .find_reader(&root.chat_id.to_string(), &user_uuid)
+            .await?;
+        let receipts = self
+            .chats_service
+            .mark_thread_read(&root.id.to_string(), user_id, &message_uuid.to_string())
+            .await
+            .map_err(GenericError::unknown)?;
+        Ok(self.publish_read_receipts(&reader, receipts))
+    }
+
+    async fn toggle_reaction(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        reaction: &str,
+    ) -> anyhow::Result<Vec<(String, MessageReaction)>> {
+        let reaction = validate_reaction(reaction)?;
+        let (message, members) = self.load_message_for_member(message_id, user_id).await?;
+        if message.is_deleted() {
+            return Err(GenericError::invalid_input(String::from(
+                "Deleted messages cannot be reacted to",
+            )));
+        }
+
+        let message_id = message.id.to_string();
+        self.chats_service
+            .toggle_reaction(&message_id, user_id, reaction)
+            .await
+            .map_err(GenericError::unknown)?;
+        let reactions = self
+            .chats_service
+            .get_message_reactions(&message_id)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        self.chat_event_broker.publish(ChatEvent::reactions_changed(
+            message.chat_id,
+            &members,
+            message.id,
+            reactions.clone(),
+        ));
+        Ok(reactions)
+    }
+
+    async fn edit_message(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        content: &str,
+    ) -> anyhow::Result<MessageBox> {
+        let (message, members) = self.load_message_for_member(message_id, user_id).await?;
+        let content = content.trim();
+        // Attachments keep their files, so only their caption may end up empty
+        if content.is_empty() && !message.is_attachment() {
+            return Err(GenericError::invalid_input(String::from(
+                "Message cannot be empty",
+            )));
+        }
+
+        let edited = self
+            .chats_service
+            .edit_message(&message.id.to_string(), user_id, content)
+            .await
+            .map_err(map_permission_error)?;
+        if edited.0.edited_at != message.edited_at {
+            self.chat_event_broker
+                .publish(ChatEvent::message_edited(&members, edited.clone()));
+        }
+        Ok(edited)
+    }
+
+    async fn delete_message(
+        &self,
+        message_id: &str,
+        user_id: &str,
+        scope: DeleteScope,
+    ) -> anyhow::Result<Message> {
+        let (message, members) = self.load_message_for_member(message_id, user_id).await?;
+        let (deleted, attachments) = self
+            .chats_service
+            .delete_message(&message.id.to_string(), user_id, scope)
+            .await
+            .map_err(map_permission_error)?;
+
+        // The message is already gone, a file left behind is not worth failing over
+        for attachment in &attachments {
+            for url in std::iter::once(&attachment.file_url).chain(&attachment.thumbnail_url) {
+                if let Err(e) = utils::remove_uploaded_file(self.storage.as_ref(), url).await {
+                    warn!(
+                        "Failed to remove {} of deleted message {}: {}",
+                        url, deleted.id, e
+                    );
+                }
+            }
+        }
+
+        let event = match scope {
+            DeleteScope::ForMe => ChatEvent::message_hidden(deleted.sender_id, &deleted),
+            DeleteScope::ForEveryone => ChatEvent::message_deleted(&members, deleted.clone()),
+        };
+        self.chat_event_broker.publish(event);
+        Ok(deleted)
+    }
+
+    async fn get_message_revisions(
+        &self,
+        message_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<Vec<MessageRevision>> {
+        let (message, _) = self.load_message_for_member(message_id, user_id).await?;
+        self.chats_service
+            .get_message_revisions(&message.id.to_string())
+            .await
+            .map_err(GenericError::unknown)
+    }
+}
+
+impl ChatUsecaseImpl {
+    async fn load_message_for_member(
+        &self,
+        message_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<(Message, Vec<ChatMember>)> {
+        let message = self
+            .chats_service
+            .get_message(message_id)
+            .await
+            .map_err(GenericError::unknown)?
+            .ok_or_else(|| GenericError::invalid_input(String::from("Message not found")))?;
+
+        let chat_id = message.chat_id.to_string();
+        let members = self
+            .chats_service
+            .get_chat_members(&chat_id)
+            .await
+            .map_err(GenericError::unknown)?;
+        let user_uuid: Uuid = user_id.parse()?;
+        if !members.iter().any(|member| member.user_id == user_uuid) {
+            return Err(GenericError::unauthorized());
+        }
+        Ok((message, members))
+    }
+
+    async fn find_reader(
+        &self,
+        chat_id: &str,
+        user_id: &Uuid,
+    ) -> anyhow::Result<ChatMemberProfile> {
+        self.chats_service
+            .get_chat_member_profiles(chat_id)
+            .await
+            .map_err(GenericError::unknown)?
+            .into_iter()
+            .find(|profile| profile.member.user_id == *user_id)
+            .ok_or_else(GenericError::unauthorized)
+    }
+
+    // Tells the senders their messages were read, returns how many were
+    fn publish_read_receipts(
+        &self,
+        reader: &ChatMemberProfile,
+        receipts: Vec<(Uuid, MessageReadReceipt)>,
+    ) -> usize {
+        if receipts.is_empty() {
+            return 0;
+        }
+
+        let mut senders: Vec<Uuid> = receipts.iter().map(|(sender_id, _)| *sender_id).collect();
+        senders.sort();
+        senders.dedup();
+        let read_count = receipts.len();
+        let receipts = receipts
+            .into_iter()
+            .map(|(_, receipt)| (reader.name.clone(), receipt))
+            .collect();
+        self.chat_event_broker.publish(ChatEvent::messages_read(
+            reader.member.chat_id,
+            senders,
+            receipts,
+        ));
+        read_count
     }
 }
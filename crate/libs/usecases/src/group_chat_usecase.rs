@@ -0,0 +1,371 @@
+use std::sync::Arc;
+
+use chats::{
+    chat_services::ChatServiceInterface,
+    entity::{Chat, ChatMemberProfile, ChatRole},
+    events::{ChatEvent, ChatEventBrokerInterface},
+};
+use commons::generic_errors::GenericError;
+use log::{info, warn};
+use shaku::{Component, Interface};
+use storage::BlobStorage;
+use users::user_services::UserServiceInterface;
+use uuid::Uuid;
+
+use crate::images;
+use crate::utils::{self, MediaFolder};
+
+const MAX_GROUP_NAME_LENGTH: usize = 100;
+// Group pictures are only ever shown as small circles
+const GROUP_AVATAR_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupChat {
+    pub chat: Chat,
+    pub members: Vec<ChatMemberProfile>,
+}
+
+impl GroupChat {
+    pub fn is_member(&self, user_id: &Uuid) -> bool {
+        self.members
+            .iter()
+            .any(|profile| &profile.member.user_id == user_id)
+    }
+
+    pub fn role_of(&self, user_id: &Uuid) -> Option<ChatRole> {
+        self.members
+            .iter()
+            .find(|profile| &profile.member.user_id == user_id)
+            .map(|profile| profile.member.role)
+    }
+}
+
+fn validate_group_name(name: &str) -> anyhow::Result<&str> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_GROUP_NAME_LENGTH {
+        return Err(GenericError::invalid_input(format!(
+            "Group name must be between 1 and {} characters",
+            MAX_GROUP_NAME_LENGTH
+        )));
+    }
+    Ok(name)
+}
+
+#[derive(Component)]
+#[shaku(interface = GroupChatUsecase)]
+pub struct GroupChatUsecaseImpl {
+    #[shaku(inject)]
+    chats_service: Arc<dyn ChatServiceInterface>,
+    #[shaku(inject)]
+    user_service: Arc<dyn UserServiceInterface>,
+    #[shaku(inject)]
+    storage: Arc<dyn BlobStorage>,
+    #[shaku(inject)]
+    chat_event_broker: Arc<dyn ChatEventBrokerInterface>,
+}
+
+#[async_trait::async_trait]
+pub trait GroupChatUsecase: Interface {
+    async fn create_group(
+        &self,
+        creator_id: &str,
+        name: &str,
+        member_usernames: &[String],
+    ) -> anyhow::Result<GroupChat>;
+    async fn get_group(&self, chat_id: &str, user_id: &str) -> anyhow::Result<GroupChat>;
+    async fn add_member(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        username: &str,
+    ) -> anyhow::Result<GroupChat>;
+    async fn remove_member(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        member_id: &str,
+    ) -> anyhow::Result<GroupChat>;
+    async fn leave_group(&self, chat_id: &str, user_id: &str) -> anyhow::Result<()>;
+    async fn rename_group(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        name: &str,
+    ) -> anyhow::Result<GroupChat>;
+    async fn update_group_avatar(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        image: &[u8],
+    ) -> anyhow::Result<GroupChat>;
+    async fn set_member_role(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        member_id: &str,
+        role: ChatRole,
+    ) -> anyhow::Result<GroupChat>;
+    async fn transfer_ownership(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        new_owner_id: &str,
+    ) -> anyhow::Result<GroupChat>;
+}
+
+// Permission and validation failures from the chat service are already meant for the user.
+pub(crate) fn map_permission_error(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref::<GenericError>() {
+        Some(_) => e,
+        None => GenericError::unknown(e),
+    }
+}
+
+// Open chats of everyone in the group before or after the change follow it live.
+pub(crate) fn publish_members_changed(
+    chat_event_broker: &dyn ChatEventBrokerInterface,
+    previous_members: &[ChatMemberProfile],
+    group: &GroupChat,
+) {
+    chat_event_broker.publish(ChatEvent::members_changed(
+        group.chat.id,
+        previous_members,
+        group.members.clone(),
+    ));
+}
+
+// Any chat that is not a group is not found, the invite links rely on that too.
+pub(crate) async fn load_group(
+    chats_service: &dyn ChatServiceInterface,
+    chat_id: &str,
+) -> anyhow::Result<GroupChat> {
+    let chat = chats_service
+        .get_chat(chat_id)
+        .await
+        .map_err(GenericError::unknown)?
+        .filter(|chat| chat.is_group)
+        .ok_or_else(|| GenericError::invalid_input(String::from("Group not found")))?;
+
+    let members = chats_service
+        .get_chat_member_profiles(chat_id)
+        .await
+        .map_err(GenericError::unknown)?;
+
+    Ok(GroupChat { chat, members })
+}
+
+impl GroupChatUsecaseImpl {
+    async fn load_group_as_member(&self, chat_id: &str, user_id: &str) -> anyhow::Result<GroupChat> {
+        let group = load_group(self.chats_service.as_ref(), chat_id).await?;
+        if !group.is_member(&user_id.parse()?) {
+            return Err(GenericError::unauthorized());
+        }
+        Ok(group)
+    }
+}
+
+#[async_trait::async_trait]
+impl GroupChatUsecase for GroupChatUsecaseImpl {
+    async fn create_group(
+        &self,
+        creator_id: &str,
+        name: &str,
+        member_usernames: &[String],
+    ) -> anyhow::Result<GroupChat> {
+        let name = validate_group_name(name)?;
+
+        let creator_id: Uuid = creator_id.parse()?;
+        let mut member_ids: Vec<Uuid> = vec![creator_id];
+        for username in member_usernames {
+            let user = self
+                .user_service
+                .get_user_by_username(username.trim())
+                .await
+                .map_err(GenericError::user_not_found)?;
+            if !member_ids.contains(&user.id) {
+                member_ids.push(user.id);
+            }
+        }
+
+        let chat = self
+            .chats_service
+            .create_group_chat(name, &creator_id, &member_ids)
+            .await
+            .map_err(GenericError::unknown)?;
+        info!("group {} created by {}", chat.id, creator_id);
+
+        let group = load_group(self.chats_service.as_ref(), &chat.id.to_string()).await?;
+        publish_members_changed(self.chat_event_broker.as_ref(), &[], &group);
+        Ok(group)
+    }
+
+    async fn get_group(&self, chat_id: &str, user_id: &str) -> anyhow::Result<GroupChat> {
+        self.load_group_as_member(chat_id, user_id).await
+    }
+
+    async fn add_member(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        username: &str,
+    ) -> anyhow::Result<GroupChat> {
+        let group = self.load_group_as_member(chat_id, actor_id).await?;
+
+        let user = self
+            .user_service
+            .get_user_by_username(username.trim())
+            .await
+            .map_err(GenericError::user_not_found)?;
+        if group.is_member(&user.id) {
+            return Err(GenericError::invalid_input(String::from(
+                "User is already a member of this group",
+            )));
+        }
+
+        self.chats_service
+            .add_chat_member(chat_id, actor_id, &user.id.to_string())
+            .await
+            .map_err(map_permission_error)?;
+        info!("{} added {} to group {}", actor_id, user.id, chat_id);
+
+        let updated = load_group(self.chats_service.as_ref(), chat_id).await?;
+        publish_members_changed(self.chat_event_broker.as_ref(), &group.members, &updated);
+        Ok(updated)
+    }
+
+    async fn remove_member(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        member_id: &str,
+    ) -> anyhow::Result<GroupChat> {
+        let group = self.load_group_as_member(chat_id, actor_id).await?;
+
+        let member_uuid: Uuid = member_id
+            .parse()
+            .map_err(|_| GenericError::invalid_input(String::from("Invalid member id")))?;
+        if !group.is_member(&member_uuid) {
+            return Err(GenericError::invalid_input(String::from(
+                "User is not a member of this group",
+            )));
+        }
+
+        self.chats_service
+            .remove_chat_member(chat_id, actor_id, member_id)
+            .await
+            .map_err(map_permission_error)?;
+        info!("{} removed {} from group {}", actor_id, member_id, chat_id);
+
+        let updated = load_group(self.chats_service.as_ref(), chat_id).await?;
+        publish_members_changed(self.chat_event_broker.as_ref(), &group.members, &updated);
+        Ok(updated)
+    }
+
+    async fn leave_group(&self, chat_id: &str, user_id: &str) -> anyhow::Result<()> {
+        let group = self.load_group_as_member(chat_id, user_id).await?;
+        self.chats_service
+            .remove_chat_member(chat_id, user_id, user_id)
+            .await
+            .map_err(map_permission_error)?;
+        info!("{} left group {}", user_id, chat_id);
+
+        let updated = load_group(self.chats_service.as_ref(), chat_id).await?;
+        publish_members_changed(self.chat_event_broker.as_ref(), &group.members, &updated);
+        Ok(())
+    }
+
+    async fn rename_group(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        name: &str,
+    ) -> anyhow::Result<GroupChat> {
+        let name = validate_group_name(name)?;
+        self.load_group_as_member(chat_id, actor_id).await?;
+        self.chats_service
+            .rename_chat(chat_id, actor_id, name)
+            .await
+            .map_err(map_permission_error)?;
+        load_group(self.chats_service.as_ref(), chat_id).await
+    }
+
+    async fn update_group_avatar(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        image: &[u8],
+    ) -> anyhow::Result<GroupChat> {
+        let group = self.load_group_as_member(chat_id, actor_id).await?;
+        // Checked up front so that nothing is stored for a refused upload
+        self.chats_service
+            .ensure_chat_role(chat_id, actor_id, ChatRole::Admin)
+            .await
+            .map_err(map_permission_error)?;
+
+        let image = image.to_vec();
+        let avatar = tokio::task::spawn_blocking(move || {
+            images::square_variants(&image, &[GROUP_AVATAR_SIZE])
+        })
+        .await?
+        .map_err(|e| GenericError::invalid_input(e.to_string()))?;
+        let avatar = utils::save_processed_image(
+            self.storage.as_ref(),
+            MediaFolder::GroupAvatars,
+            &format!("group_{}", chat_id),
+            &avatar[0],
+        )
+        .await
+        .map_err(GenericError::unknown)?;
+        self.chats_service
+            .update_chat_avatar(chat_id, actor_id, &avatar)
+            .await
+            .map_err(map_permission_error)?;
+
+        // The new picture is already in place, a file left behind is not worth failing over
+        if let Some(previous) = &group.chat.avatar {
+            if let Err(e) = utils::remove_uploaded_file(self.storage.as_ref(), previous).await {
+                warn!(
+                    "Failed to remove previous avatar {} of group {}: {}",
+                    previous, chat_id, e
+                );
+            }
+        }
+        load_group(self.chats_service.as_ref(), chat_id).await
+    }
+
+    async fn set_member_role(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        member_id: &str,
+        role: ChatRole,
+    ) -> anyhow::Result<GroupChat> {
+        let group = self.load_group_as_member(chat_id, actor_id).await?;
+        self.chats_service
+            .update_member_role(chat_id, actor_id, member_id, role)
+            .await
+            .map_err(map_permission_error)?;
+        info!("{} made {} {} of group {}", actor_id, member_id, role.as_str(), chat_id);
+
+        let updated = load_group(self.chats_service.as_ref(), chat_id).await?;
+        publish_members_changed(self.chat_event_broker.as_ref(), &group.members, &updated);
+        Ok(updated)
+    }
+
+    async fn transfer_ownership(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        new_owner_id: &str,
+    ) -> anyhow::Result<GroupChat> {
+        let group = self.load_group_as_member(chat_id, actor_id).await?;
+        self.chats_service
+            .transfer_ownership(chat_id, actor_id, new_owner_id)
+            .await
+            .map_err(map_permission_error)?;
+
+        let updated = load_group(self.chats_service.as_ref(), chat_id).await?;
+        publish_members_changed(self.chat_event_broker.as_ref(), &group.members, &updated);
+        Ok(updated)
+    }
+}
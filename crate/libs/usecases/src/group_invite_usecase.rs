@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use chats::{
+    chat_services::ChatServiceInterface,
+    entity::{ChatInvite, ChatRole},
+    events::ChatEventBrokerInterface,
+};
+use commons::generic_errors::GenericError;
+use crypto::Encrypt;
+use log::info;
+use shaku::{Component, Interface};
+use uuid::Uuid;
+
+use crate::group_chat_usecase::{
+    load_group, map_permission_error, publish_members_changed, GroupChat,
+};
+
+const MAX_INVITE_USES: i32 = 1000;
+const MAX_INVITE_LIFETIME_HOURS: i64 = 24 * 30;
+
+/// An invite together with the token that goes into its `/join/{token}` link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInvite {
+    pub invite: ChatInvite,
+    pub token: String,
+}
+
+/// What someone opening an invite link gets to see before joining.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvitePreview {
+    pub group: GroupChat,
+    pub invite: ChatInvite,
+    pub is_member: bool,
+}
+
+#[derive(Component)]
+#[shaku(interface = GroupInviteUsecase)]
+pub struct GroupInviteUsecaseImpl {
+    #[shaku(inject)]
+    chats_service: Arc<dyn ChatServiceInterface>,
+    #[shaku(inject)]
+    crypto: Arc<dyn Encrypt>,
+    #[shaku(inject)]
+    chat_event_broker: Arc<dyn ChatEventBrokerInterface>,
+}
+
+#[async_trait::async_trait]
+pub trait GroupInviteUsecase: Interface {
+    /// `None` for either limit means the link never expires or never runs out.
+    async fn create_invite(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        expires_in_hours: Option<i64>,
+        max_uses: Option<i32>,
+    ) -> anyhow::Result<GroupInvite>;
+    async fn list_invites(&self, chat_id: &str, actor_id: &str)
+        -> anyhow::Result<Vec<GroupInvite>>;
+    async fn revoke_invite(&self, invite_id: &str, actor_id: &str) -> anyhow::Result<()>;
+    async fn preview_invite(&self, token: &str, user_id: &str) -> anyhow::Result<InvitePreview>;
+    /// Joining a group the user already belongs to succeeds without spending a use.
+    async fn join_group(&self, token: &str, user_id: &str) -> anyhow::Result<GroupChat>;
+}
+
+fn invalid_link() -> anyhow::Error {
+    GenericError::invalid_input(String::from("This invite link is invalid"))
+}
+
+impl GroupInviteUsecaseImpl {
+    async fn to_group_invite(&self, invite: ChatInvite) -> anyhow::Result<GroupInvite> {
+        let token = self
+            .crypto
+            .encrypt(&invite.id.to_string())
+            .await
+            .map_err(GenericError::unknown)?;
+        Ok(GroupInvite { invite, token })
+    }
+
+    async fn decode_invite(&self, token: &str) -> anyhow::Result<ChatInvite> {
+        // Anything shorter cannot even hold the nonce, see Crypto::decrypt
+        if token.len() <= 16 || !token.is_ascii() {
+            return Err(invalid_link());
+        }
+        let invite_id = self
+            .crypto
+            .decrypt(token)
+            .await
+            .map_err(|_| invalid_link())?;
+        let invite = self
+            .chats_service
+            .get_chat_invite(&invite_id)
+            .await
+            .map_err(GenericError::unknown)?
+            .ok_or_else(invalid_link)?;
+
+        if invite.revoked_at.is_some() {
+            return Err(GenericError::invalid_input(String::from(
+                "This invite link has been revoked",
+            )));
+        }
+        if invite.is_expired() {
+            return Err(GenericError::invalid_input(String::from(
+                "This invite link has expired",
+            )));
+        }
+        if invite.is_used_up() {
+            return Err(GenericError::invalid_input(String::from(
+                "This invite link has reached its usage limit",
+            )));
+        }
+        Ok(invite)
+    }
+}
+
+#[async_trait::async_trait]
+impl GroupInviteUsecase for GroupInviteUsecaseImpl {
+    async fn create_invite(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+        expires_in_hours: Option<i64>,
+        max_uses: Option<i32>,
+    ) -> anyhow::Result<GroupInvite> {
+        if expires_in_hours.is_some_and(|hours| !(1..=MAX_INVITE_LIFETIME_HOURS).contains(&hours)) {
+            return Err(GenericError::invalid_input(format!(
+                "Invite links can last between 1 and {} hours",
+                MAX_INVITE_LIFETIME_HOURS
+            )));
+        }
+        if max_uses.is_some_and(|uses| !(1..=MAX_INVITE_USES).contains(&uses)) {
+            return Err(GenericError::invalid_input(format!(
+                "Invite links can be used between 1 and {} times",
+                MAX_INVITE_USES
+            )));
+        }
+
+        let group = load_group(self.chats_service.as_ref(), chat_id).await?;
+        let actor_uuid: Uuid = actor_id.parse()?;
+        if !group.is_member(&actor_uuid) {
+            return Err(GenericError::unauthorized());
+        }
+
+        let expires_at = expires_in_hours
+            .map(|hours| chrono::Local::now().naive_local() + chrono::Duration::hours(hours));
+        let invite = ChatInvite::new(group.chat.id, actor_uuid, expires_at, max_uses);
+        self.chats_service
+            .create_chat_invite(&invite)
+            .await
+            .map_err(map_permission_error)?;
+        info!(
+            "{} created invite {} for group {}",
+            actor_id, invite.id, chat_id
+        );
+
+        self.to_group_invite(invite).await
+    }
+
+    async fn list_invites(
+        &self,
+        chat_id: &str,
+        actor_id: &str,
+    ) -> anyhow::Result<Vec<GroupInvite>> {
+        self.chats_service
+            .ensure_chat_role(chat_id, actor_id, ChatRole::Admin)
+            .await
+            .map_err(map_permission_error)?;
+        let invites = self
+            .chats_service
+            .get_active_chat_invites(chat_id)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        let mut group_invites = Vec::with_capacity(invites.len());
+        for invite in invites {
+            group_invites.push(self.to_group_invite(invite).await?);
+        }
+        Ok(group_invites)
+    }
+
+    async fn revoke_invite(&self, invite_id: &str, actor_id: &str) -> anyhow::Result<()> {
+        self.chats_service
+            .revoke_chat_invite(invite_id, actor_id)
+            .await
+            .map_err(map_permission_error)
+    }
+
+    async fn preview_invite(&self, token: &str, user_id: &str) -> anyhow::Result<InvitePreview> {
+        let invite = self.decode_invite(token).await?;
+        let group = load_group(self.chats_service.as_ref(), &invite.chat_id.to_string()).await?;
+        let is_member = group.is_member(&user_id.parse()?);
+        Ok(InvitePreview {
+            group,
+            invite,
+            is_member,
+        })
+    }
+
+    async fn join_group(&self, token: &str, user_id: &str) -> anyhow::Result<GroupChat> {
+        let invite = self.decode_invite(token).await?;
+        let chat_id = invite.chat_id.to_string();
+        let group = load_group(self.chats_service.as_ref(), &chat_id).await?;
+        if group.is_member(&user_id.parse()?) {
+            return Ok(group);
+        }
+
+        self.chats_service
+            .redeem_chat_invite(&invite.id.to_string(), user_id)
+            .await
+            .map_err(map_permission_error)?;
+
+        let updated = load_group(self.chats_service.as_ref(), &chat_id).await?;
+        publish_members_changed(self.chat_event_broker.as_ref(), &group.members, &updated);
+        Ok(updated)
+    }
+}
@@ -0,0 +1,115 @@
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+
+// Anything larger is far beyond what a camera produces and is most likely a decompression bomb
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+const JPEG_QUALITY: u8 = 85;
+const SUPPORTED_FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+];
+
+/// An image re-encoded from decoded pixels, so none of the uploaded metadata survives.
+#[derive(Debug, Clone)]
+pub struct ProcessedImage {
+    pub data: Vec<u8>,
+    pub format: ImageFormat,
+}
+
+impl ProcessedImage {
+    pub fn extension(&self) -> &'static str {
+        self.format.extensions_str()[0]
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        self.format.to_mime_type()
+    }
+}
+
+fn decode(data: &[u8]) -> anyhow::Result<(DynamicImage, ImageFormat)> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .filter(|format| SUPPORTED_FORMATS.contains(format))
+        .ok_or_else(|| anyhow::anyhow!("Images must be JPEG, PNG, GIF or WebP"))?;
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder()?;
+    // The EXIF orientation is dropped with the rest of the metadata, so bake it into the pixels
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok((image, format))
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> anyhow::Result<ProcessedImage> {
+    let mut data = Vec::new();
+    match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY))?,
+        ImageFormat::WebP => DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(&mut data))?,
+        _ => image.write_with_encoder(PngEncoder::new(&mut data))?,
+    }
+    Ok(ProcessedImage {
+        data,
+        format: match format {
+            ImageFormat::Jpeg | ImageFormat::WebP => format,
+            _ => ImageFormat::Png,
+        },
+    })
+}
+
+// Photos compress far better as JPEG, PNG is only needed to keep transparency
+fn preview_format(image: &DynamicImage) -> ImageFormat {
+    if image.color().has_alpha() {
+        ImageFormat::Png
+    } else {
+        ImageFormat::Jpeg
+    }
+}
+
+/// Square crops of the image, one per entry of `sizes`, for profile and group pictures.
+pub fn square_variants(data: &[u8], sizes: &[u32]) -> anyhow::Result<Vec<ProcessedImage>> {
+    let (image, _) = decode(data)?;
+    let format = preview_format(&image);
+    sizes
+        .iter()
+        .map(|&size| encode(&image.resize_to_fill(size, size, FilterType::Lanczos3), format))
+        .collect()
+}
+
+/// Returns the image without its metadata, in the format it was uploaded in,
+/// together with a thumbnail that fits in a `thumbnail_size` square.
+pub fn strip_and_thumbnail(
+    data: &[u8],
+    thumbnail_size: u32,
+) -> anyhow::Result<(ProcessedImage, ProcessedImage)> {
+    let (image, format) = decode(data)?;
+    let stripped = match format {
+        // GIF has no room for EXIF, and re-encoding would only keep the first frame
+        ImageFormat::Gif => ProcessedImage {
+            data: data.to_vec(),
+            format,
+        },
+        format => encode(&image, format)?,
+    };
+
+    let thumbnail = if image.width() > thumbnail_size || image.height() > thumbnail_size {
+        image.resize(thumbnail_size, thumbnail_size, FilterType::Triangle)
+    } else {
+        image
+    };
+    Ok((stripped, encode(&thumbnail, preview_format(&thumbnail))?))
+}
@@ -1,4 +1,7 @@
-use chats::{chat_services::ChatServiceInterface, entity::ChatMessages};
+use chats::{
+    chat_services::ChatServiceInterface,
+    entity::{ChatMessages, MessageCursor},
+};
 use commons::generic_errors::GenericError;
 use log::{error, info};
 use shaku::{Component, Interface};
@@ -7,7 +10,7 @@ use user_details::user_detail_service::UserDetailService;
 use users::{user::UserInfo, user_services::UserServiceInterface};
 use uuid::Uuid;
 
-use crate::userdetail_usecase;
+use crate::{chat_usecase::DEFAULT_MESSAGE_PAGE_SIZE, userdetail_usecase};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct InvitePrivateChatRequest {
@@ -44,9 +47,9 @@ pub struct InvitePrivateChatResponse {
     //pub chat_list: Vec<String>,
 }
 
-impl ToString for InvitePrivateChatResponse {
-    fn to_string(&self) -> String {
-        self.chat_id.to_string()
+impl std::fmt::Display for InvitePrivateChatResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.chat_id)
     }
 }
 
@@ -112,16 +115,25 @@ impl InvitePrivateChatUsecaseInterface for InvitePrivateChatUsecase {
                 target_user.id.to_string().as_str(),
             )
             .await
-            .map_err(|e| GenericError::unknown(e))?;
+            .map_err(GenericError::unknown)?;
 
-        if value.is_some() {
-            let id = value.unwrap().id;
+        if let Some(chat) = value {
+            let id = chat.id;
             info!("chat already exist with id: {}", id);
-            return Ok(InvitePrivateChatResponse::new(
-                id,
-                target_user.id,
-                user_info,
-            ));
+            let chat_messages = self
+                .chats_service
+                .get_messages_of_chat(
+                    id.to_string().as_str(),
+                    request.user_id.to_string().as_str(),
+                    MessageCursor::Latest,
+                    DEFAULT_MESSAGE_PAGE_SIZE,
+                )
+                .await
+                .map_err(GenericError::unknown)?;
+            return Ok(
+                InvitePrivateChatResponse::new(id, target_user.id, user_info)
+                    .with_chat_messages(chat_messages),
+            );
         }
 
         info!("target user found with id: {}", target_user.id);
@@ -134,17 +146,22 @@ impl InvitePrivateChatUsecaseInterface for InvitePrivateChatUsecase {
             .await
             .map_err(GenericError::unknown)
             .map(|chat| InvitePrivateChatResponse::new(chat, target_user.id, user_info))
-            .map_err(|e| GenericError::unknown(e))?;
+            .map_err(GenericError::unknown)?;
 
         info!("chat created with id: {}", response.chat_id);
         let chat_messsage = self
             .chats_service
-            .get_messages_of_chat(response.chat_id.to_string().as_str())
+            .get_messages_of_chat(
+                response.chat_id.to_string().as_str(),
+                request.user_id.to_string().as_str(),
+                MessageCursor::Latest,
+                DEFAULT_MESSAGE_PAGE_SIZE,
+            )
             .await
-            .map_err(|e| GenericError::unknown(e))?;
+            .map_err(GenericError::unknown)?;
 
         let response = response.with_chat_messages(chat_messsage);
-        return Ok(response);
+        Ok(response)
     }
     async fn find_user_info_list(&self, query: &str) -> anyhow::Result<Vec<UserInfo>> {
         self.user_service
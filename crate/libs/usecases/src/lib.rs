@@ -1,8 +1,16 @@
+pub mod account_usecase;
 pub mod chat_usecase;
+pub mod group_chat_usecase;
+pub mod group_invite_usecase;
+pub mod images;
 pub mod invite_private_chat_usecase;
 pub mod login_usecase;
 mod macros;
+pub mod media_usecase;
+pub mod password_reset_usecase;
 pub mod register_usecase;
+pub mod session_usecase;
+pub mod two_factor_usecase;
 pub mod userdetail_usecase;
 pub mod utils;
 
@@ -10,7 +18,10 @@ pub use register_usecase::{
     RegisterRequest, RegisterResponse, RegisterUseCase, RegisterUseCaseInterface,
 };
 
-pub use login_usecase::{LoginRequest, LoginResponse, LoginUseCase, LoginUseCaseInterface};
+pub use login_usecase::{
+    LoginOutcome, LoginRequest, LoginResponse, LoginUseCase, LoginUseCaseInterface,
+    RefreshResponse, TwoFactorLoginRequest, ACCOUNT_MAX_LOGIN_FAILURES, IP_MAX_LOGIN_FAILURES,
+};
 
 pub use invite_private_chat_usecase::{
     InvitePrivateChatRequest, InvitePrivateChatUsecase, InvitePrivateChatUsecaseInterface,
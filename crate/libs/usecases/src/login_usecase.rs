@@ -1,13 +1,38 @@
 use commons::generic_errors::GenericError;
+use commons::secret_tokens::hash_secret;
 use credentials::credential_services::CredentialServiceInterface;
+use crypto::Encrypt;
 use jwt::{AccessClaims, JWTInterface, Role};
-use log::error;
-use sessions::entity::Session;
+use log::{error, warn};
+use mail::SendEmail;
+use persistence::env::myenv::EnvInterface;
+use sessions::entity::{LoginChallenge, LoginScope, RefreshToken, Session};
 use sessions::services::SessionServiceInterface;
 use shaku::{Component, Interface};
 use sqlx::Error;
 use std::sync::Arc;
 use users::user_services::UserServiceInterface;
+use uuid::Uuid;
+
+use crate::two_factor_usecase::verify_second_factor;
+
+// Parallel requests with an expired access token all present the same refresh token,
+// only the first one rotates it and the rest must not look like a stolen token
+const REFRESH_TOKEN_REUSE_GRACE_SECONDS: i64 = 10;
+// How long the code form stays valid after the password was accepted
+const LOGIN_CHALLENGE_TTL_SECONDS: i64 = 5 * 60;
+// Wrong codes before the password has to be entered again
+const LOGIN_CHALLENGE_MAX_ATTEMPTS: i64 = 5;
+const LOGIN_CHALLENGE_EXPIRED: &str = "Your login has expired, please log in again";
+// Wrong passwords or codes for one account before logging in to it is locked
+pub const ACCOUNT_MAX_LOGIN_FAILURES: i64 = 5;
+// Higher, a whole office can share one address
+pub const IP_MAX_LOGIN_FAILURES: i64 = 20;
+// The first lockout, every failure after it doubles the wait up to the cap
+const LOGIN_LOCKOUT_BASE_SECONDS: i64 = 60;
+const LOGIN_LOCKOUT_MAX_SECONDS: i64 = 60 * 60;
+// Failures are forgotten once nobody tried for this long
+const LOGIN_FAILURE_MEMORY_HOURS: i64 = 24;
 
 #[derive(Component)]
 #[shaku(interface = LoginUseCaseInterface)]
@@ -20,6 +45,12 @@ pub struct LoginUseCase {
     credential_service: Arc<dyn CredentialServiceInterface>,
     #[shaku(inject)]
     session_service: Arc<dyn SessionServiceInterface>,
+    #[shaku(inject)]
+    env: Arc<dyn EnvInterface>,
+    #[shaku(inject)]
+    crypto: Arc<dyn Encrypt>,
+    #[shaku(inject)]
+    mail: Arc<dyn SendEmail>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -51,61 +82,196 @@ impl LoginRequest<'_> {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
+    pub refresh_token_expires_in: i64,
     pub private_key: String,
     pub public_key: String,
 }
 
+/// Where a login stands once the password was accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginOutcome {
+    LoggedIn(LoginResponse),
+    /// The user has two-factor login on, the token goes back with the code to
+    /// `complete_two_factor_login`. No session exists yet.
+    TwoFactorRequired {
+        challenge_token: String,
+    },
+}
+
+impl LoginOutcome {
+    pub fn logged_in(self) -> Option<LoginResponse> {
+        match self {
+            LoginOutcome::LoggedIn(response) => Some(response),
+            LoginOutcome::TwoFactorRequired { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwoFactorLoginRequest<'a> {
+    pub challenge_token: &'a str,
+    // From the authenticator app, or a recovery code
+    pub code: &'a str,
+    pub user_agent: &'a str,
+    pub ip_address: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshResponse {
+    pub claims: AccessClaims,
+    pub token: String,
+    /// The next refresh token, `None` when a parallel request already rotated it.
+    pub refresh_token: Option<String>,
+    pub refresh_token_expires_in: i64,
+}
+
 #[async_trait::async_trait]
 pub trait LoginUseCaseInterface: Interface {
-    async fn login(&self, request: LoginRequest<'_>) -> anyhow::Result<LoginResponse>;
+    /// Too many failures for the account or from the address lock logging in for a while,
+    /// the error is then `GenericError::LoginLocked`.
+    async fn login(&self, request: LoginRequest<'_>) -> anyhow::Result<LoginOutcome>;
+    /// The second step of a login for users with two-factor login on.
+    /// Too many wrong codes end the challenge, the password has to be entered again.
+    async fn complete_two_factor_login(
+        &self,
+        request: TwoFactorLoginRequest<'_>,
+    ) -> anyhow::Result<LoginResponse>;
     async fn authorize_current_user(&self, token: &str) -> anyhow::Result<AccessClaims>;
+    /// Exchanges a refresh token for a new access token and the next refresh token.
+    /// Presenting a token that was already exchanged revokes the whole session.
+    async fn refresh(&self, refresh_token: &str) -> anyhow::Result<RefreshResponse>;
+    /// Revokes the session the claims were issued for, the token stops working right away.
+    async fn logout(&self, claims: &AccessClaims) -> anyhow::Result<()>;
+    /// Revokes every session of the user, on all devices.
+    async fn logout_everywhere(&self, claims: &AccessClaims) -> anyhow::Result<()>;
 }
 
 #[async_trait::async_trait]
 impl LoginUseCaseInterface for LoginUseCase {
-    async fn login(&self, request: LoginRequest<'_>) -> anyhow::Result<LoginResponse> {
+    async fn login(&self, request: LoginRequest<'_>) -> anyhow::Result<LoginOutcome> {
         request.validate().await?;
+        // Checked before the password, a locked out guesser gets no answer from bcrypt
+        self.check_login_lock(LoginScope::Ip, request.ip_address)
+            .await?;
 
-        let user = self
+        let user = match self
             .user_service
             .get_user_by_username(request.username)
             .await
-            .map_err(|e| match e.downcast_ref::<Error>() {
-                Some(Error::RowNotFound) => GenericError::login_failed(),
-                _ => GenericError::unknown(e),
-            })?;
+        {
+            Ok(user) => user,
+            Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::RowNotFound)) => {
+                return Err(self.failed_login(None, request.ip_address).await);
+            }
+            Err(e) => return Err(GenericError::unknown(e)),
+        };
+        self.check_login_lock(LoginScope::Account, &user.id.to_string())
+            .await?;
 
         let is_password_valid = user.match_password(request.password);
         if !is_password_valid {
-            return Err(GenericError::login_failed());
+            return Err(self.failed_login(Some(user.id), request.ip_address).await);
         }
 
-        let credential = self
-            .credential_service
-            .get_credential_by_user_id(user.id)
-            .await
-            .map_err(GenericError::unknown)?;
+        let two_factor = self.user_service.get_two_factor(user.id).await?;
+        if two_factor.is_some_and(|two_factor| two_factor.is_enabled()) {
+            let (challenge, challenge_token) =
+                LoginChallenge::generate(user.id, LOGIN_CHALLENGE_TTL_SECONDS);
+            self.session_service
+                .create_login_challenge(&challenge)
+                .await?;
+            return Ok(LoginOutcome::TwoFactorRequired { challenge_token });
+        }
 
-        let access_claim = AccessClaims::new(user.id.to_string(), Role::User);
+        self.start_session(user.id, request.user_agent, request.ip_address)
+            .await
+            .map(LoginOutcome::LoggedIn)
+    }
 
-        self.session_service
-            .create_session(&Session::new(
-                access_claim.jti.parse()?,
-                user.id,
-                request.user_agent.to_string(),
-                request.ip_address.to_string(),
-            ))
+    async fn complete_two_factor_login(
+        &self,
+        request: TwoFactorLoginRequest<'_>,
+    ) -> anyhow::Result<LoginResponse> {
+        let expired = || GenericError::invalid_input(LOGIN_CHALLENGE_EXPIRED.to_string());
+        let challenge = self
+            .session_service
+            .get_login_challenge(&hash_secret(request.challenge_token))
+            .await?
+            .ok_or_else(expired)?;
+        if challenge.is_expired() {
+            self.session_service
+                .delete_login_challenge(challenge.id)
+                .await?;
+            return Err(expired());
+        }
+        self.check_login_lock(LoginScope::Ip, request.ip_address)
+            .await?;
+        self.check_login_lock(LoginScope::Account, &challenge.user_id.to_string())
             .await?;
 
-        self.jwt_service
-            .generate_token(&access_claim)
+        // Turned off in between, e.g. from another device
+        let Some(two_factor) = self
+            .user_service
+            .get_two_factor(challenge.user_id)
+            .await?
+            .filter(|two_factor| two_factor.is_enabled())
+        else {
+            self.session_service
+                .delete_login_challenge(challenge.id)
+                .await?;
+            return Err(expired());
+        };
+
+        if !verify_second_factor(
+            self.user_service.as_ref(),
+            self.crypto.as_ref(),
+            &two_factor,
+            request.code,
+        )
+        .await?
+        {
+            // Counted against the account too, new challenges do not bring new guesses
+            let locked_for = self
+                .count_failed_login(Some(challenge.user_id), request.ip_address)
+                .await?;
+            if let Some(locked_for) = locked_for {
+                self.session_service
+                    .delete_login_challenge(challenge.id)
+                    .await?;
+                return Err(GenericError::login_locked(locked_for.num_seconds()));
+            }
+            let attempts = self
+                .session_service
+                .record_login_challenge_attempt(challenge.id)
+                .await?;
+            if attempts.is_none_or(|attempts| attempts >= LOGIN_CHALLENGE_MAX_ATTEMPTS) {
+                warn!(
+                    "Too many wrong codes for user {}, ending the login",
+                    challenge.user_id
+                );
+                self.session_service
+                    .delete_login_challenge(challenge.id)
+                    .await?;
+                return Err(GenericError::invalid_input(
+                    "Too many wrong codes, please log in again".to_string(),
+                ));
+            }
+            return Err(GenericError::invalid_input(
+                "Invalid verification code".to_string(),
+            ));
+        }
+
+        // A challenge completes once, even when the form is sent twice
+        if !self
+            .session_service
+            .delete_login_challenge(challenge.id)
+            .await?
+        {
+            return Err(expired());
+        }
+        self.start_session(challenge.user_id, request.user_agent, request.ip_address)
             .await
-            .map_err(GenericError::unknown)
-            .map(|token| LoginResponse {
-                token: token.token,
-                private_key: credential.private_key,
-                public_key: credential.public_key,
-            })
     }
 
     async fn authorize_current_user(&self, token: &str) -> anyhow::Result<AccessClaims> {
@@ -127,8 +293,286 @@ impl LoginUseCaseInterface for LoginUseCase {
             return Err(GenericError::unauthorized());
         }
 
+        // Only feeds the last active time on the sessions page, not worth failing the request
+        if let Err(e) = self.session_service.touch_session(&claims.jti).await {
+            error!("Error when touching session: {}", e);
+        }
+
         Ok(claims)
     }
+
+    async fn refresh(&self, refresh_token: &str) -> anyhow::Result<RefreshResponse> {
+        let token = self
+            .session_service
+            .get_refresh_token(&RefreshToken::hash(refresh_token))
+            .await?
+            .ok_or_else(GenericError::invalid_token)?;
+        let session_id = token.session_id.to_string();
+
+        // Gone after a logout or a revoke, its tokens went with it
+        let session = self
+            .session_service
+            .get_session(&session_id)
+            .await?
+            .ok_or_else(GenericError::unauthorized)?;
+
+        if token.is_expired() {
+            return Err(GenericError::token_expired());
+        }
+
+        let rotated =
+            token.used_at.is_none() && self.session_service.use_refresh_token(token.id).await?;
+        if !rotated {
+            // Losing the race above means it was used just now
+            let now = chrono::Local::now().naive_local();
+            let used_at = token.used_at.unwrap_or(now);
+            if now - used_at > chrono::Duration::seconds(REFRESH_TOKEN_REUSE_GRACE_SECONDS) {
+                warn!(
+                    "Refresh token of session {} was used again, revoking the session",
+                    session_id
+                );
+                self.session_service.delete_session(&session_id).await?;
+                return Err(GenericError::unauthorized());
+            }
+        }
+
+        let refresh_token = if rotated {
+            Some(self.issue_refresh_token(token.session_id).await?)
+        } else {
+            None
+        };
+        let claims = AccessClaims::for_session(
+            session.user_id.to_string(),
+            Role::User,
+            session_id,
+            self.env.get_access_token_ttl_seconds(),
+        );
+        let access_token = self
+            .jwt_service
+            .generate_token(&claims)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        if let Err(e) = self.session_service.touch_session(&claims.jti).await {
+            error!("Error when touching session: {}", e);
+        }
+
+        Ok(RefreshResponse {
+            claims,
+            token: access_token.token,
+            refresh_token,
+            refresh_token_expires_in: self.env.get_refresh_token_ttl_seconds(),
+        })
+    }
+
+    async fn logout(&self, claims: &AccessClaims) -> anyhow::Result<()> {
+        self.session_service
+            .delete_session(&claims.jti)
+            .await
+            .map_err(GenericError::unknown)
+    }
+
+    async fn logout_everywhere(&self, claims: &AccessClaims) -> anyhow::Result<()> {
+        self.session_service
+            .delete_sessions_by_user(claims.user_id.parse()?)
+            .await
+            .map_err(GenericError::unknown)
+    }
+}
+
+impl LoginUseCase {
+    async fn start_session(
+        &self,
+        user_id: Uuid,
+        user_agent: &str,
+        ip_address: &str,
+    ) -> anyhow::Result<LoginResponse> {
+        // The owner got in, earlier typos should not count towards a lockout
+        self.session_service
+            .clear_login_failures(LoginScope::Account, &user_id.to_string())
+            .await?;
+        let credential = self
+            .credential_service
+            .get_credential_by_user_id(user_id)
+            .await
+            .map_err(GenericError::unknown)?;
+
+        let session_id = Uuid::new_v4();
+        let access_claim = AccessClaims::for_session(
+            user_id.to_string(),
+            Role::User,
+            session_id.to_string(),
+            self.env.get_access_token_ttl_seconds(),
+        );
+
+        self.session_service
+            .create_session(&Session::new(
+                session_id,
+                user_id,
+                user_agent.to_string(),
+                ip_address.to_string(),
+            ))
+            .await?;
+        let refresh_token = self.issue_refresh_token(session_id).await?;
+
+        self.jwt_service
+            .generate_token(&access_claim)
+            .await
+            .map_err(GenericError::unknown)
+            .map(|token| LoginResponse {
+                token: token.token,
+                refresh_token,
+                refresh_token_expires_in: self.env.get_refresh_token_ttl_seconds(),
+                private_key: credential.private_key,
+                public_key: credential.public_key,
+            })
+    }
+
+    // Every refresh starts a new sliding window, a session ends once it sits unused for that long
+    async fn issue_refresh_token(&self, session_id: Uuid) -> anyhow::Result<String> {
+        let (refresh_token, secret) =
+            RefreshToken::generate(session_id, self.env.get_refresh_token_ttl_seconds());
+        self.session_service
+            .create_refresh_token(&refresh_token)
+            .await?;
+        Ok(secret)
+    }
+
+    async fn check_login_lock(&self, scope: LoginScope, subject: &str) -> anyhow::Result<()> {
+        let Some(locked_until) = self.session_service.get_login_lock(scope, subject).await? else {
+            return Ok(());
+        };
+        let retry_after = locked_until - chrono::Local::now().naive_local();
+        Err(GenericError::login_locked(retry_after.num_seconds().max(1)))
+    }
+
+    // The error for a wrong password, or the lockout it just started
+    async fn failed_login(&self, user_id: Option<Uuid>, ip_address: &str) -> anyhow::Error {
+        match self.count_failed_login(user_id, ip_address).await {
+            Ok(Some(locked_for)) => GenericError::login_locked(locked_for.num_seconds()),
+            Ok(None) => GenericError::login_failed(),
+            Err(e) => GenericError::unknown(e),
+        }
+    }
+
+    /// Counts a failure against the address and the account,
+    /// returns how long logging in is locked when it started a lockout.
+    async fn count_failed_login(
+        &self,
+        user_id: Option<Uuid>,
+        ip_address: &str,
+    ) -> anyhow::Result<Option<chrono::Duration>> {
+        let ip_lock = self
+            .record_login_failure(LoginScope::Ip, ip_address)
+            .await?
+            .map(|(locked_for, _)| locked_for);
+        if let Some(locked_for) = ip_lock {
+            warn!(
+                "Too many failed logins from {}, locked for {} seconds",
+                ip_address,
+                locked_for.num_seconds()
+            );
+        }
+        let Some(user_id) = user_id else {
+            return Ok(ip_lock);
+        };
+
+        let account_lock = self
+            .record_login_failure(LoginScope::Account, &user_id.to_string())
+            .await?;
+        if let Some((locked_for, failures)) = account_lock {
+            warn!(
+                "Too many failed logins for user {}, locked for {} seconds",
+                user_id,
+                locked_for.num_seconds()
+            );
+            // Only the first lockout in a row is mailed, not every guess after it
+            if failures == ACCOUNT_MAX_LOGIN_FAILURES {
+                // In the background, so the locked out response does not wait on the mail
+                tokio::spawn({
+                    let user_service = self.user_service.clone();
+                    let mail = self.mail.clone();
+                    let ip_address = ip_address.to_string();
+
+                    async move {
+                        let result = send_lockout_email(
+                            user_service.as_ref(),
+                            mail.as_ref(),
+                            user_id,
+                            &ip_address,
+                        )
+                        .await;
+                        if let Err(e) = result {
+                            error!("Error when sending lockout email: {}", e);
+                        }
+                    }
+                });
+            }
+        }
+        Ok(ip_lock.max(account_lock.map(|(locked_for, _)| locked_for)))
+    }
+
+    // Returns the lockout with the failure count when this failure started one
+    async fn record_login_failure(
+        &self,
+        scope: LoginScope,
+        subject: &str,
+    ) -> anyhow::Result<Option<(chrono::Duration, i64)>> {
+        let now = chrono::Local::now().naive_local();
+        let failures = self
+            .session_service
+            .record_login_failure(
+                scope,
+                subject,
+                now - chrono::Duration::hours(LOGIN_FAILURE_MEMORY_HOURS),
+            )
+            .await?;
+        let max_failures = match scope {
+            LoginScope::Account => ACCOUNT_MAX_LOGIN_FAILURES,
+            LoginScope::Ip => IP_MAX_LOGIN_FAILURES,
+        };
+        let Some(locked_for) = lockout_duration(failures, max_failures) else {
+            return Ok(None);
+        };
+        self.session_service
+            .lock_login(scope, subject, now + locked_for)
+            .await?;
+        Ok(Some((locked_for, failures)))
+    }
+}
+
+async fn send_lockout_email(
+    user_service: &dyn UserServiceInterface,
+    mail: &dyn SendEmail,
+    user_id: Uuid,
+    ip_address: &str,
+) -> anyhow::Result<()> {
+    let user = user_service.get_user_by_uuid(user_id).await?;
+    let message = format!(
+        r#"
+        There were {} failed attempts to log in to your account {}, the last one from {}.
+        Logging in to it is paused for a while.
+        If it was you, wait a few minutes or reset your password from the login page.
+        If it was not you, change your password once you can log in again. "#,
+        ACCOUNT_MAX_LOGIN_FAILURES, user.username, ip_address
+    );
+    mail.send_email(
+        user.username.as_str(),
+        user.email.as_str(),
+        "Failed login attempts on your account",
+        &message,
+    )
+    .await
+}
+
+fn lockout_duration(failures: i64, max_failures: i64) -> Option<chrono::Duration> {
+    if failures < max_failures {
+        return None;
+    }
+    let doublings = (failures - max_failures).min(16) as u32;
+    Some(chrono::Duration::seconds(
+        (LOGIN_LOCKOUT_BASE_SECONDS << doublings).min(LOGIN_LOCKOUT_MAX_SECONDS),
+    ))
 }
 
 #[cfg(test)]
@@ -136,7 +580,9 @@ mod tests {
     use crate::login_usecase::{LoginRequest, LoginUseCase, LoginUseCaseInterface};
     use commons::generic_errors::GenericError;
     use credentials::credential_services::CredentialService;
+    use crypto::Crypto;
     use jwt::JWT;
+    use mail::Mail;
     use persistence::db::database::DBParameters;
     use persistence::db::sqlite::create_sqlite_db_pool;
     use persistence::env::myenv::EnvInterface;
@@ -148,7 +594,7 @@ mod tests {
 
     module! {
         MyModule {
-            components = [LoginUseCase, UserService, CredentialService, Env, DB, JWT, SessionService],
+            components = [LoginUseCase, UserService, CredentialService, Env, DB, JWT, SessionService, Crypto, Mail],
             providers = []
         }
     }
@@ -180,8 +626,8 @@ mod tests {
         assert!(result.is_err());
         match result.unwrap_err().downcast_ref::<GenericError>() {
             Some(GenericError::LoginFailed(u32)) => assert_eq!(*u32, 401),
-            Some(GenericError::Unknown()) =>  assert!(true),
-            _ => assert!(false),
+            Some(GenericError::Unknown()) => {}
+            _ => panic!("unexpected error"),
         };
     }
 }
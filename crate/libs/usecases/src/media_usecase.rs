@@ -0,0 +1,318 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use chats::chat_services::ChatServiceInterface;
+use commons::generic_errors::GenericError;
+use crypto::Encrypt;
+use futures::TryStreamExt;
+use log::{info, warn};
+use shaku::{Component, Interface};
+use storage::{BlobStorage, BlobStream};
+use user_details::user_detail_service::UserDetailService;
+use uuid::Uuid;
+
+use crate::utils::{self, MediaFolder};
+
+pub const SIGNED_MEDIA_URL_TTL_SECONDS: i64 = 5 * 60;
+// Enough for `infer` to recognise every format it knows
+const CONTENT_SNIFF_LENGTH: u64 = 512;
+// Where uploads were served from before the `/media` route, straight out of the web assets
+const LEGACY_UPLOADS_URL_PREFIX: &str = "/assets/uploads/";
+
+/// A file the viewer was allowed to read, with what the response needs to describe it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaFile {
+    pub key: String,
+    pub size: u64,
+    pub content_type: String,
+    // Only attachments keep the name they were uploaded with
+    pub file_name: Option<String>,
+}
+
+#[derive(Component)]
+#[shaku(interface = MediaUsecase)]
+pub struct MediaUsecaseImpl {
+    #[shaku(inject)]
+    chats_service: Arc<dyn ChatServiceInterface>,
+    #[shaku(inject)]
+    user_detail_service: Arc<dyn UserDetailService>,
+    #[shaku(inject)]
+    crypto: Arc<dyn Encrypt>,
+    #[shaku(inject)]
+    storage: Arc<dyn BlobStorage>,
+}
+
+#[async_trait::async_trait]
+pub trait MediaUsecase: Interface {
+    /// Attachments and group pictures are for chat members, profile pictures for any signed in
+    /// user. A signature from `sign_media_url` stands in for the viewer until it expires.
+    async fn open_media(
+        &self,
+        media_url: &str,
+        viewer_id: Option<&str>,
+        signature: Option<&str>,
+    ) -> anyhow::Result<MediaFile>;
+    /// Streams a file returned by `open_media`, or the inclusive byte `range` of it.
+    async fn read_media(
+        &self,
+        media: &MediaFile,
+        range: Option<(u64, u64)>,
+    ) -> anyhow::Result<BlobStream>;
+    /// Returns the URL with a `signature` that grants access for a few minutes.
+    async fn sign_media_url(&self, media_url: &str) -> anyhow::Result<String>;
+    /// Moves the files of `legacy_dir`, once served publicly as `/assets/uploads`, into the
+    /// blob storage and points whatever uses them at their new `/media` URL. Files nothing
+    /// uses are left where they are. Returns how many were moved.
+    async fn import_legacy_uploads(&self, legacy_dir: &Path) -> anyhow::Result<usize>;
+}
+
+fn media_not_found() -> anyhow::Error {
+    GenericError::invalid_input(String::from("File not found"))
+}
+
+impl MediaUsecaseImpl {
+    async fn is_signature_valid(&self, media_url: &str, signature: &str) -> bool {
+        // Anything shorter cannot even hold the nonce, see Crypto::decrypt
+        if signature.len() <= 16 || !signature.is_ascii() {
+            return false;
+        }
+        let Ok(payload) = self.crypto.decrypt(signature).await else {
+            return false;
+        };
+        let Some((signed_url, expires_at)) = payload.rsplit_once('|') else {
+            return false;
+        };
+        let Ok(expires_at) = expires_at.parse::<i64>() else {
+            return false;
+        };
+        signed_url == media_url && expires_at > chrono::Utc::now().timestamp()
+    }
+
+    async fn ensure_chat_member(
+        &self,
+        chat_id: &Uuid,
+        viewer_id: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let viewer_id: Uuid = viewer_id.ok_or_else(GenericError::unauthorized)?.parse()?;
+        let members = self
+            .chats_service
+            .get_chat_members(&chat_id.to_string())
+            .await
+            .map_err(GenericError::unknown)?;
+        if !members.iter().any(|member| member.user_id == viewer_id) {
+            return Err(GenericError::permission_denied(String::from(
+                "Only chat members can view this file",
+            )));
+        }
+        Ok(())
+    }
+
+    // Returns whether the file was in use and got moved
+    async fn import_legacy_upload(&self, file_name: &str, data: Vec<u8>) -> anyhow::Result<bool> {
+        let legacy_url = format!("{}{}", LEGACY_UPLOADS_URL_PREFIX, file_name);
+        let (folder, content_type) = if self
+            .user_detail_service
+            .is_profile_picture_in_use(&legacy_url)
+            .await?
+        {
+            (MediaFolder::Avatars, None)
+        } else if self
+            .chats_service
+            .get_chat_id_by_avatar(&legacy_url)
+            .await?
+            .is_some()
+        {
+            (MediaFolder::GroupAvatars, None)
+        } else if let Some((_, attachment)) = self
+            .chats_service
+            .get_attachment_by_url(&legacy_url)
+            .await?
+        {
+            (MediaFolder::Attachments, Some(attachment.file_type))
+        } else {
+            return Ok(false);
+        };
+
+        let media_url = format!(
+            "{}{}/{}",
+            utils::MEDIA_URL_PREFIX,
+            folder.as_str(),
+            file_name
+        );
+        let (_, key) = utils::media_storage_key(&media_url)
+            .ok_or_else(|| anyhow::anyhow!("Unexpected file name {}", file_name))?;
+        let content_type = content_type.unwrap_or_else(|| {
+            infer::get(&data)
+                .map(|kind| kind.mime_type())
+                .unwrap_or("application/octet-stream")
+                .to_string()
+        });
+        // Stored before any row points at it
+        self.storage.put(&key, data, &content_type).await?;
+        match folder {
+            MediaFolder::Avatars => {
+                self.user_detail_service
+                    .replace_profile_picture_path(&legacy_url, &media_url)
+                    .await?;
+            }
+            MediaFolder::GroupAvatars | MediaFolder::Attachments => {
+                self.chats_service
+                    .replace_media_url(&legacy_url, &media_url)
+                    .await?;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn sniff_content_type(&self, key: &str, size: u64) -> anyhow::Result<String> {
+        if size == 0 {
+            return Ok(String::from("application/octet-stream"));
+        }
+        let head: Vec<_> = self
+            .storage
+            .get(key, Some((0, size.min(CONTENT_SNIFF_LENGTH) - 1)))
+            .await?
+            .try_collect()
+            .await?;
+        Ok(infer::get(&head.concat())
+            .map(|kind| kind.mime_type())
+            .unwrap_or("application/octet-stream")
+            .to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl MediaUsecase for MediaUsecaseImpl {
+    async fn open_media(
+        &self,
+        media_url: &str,
+        viewer_id: Option<&str>,
+        signature: Option<&str>,
+    ) -> anyhow::Result<MediaFile> {
+        let (folder, key) = utils::media_storage_key(media_url).ok_or_else(media_not_found)?;
+        let is_signed = match signature {
+            Some(signature) => self.is_signature_valid(media_url, signature).await,
+            None => false,
+        };
+
+        let mut file_name = None;
+        let mut content_type = None;
+        match folder {
+            MediaFolder::Attachments => {
+                let (chat_id, attachment) = self
+                    .chats_service
+                    .get_attachment_by_url(media_url)
+                    .await
+                    .map_err(GenericError::unknown)?
+                    .ok_or_else(media_not_found)?;
+                if !is_signed {
+                    self.ensure_chat_member(&chat_id, viewer_id).await?;
+                }
+                // Thumbnails are always served inline, their type is sniffed below
+                if attachment.file_url == media_url {
+                    file_name = Some(attachment.file_name);
+                    content_type = Some(attachment.file_type);
+                }
+            }
+            MediaFolder::GroupAvatars => {
+                let chat_id = self
+                    .chats_service
+                    .get_chat_id_by_avatar(media_url)
+                    .await
+                    .map_err(GenericError::unknown)?
+                    .ok_or_else(media_not_found)?;
+                if !is_signed {
+                    self.ensure_chat_member(&chat_id, viewer_id).await?;
+                }
+            }
+            MediaFolder::Avatars => {
+                // Profiles are public to every user, search results show them to strangers too
+                if !is_signed && viewer_id.is_none() {
+                    return Err(GenericError::unauthorized());
+                }
+                let in_use = self
+                    .user_detail_service
+                    .is_profile_picture_in_use(media_url)
+                    .await
+                    .map_err(GenericError::unknown)?;
+                if !in_use {
+                    return Err(media_not_found());
+                }
+            }
+        }
+
+        let size = self
+            .storage
+            .size(&key)
+            .await
+            .map_err(GenericError::unknown)?
+            .ok_or_else(media_not_found)?;
+        let content_type = match content_type {
+            Some(content_type) => content_type,
+            None => self
+                .sniff_content_type(&key, size)
+                .await
+                .map_err(GenericError::unknown)?,
+        };
+        Ok(MediaFile {
+            key,
+            size,
+            content_type,
+            file_name,
+        })
+    }
+
+    async fn read_media(
+        &self,
+        media: &MediaFile,
+        range: Option<(u64, u64)>,
+    ) -> anyhow::Result<BlobStream> {
+        self.storage
+            .get(&media.key, range)
+            .await
+            .map_err(GenericError::unknown)
+    }
+
+    async fn sign_media_url(&self, media_url: &str) -> anyhow::Result<String> {
+        if utils::media_storage_key(media_url).is_none() {
+            return Err(media_not_found());
+        }
+        let expires_at = chrono::Utc::now().timestamp() + SIGNED_MEDIA_URL_TTL_SECONDS;
+        let signature = self
+            .crypto
+            .encrypt(&format!("{}|{}", media_url, expires_at))
+            .await
+            .map_err(GenericError::unknown)?;
+        Ok(format!("{}?signature={}", media_url, signature))
+    }
+
+    async fn import_legacy_uploads(&self, legacy_dir: &Path) -> anyhow::Result<usize> {
+        let mut entries = match tokio::fs::read_dir(legacy_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut imported = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let data = tokio::fs::read(entry.path()).await?;
+            if self.import_legacy_upload(&file_name, data).await? {
+                tokio::fs::remove_file(entry.path()).await?;
+                imported += 1;
+            } else {
+                warn!(
+                    "Legacy upload {} is not used anywhere, left in place",
+                    file_name
+                );
+            }
+        }
+        if imported > 0 {
+            info!("Moved {} legacy uploads into the blob storage", imported);
+        }
+        Ok(imported)
+    }
+}
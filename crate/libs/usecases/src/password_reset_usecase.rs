@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use commons::generic_errors::GenericError;
+use commons::secret_tokens::hash_secret;
+use log::{error, info};
+use mail::SendEmail;
+use persistence::env::myenv::EnvInterface;
+use sessions::services::SessionServiceInterface;
+use shaku::{Component, Interface};
+use users::user::{PasswordReset, User};
+use users::user_services::UserServiceInterface;
+
+use crate::register_usecase::validate_password;
+
+pub const PASSWORD_RESET_TTL_MINUTES: i64 = 60;
+
+const INVALID_RESET_LINK: &str = "This reset link is invalid or has expired";
+
+#[derive(Component)]
+#[shaku(interface = PasswordResetUsecase)]
+pub struct PasswordResetUsecaseImpl {
+    #[shaku(inject)]
+    user_service: Arc<dyn UserServiceInterface>,
+    #[shaku(inject)]
+    session_service: Arc<dyn SessionServiceInterface>,
+    #[shaku(inject)]
+    env: Arc<dyn EnvInterface>,
+    #[shaku(inject)]
+    mail: Arc<dyn SendEmail>,
+}
+
+#[async_trait::async_trait]
+pub trait PasswordResetUsecase: Interface {
+    /// Emails a reset link when an active account uses the address.
+    /// Succeeds either way, so the answer does not tell who has an account.
+    async fn request_reset(&self, email: &str) -> anyhow::Result<()>;
+    /// Whether the link can still be used, checked before showing the form.
+    async fn check_reset_token(&self, token: &str) -> anyhow::Result<()>;
+    /// Sets the new password and logs the user out everywhere, the link stops working.
+    async fn reset_password(
+        &self,
+        token: &str,
+        password: &str,
+        password_confirmation: &str,
+    ) -> anyhow::Result<()>;
+}
+
+#[async_trait::async_trait]
+impl PasswordResetUsecase for PasswordResetUsecaseImpl {
+    async fn request_reset(&self, email: &str) -> anyhow::Result<()> {
+        if email.trim().is_empty() {
+            return Err(GenericError::invalid_input("Email is empty".to_string()));
+        }
+
+        let Some(user) = self.user_service.get_user_by_email(email).await? else {
+            info!("Password reset requested for an unknown email");
+            return Ok(());
+        };
+
+        let (reset, token) = PasswordReset::generate(
+            user.id,
+            chrono::Duration::minutes(PASSWORD_RESET_TTL_MINUTES),
+        );
+        self.user_service.create_password_reset(&reset).await?;
+        // In the background, a slow or failing mail would tell that the account exists
+        tokio::spawn({
+            let env = self.env.clone();
+            let mail = self.mail.clone();
+
+            async move {
+                let result = send_reset_email(env.as_ref(), mail.as_ref(), &user, &token).await;
+                if let Err(e) = result {
+                    error!("Error when sending password reset email: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn check_reset_token(&self, token: &str) -> anyhow::Result<()> {
+        match self
+            .user_service
+            .get_password_reset(&hash_secret(token))
+            .await?
+        {
+            Some(reset) if !reset.is_expired() => Ok(()),
+            _ => Err(GenericError::invalid_input(INVALID_RESET_LINK.to_string())),
+        }
+    }
+
+    async fn reset_password(
+        &self,
+        token: &str,
+        password: &str,
+        password_confirmation: &str,
+    ) -> anyhow::Result<()> {
+        // Checked before the link is used up, a typo should not cost the user their link
+        validate_password(password)?;
+        if password != password_confirmation {
+            return Err(GenericError::invalid_input(
+                "Passwords do not match".to_string(),
+            ));
+        }
+
+        let reset = match self
+            .user_service
+            .consume_password_reset(&hash_secret(token))
+            .await?
+        {
+            Some(reset) if !reset.is_expired() => reset,
+            _ => return Err(GenericError::invalid_input(INVALID_RESET_LINK.to_string())),
+        };
+
+        self.user_service
+            .update_password(reset.user_id, &User::hash_password(password)?)
+            .await?;
+        // Whoever knew the old password is logged out, and older links die with it
+        self.session_service
+            .delete_sessions_by_user(reset.user_id)
+            .await?;
+        self.user_service
+            .delete_password_resets_by_user(reset.user_id)
+            .await?;
+        info!("Password of user {} was reset", reset.user_id);
+        Ok(())
+    }
+}
+
+async fn send_reset_email(
+    env: &dyn EnvInterface,
+    mail: &dyn SendEmail,
+    user: &User,
+    token: &str,
+) -> anyhow::Result<()> {
+    let button = format!(
+        r#"<a href="{}/reset-password/{}">Reset password</a>"#,
+        env.get_app_callback_url(),
+        token
+    );
+    let message = format!(
+        r#"
+        Someone asked to reset the password of your account {}.
+        Click the link below within {} minutes to choose a new password,
+        {}
+        If it was not you, ignore this email and your password stays the same. "#,
+        user.username, PASSWORD_RESET_TTL_MINUTES, button
+    );
+    mail.send_email(
+        user.username.as_str(),
+        user.email.as_str(),
+        "Reset your password",
+        &message,
+    )
+    .await
+}
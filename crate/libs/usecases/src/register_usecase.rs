@@ -3,6 +3,7 @@ use commons::generic_errors::GenericError;
 use credentials::credential::Credential;
 use credentials::credential_services::CredentialServiceInterface;
 use crypto::Encrypt;
+use log::{error, info};
 use mail::SendEmail;
 use persistence::env::myenv::EnvInterface;
 use serde::Deserialize;
@@ -10,6 +11,15 @@ use shaku::{Component, Interface};
 use std::sync::Arc;
 use users::user::User;
 use users::user_services::UserServiceInterface;
+use uuid::Uuid;
+
+pub const ACTIVATION_TOKEN_TTL_HOURS: i64 = 48;
+// Both for resending and right after sign up
+pub const ACTIVATION_RESEND_INTERVAL_MINUTES: i64 = 5;
+
+const ACTIVATION_PURPOSE: &str = "activate";
+const INVALID_ACTIVATION_LINK: &str = "This activation link is invalid";
+const EXPIRED_ACTIVATION_LINK: &str = "This activation link has expired";
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct RegisterRequest<'a> {
@@ -29,16 +39,8 @@ impl RegisterRequest<'_> {
     }
 
     async fn validate(&self) -> anyhow::Result<()> {
-        // check if email is valid
-        let email_err = "Email is not valid";
-        if !self.email.contains('@') || !self.email.contains('.') {
-            return Err(GenericError::invalid_input(String::from(email_err)));
-        }
-
-        let password_err = "Password must be at least 8 characters and contain at least one number";
-        if self.password.len() < 8 || !self.password.chars().any(char::is_numeric) {
-            return Err(GenericError::invalid_input(String::from(password_err)));
-        }
+        validate_email(self.email)?;
+        validate_password(self.password)?;
 
         let username_err = "Username must be at least 3 characters";
         if self.username.len() < 3 {
@@ -59,9 +61,78 @@ impl RegisterRequest<'_> {
     }
 }
 
+/// The same check for the address given on sign up and for a new one set later.
+pub(crate) fn validate_email(email: &str) -> anyhow::Result<()> {
+    let email_err = "Email is not valid";
+    if !email.contains('@') || !email.contains('.') {
+        return Err(GenericError::invalid_input(String::from(email_err)));
+    }
+    Ok(())
+}
+
+/// The rule every new password follows, whether on sign up or when it is replaced.
+pub(crate) fn validate_password(password: &str) -> anyhow::Result<()> {
+    let password_err = "Password must be at least 8 characters and contain at least one number";
+    if password.len() < 8 || !password.chars().any(char::is_numeric) {
+        return Err(GenericError::invalid_input(String::from(password_err)));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RegisterResponse {
-    pub encrypted_user_id: String,
+    pub activation_token: String,
+}
+
+/// What an activation link carries, encrypted so it cannot be forged.
+/// The purpose keeps other encrypted values from passing as one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivationToken {
+    pub user_id: Uuid,
+    // Unix timestamp
+    pub expires_at: i64,
+}
+
+impl ActivationToken {
+    pub fn new(user_id: Uuid, ttl: chrono::Duration) -> Self {
+        Self {
+            user_id,
+            expires_at: (chrono::Utc::now() + ttl).timestamp(),
+        }
+    }
+
+    pub async fn seal(&self, crypto: &dyn Encrypt) -> anyhow::Result<String> {
+        let plaintext = format!(
+            "{}:{}:{}",
+            ACTIVATION_PURPOSE, self.user_id, self.expires_at
+        );
+        crypto.encrypt(&plaintext).await
+    }
+
+    /// Expired tokens open fine, check `is_expired` before using one.
+    pub async fn open(crypto: &dyn Encrypt, token: &str) -> anyhow::Result<Self> {
+        let invalid = || GenericError::invalid_input(String::from(INVALID_ACTIVATION_LINK));
+        // The nonce alone takes 16 characters, decrypt would panic on anything shorter
+        if token.len() <= 16 || !token.is_ascii() {
+            return Err(invalid());
+        }
+        let plaintext = crypto.decrypt(token).await.map_err(|_| invalid())?;
+
+        let mut parts = plaintext.splitn(3, ':');
+        let (Some(ACTIVATION_PURPOSE), Some(user_id), Some(expires_at)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(Self {
+            user_id: user_id.parse().map_err(|_| invalid())?,
+            expires_at: expires_at.parse().map_err(|_| invalid())?,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= chrono::Utc::now().timestamp()
+    }
 }
 
 #[derive(Component)]
@@ -97,15 +168,26 @@ impl RegisterUseCase {
     }
 }
 
+impl RegisterUseCase {
+    async fn issue_activation_token(&self, user_id: Uuid) -> anyhow::Result<String> {
+        ActivationToken::new(user_id, chrono::Duration::hours(ACTIVATION_TOKEN_TTL_HOURS))
+            .seal(self.crypto.as_ref())
+            .await
+    }
+}
+
 #[async_trait]
 pub trait RegisterUseCaseInterface: Interface {
     async fn register<'a>(&self, request: &RegisterRequest<'a>)
         -> anyhow::Result<RegisterResponse>;
-    async fn activate_user<'a>(&self, encrypted_user_id: &'a str) -> anyhow::Result<()>;
+    async fn activate_user<'a>(&self, activation_token: &'a str) -> anyhow::Result<()>;
+    /// Sends a new link when an account with the email waits for activation,
+    /// at most once every few minutes. Succeeds either way, like a password reset request.
+    async fn resend_activation(&self, email: &str) -> anyhow::Result<()>;
 
     async fn send_activation_email(
         &self,
-        user_id: String,
+        activation_token: String,
         username: String,
         email: String,
     ) -> anyhow::Result<()>;
@@ -133,48 +215,113 @@ impl RegisterUseCaseInterface for RegisterUseCase {
             .create_credential(&credential)
             .await?;
 
-        let encrypted_user_id = self.crypto.encrypt(user.id.to_string().as_str()).await?;
-        self.send_activation_email(encrypted_user_id.clone(), user.username, user.email)
+        let activation_token = self.issue_activation_token(user.id).await?;
+        // Starts the resend interval, the first email is on its way
+        self.user_service
+            .mark_activation_sent(user.id, chrono::Utc::now().naive_utc())
+            .await?;
+        self.send_activation_email(activation_token.clone(), user.username, user.email)
             .await?;
 
-        Ok(RegisterResponse { encrypted_user_id })
+        Ok(RegisterResponse { activation_token })
     }
 
-    async fn activate_user<'a>(&self, encrypted_user_id: &'a str) -> anyhow::Result<()> {
-        let user_id = self.crypto.decrypt(encrypted_user_id).await?;
-        self.user_service.activate_user(user_id.parse()?).await?;
+    async fn activate_user<'a>(&self, activation_token: &'a str) -> anyhow::Result<()> {
+        let token = ActivationToken::open(self.crypto.as_ref(), activation_token).await?;
+        if token.is_expired() {
+            return Err(GenericError::invalid_input(String::from(
+                EXPIRED_ACTIVATION_LINK,
+            )));
+        }
+        self.user_service.activate_user(token.user_id).await?;
+        Ok(())
+    }
+
+    async fn resend_activation(&self, email: &str) -> anyhow::Result<()> {
+        if email.trim().is_empty() {
+            return Err(GenericError::invalid_input("Email is empty".to_string()));
+        }
+
+        let Some(user) = self.user_service.get_inactive_user_by_email(email).await? else {
+            info!("Activation email requested for an unknown or active account");
+            return Ok(());
+        };
+        let not_since = chrono::Utc::now().naive_utc()
+            - chrono::Duration::minutes(ACTIVATION_RESEND_INTERVAL_MINUTES);
+        if !self
+            .user_service
+            .mark_activation_sent(user.id, not_since)
+            .await?
+        {
+            info!(
+                "Activation email for user {} was sent too recently",
+                user.id
+            );
+            return Ok(());
+        }
+
+        let activation_token = self.issue_activation_token(user.id).await?;
+        // In the background, a slow or failing mail would tell that the account exists
+        tokio::spawn({
+            let env = self.env.clone();
+            let mail = self.mail.clone();
+
+            async move {
+                let result = send_activation_email(
+                    env.as_ref(),
+                    mail.as_ref(),
+                    &activation_token,
+                    &user.username,
+                    &user.email,
+                )
+                .await;
+                if let Err(e) = result {
+                    error!("Error when sending activation email: {}", e);
+                }
+            }
+        });
         Ok(())
     }
 
     async fn send_activation_email(
         &self,
-        encrypted_user_id: String,
+        activation_token: String,
         username: String,
         email: String,
     ) -> anyhow::Result<()> {
-        let button = format!(
-            r#"<a href="{}/activate/{}">Activate account</a>"#,
-            self.env.get_app_callback_url(),
-            encrypted_user_id
-        );
-        let message = format!(
-            r#"
+        send_activation_email(
+            self.env.as_ref(),
+            self.mail.as_ref(),
+            &activation_token,
+            &username,
+            &email,
+        )
+        .await
+    }
+}
+
+async fn send_activation_email(
+    env: &dyn EnvInterface,
+    mail: &dyn SendEmail,
+    activation_token: &str,
+    username: &str,
+    email: &str,
+) -> anyhow::Result<()> {
+    let button = format!(
+        r#"<a href="{}/activate/{}">Activate account</a>"#,
+        env.get_app_callback_url(),
+        activation_token
+    );
+    let message = format!(
+        r#"
         Your account has been created successfully,
         Please activate your account.
-        Click the link below to activate your account,
+        Click the link below within {} hours to activate your account,
         {} "#,
-            button
-        );
-        self.mail
-            .send_email(
-                username.as_str(),
-                email.as_str(),
-                "Registration successful",
-                &message,
-            )
-            .await?;
-        Ok(())
-    }
+        ACTIVATION_TOKEN_TTL_HOURS, button
+    );
+    mail.send_email(username, email, "Registration successful", &message)
+        .await
 }
 
 #[cfg(test)]
@@ -250,12 +397,9 @@ mod tests {
         let error = result.unwrap_err();
         match error.downcast::<GenericError>().unwrap() {
             GenericError::InvalidInput(message, 400) => {
-                assert!(message
-                    .to_string()
-                    .to_lowercase()
-                    .contains(expected_message));
+                assert!(message.to_lowercase().contains(expected_message));
             }
-            _ => assert!(false),
+            _ => panic!("unexpected error"),
         }
     }
 }
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use commons::generic_errors::GenericError;
+use jwt::AccessClaims;
+use sessions::entity::Device;
+use sessions::services::SessionServiceInterface;
+use shaku::{Component, Interface};
+use uuid::Uuid;
+
+/// A login of the user, as listed on the sessions page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveSession {
+    pub id: Uuid,
+    pub device: Device,
+    pub ip_address: String,
+    pub first_seen_at: Option<chrono::NaiveDateTime>,
+    pub last_active_at: Option<chrono::NaiveDateTime>,
+    // The session the request was made with
+    pub is_current: bool,
+}
+
+#[derive(Component)]
+#[shaku(interface = SessionUsecase)]
+pub struct SessionUsecaseImpl {
+    #[shaku(inject)]
+    session_service: Arc<dyn SessionServiceInterface>,
+}
+
+#[async_trait::async_trait]
+pub trait SessionUsecase: Interface {
+    /// Every session of the user, the current one first.
+    async fn get_active_sessions(
+        &self,
+        claims: &AccessClaims,
+    ) -> anyhow::Result<Vec<ActiveSession>>;
+    /// Signs out one of the user's other sessions, the current one ends with a logout.
+    async fn revoke_session(&self, claims: &AccessClaims, session_id: &str) -> anyhow::Result<()>;
+}
+
+#[async_trait::async_trait]
+impl SessionUsecase for SessionUsecaseImpl {
+    async fn get_active_sessions(
+        &self,
+        claims: &AccessClaims,
+    ) -> anyhow::Result<Vec<ActiveSession>> {
+        let mut sessions: Vec<ActiveSession> = self
+            .session_service
+            .get_sessions_by_user(claims.user_id.parse()?)
+            .await
+            .map_err(GenericError::unknown)?
+            .into_iter()
+            .map(|session| ActiveSession {
+                id: session.id,
+                device: session.device(),
+                is_current: session.session_id.to_string() == claims.jti,
+                ip_address: session.ip_address,
+                first_seen_at: session.created_at,
+                last_active_at: session.updated_at,
+            })
+            .collect();
+        // Stable, so the others stay most recently active first
+        sessions.sort_by_key(|session| !session.is_current);
+        Ok(sessions)
+    }
+
+    async fn revoke_session(&self, claims: &AccessClaims, session_id: &str) -> anyhow::Result<()> {
+        let not_found = || GenericError::invalid_input(String::from("Session not found"));
+        let session_id: Uuid = session_id.parse().map_err(|_| not_found())?;
+        let user_id: Uuid = claims.user_id.parse()?;
+
+        let sessions = self
+            .session_service
+            .get_sessions_by_user(user_id)
+            .await
+            .map_err(GenericError::unknown)?;
+        let session = sessions
+            .iter()
+            .find(|session| session.id == session_id)
+            .ok_or_else(not_found)?;
+        if session.session_id.to_string() == claims.jti {
+            return Err(GenericError::invalid_input(String::from(
+                "Log out to end the session you are using",
+            )));
+        }
+
+        let deleted = self
+            .session_service
+            .delete_session_of_user(session_id, user_id)
+            .await
+            .map_err(GenericError::unknown)?;
+        if !deleted {
+            return Err(not_found());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use commons::generic_errors::GenericError;
+use commons::totp::{generate_totp_secret, otpauth_uri, verify_totp};
+use crypto::Encrypt;
+use jwt::AccessClaims;
+use log::info;
+use shaku::{Component, Interface};
+use users::user::{RecoveryCode, TwoFactor};
+use users::user_services::UserServiceInterface;
+use uuid::Uuid;
+
+// Shown as the account name in authenticator apps
+pub const TOTP_ISSUER: &str = "Chaty";
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const INVALID_CODE: &str = "Invalid verification code";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub recovery_codes_left: i64,
+}
+
+/// What the user scans or types into their authenticator app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwoFactorEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Component)]
+#[shaku(interface = TwoFactorUsecase)]
+pub struct TwoFactorUsecaseImpl {
+    #[shaku(inject)]
+    user_service: Arc<dyn UserServiceInterface>,
+    #[shaku(inject)]
+    crypto: Arc<dyn Encrypt>,
+}
+
+#[async_trait::async_trait]
+pub trait TwoFactorUsecase: Interface {
+    async fn get_status(&self, claims: &AccessClaims) -> anyhow::Result<TwoFactorStatus>;
+    /// A new secret to scan, login does not ask for codes until `confirm_enrollment`.
+    async fn start_enrollment(&self, claims: &AccessClaims) -> anyhow::Result<TwoFactorEnrollment>;
+    /// Turns two-factor login on once a code from the app matches.
+    /// Returns the recovery codes, they are not shown again.
+    async fn confirm_enrollment(
+        &self,
+        claims: &AccessClaims,
+        code: &str,
+    ) -> anyhow::Result<Vec<String>>;
+    /// Turns two-factor login off, the password and a code or recovery code are asked again.
+    async fn disable(
+        &self,
+        claims: &AccessClaims,
+        password: &str,
+        code: &str,
+    ) -> anyhow::Result<()>;
+}
+
+#[async_trait::async_trait]
+impl TwoFactorUsecase for TwoFactorUsecaseImpl {
+    async fn get_status(&self, claims: &AccessClaims) -> anyhow::Result<TwoFactorStatus> {
+        let user_id: Uuid = claims.user_id.parse()?;
+        let enabled = self
+            .user_service
+            .get_two_factor(user_id)
+            .await?
+            .is_some_and(|two_factor| two_factor.is_enabled());
+        let recovery_codes_left = if enabled {
+            self.user_service
+                .count_unused_recovery_codes(user_id)
+                .await?
+        } else {
+            0
+        };
+        Ok(TwoFactorStatus {
+            enabled,
+            recovery_codes_left,
+        })
+    }
+
+    async fn start_enrollment(&self, claims: &AccessClaims) -> anyhow::Result<TwoFactorEnrollment> {
+        let user_id: Uuid = claims.user_id.parse()?;
+        if let Some(two_factor) = self.user_service.get_two_factor(user_id).await? {
+            if two_factor.is_enabled() {
+                return Err(already_enabled());
+            }
+        }
+        let user = self.user_service.get_user_by_uuid(user_id).await?;
+
+        let secret = generate_totp_secret();
+        self.user_service
+            .save_two_factor(&TwoFactor {
+                user_id,
+                secret: self.crypto.encrypt(&secret).await?,
+                enabled_at: None,
+                last_used_step: None,
+                created_at: Some(chrono::Local::now().naive_local()),
+            })
+            .await?;
+
+        Ok(TwoFactorEnrollment {
+            otpauth_uri: otpauth_uri(TOTP_ISSUER, &user.username, &secret),
+            secret,
+        })
+    }
+
+    async fn confirm_enrollment(
+        &self,
+        claims: &AccessClaims,
+        code: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let user_id: Uuid = claims.user_id.parse()?;
+        let two_factor = match self.user_service.get_two_factor(user_id).await? {
+            Some(two_factor) if two_factor.is_enabled() => return Err(already_enabled()),
+            Some(two_factor) => two_factor,
+            None => {
+                return Err(GenericError::invalid_input(
+                    "Start the setup again".to_string(),
+                ))
+            }
+        };
+
+        let secret = self.crypto.decrypt(&two_factor.secret).await?;
+        let step = verify_totp(&secret, code, unix_now())
+            .ok_or_else(|| GenericError::invalid_input(INVALID_CODE.to_string()))?;
+        let (recovery_codes, codes) = RecoveryCode::generate(user_id, RECOVERY_CODE_COUNT);
+        self.user_service
+            .enable_two_factor(user_id, step as i64, &recovery_codes)
+            .await?;
+        info!("Two-factor login turned on for user {}", user_id);
+        Ok(codes)
+    }
+
+    async fn disable(
+        &self,
+        claims: &AccessClaims,
+        password: &str,
+        code: &str,
+    ) -> anyhow::Result<()> {
+        let user_id: Uuid = claims.user_id.parse()?;
+        let user = self.user_service.get_user_by_uuid(user_id).await?;
+        if !user.match_password(password) {
+            return Err(GenericError::invalid_input(
+                "Current password is incorrect".to_string(),
+            ));
+        }
+        let two_factor = self
+            .user_service
+            .get_two_factor(user_id)
+            .await?
+            .filter(TwoFactor::is_enabled)
+            .ok_or_else(|| {
+                GenericError::invalid_input("Two-factor authentication is off".to_string())
+            })?;
+
+        if !verify_second_factor(
+            self.user_service.as_ref(),
+            self.crypto.as_ref(),
+            &two_factor,
+            code,
+        )
+        .await?
+        {
+            return Err(GenericError::invalid_input(INVALID_CODE.to_string()));
+        }
+        self.user_service.delete_two_factor(user_id).await?;
+        info!("Two-factor login turned off for user {}", user_id);
+        Ok(())
+    }
+}
+
+/// Checks a code from the authenticator app, or else a recovery code, and uses it up.
+pub(crate) async fn verify_second_factor(
+    user_service: &dyn UserServiceInterface,
+    crypto: &dyn Encrypt,
+    two_factor: &TwoFactor,
+    code: &str,
+) -> anyhow::Result<bool> {
+    let secret = crypto.decrypt(&two_factor.secret).await?;
+    if let Some(step) = verify_totp(&secret, code, unix_now()) {
+        return user_service
+            .use_two_factor_step(two_factor.user_id, step as i64)
+            .await;
+    }
+    user_service
+        .use_recovery_code(two_factor.user_id, &RecoveryCode::hash(code))
+        .await
+}
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+fn already_enabled() -> anyhow::Error {
+    GenericError::invalid_input("Two-factor authentication is already on".to_string())
+}
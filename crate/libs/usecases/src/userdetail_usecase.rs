@@ -1,17 +1,24 @@
-use std::{fs, path::Path, sync::Arc};
-use uuid::Uuid;
+use std::sync::Arc;
 
-use anyhow::Context;
+use crate::images::{self, ProcessedImage};
+use crate::utils::{self, MediaFolder};
 use async_trait::async_trait;
 use commons::generic_errors::GenericError;
-use infer::Infer;
+use log::warn;
 use shaku::{Component, Interface};
-use user_details::{entity::UserDetail, user_detail_service::UserDetailService};
+use storage::BlobStorage;
+use user_details::{
+    entity::{ProfilePicture, UserDetail},
+    user_detail_service::UserDetailService,
+};
 use users::{
     user::{User, UserInfoDisplay},
     user_services::UserServiceInterface,
 };
 
+/// Large, medium and small, the profile page uses the medium one and lists the small one.
+pub const PROFILE_PICTURE_SIZES: [u32; 3] = [512, 256, 96];
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UserInfo {
     pub username: String,
@@ -33,15 +40,18 @@ impl UserInfoDisplay for UserInfo {
     fn get_profile_picture(&self) -> String {
         self.user_details
             .as_ref()
-            .and_then(|details| details.profile_picture.clone())
+            .and_then(|details| {
+                details
+                    .profile_picture_small
+                    .clone()
+                    .or_else(|| details.profile_picture.clone())
+            })
             .unwrap_or_else(|| self.get_default_profile_picture())
     }
     fn get_full_name(&self) -> String {
         self.user_details
             .as_ref()
-            .and_then(|details| {
-                return Some(format!("{} {}", details.first_name, details.last_name));
-            })
+            .map(|details| format!("{} {}", details.first_name, details.last_name))
             .unwrap_or_else(|| self.username.clone())
     }
     fn get_user_name(&self) -> String {
@@ -56,13 +66,38 @@ pub struct UserDetailUsecaseImpl {
     user_detail_service: Arc<dyn UserDetailService>,
     #[shaku(inject)]
     user_service: Arc<dyn UserServiceInterface>,
+    #[shaku(inject)]
+    storage: Arc<dyn BlobStorage>,
+}
+
+impl UserDetailUsecaseImpl {
+    async fn save_avatar(
+        &self,
+        user_id: &str,
+        size: u32,
+        image: &ProcessedImage,
+    ) -> anyhow::Result<String> {
+        utils::save_processed_image(
+            self.storage.as_ref(),
+            MediaFolder::Avatars,
+            &format!("{}_{}", user_id, size),
+            image,
+        )
+        .await
+        .map_err(GenericError::unknown)
+    }
 }
 
 #[async_trait]
 pub trait UserDetailUsecase: Interface {
     async fn update_profile(&self, user_detail: &UserDetail) -> anyhow::Result<()>;
     async fn get_user_info(&self, user_id: &str) -> anyhow::Result<UserInfo>;
-    async fn upload_profile_picture(&self, user_id: &str, image: &[u8]) -> anyhow::Result<String>;
+    /// Stores the picture in every size of `PROFILE_PICTURE_SIZES`, stripped of its metadata.
+    async fn upload_profile_picture(
+        &self,
+        user_id: &str,
+        image: &[u8],
+    ) -> anyhow::Result<ProfilePicture>;
 }
 
 #[async_trait]
@@ -94,33 +129,56 @@ impl UserDetailUsecase for UserDetailUsecaseImpl {
         })
     }
 
-    async fn upload_profile_picture(&self, user_id: &str, image: &[u8]) -> anyhow::Result<String> {
-        // Validate the file type
-        let infer = Infer::new();
-        if !infer.is_image(image) {
-            return Err(anyhow::anyhow!("Uploaded file is not a valid image"));
-        }
-
-        // Create the uploads directory if it doesn't exist
-        let upload_dir = Path::new("crate/application/web/assets/uploads");
-        if !upload_dir.exists() && fs::create_dir_all(upload_dir).is_err() {
-            return Err(anyhow::anyhow!("Failed to create uploads directory"));
-        }
-
-        // Generate a unique filename using UUID and the user ID
-        let file_name = format!("{}_{}.png", user_id, Uuid::new_v4());
-        let file_path = upload_dir.join(file_name.clone());
-
-        // Write the image bytes to the file
-        fs::write(&file_path, image).context("Failed to write image to file")?;
-
-        let file_name = format!("/assets/uploads/{}", file_name);
+    async fn upload_profile_picture(
+        &self,
+        user_id: &str,
+        image: &[u8],
+    ) -> anyhow::Result<ProfilePicture> {
+        let image = image.to_vec();
+        let variants = tokio::task::spawn_blocking(move || {
+            images::square_variants(&image, &PROFILE_PICTURE_SIZES)
+        })
+        .await?
+        .map_err(|e| GenericError::invalid_input(e.to_string()))?;
+
+        let ([large, medium, small], [large_size, medium_size, small_size]) =
+            (&variants[..], PROFILE_PICTURE_SIZES)
+        else {
+            return Err(GenericError::unknown(anyhow::anyhow!(
+                "Missing profile picture sizes"
+            )));
+        };
+        let profile_picture = ProfilePicture {
+            large: self.save_avatar(user_id, large_size, large).await?,
+            medium: self.save_avatar(user_id, medium_size, medium).await?,
+            small: self.save_avatar(user_id, small_size, small).await?,
+        };
+
+        let previous = self
+            .user_detail_service
+            .get_user_detail_by_user_id(user_id)
+            .await
+            .ok();
         self.user_detail_service
-            .update_profile_picture(user_id, file_name.as_str())
+            .update_profile_picture(user_id, &profile_picture)
             .await
             .map_err(GenericError::unknown)?;
 
-        // Return the file path or URL
-        Ok(file_name)
+        let previous_files = previous.into_iter().flat_map(|detail| {
+            [
+                detail.profile_picture,
+                detail.profile_picture_medium,
+                detail.profile_picture_small,
+            ]
+        });
+        for previous in previous_files.flatten() {
+            if let Err(e) = utils::remove_uploaded_file(self.storage.as_ref(), &previous).await {
+                warn!(
+                    "Failed to remove previous profile picture {} of user {}: {}",
+                    previous, user_id, e
+                );
+            }
+        }
+        Ok(profile_picture)
     }
 }
@@ -1,39 +1,17 @@
+use anyhow::Context;
 use persistence::db::database::DBParameters;
 use persistence::db::sqlite::create_sqlite_db_pool;
 use persistence::env::myenv::EnvInterface;
 use persistence::{DatabaseInterface, Env, DB};
 use shaku::{HasComponent, ModuleBuilder};
 use std::sync::Arc;
+use storage::BlobStorage;
+use uuid::Uuid;
 
-#[allow(dead_code)]
-pub async fn setup_db() -> Arc<dyn DatabaseInterface> {
-    let db_path = ":memory:"; // Use an in-memory database for tests
-    let env = Env {
-        db_url: format!("sqlite:{}", db_path),
-        email_from: "".to_string(),
-        email_from_email: "".to_string(),
-        email_smtp_username: "".to_string(),
-        email_smtp_password: "".to_string(),
-        email_smtp_host: "".to_string(),
-        email_smtp_port: "".to_string(),
-        app_key_main: "".to_string(),
-        app_callback_url: "".to_string(),
-        app_key_jwt: "".to_string(),
-    };
-    let db = Arc::new(DB::new(env).await.unwrap());
-
-    // Apply migrations
-    let pool = db.get_pool();
-    sqlx::migrate!("../../../migrations")
-        .run(&*pool)
-        .await
-        .expect("Failed to run database migrations");
-
-    db
-}
+use crate::images::ProcessedImage;
 
 pub async fn setup_module<
-    T: shaku::Module + HasComponent<(dyn EnvInterface)> + HasComponent<(dyn DatabaseInterface)>,
+    T: shaku::Module + HasComponent<dyn EnvInterface> + HasComponent<dyn DatabaseInterface>,
 >(
     module_builder: ModuleBuilder<T>,
     env: Env,
@@ -47,3 +25,93 @@ pub async fn setup_module<
         .with_component_override::<dyn EnvInterface>(Box::new(env))
         .build()
 }
+
+pub const MEDIA_URL_PREFIX: &str = "/media/";
+
+/// Where an upload lives, which also decides who may read it back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaFolder {
+    Avatars,
+    GroupAvatars,
+    Attachments,
+}
+
+impl MediaFolder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaFolder::Avatars => "avatars",
+            MediaFolder::GroupAvatars => "groups",
+            MediaFolder::Attachments => "attachments",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "avatars" => Some(MediaFolder::Avatars),
+            "groups" => Some(MediaFolder::GroupAvatars),
+            "attachments" => Some(MediaFolder::Attachments),
+            _ => None,
+        }
+    }
+}
+
+/// Stores an image produced by the `images` pipeline and returns its `/media` URL.
+pub async fn save_processed_image(
+    storage: &dyn BlobStorage,
+    folder: MediaFolder,
+    file_prefix: &str,
+    image: &ProcessedImage,
+) -> anyhow::Result<String> {
+    save_uploaded_file(storage, folder, file_prefix, image.extension(), &image.data).await
+}
+
+/// Stores any uploaded file and returns its `/media` URL.
+/// The extension is the caller's to decide, never take it from the client.
+pub async fn save_uploaded_file(
+    storage: &dyn BlobStorage,
+    folder: MediaFolder,
+    file_prefix: &str,
+    extension: &str,
+    data: &[u8],
+) -> anyhow::Result<String> {
+    // Generate a unique filename using UUID and the prefix
+    let file_name = format!("{}_{}.{}", file_prefix, Uuid::new_v4(), extension);
+    let key = format!("{}/{}", folder.as_str(), file_name);
+    let content_type = infer::get(data)
+        .map(|kind| kind.mime_type())
+        .unwrap_or("application/octet-stream");
+
+    storage
+        .put(&key, data.to_vec(), content_type)
+        .await
+        .context("Failed to store file")?;
+
+    Ok(format!("{}{}", MEDIA_URL_PREFIX, key))
+}
+
+/// Maps a `/media` URL back to its key in the blob storage.
+/// Returns `None` for anything `save_uploaded_file` could not have produced.
+pub fn media_storage_key(media_url: &str) -> Option<(MediaFolder, String)> {
+    let (folder, file_name) = media_url.strip_prefix(MEDIA_URL_PREFIX)?.split_once('/')?;
+    let folder = MediaFolder::from_name(folder)?;
+    let is_safe = !file_name.is_empty()
+        && !file_name.starts_with('.')
+        && file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !is_safe {
+        return None;
+    }
+    Some((folder, format!("{}/{}", folder.as_str(), file_name)))
+}
+
+/// Deletes a file stored by `save_uploaded_file`, URLs it could not have produced are ignored.
+pub async fn remove_uploaded_file(
+    storage: &dyn BlobStorage,
+    media_url: &str,
+) -> anyhow::Result<()> {
+    match media_storage_key(media_url) {
+        Some((_, key)) => storage.delete(&key).await,
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,213 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, RecordingMail, SentEmails, TestApp};
+    use credentials::credential_services::CredentialService;
+    use crypto::Crypto;
+    use jwt::JWT;
+    use persistence::{DatabaseInterface, Env, DB};
+    use sessions::services::SessionService;
+    use shaku::{module, HasComponent};
+    use usecases::account_usecase::{AccountUsecase, AccountUsecaseImpl};
+    use usecases::{LoginRequest, LoginUseCase, LoginUseCaseInterface};
+    use users::user::User;
+    use users::user_services::{UserService, UserServiceInterface};
+
+    module! {
+        TestModule {
+            components = [
+                AccountUsecaseImpl,
+                LoginUseCase,
+                UserService,
+                SessionService,
+                CredentialService,
+                Crypto,
+                RecordingMail,
+                JWT,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    async fn setup() -> (TestApp<TestModule>, SentEmails) {
+        common::setup_with_mail(TestModule::builder()).await
+    }
+
+    fn confirmation_token(body: &str) -> String {
+        let link = body.split("/confirm-email/").nth(1).unwrap();
+        link.split('"').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_change_password_keeps_current_session() {
+        let (module, _) = setup().await;
+        let account_usecase: &dyn AccountUsecase = module.resolve_ref();
+        let login_usecase: &dyn LoginUseCaseInterface = module.resolve_ref();
+        module.create_user_with_credential("accountuser").await;
+        let claims = module.claims("accountuser", "password8").await.unwrap();
+        let other_login = login_usecase
+            .login(LoginRequest {
+                username: "accountuser",
+                password: "password8",
+                user_agent: "other_agent",
+                ip_address: "ip_address",
+            })
+            .await
+            .unwrap()
+            .logged_in()
+            .unwrap();
+
+        assert!(account_usecase
+            .change_password(&claims, "wrongpassword8", "newpassword9", "newpassword9")
+            .await
+            .is_err());
+        assert!(account_usecase
+            .change_password(&claims, "password8", "short", "short")
+            .await
+            .is_err());
+        assert!(account_usecase
+            .change_password(&claims, "password8", "newpassword9", "newpassword0")
+            .await
+            .is_err());
+        module.claims("accountuser", "password8").await.unwrap();
+
+        account_usecase
+            .change_password(&claims, "password8", "newpassword9", "newpassword9")
+            .await
+            .unwrap();
+        assert!(module.claims("accountuser", "password8").await.is_err());
+        module.claims("accountuser", "newpassword9").await.unwrap();
+        assert!(
+            login_usecase
+                .authorize_current_user(&other_login.token)
+                .await
+                .is_err(),
+            "other sessions should be logged out"
+        );
+        assert!(login_usecase
+            .refresh(&other_login.refresh_token)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_change_email_after_confirmation() {
+        let (module, sent) = setup().await;
+        let account_usecase: &dyn AccountUsecase = module.resolve_ref();
+        let user_service: &dyn UserServiceInterface = module.resolve_ref();
+        let user = module.create_user_with_credential("emailuser").await;
+        let taken = module.create_user_with_credential("takenuser").await;
+        // Signed up but never activated, the address is still spoken for
+        let pending = User::new(
+            String::from("pendinguser"),
+            String::from("pendinguser@gmail.com"),
+            String::from("password8"),
+        )
+        .unwrap();
+        user_service.create_user(&pending).await.unwrap();
+        let claims = module.claims("emailuser", "password8").await.unwrap();
+
+        assert!(account_usecase
+            .request_email_change(&claims, "new@gmail.com", "wrongpassword8")
+            .await
+            .is_err());
+        assert!(account_usecase
+            .request_email_change(&claims, "not-an-email", "password8")
+            .await
+            .is_err());
+        assert!(account_usecase
+            .request_email_change(&claims, &taken.email, "password8")
+            .await
+            .is_err());
+        assert!(account_usecase
+            .request_email_change(&claims, "PendingUser@gmail.com", "password8")
+            .await
+            .is_err());
+        assert!(account_usecase
+            .request_email_change(&claims, "EmailUser@gmail.com", "password8")
+            .await
+            .is_err());
+        assert!(sent.lock().unwrap().is_empty());
+
+        account_usecase
+            .request_email_change(&claims, "old-request@gmail.com", "password8")
+            .await
+            .unwrap();
+        account_usecase
+            .request_email_change(&claims, " new@gmail.com ", "password8")
+            .await
+            .unwrap();
+        let (old_token, token) = {
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent.len(), 4);
+            assert_eq!(sent[2].0, "new@gmail.com");
+            assert_eq!(sent[3].0, user.email);
+            assert!(sent[3].1.contains("new@gmail.com"));
+            assert!(!sent[3].1.contains("/confirm-email/"));
+            (
+                confirmation_token(&sent[0].1),
+                confirmation_token(&sent[2].1),
+            )
+        };
+        assert_eq!(
+            account_usecase.get_pending_email(&claims).await.unwrap(),
+            Some("new@gmail.com".to_string())
+        );
+        // Nothing changes until the link is opened
+        assert_eq!(
+            user_service.get_user_by_uuid(user.id).await.unwrap().email,
+            user.email
+        );
+
+        // Only the latest request counts
+        assert!(account_usecase
+            .confirm_email_change(&old_token)
+            .await
+            .is_err());
+        account_usecase.confirm_email_change(&token).await.unwrap();
+        assert_eq!(
+            user_service.get_user_by_uuid(user.id).await.unwrap().email,
+            "new@gmail.com"
+        );
+        assert_eq!(
+            account_usecase.get_pending_email(&claims).await.unwrap(),
+            None
+        );
+        assert!(account_usecase.confirm_email_change(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_email_confirmation() {
+        let (module, sent) = setup().await;
+        let account_usecase: &dyn AccountUsecase = module.resolve_ref();
+        let user_service: &dyn UserServiceInterface = module.resolve_ref();
+        let db: &dyn DatabaseInterface = module.resolve_ref();
+        let user = module.create_user_with_credential("expireduser").await;
+        let claims = module.claims("expireduser", "password8").await.unwrap();
+
+        account_usecase
+            .request_email_change(&claims, "expired-new@gmail.com", "password8")
+            .await
+            .unwrap();
+        let token = confirmation_token(&sent.lock().unwrap()[0].1);
+        sqlx::query("UPDATE email_changes SET expires_at = ? WHERE user_id = ?")
+            .bind(chrono::Local::now().naive_local() - chrono::Duration::minutes(1))
+            .bind(user.id.to_string())
+            .execute(&*db.get_pool())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            account_usecase.get_pending_email(&claims).await.unwrap(),
+            None
+        );
+        assert!(account_usecase.confirm_email_change(&token).await.is_err());
+        assert_eq!(
+            user_service.get_user_by_uuid(user.id).await.unwrap().email,
+            user.email
+        );
+    }
+}
@@ -0,0 +1,1232 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, TestApp};
+    use chats::chat_services::{ChatService, ChatServiceInterface};
+    use chats::entity::{
+        DeleteScope, MessageBox, MessageCursor, MessageSearchResults, ThreadSummary,
+    };
+    use chats::events::{ChatEventBroker, ChatEventBrokerInterface, ChatEventKind};
+    use commons::generic_errors::GenericError;
+    use futures::TryStreamExt;
+    use image::{DynamicImage, ImageFormat};
+    use persistence::{Env, DB};
+    use shaku::{module, HasComponent};
+    use std::io::Cursor;
+    use storage::{BlobStorage, LocalStorage};
+    use usecases::chat_usecase::{
+        AttachmentUpload, ChatUsecase, ChatUsecaseImpl, MAX_ATTACHMENT_SIZE,
+    };
+    use usecases::utils;
+    use users::user_services::UserService;
+    use uuid::Uuid;
+
+    module! {
+        TestModule {
+            components = [
+                ChatUsecaseImpl,
+                ChatService,
+                ChatEventBroker,
+                UserService,
+                LocalStorage,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    async fn setup() -> TestApp<TestModule> {
+        common::setup(TestModule::builder()).await
+    }
+
+    #[tokio::test]
+    async fn test_send_message_publishes_event_to_chat_members() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+        let broker: &dyn ChatEventBrokerInterface = module.resolve_ref();
+
+        let user1 = module.create_user("sender").await;
+        let user2 = module.create_user("receiver").await;
+        let chat_id = chat_service
+            .initiate_private_chat(&user1.id.to_string(), &user2.id.to_string())
+            .await
+            .unwrap();
+
+        let mut events = broker.subscribe();
+        let message = chat_usecase
+            .send_message_to_chat(&chat_id.to_string(), &user1.id.to_string(), "hello", None)
+            .await
+            .unwrap();
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.chat_id, chat_id);
+        assert!(event.is_recipient(&user1.id));
+        assert!(event.is_recipient(&user2.id));
+        match event.kind {
+            ChatEventKind::NewMessage(message_box) => assert_eq!(message_box, message),
+            other => panic!("expected new message event, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_message_by_non_member_should_fail() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+
+        let user1 = module.create_user("member1").await;
+        let user2 = module.create_user("member2").await;
+        let stranger = module.create_user("stranger").await;
+        let chat_id = chat_service
+            .initiate_private_chat(&user1.id.to_string(), &user2.id.to_string())
+            .await
+            .unwrap();
+
+        let result = chat_usecase
+            .send_message_to_chat(
+                &chat_id.to_string(),
+                &stranger.id.to_string(),
+                "hello",
+                None,
+            )
+            .await;
+
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::Unauthorized()) => {}
+            _ => panic!("expected unauthorized error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_missed_chat_events_after_last_event_id() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+
+        let user1 = module.create_user("resumer1").await;
+        let user2 = module.create_user("resumer2").await;
+        let chat_id = chat_service
+            .initiate_private_chat(&user1.id.to_string(), &user2.id.to_string())
+            .await
+            .unwrap()
+            .to_string();
+
+        let user1_id = user1.id.to_string();
+        let first = chat_usecase
+            .send_message_to_chat(&chat_id, &user1_id, "first", None)
+            .await
+            .unwrap();
+        let second = chat_usecase
+            .send_message_to_chat(&chat_id, &user1_id, "second", None)
+            .await
+            .unwrap();
+        let third = chat_usecase
+            .send_message_to_chat(&chat_id, &user1_id, "third", None)
+            .await
+            .unwrap();
+
+        let events = chat_usecase
+            .get_missed_chat_events(&user2.id.to_string(), &first.0.id.to_string())
+            .await
+            .unwrap();
+
+        let missed: Vec<_> = events
+            .into_iter()
+            .map(|event| match event.kind {
+                ChatEventKind::NewMessage(message_box) => message_box.0.id,
+                other => panic!("expected new message event, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(missed, vec![second.0.id, third.0.id]);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_chat_list_orders_by_last_activity_with_unread_count() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+
+        let me = module.create_user("listowner").await;
+        let alice = module.create_user("alice").await;
+        let bob = module.create_user("bob").await;
+        let me_id = me.id.to_string();
+        let alice_chat = chat_service
+            .initiate_private_chat(&me_id, &alice.id.to_string())
+            .await
+            .unwrap();
+        let bob_chat = chat_service
+            .initiate_private_chat(&me_id, &bob.id.to_string())
+            .await
+            .unwrap();
+
+        chat_usecase
+            .send_message_to_chat(
+                &bob_chat.to_string(),
+                &bob.id.to_string(),
+                "hi from bob",
+                None,
+            )
+            .await
+            .unwrap();
+        chat_usecase
+            .send_message_to_chat(&alice_chat.to_string(), &alice.id.to_string(), "hi", None)
+            .await
+            .unwrap();
+        chat_usecase
+            .send_message_to_chat(
+                &alice_chat.to_string(),
+                &alice.id.to_string(),
+                "you there?",
+                None,
+            )
+            .await
+            .unwrap();
+        chat_usecase
+            .send_message_to_chat(&alice_chat.to_string(), &me_id, "yes", None)
+            .await
+            .unwrap();
+
+        let chats = chat_usecase.get_user_chat_list(&me_id).await.unwrap();
+
+        assert_eq!(chats.len(), 2);
+        assert_eq!(chats[0].chat_id, alice_chat);
+        assert_eq!(chats[0].name, "alice");
+        assert_eq!(chats[0].counterpart_username.as_deref(), Some("alice"));
+        assert_eq!(chats[0].unread_message_count, 2);
+        assert_eq!(chats[0].last_message.as_ref().unwrap().content, "yes");
+        assert_eq!(chats[1].chat_id, bob_chat);
+        assert_eq!(chats[1].name, "bob");
+        assert_eq!(chats[1].unread_message_count, 1);
+        assert_eq!(
+            chats[1].last_message.as_ref().unwrap().content,
+            "hi from bob"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_messages_of_chat_paginates_with_cursors() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+
+        let user1 = module.create_user("pager1").await;
+        let user2 = module.create_user("pager2").await;
+        let stranger = module.create_user("pagerstranger").await;
+        let user1_id = user1.id.to_string();
+        let chat_id = chat_service
+            .initiate_private_chat(&user1_id, &user2.id.to_string())
+            .await
+            .unwrap()
+            .to_string();
+
+        let mut sent = Vec::new();
+        for i in 0..5 {
+            let message = chat_usecase
+                .send_message_to_chat(&chat_id, &user1_id, &format!("message {}", i), None)
+                .await
+                .unwrap();
+            sent.push(message.0.id);
+        }
+        let ids = |page: &chats::entity::ChatMessages| -> Vec<_> {
+            page.messages.iter().map(|message| message.0.id).collect()
+        };
+
+        let latest = chat_usecase
+            .get_messages_of_chat(&chat_id, &user1_id, MessageCursor::Latest, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(ids(&latest), sent[3..5]);
+        assert!(latest.has_more);
+        assert_eq!(latest.chat_members.len(), 2);
+
+        let older = chat_usecase
+            .get_messages_of_chat(&chat_id, &user1_id, MessageCursor::Before(sent[3]), Some(2))
+            .await
+            .unwrap();
+        assert_eq!(ids(&older), sent[1..3]);
+        assert!(older.has_more);
+
+        let oldest = chat_usecase
+            .get_messages_of_chat(&chat_id, &user1_id, MessageCursor::Before(sent[1]), Some(2))
+            .await
+            .unwrap();
+        assert_eq!(ids(&oldest), sent[0..1]);
+        assert!(!oldest.has_more);
+
+        let newer = chat_usecase
+            .get_messages_of_chat(&chat_id, &user1_id, MessageCursor::After(sent[1]), Some(10))
+            .await
+            .unwrap();
+        assert_eq!(ids(&newer), sent[2..5]);
+        assert!(!newer.has_more);
+
+        let result = chat_usecase
+            .get_messages_of_chat(
+                &chat_id,
+                &stranger.id.to_string(),
+                MessageCursor::Latest,
+                None,
+            )
+            .await;
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::Unauthorized()) => {}
+            _ => panic!("expected unauthorized error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mark_chat_read_records_receipts_of_every_reader() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+        let broker: &dyn ChatEventBrokerInterface = module.resolve_ref();
+
+        let alice = module.create_user("readeralice").await;
+        let bob = module.create_user("readerbob").await;
+        let carol = module.create_user("readercarol").await;
+        let stranger = module.create_user("readerstranger").await;
+        let alice_id = alice.id.to_string();
+        let bob_id = bob.id.to_string();
+        let carol_id = carol.id.to_string();
+        let chat_id = chat_service
+            .create_group_chat("Readers", &alice.id, &[bob.id, carol.id])
+            .await
+            .unwrap()
+            .id
+            .to_string();
+
+        let mut sent = Vec::new();
+        for i in 0..3 {
+            let message = chat_usecase
+                .send_message_to_chat(&chat_id, &alice_id, &format!("message {}", i), None)
+                .await
+                .unwrap();
+            sent.push(message.0.id.to_string());
+        }
+
+        let mut events = broker.subscribe();
+        let read = chat_usecase
+            .mark_chat_read(&chat_id, &bob_id, &sent[1])
+            .await
+            .unwrap();
+        assert_eq!(read, 2);
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.recipients, vec![alice.id]);
+        match event.kind {
+            ChatEventKind::MessagesRead(receipts) => {
+                assert_eq!(receipts.len(), 2);
+                assert!(receipts
+                    .iter()
+                    .all(|(name, receipt)| name == "readerbob" && receipt.user_id == bob.id));
+            }
+            other => panic!("expected messages read event, got {:?}", other),
+        }
+
+        // Already read and own messages are skipped
+        let read = chat_usecase
+            .mark_chat_read(&chat_id, &bob_id, &sent[1])
+            .await
+            .unwrap();
+        assert_eq!(read, 0);
+        let read = chat_usecase
+            .mark_chat_read(&chat_id, &alice_id, &sent[2])
+            .await
+            .unwrap();
+        assert_eq!(read, 0);
+        let read = chat_usecase
+            .mark_chat_read(&chat_id, &carol_id, &sent[2])
+            .await
+            .unwrap();
+        assert_eq!(read, 3);
+
+        let messages = chat_usecase
+            .get_messages_of_chat(&chat_id, &alice_id, MessageCursor::Latest, None)
+            .await
+            .unwrap()
+            .messages;
+        let readers = |index: usize| -> Vec<_> {
+            let mut readers: Vec<_> = messages[index]
+                .1
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            readers.sort();
+            readers
+        };
+        assert_eq!(readers(0), vec!["readerbob", "readercarol"]);
+        assert_eq!(readers(1), vec!["readerbob", "readercarol"]);
+        assert_eq!(readers(2), vec!["readercarol"]);
+
+        let bob_chats = chat_usecase.get_user_chat_list(&bob_id).await.unwrap();
+        assert_eq!(bob_chats[0].unread_message_count, 1);
+
+        let result = chat_usecase
+            .mark_chat_read(&chat_id, &stranger.id.to_string(), &sent[2])
+            .await;
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::Unauthorized()) => {}
+            _ => panic!("expected unauthorized error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_toggle_reaction_adds_and_removes_reactions() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+        let broker: &dyn ChatEventBrokerInterface = module.resolve_ref();
+
+        let alice = module.create_user("reactalice").await;
+        let bob = module.create_user("reactbob").await;
+        let stranger = module.create_user("reactstranger").await;
+        let alice_id = alice.id.to_string();
+        let bob_id = bob.id.to_string();
+        let chat_id = chat_service
+            .create_group_chat("Reactors", &alice.id, &[bob.id])
+            .await
+            .unwrap()
+            .id
+            .to_string();
+        let message_id = chat_usecase
+            .send_message_to_chat(&chat_id, &alice_id, "react to me", None)
+            .await
+            .unwrap()
+            .0
+            .id;
+
+        let mut events = broker.subscribe();
+        let reactions = chat_usecase
+            .toggle_reaction(&message_id.to_string(), &bob_id, "👍")
+            .await
+            .unwrap();
+        assert_eq!(reactions.len(), 1);
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.recipients.len(), 2);
+        match event.kind {
+            ChatEventKind::ReactionsChanged(id, reactions) => {
+                assert_eq!(id, message_id);
+                assert_eq!(reactions[0].0, "reactbob");
+            }
+            other => panic!("expected reactions changed event, got {:?}", other),
+        }
+
+        chat_usecase
+            .toggle_reaction(&message_id.to_string(), &alice_id, "👍")
+            .await
+            .unwrap();
+        chat_usecase
+            .toggle_reaction(&message_id.to_string(), &alice_id, "😂")
+            .await
+            .unwrap();
+        let message = chat_usecase
+            .get_messages_of_chat(&chat_id, &alice_id, MessageCursor::Latest, None)
+            .await
+            .unwrap()
+            .messages
+            .remove(0);
+        let summaries = message.reaction_summaries();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].reaction, "👍");
+        assert_eq!(summaries[0].count, 2);
+        assert_eq!(summaries[0].names, vec!["reactbob", "reactalice"]);
+        assert_eq!(summaries[1].count, 1);
+
+        // Toggling the same emoji again takes it back
+        let reactions = chat_usecase
+            .toggle_reaction(&message_id.to_string(), &bob_id, "👍")
+            .await
+            .unwrap();
+        assert_eq!(reactions.len(), 2);
+        assert!(reactions
+            .iter()
+            .all(|(_, reaction)| reaction.user_id == alice.id));
+
+        for reaction in ["abc", "🦀", "👍👍"] {
+            let result = chat_usecase
+                .toggle_reaction(&message_id.to_string(), &bob_id, reaction)
+                .await;
+            match result.unwrap_err().downcast_ref::<GenericError>() {
+                Some(GenericError::InvalidInput(_, _)) => {}
+                other => panic!("expected invalid input error, got {:?}", other),
+            }
+        }
+
+        let result = chat_usecase
+            .toggle_reaction(&message_id.to_string(), &stranger.id.to_string(), "👍")
+            .await;
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::Unauthorized()) => {}
+            _ => panic!("expected unauthorized error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deleted_message_cannot_be_reacted_to() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+
+        let alice = module.create_user("goneralice").await;
+        let bob = module.create_user("gonerbob").await;
+        let alice_id = alice.id.to_string();
+        let chat_id = chat_service
+            .create_group_chat("Goners", &alice.id, &[bob.id])
+            .await
+            .unwrap()
+            .id
+            .to_string();
+        let message_id = chat_usecase
+            .send_message_to_chat(&chat_id, &alice_id, "soon gone", None)
+            .await
+            .unwrap()
+            .0
+            .id
+            .to_string();
+
+        chat_usecase
+            .delete_message(&message_id, &alice_id, DeleteScope::ForEveryone)
+            .await
+            .unwrap();
+        assert_invalid_input(
+            chat_usecase
+                .toggle_reaction(&message_id, &bob.id.to_string(), "👍")
+                .await,
+            "Deleted messages",
+        );
+    }
+
+    fn assert_invalid_input<T: std::fmt::Debug>(result: anyhow::Result<T>, expected: &str) {
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::InvalidInput(message, _)) => assert!(
+                message.contains(expected),
+                "expected '{}' in '{}'",
+                expected,
+                message
+            ),
+            other => panic!("expected invalid input error, got {:?}", other),
+        }
+    }
+
+    fn encode_image(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut Cursor::new(&mut data), format)
+            .unwrap();
+        data
+    }
+
+    // A JPEG carrying an (empty) EXIF block, the way cameras write it right after the SOI marker
+    fn jpeg_with_exif() -> Vec<u8> {
+        let jpeg = encode_image(64, 64, ImageFormat::Jpeg);
+        let exif = b"Exif\0\0II*\0\x08\0\0\0\0\0\0\0\0\0";
+        let mut data = jpeg[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1]);
+        data.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        data.extend_from_slice(exif);
+        data.extend_from_slice(&jpeg[2..]);
+        data
+    }
+
+    async fn read_media(module: &TestModule, media_url: &str) -> Vec<u8> {
+        let storage: &dyn BlobStorage = module.resolve_ref();
+        let (_, key) = utils::media_storage_key(media_url).unwrap();
+        let chunks: Vec<_> = storage
+            .get(&key, None)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat()
+    }
+
+    fn upload(file_name: &str, data: &[u8]) -> AttachmentUpload {
+        AttachmentUpload {
+            file_name: file_name.to_string(),
+            data: data.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_attachments_stores_files_with_the_message() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+
+        let sender = module.create_user("attachsender").await;
+        let receiver = module.create_user("attachreceiver").await;
+        let stranger = module.create_user("attachstranger").await;
+        let sender_id = sender.id.to_string();
+        let chat_id = chat_service
+            .initiate_private_chat(&sender_id, &receiver.id.to_string())
+            .await
+            .unwrap()
+            .to_string();
+        let first = chat_usecase
+            .send_message_to_chat(&chat_id, &sender_id, "files incoming", None)
+            .await
+            .unwrap();
+
+        let png = &encode_image(800, 400, ImageFormat::Png);
+        let jpeg = &jpeg_with_exif();
+        let pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n";
+        let sent = chat_usecase
+            .send_attachments_to_chat(
+                &chat_id,
+                &sender_id,
+                "  holiday  ",
+                &[
+                    upload("photo.png", png),
+                    upload("C:\\docs\\plan.pdf", pdf),
+                    upload("camera.jpg", jpeg),
+                ],
+            )
+            .await
+            .unwrap();
+        assert!(sent.0.is_attachment());
+        assert_eq!(sent.0.content, "holiday");
+        assert_eq!(sent.3.len(), 3);
+        assert!(sent.3[0].is_image());
+        assert_eq!(sent.3[0].file_type, "image/png");
+        assert_eq!(sent.3[1].file_name, "plan.pdf");
+        assert_eq!(sent.3[1].file_type, "application/pdf");
+        assert_eq!(sent.3[1].file_size, pdf.len() as i32);
+        assert_eq!(sent.3[1].thumbnail_url, None);
+
+        // Images are re-encoded without their metadata and get a thumbnail
+        let thumbnail = read_media(&module, sent.3[0].thumbnail_url.as_ref().unwrap()).await;
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 160));
+        let camera = read_media(&module, &sent.3[2].file_url).await;
+        assert!(jpeg.windows(4).any(|window| window == b"Exif"));
+        assert!(!camera.windows(4).any(|window| window == b"Exif"));
+        assert_eq!(sent.3[2].file_type, "image/jpeg");
+        assert_eq!(sent.3[2].file_size, camera.len() as i32);
+
+        let messages = chat_usecase
+            .get_messages_of_chat(&chat_id, &sender_id, MessageCursor::Latest, None)
+            .await
+            .unwrap()
+            .messages;
+        assert_eq!(messages.last().unwrap().3, sent.3);
+
+        let events = chat_usecase
+            .get_missed_chat_events(&receiver.id.to_string(), &first.0.id.to_string())
+            .await
+            .unwrap();
+        match &events[0].kind {
+            ChatEventKind::NewMessage(message_box) => assert_eq!(message_box.3.len(), 3),
+            other => panic!("expected new message event, got {:?}", other),
+        }
+
+        let storage: &dyn BlobStorage = module.resolve_ref();
+        for attachment in &sent.3 {
+            for url in [
+                Some(&attachment.file_url),
+                attachment.thumbnail_url.as_ref(),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                let (_, key) = utils::media_storage_key(url).unwrap();
+                storage.delete(&key).await.unwrap();
+            }
+        }
+
+        assert_invalid_input(
+            chat_usecase
+                .send_attachments_to_chat(&chat_id, &sender_id, "", &[])
+                .await,
+            "Attach between",
+        );
+        assert_invalid_input(
+            chat_usecase
+                .send_attachments_to_chat(&chat_id, &sender_id, "", &[upload("notes.txt", b"hi")])
+                .await,
+            "not a supported file type",
+        );
+        assert_invalid_input(
+            chat_usecase
+                .send_attachments_to_chat(
+                    &chat_id,
+                    &sender_id,
+                    "",
+                    &[upload("broken.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR")],
+                )
+                .await,
+            "broken.png",
+        );
+        let mut large = png.to_vec();
+        large.resize(MAX_ATTACHMENT_SIZE + 1, 0);
+        assert_invalid_input(
+            chat_usecase
+                .send_attachments_to_chat(&chat_id, &sender_id, "", &[upload("large.png", &large)])
+                .await,
+            "larger than",
+        );
+
+        let result = chat_usecase
+            .send_attachments_to_chat(
+                &chat_id,
+                &stranger.id.to_string(),
+                "",
+                &[upload("photo.png", png)],
+            )
+            .await;
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::Unauthorized()) => {}
+            _ => panic!("expected unauthorized error"),
+        }
+    }
+
+    fn assert_permission_denied<T: std::fmt::Debug>(result: anyhow::Result<T>) {
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::PermissionDenied(_, _)) => {}
+            other => panic!("expected permission denied error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_edit_message_keeps_revisions_and_notifies_members() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+        let broker: &dyn ChatEventBrokerInterface = module.resolve_ref();
+
+        let alice = module.create_user("editalice").await;
+        let bob = module.create_user("editbob").await;
+        let alice_id = alice.id.to_string();
+        let bob_id = bob.id.to_string();
+        let chat_id = chat_service
+            .create_group_chat("Editors", &alice.id, &[bob.id])
+            .await
+            .unwrap()
+            .id
+            .to_string();
+        let message_id = chat_usecase
+            .send_message_to_chat(&chat_id, &alice_id, "helo", None)
+            .await
+            .unwrap()
+            .0
+            .id
+            .to_string();
+        chat_usecase
+            .toggle_reaction(&message_id, &bob_id, "👍")
+            .await
+            .unwrap();
+
+        let mut events = broker.subscribe();
+        let edited = chat_usecase
+            .edit_message(&message_id, &alice_id, " hello ")
+            .await
+            .unwrap();
+        assert_eq!(edited.0.content, "hello");
+        assert!(edited.0.is_edited());
+        // Reactions survive the edit
+        assert_eq!(edited.2.len(), 1);
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.recipients.len(), 2);
+        match event.kind {
+            ChatEventKind::MessageEdited(message_box) => assert_eq!(message_box, edited),
+            other => panic!("expected message edited event, got {:?}", other),
+        }
+
+        chat_usecase
+            .edit_message(&message_id, &alice_id, "hello, world")
+            .await
+            .unwrap();
+        // Saving the same content again is not another revision
+        chat_usecase
+            .edit_message(&message_id, &alice_id, "hello, world")
+            .await
+            .unwrap();
+        let revisions = chat_usecase
+            .get_message_revisions(&message_id, &bob_id)
+            .await
+            .unwrap();
+        let contents: Vec<_> = revisions
+            .iter()
+            .map(|revision| revision.content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["helo", "hello"]);
+
+        let messages = chat_usecase
+            .get_messages_of_chat(&chat_id, &bob_id, MessageCursor::Latest, None)
+            .await
+            .unwrap()
+            .messages;
+        assert_eq!(messages[0].0.content, "hello, world");
+        assert!(messages[0].0.is_edited());
+
+        assert_permission_denied(
+            chat_usecase
+                .edit_message(&message_id, &bob_id, "not mine")
+                .await,
+        );
+        assert_invalid_input(
+            chat_usecase
+                .edit_message(&message_id, &alice_id, "   ")
+                .await,
+            "cannot be empty",
+        );
+    }
+
+    async fn visible_messages(
+        module: &TestModule,
+        chat_id: &str,
+        user_id: &str,
+    ) -> Vec<MessageBox> {
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+        chat_usecase
+            .get_messages_of_chat(chat_id, user_id, MessageCursor::Latest, None)
+            .await
+            .unwrap()
+            .messages
+    }
+
+    #[tokio::test]
+    async fn test_delete_message_for_me_and_for_everyone() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+        let broker: &dyn ChatEventBrokerInterface = module.resolve_ref();
+        let storage: &dyn BlobStorage = module.resolve_ref();
+
+        let alice = module.create_user("deletealice").await;
+        let bob = module.create_user("deletebob").await;
+        let alice_id = alice.id.to_string();
+        let bob_id = bob.id.to_string();
+        let chat_id = chat_service
+            .initiate_private_chat(&alice_id, &bob_id)
+            .await
+            .unwrap()
+            .to_string();
+        let private = chat_usecase
+            .send_message_to_chat(&chat_id, &alice_id, "only I should forget this", None)
+            .await
+            .unwrap()
+            .0;
+        let photo = chat_usecase
+            .send_attachments_to_chat(
+                &chat_id,
+                &alice_id,
+                "oops",
+                &[upload(
+                    "photo.png",
+                    &encode_image(640, 480, ImageFormat::Png),
+                )],
+            )
+            .await
+            .unwrap();
+
+        let mut events = broker.subscribe();
+        chat_usecase
+            .delete_message(&private.id.to_string(), &alice_id, DeleteScope::ForMe)
+            .await
+            .unwrap();
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.recipients, vec![alice.id]);
+        assert_eq!(event.kind, ChatEventKind::MessageHidden(private.id));
+
+        let alice_view = visible_messages(&module, &chat_id, &alice_id).await;
+        assert_eq!(alice_view.len(), 1);
+        assert_eq!(alice_view[0].0.id, photo.0.id);
+        assert_eq!(visible_messages(&module, &chat_id, &bob_id).await.len(), 2);
+
+        assert_permission_denied(
+            chat_usecase
+                .delete_message(&photo.0.id.to_string(), &bob_id, DeleteScope::ForEveryone)
+                .await,
+        );
+
+        let tombstone = chat_usecase
+            .delete_message(&photo.0.id.to_string(), &alice_id, DeleteScope::ForEveryone)
+            .await
+            .unwrap();
+        assert!(tombstone.is_deleted());
+        assert_eq!(tombstone.content, "");
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.recipients.len(), 2);
+        assert_eq!(event.kind, ChatEventKind::MessageDeleted(tombstone.clone()));
+
+        let bob_view = visible_messages(&module, &chat_id, &bob_id).await;
+        assert_eq!(bob_view.len(), 2);
+        assert_eq!(bob_view[1].0, tombstone);
+        assert!(bob_view[1].3.is_empty());
+        // The files go with the message
+        for url in [
+            &photo.3[0].file_url,
+            photo.3[0].thumbnail_url.as_ref().unwrap(),
+        ] {
+            let (_, key) = utils::media_storage_key(url).unwrap();
+            assert_eq!(storage.size(&key).await.unwrap(), None);
+        }
+
+        let chat_list = chat_usecase.get_user_chat_list(&bob_id).await.unwrap();
+        assert_eq!(chat_list[0].last_message.as_ref(), Some(&tombstone));
+        assert_invalid_input(
+            chat_usecase
+                .edit_message(&tombstone.id.to_string(), &alice_id, "undo")
+                .await,
+            "cannot be edited",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reply_quotes_the_original_message() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+
+        let alice = module.create_user("replyalice").await;
+        let bob = module.create_user("replybob").await;
+        let carol = module.create_user("replycarol").await;
+        let alice_id = alice.id.to_string();
+        let bob_id = bob.id.to_string();
+        let chat_id = chat_service
+            .initiate_private_chat(&alice_id, &bob_id)
+            .await
+            .unwrap()
+            .to_string();
+        let other_chat_id = chat_service
+            .initiate_private_chat(&alice_id, &carol.id.to_string())
+            .await
+            .unwrap()
+            .to_string();
+
+        let long_question = "a".repeat(150);
+        let original = chat_usecase
+            .send_message_to_chat(&chat_id, &alice_id, &long_question, None)
+            .await
+            .unwrap()
+            .0;
+        let original_id = original.id.to_string();
+        let reply = chat_usecase
+            .send_message_to_chat(&chat_id, &bob_id, "sure", Some(&original_id))
+            .await
+            .unwrap();
+        assert_eq!(reply.0.reply_to_message_id, Some(original.id));
+        let quote = reply.4.clone().unwrap();
+        assert_eq!(quote.message_id, original.id);
+        assert_eq!(quote.sender_name, "replyalice");
+        assert_eq!(quote.snippet, format!("{}…", "a".repeat(100)));
+        assert!(!quote.is_deleted);
+
+        let messages = visible_messages(&module, &chat_id, &alice_id).await;
+        assert_eq!(messages[0].4, None);
+        assert_eq!(messages[1].4, Some(quote));
+
+        // Only messages of the same chat can be quoted
+        let elsewhere = chat_usecase
+            .send_message_to_chat(&other_chat_id, &alice_id, "hi carol", None)
+            .await
+            .unwrap()
+            .0;
+        assert_invalid_input(
+            chat_usecase
+                .send_message_to_chat(&chat_id, &bob_id, "what?", Some(&elsewhere.id.to_string()))
+                .await,
+            "no longer available",
+        );
+
+        chat_usecase
+            .delete_message(&original_id, &alice_id, DeleteScope::ForEveryone)
+            .await
+            .unwrap();
+        assert_invalid_input(
+            chat_usecase
+                .send_message_to_chat(&chat_id, &bob_id, "again", Some(&original_id))
+                .await,
+            "no longer available",
+        );
+        // The reply stays, but no longer shows what was deleted
+        let quote = visible_messages(&module, &chat_id, &bob_id).await[1]
+            .4
+            .clone()
+            .unwrap();
+        assert!(quote.is_deleted);
+        assert_eq!(quote.snippet, "");
+    }
+
+    fn thread_of(messages: &[MessageBox], root: &MessageBox) -> ThreadSummary {
+        messages
+            .iter()
+            .find(|message| message.0.id == root.0.id)
+            .and_then(|message| message.5.clone())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_thread_replies_stay_out_of_the_main_timeline() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+        let broker: &dyn ChatEventBrokerInterface = module.resolve_ref();
+
+        let alice = module.create_user("threadalice").await;
+        let bob = module.create_user("threadbob").await;
+        let carol = module.create_user("threadcarol").await;
+        let alice_id = alice.id.to_string();
+        let bob_id = bob.id.to_string();
+        let chat_id = chat_service
+            .initiate_private_chat(&alice_id, &bob_id)
+            .await
+            .unwrap()
+            .to_string();
+        let root = chat_usecase
+            .send_message_to_chat(&chat_id, &alice_id, "where do we eat?", None)
+            .await
+            .unwrap();
+        let root_id = root.0.id.to_string();
+        let latest = chat_usecase
+            .send_message_to_chat(&chat_id, &alice_id, "unrelated", None)
+            .await
+            .unwrap();
+
+        let mut events = broker.subscribe();
+        let mut replies = Vec::new();
+        for (sender_id, text) in [
+            (&bob_id, "pizza"),
+            (&alice_id, "again?"),
+            (&bob_id, "sushi"),
+        ] {
+            let reply = chat_usecase
+                .send_thread_reply(&root_id, sender_id, text)
+                .await
+                .unwrap();
+            assert_eq!(reply.0.thread_root_id, Some(root.0.id));
+            let event = events.recv().await.unwrap();
+            assert_eq!(event.recipients.len(), 2);
+            match event.kind {
+                ChatEventKind::NewThreadReply(message, thread) => {
+                    assert_eq!(message, reply);
+                    assert_eq!(thread.reply_count, replies.len() as i64 + 1);
+                }
+                kind => panic!("expected a thread reply, got {:?}", kind),
+            }
+            replies.push(reply);
+        }
+
+        // The timeline only shows the root, which tells how busy its thread is
+        let alice_view = visible_messages(&module, &chat_id, &alice_id).await;
+        assert_eq!(alice_view.len(), 2);
+        let thread = thread_of(&alice_view, &root);
+        assert_eq!(thread.reply_count, 3);
+        assert_eq!(thread.last_reply_at, replies[2].0.sent_at);
+        assert_eq!(thread.unread_count, 2);
+        assert_eq!(alice_view[1].5, None);
+        let bob_view = visible_messages(&module, &chat_id, &bob_id).await;
+        assert_eq!(thread_of(&bob_view, &root).unread_count, 1);
+
+        let chat_list = chat_usecase.get_user_chat_list(&bob_id).await.unwrap();
+        assert_eq!(chat_list[0].last_message.as_ref(), Some(&latest.0));
+        assert_eq!(chat_list[0].unread_message_count, 2);
+
+        let page = chat_usecase
+            .get_thread_messages(&root_id, &alice_id, MessageCursor::Latest, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(page.root.0, root.0);
+        assert_eq!(page.root.5.as_ref().unwrap().unread_count, 2);
+        assert!(page.has_more);
+        assert_eq!(page.messages[0].0, replies[1].0);
+        assert_eq!(page.messages[1].0, replies[2].0);
+        let older = chat_usecase
+            .get_thread_messages(
+                &root_id,
+                &alice_id,
+                MessageCursor::Before(replies[1].0.id),
+                Some(2),
+            )
+            .await
+            .unwrap();
+        assert!(!older.has_more);
+        assert_eq!(older.messages.len(), 1);
+        assert_eq!(older.messages[0].0, replies[0].0);
+
+        // Reading the thread and reading the chat are tracked apart
+        let read = chat_usecase
+            .mark_thread_read(&root_id, &alice_id, &replies[2].0.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(read, 2);
+        let alice_view = visible_messages(&module, &chat_id, &alice_id).await;
+        assert_eq!(thread_of(&alice_view, &root).unread_count, 0);
+        let read = chat_usecase
+            .mark_chat_read(&chat_id, &bob_id, &latest.0.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(read, 2);
+        let bob_view = visible_messages(&module, &chat_id, &bob_id).await;
+        assert_eq!(thread_of(&bob_view, &root).unread_count, 1);
+
+        assert_invalid_input(
+            chat_usecase
+                .send_thread_reply(&replies[0].0.id.to_string(), &bob_id, "nested")
+                .await,
+            "no longer available",
+        );
+        let result = chat_usecase
+            .send_thread_reply(&root_id, &carol.id.to_string(), "let me in")
+            .await;
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::Unauthorized()) => {}
+            _ => panic!("expected unauthorized error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_messages_of_the_users_chats() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+
+        let alice = module.create_user("searchalice").await;
+        let bob = module.create_user("searchbob").await;
+        let carol = module.create_user("searchcarol").await;
+        let alice_id = alice.id.to_string();
+        let bob_id = bob.id.to_string();
+        let carol_id = carol.id.to_string();
+        let private_chat = chat_service
+            .initiate_private_chat(&alice_id, &bob_id)
+            .await
+            .unwrap()
+            .to_string();
+        let group = chat_service
+            .create_group_chat("Launch Crew", &alice.id, &[carol.id])
+            .await
+            .unwrap()
+            .id
+            .to_string();
+        let elsewhere = chat_service
+            .initiate_private_chat(&bob_id, &carol_id)
+            .await
+            .unwrap()
+            .to_string();
+
+        let friday = chat_usecase
+            .send_message_to_chat(&private_chat, &alice_id, "The launch is on friday", None)
+            .await
+            .unwrap()
+            .0;
+        let launching = chat_usecase
+            .send_message_to_chat(&private_chat, &bob_id, "Launching rockets is fun", None)
+            .await
+            .unwrap()
+            .0;
+        let checklist = chat_usecase
+            .send_attachments_to_chat(
+                &group,
+                &carol_id,
+                "Launch checklist",
+                &[upload("checklist.pdf", b"%PDF-1.4 fuel")],
+            )
+            .await
+            .unwrap()
+            .0;
+        chat_usecase
+            .send_message_to_chat(&elsewhere, &bob_id, "Secret launch plans", None)
+            .await
+            .unwrap();
+
+        let search = |query: &'static str| {
+            let alice_id = alice_id.clone();
+            async move {
+                chat_usecase
+                    .search_messages(&alice_id, query, None, None)
+                    .await
+                    .unwrap()
+            }
+        };
+        let ids = |results: &MessageSearchResults| -> Vec<Uuid> {
+            results.hits.iter().map(|hit| hit.message.id).collect()
+        };
+
+        // Newest first, words also match longer ones they start
+        let results = search("launch").await;
+        assert_eq!(ids(&results), vec![checklist.id, launching.id, friday.id]);
+        assert!(!results.has_more);
+        assert_eq!(results.hits[0].chat_name, "Launch Crew");
+        assert_eq!(results.hits[0].sender_name, "searchcarol");
+        assert_eq!(results.hits[1].chat_name, "searchbob");
+        assert_eq!(
+            results.hits[1].highlights(),
+            vec![("Launching", true), (" rockets is fun", false)]
+        );
+
+        assert_eq!(ids(&search("\"launch is\"").await), vec![friday.id]);
+        assert_eq!(
+            ids(&search("launch from:searchbob").await),
+            vec![launching.id]
+        );
+        assert_eq!(ids(&search("in:\"launch crew\"").await), vec![checklist.id]);
+        assert_eq!(
+            ids(&search("in:searchbob").await),
+            vec![launching.id, friday.id]
+        );
+        assert_eq!(ids(&search("has:attachment").await), vec![checklist.id]);
+        assert_eq!(
+            search("has:attachment").await.hits[0].snippet,
+            "Launch checklist"
+        );
+        assert!(search("launch before:2000-01-01").await.hits.is_empty());
+        assert_eq!(search("launch after:2000-01-01").await.hits.len(), 3);
+
+        let page = chat_usecase
+            .search_messages(&alice_id, "launch", None, Some(2))
+            .await
+            .unwrap();
+        assert_eq!(ids(&page), vec![checklist.id, launching.id]);
+        assert!(page.has_more);
+        let page = chat_usecase
+            .search_messages(&alice_id, "launch", Some(launching.id), Some(2))
+            .await
+            .unwrap();
+        assert_eq!(ids(&page), vec![friday.id]);
+        assert!(!page.has_more);
+
+        // The index follows edits and deletes
+        chat_usecase
+            .edit_message(
+                &friday.id.to_string(),
+                &alice_id,
+                "The liftoff is on friday",
+            )
+            .await
+            .unwrap();
+        assert_eq!(ids(&search("liftoff").await), vec![friday.id]);
+        assert!(!ids(&search("launch").await).contains(&friday.id));
+        chat_usecase
+            .delete_message(&launching.id.to_string(), &bob_id, DeleteScope::ForMe)
+            .await
+            .unwrap();
+        assert_eq!(ids(&search("rockets").await), vec![launching.id]);
+        assert!(chat_usecase
+            .search_messages(&bob_id, "rockets", None, None)
+            .await
+            .unwrap()
+            .hits
+            .is_empty());
+        // Stray punctuation is taken as text, not as query syntax
+        assert_eq!(
+            ids(&search("launch - ( * \"").await),
+            vec![checklist.id, launching.id]
+        );
+
+        for (query, error) in [
+            ("   ", "Type something"),
+            ("before:yesterday", "YYYY-MM-DD"),
+            ("has:link", "has:attachment"),
+        ] {
+            assert_invalid_input(
+                chat_usecase
+                    .search_messages(&alice_id, query, None, None)
+                    .await,
+                error,
+            );
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Fixtures shared by the usecase tests, each test file pulls them in with `mod common;`.
+#![allow(dead_code)]
+
+use credentials::credential::Credential;
+use credentials::credential_services::CredentialServiceInterface;
+use jwt::AccessClaims;
+use mail::SendEmail;
+use persistence::{DatabaseInterface, Env, EnvInterface, DB};
+use shaku::{Component, HasComponent, Module, ModuleBuilder};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use usecases::utils;
+use usecases::{LoginOutcome, LoginRequest, LoginUseCaseInterface};
+use users::user::User;
+use users::user_services::UserServiceInterface;
+
+/// The password of every user made by `TestApp::create_user`.
+pub const PASSWORD: &str = "password8";
+
+/// The recipient address and the body of every email sent.
+pub type SentEmails = Arc<Mutex<Vec<(String, String)>>>;
+
+// Keeps the emails instead of sending them, so tests can read links back from there
+#[derive(Component)]
+#[shaku(interface = SendEmail)]
+pub struct RecordingMail {
+    sent: SentEmails,
+}
+
+impl RecordingMail {
+    /// For tests that wire their usecases by hand instead of through a module.
+    pub fn new(sent: SentEmails) -> Self {
+        Self { sent }
+    }
+}
+
+#[async_trait::async_trait]
+impl SendEmail for RecordingMail {
+    async fn send_email(
+        &self,
+        _to: &str,
+        to_email: &str,
+        _subject: &str,
+        body: &str,
+    ) -> anyhow::Result<()> {
+        self.sent
+            .lock()
+            .unwrap()
+            .push((to_email.to_string(), body.to_string()));
+        Ok(())
+    }
+}
+
+/// Waits for emails sent in the background until `count` of them are there.
+pub async fn wait_for_emails(sent: &SentEmails, count: usize) {
+    for _ in 0..100 {
+        if sent.lock().unwrap().len() >= count {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("expected {} emails to be sent", count);
+}
+
+/// A migrated in-memory database, for tests that wire their services by hand.
+pub async fn setup_db() -> Arc<dyn DatabaseInterface> {
+    DB::new_and_migrate(Env::load_test()).await
+}
+
+/// A module on a migrated in-memory database. Its uploads go to a temporary
+/// directory of its own, which is removed again when the test is done.
+pub struct TestApp<M> {
+    module: M,
+    storage_root: String,
+}
+
+pub async fn setup<M>(builder: ModuleBuilder<M>) -> TestApp<M>
+where
+    M: Module + HasComponent<dyn EnvInterface> + HasComponent<dyn DatabaseInterface>,
+{
+    let env = Env::load_test();
+    let storage_root = env.storage_local_root.clone();
+    let module = utils::setup_module(builder, env).await;
+    let db: &dyn DatabaseInterface = module.resolve_ref();
+    db.migrate().await;
+    TestApp {
+        module,
+        storage_root,
+    }
+}
+
+/// Like `setup`, for modules sending their emails through `RecordingMail`.
+pub async fn setup_with_mail<M>(builder: ModuleBuilder<M>) -> (TestApp<M>, SentEmails)
+where
+    M: Module
+        + HasComponent<dyn EnvInterface>
+        + HasComponent<dyn DatabaseInterface>
+        + HasComponent<dyn SendEmail>,
+    RecordingMail: Component<M, Interface = dyn SendEmail, Parameters = RecordingMailParameters>,
+{
+    let sent = SentEmails::default();
+    let builder = builder
+        .with_component_parameters::<RecordingMail>(RecordingMailParameters { sent: sent.clone() });
+    (setup(builder).await, sent)
+}
+
+impl<M> Deref for TestApp<M> {
+    type Target = M;
+
+    fn deref(&self) -> &M {
+        &self.module
+    }
+}
+
+impl<M> TestApp<M> {
+    /// The directory of this run's local storage, removed along with everything under it.
+    pub fn storage_root(&self) -> &Path {
+        Path::new(&self.storage_root)
+    }
+}
+
+impl<M> Drop for TestApp<M> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.storage_root);
+    }
+}
+
+impl<M: HasComponent<dyn UserServiceInterface>> TestApp<M> {
+    /// An activated user with the email `<username>@gmail.com` and `PASSWORD`.
+    pub async fn create_user(&self, username: &str) -> User {
+        let user_service: &dyn UserServiceInterface = self.module.resolve_ref();
+        let mut user = User::new(
+            username.to_string(),
+            format!("{}@gmail.com", username),
+            String::from(PASSWORD),
+        )
+        .unwrap();
+        user.is_active = true;
+        user_service.create_user(&user).await.unwrap();
+        user
+    }
+}
+
+impl<M> TestApp<M>
+where
+    M: HasComponent<dyn UserServiceInterface> + HasComponent<dyn CredentialServiceInterface>,
+{
+    /// Like `create_user`, with the key pair a login needs.
+    pub async fn create_user_with_credential(&self, username: &str) -> User {
+        let credential_service: &dyn CredentialServiceInterface = self.module.resolve_ref();
+        let user = self.create_user(username).await;
+        credential_service
+            .create_credential(&Credential::new(
+                user.id,
+                "private_key_example",
+                "public_key_example",
+            ))
+            .await
+            .unwrap();
+        user
+    }
+}
+
+impl<M: HasComponent<dyn LoginUseCaseInterface>> TestApp<M> {
+    pub async fn login(&self, username: &str, password: &str) -> anyhow::Result<LoginOutcome> {
+        self.login_from(username, password, "ip_address").await
+    }
+
+    pub async fn login_from(
+        &self,
+        username: &str,
+        password: &str,
+        ip_address: &str,
+    ) -> anyhow::Result<LoginOutcome> {
+        let login_usecase: &dyn LoginUseCaseInterface = self.module.resolve_ref();
+        login_usecase
+            .login(LoginRequest {
+                username,
+                password,
+                user_agent: "user_agent",
+                ip_address,
+            })
+            .await
+    }
+
+    /// The access token of a login without two-factor authentication.
+    pub async fn session_token(&self, username: &str, password: &str) -> anyhow::Result<String> {
+        let response = self.login(username, password).await?.logged_in().unwrap();
+        Ok(response.token)
+    }
+
+    /// The claims of a login without two-factor authentication.
+    pub async fn claims(&self, username: &str, password: &str) -> anyhow::Result<AccessClaims> {
+        let login_usecase: &dyn LoginUseCaseInterface = self.module.resolve_ref();
+        let token = self.session_token(username, password).await?;
+        login_usecase.authorize_current_user(&token).await
+    }
+}
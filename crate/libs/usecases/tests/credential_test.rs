@@ -1,15 +1,17 @@
+mod common;
+
 #[cfg(test)]
 mod tests {
+    use crate::common;
     use credentials::credential::Credential;
     use credentials::credential_services::{CredentialService, CredentialServiceInterface};
     use std::sync::Arc;
-    use usecases::utils::setup_db;
     use users::user::User;
     use users::user_services::{UserService, UserServiceInterface};
 
     #[tokio::test]
     async fn test_create_credential_and_get_credential_by_user_id() {
-        let db = setup_db().await;
+        let db = common::setup_db().await;
         let user_service = UserService::new(Arc::clone(&db));
         let user = User::new(
             String::from("testnumber1"),
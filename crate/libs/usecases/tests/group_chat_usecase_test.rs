@@ -0,0 +1,417 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, TestApp};
+    use chats::chat_services::{ChatService, ChatServiceInterface};
+    use chats::entity::ChatRole;
+    use chats::events::{ChatEvent, ChatEventBroker, ChatEventBrokerInterface, ChatEventKind};
+    use commons::generic_errors::GenericError;
+    use image::{DynamicImage, ImageFormat};
+    use persistence::{Env, DB};
+    use shaku::{module, HasComponent};
+    use std::io::Cursor;
+    use storage::{BlobStorage, LocalStorage};
+    use usecases::group_chat_usecase::{GroupChat, GroupChatUsecase, GroupChatUsecaseImpl};
+    use usecases::utils;
+    use users::user_services::UserService;
+
+    module! {
+        TestModule {
+            components = [
+                GroupChatUsecaseImpl,
+                ChatService,
+                ChatEventBroker,
+                UserService,
+                LocalStorage,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    async fn setup() -> TestApp<TestModule> {
+        common::setup(TestModule::builder()).await
+    }
+
+    fn usernames(group: &GroupChat) -> Vec<&str> {
+        let mut usernames: Vec<&str> = group
+            .members
+            .iter()
+            .map(|profile| profile.username.as_str())
+            .collect();
+        usernames.sort();
+        usernames
+    }
+
+    #[tokio::test]
+    async fn test_create_group_with_initial_members() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+
+        let owner = module.create_user("groupowner").await;
+        module.create_user("groupmember1").await;
+        module.create_user("groupmember2").await;
+
+        let group = group_usecase
+            .create_group(
+                &owner.id.to_string(),
+                " Team ",
+                &[
+                    String::from("groupmember1"),
+                    String::from("groupmember2"),
+                    String::from("groupowner"),
+                ],
+            )
+            .await
+            .unwrap();
+
+        assert!(group.chat.is_group);
+        assert_eq!(group.chat.name, "Team");
+        assert_eq!(group.role_of(&owner.id), Some(ChatRole::Owner));
+        assert_eq!(
+            usernames(&group),
+            vec!["groupmember1", "groupmember2", "groupowner"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_group_with_unknown_member_should_fail() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+
+        let owner = module.create_user("lonelyowner").await;
+        let result = group_usecase
+            .create_group(&owner.id.to_string(), "Team", &[String::from("nobody")])
+            .await;
+
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::UserNotFound()) => {}
+            _ => panic!("expected user not found error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_remove_and_leave_group() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+
+        let owner = module.create_user("rosterowner").await;
+        let member = module.create_user("rostermember").await;
+        let newcomer = module.create_user("rosternewcomer").await;
+        let owner_id = owner.id.to_string();
+
+        let group = group_usecase
+            .create_group(&owner_id, "Roster", &[String::from("rostermember")])
+            .await
+            .unwrap();
+        let chat_id = group.chat.id.to_string();
+
+        let group = group_usecase
+            .add_member(&chat_id, &owner_id, "rosternewcomer")
+            .await
+            .unwrap();
+        assert!(group.is_member(&newcomer.id));
+
+        let result = group_usecase
+            .add_member(&chat_id, &owner_id, "rosternewcomer")
+            .await;
+        assert!(result.is_err(), "adding an existing member should fail");
+
+        let group = group_usecase
+            .remove_member(&chat_id, &owner_id, &member.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(usernames(&group), vec!["rosternewcomer", "rosterowner"]);
+
+        group_usecase
+            .leave_group(&chat_id, &newcomer.id.to_string())
+            .await
+            .unwrap();
+        let members = chat_service.get_chat_members(&chat_id).await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].user_id, owner.id);
+    }
+
+    fn member_usernames(event: &ChatEvent) -> Vec<&str> {
+        let ChatEventKind::MembersChanged(members) = &event.kind else {
+            panic!(
+                "expected a members_changed event, got {}",
+                event.kind.name()
+            );
+        };
+        let mut usernames: Vec<&str> = members
+            .iter()
+            .map(|profile| profile.username.as_str())
+            .collect();
+        usernames.sort();
+        usernames
+    }
+
+    #[tokio::test]
+    async fn test_membership_changes_publish_event_to_old_and_new_members() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+        let broker: &dyn ChatEventBrokerInterface = module.resolve_ref();
+
+        let owner = module.create_user("eventowner").await;
+        let member = module.create_user("eventmember").await;
+        let newcomer = module.create_user("eventnewcomer").await;
+        let owner_id = owner.id.to_string();
+
+        let mut events = broker.subscribe();
+        let group = group_usecase
+            .create_group(&owner_id, "Events", &[String::from("eventmember")])
+            .await
+            .unwrap();
+        let chat_id = group.chat.id.to_string();
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.chat_id, group.chat.id);
+        assert!(event.is_recipient(&member.id));
+        assert_eq!(member_usernames(&event), vec!["eventmember", "eventowner"]);
+
+        group_usecase
+            .add_member(&chat_id, &owner_id, "eventnewcomer")
+            .await
+            .unwrap();
+        let event = events.recv().await.unwrap();
+        assert!(event.is_recipient(&member.id));
+        assert!(event.is_recipient(&newcomer.id));
+        assert_eq!(
+            member_usernames(&event),
+            vec!["eventmember", "eventnewcomer", "eventowner"]
+        );
+
+        group_usecase
+            .set_member_role(
+                &chat_id,
+                &owner_id,
+                &newcomer.id.to_string(),
+                ChatRole::Admin,
+            )
+            .await
+            .unwrap();
+        let event = events.recv().await.unwrap();
+        let ChatEventKind::MembersChanged(members) = &event.kind else {
+            panic!("expected a members_changed event");
+        };
+        assert!(members
+            .iter()
+            .any(|profile| profile.member.user_id == newcomer.id
+                && profile.member.role == ChatRole::Admin));
+
+        // The removed member still hears about it, to close the group
+        group_usecase
+            .remove_member(&chat_id, &owner_id, &member.id.to_string())
+            .await
+            .unwrap();
+        let event = events.recv().await.unwrap();
+        assert!(event.is_recipient(&member.id));
+        assert_eq!(
+            member_usernames(&event),
+            vec!["eventnewcomer", "eventowner"]
+        );
+
+        group_usecase
+            .leave_group(&chat_id, &newcomer.id.to_string())
+            .await
+            .unwrap();
+        let event = events.recv().await.unwrap();
+        assert!(event.is_recipient(&newcomer.id));
+        assert!(event.is_recipient(&owner.id));
+        assert!(!event.is_recipient(&member.id));
+        assert_eq!(member_usernames(&event), vec!["eventowner"]);
+    }
+
+    #[tokio::test]
+    async fn test_non_member_cannot_manage_group() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+
+        let owner = module.create_user("privateowner").await;
+        let stranger = module.create_user("privatestranger").await;
+        let group = group_usecase
+            .create_group(&owner.id.to_string(), "Private", &[])
+            .await
+            .unwrap();
+
+        let result = group_usecase
+            .add_member(
+                &group.chat.id.to_string(),
+                &stranger.id.to_string(),
+                "privatestranger",
+            )
+            .await;
+
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::Unauthorized()) => {}
+            _ => panic!("expected unauthorized error"),
+        }
+    }
+
+    fn assert_permission_denied<T: std::fmt::Debug>(result: anyhow::Result<T>) {
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::PermissionDenied(_, _)) => {}
+            other => panic!("expected permission denied error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_only_admins_can_manage_group() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+
+        let owner = module.create_user("adminowner").await;
+        let admin = module.create_user("adminadmin").await;
+        let member = module.create_user("adminmember").await;
+        module.create_user("adminnewcomer").await;
+        let chat_id = group_usecase
+            .create_group(
+                &owner.id.to_string(),
+                "Admins",
+                &[String::from("adminadmin"), String::from("adminmember")],
+            )
+            .await
+            .unwrap()
+            .chat
+            .id
+            .to_string();
+        let admin_id = admin.id.to_string();
+        let member_id = member.id.to_string();
+
+        assert_permission_denied(
+            group_usecase
+                .add_member(&chat_id, &member_id, "adminnewcomer")
+                .await,
+        );
+        assert_permission_denied(group_usecase.rename_group(&chat_id, &member_id, "Mine").await);
+        assert_permission_denied(
+            group_usecase
+                .set_member_role(&chat_id, &member_id, &member_id, ChatRole::Admin)
+                .await,
+        );
+
+        let group = group_usecase
+            .set_member_role(&chat_id, &owner.id.to_string(), &admin_id, ChatRole::Admin)
+            .await
+            .unwrap();
+        assert_eq!(group.role_of(&admin.id), Some(ChatRole::Admin));
+
+        let group = group_usecase
+            .rename_group(&chat_id, &admin_id, "Renamed")
+            .await
+            .unwrap();
+        assert_eq!(group.chat.name, "Renamed");
+        group_usecase
+            .add_member(&chat_id, &admin_id, "adminnewcomer")
+            .await
+            .unwrap();
+        assert_permission_denied(
+            group_usecase
+                .remove_member(&chat_id, &admin_id, &owner.id.to_string())
+                .await,
+        );
+        let group = group_usecase
+            .remove_member(&chat_id, &admin_id, &member_id)
+            .await
+            .unwrap();
+        assert!(!group.is_member(&member.id));
+    }
+
+    #[tokio::test]
+    async fn test_new_group_avatar_replaces_the_previous_file() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+        let storage: &dyn BlobStorage = module.resolve_ref();
+
+        let owner = module.create_user("pictureowner").await;
+        let owner_id = owner.id.to_string();
+        let chat_id = group_usecase
+            .create_group(&owner_id, "Pictures", &[])
+            .await
+            .unwrap()
+            .chat
+            .id
+            .to_string();
+
+        let mut png = Vec::new();
+        DynamicImage::new_rgba8(300, 300)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let mut avatars = Vec::new();
+        for _ in 0..2 {
+            let group = group_usecase
+                .update_group_avatar(&chat_id, &owner_id, &png)
+                .await
+                .unwrap();
+            let (_, key) = utils::media_storage_key(&group.chat.avatar.unwrap()).unwrap();
+            avatars.push(key);
+        }
+
+        assert_eq!(storage.size(&avatars[0]).await.unwrap(), None);
+        assert!(storage.size(&avatars[1]).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_last_owner_must_transfer_before_leaving() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+
+        let owner = module.create_user("transferowner").await;
+        let heir = module.create_user("transferheir").await;
+        let owner_id = owner.id.to_string();
+        let chat_id = group_usecase
+            .create_group(&owner_id, "Heritage", &[String::from("transferheir")])
+            .await
+            .unwrap()
+            .chat
+            .id
+            .to_string();
+
+        assert_permission_denied(group_usecase.leave_group(&chat_id, &owner_id).await);
+
+        let group = group_usecase
+            .transfer_ownership(&chat_id, &owner_id, &heir.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(group.role_of(&heir.id), Some(ChatRole::Owner));
+        assert_eq!(group.role_of(&owner.id), Some(ChatRole::Admin));
+
+        group_usecase.leave_group(&chat_id, &owner_id).await.unwrap();
+        let group = group_usecase
+            .get_group(&chat_id, &heir.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(group.members.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_owner_cannot_transfer_ownership_to_themselves() {
+        let module = setup().await;
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+
+        let owner = module.create_user("selfowner").await;
+        module.create_user("selfmember").await;
+        let owner_id = owner.id.to_string();
+        let chat_id = group_usecase
+            .create_group(&owner_id, "Mirror", &[String::from("selfmember")])
+            .await
+            .unwrap()
+            .chat
+            .id
+            .to_string();
+
+        let result = group_usecase
+            .transfer_ownership(&chat_id, &owner_id, &owner_id)
+            .await;
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::InvalidInput(_, _)) => {}
+            _ => panic!("expected invalid input error"),
+        }
+
+        let group = group_usecase.get_group(&chat_id, &owner_id).await.unwrap();
+        assert_eq!(group.role_of(&owner.id), Some(ChatRole::Owner));
+    }
+}
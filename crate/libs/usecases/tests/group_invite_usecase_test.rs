@@ -0,0 +1,268 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, TestApp};
+    use chats::chat_services::{ChatService, ChatServiceInterface};
+    use chats::entity::ChatInvite;
+    use chats::events::{ChatEventBroker, ChatEventBrokerInterface, ChatEventKind};
+    use commons::generic_errors::GenericError;
+    use crypto::{Crypto, Encrypt};
+    use persistence::{Env, DB};
+    use shaku::{module, HasComponent};
+    use storage::LocalStorage;
+    use usecases::group_chat_usecase::{GroupChatUsecase, GroupChatUsecaseImpl};
+    use usecases::group_invite_usecase::{GroupInviteUsecase, GroupInviteUsecaseImpl};
+    use users::user::User;
+    use users::user_services::UserService;
+
+    module! {
+        TestModule {
+            components = [
+                GroupInviteUsecaseImpl,
+                GroupChatUsecaseImpl,
+                ChatService,
+                ChatEventBroker,
+                UserService,
+                LocalStorage,
+                Crypto,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    async fn setup() -> TestApp<TestModule> {
+        common::setup(TestModule::builder()).await
+    }
+
+    async fn create_group(module: &TestModule, owner: &User, members: &[&str]) -> String {
+        let group_usecase: &dyn GroupChatUsecase = module.resolve_ref();
+        let members: Vec<String> = members.iter().map(|name| name.to_string()).collect();
+        group_usecase
+            .create_group(&owner.id.to_string(), "Invited", &members)
+            .await
+            .unwrap()
+            .chat
+            .id
+            .to_string()
+    }
+
+    fn assert_invalid_input<T: std::fmt::Debug>(result: anyhow::Result<T>, expected: &str) {
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::InvalidInput(message, _)) => assert!(
+                message.contains(expected),
+                "expected '{}' in '{}'",
+                expected,
+                message
+            ),
+            other => panic!("expected invalid input error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_join_group_with_invite_link() {
+        let module = setup().await;
+        let invite_usecase: &dyn GroupInviteUsecase = module.resolve_ref();
+
+        let owner = module.create_user("inviteowner").await;
+        let guest = module.create_user("inviteguest").await;
+        let chat_id = create_group(&module, &owner, &[]).await;
+
+        let group_invite = invite_usecase
+            .create_invite(&chat_id, &owner.id.to_string(), Some(24), Some(5))
+            .await
+            .unwrap();
+        assert!(group_invite.invite.expires_at.is_some());
+
+        let preview = invite_usecase
+            .preview_invite(&group_invite.token, &guest.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(preview.group.chat.name, "Invited");
+        assert!(!preview.is_member);
+
+        let group = invite_usecase
+            .join_group(&group_invite.token, &guest.id.to_string())
+            .await
+            .unwrap();
+        assert!(group.is_member(&guest.id));
+
+        // Joining again is a no-op and does not spend another use
+        invite_usecase
+            .join_group(&group_invite.token, &guest.id.to_string())
+            .await
+            .unwrap();
+        let invites = invite_usecase
+            .list_invites(&chat_id, &owner.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].invite.use_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_joining_with_invite_link_publishes_event_to_members() {
+        let module = setup().await;
+        let invite_usecase: &dyn GroupInviteUsecase = module.resolve_ref();
+        let broker: &dyn ChatEventBrokerInterface = module.resolve_ref();
+
+        let owner = module.create_user("joinedowner").await;
+        let guest = module.create_user("joinedguest").await;
+        let chat_id = create_group(&module, &owner, &[]).await;
+        let token = invite_usecase
+            .create_invite(&chat_id, &owner.id.to_string(), None, None)
+            .await
+            .unwrap()
+            .token;
+
+        let mut events = broker.subscribe();
+        invite_usecase
+            .join_group(&token, &guest.id.to_string())
+            .await
+            .unwrap();
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.chat_id.to_string(), chat_id);
+        assert!(event.is_recipient(&owner.id));
+        assert!(event.is_recipient(&guest.id));
+        let ChatEventKind::MembersChanged(members) = &event.kind else {
+            panic!(
+                "expected a members_changed event, got {}",
+                event.kind.name()
+            );
+        };
+        assert!(members
+            .iter()
+            .any(|profile| profile.member.user_id == guest.id));
+    }
+
+    #[tokio::test]
+    async fn test_invite_link_stops_working_after_max_uses() {
+        let module = setup().await;
+        let invite_usecase: &dyn GroupInviteUsecase = module.resolve_ref();
+
+        let owner = module.create_user("limitowner").await;
+        let first = module.create_user("limitfirst").await;
+        let second = module.create_user("limitsecond").await;
+        let chat_id = create_group(&module, &owner, &[]).await;
+
+        let token = invite_usecase
+            .create_invite(&chat_id, &owner.id.to_string(), None, Some(1))
+            .await
+            .unwrap()
+            .token;
+        invite_usecase
+            .join_group(&token, &first.id.to_string())
+            .await
+            .unwrap();
+
+        assert_invalid_input(
+            invite_usecase
+                .join_group(&token, &second.id.to_string())
+                .await,
+            "usage limit",
+        );
+        let invites = invite_usecase
+            .list_invites(&chat_id, &owner.id.to_string())
+            .await
+            .unwrap();
+        assert!(invites.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expired_revoked_and_forged_invite_links_are_rejected() {
+        let module = setup().await;
+        let invite_usecase: &dyn GroupInviteUsecase = module.resolve_ref();
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let crypto: &dyn Encrypt = module.resolve_ref();
+
+        let owner = module.create_user("expiredowner").await;
+        let guest = module.create_user("expiredguest").await;
+        let chat_id = create_group(&module, &owner, &[]).await;
+        let guest_id = guest.id.to_string();
+
+        let expired = ChatInvite::new(
+            chat_id.parse().unwrap(),
+            owner.id,
+            Some(chrono::Local::now().naive_local() - chrono::Duration::hours(1)),
+            None,
+        );
+        chat_service.create_chat_invite(&expired).await.unwrap();
+        let expired_token = crypto.encrypt(&expired.id.to_string()).await.unwrap();
+        assert_invalid_input(
+            invite_usecase
+                .preview_invite(&expired_token, &guest_id)
+                .await,
+            "expired",
+        );
+        assert_invalid_input(
+            invite_usecase.join_group(&expired_token, &guest_id).await,
+            "expired",
+        );
+
+        let revoked = invite_usecase
+            .create_invite(&chat_id, &owner.id.to_string(), None, None)
+            .await
+            .unwrap();
+        invite_usecase
+            .revoke_invite(&revoked.invite.id.to_string(), &owner.id.to_string())
+            .await
+            .unwrap();
+        assert_invalid_input(
+            invite_usecase.join_group(&revoked.token, &guest_id).await,
+            "revoked",
+        );
+
+        assert_invalid_input(
+            invite_usecase.join_group("short", &guest_id).await,
+            "invalid",
+        );
+        assert_invalid_input(
+            invite_usecase
+                .join_group("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA", &guest_id)
+                .await,
+            "invalid",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_only_admins_can_manage_invite_links() {
+        let module = setup().await;
+        let invite_usecase: &dyn GroupInviteUsecase = module.resolve_ref();
+
+        let owner = module.create_user("linkowner").await;
+        let member = module.create_user("linkmember").await;
+        let chat_id = create_group(&module, &owner, &["linkmember"]).await;
+        let member_id = member.id.to_string();
+
+        let result = invite_usecase
+            .create_invite(&chat_id, &member_id, None, None)
+            .await;
+        match result.unwrap_err().downcast_ref::<GenericError>() {
+            Some(GenericError::PermissionDenied(_, _)) => {}
+            other => panic!("expected permission denied error, got {:?}", other),
+        }
+
+        let invite = invite_usecase
+            .create_invite(&chat_id, &owner.id.to_string(), None, None)
+            .await
+            .unwrap();
+        assert!(invite_usecase
+            .revoke_invite(&invite.invite.id.to_string(), &member_id)
+            .await
+            .is_err());
+        assert!(invite_usecase
+            .list_invites(&chat_id, &member_id)
+            .await
+            .is_err());
+
+        assert_invalid_input(
+            invite_usecase
+                .create_invite(&chat_id, &owner.id.to_string(), Some(0), None)
+                .await,
+            "hours",
+        );
+    }
+}
@@ -29,7 +29,7 @@ mod tests {
         }
     }
     #[tokio::test]
-    async fn test_invite_private_chat_usecase_should_faile_due_to_user_detail_not_found() {
+    async fn test_invite_private_chat_usecase_without_user_detail() {
         setup();
         info!("starting test_invite_private_chat_usecase");
         let env = Env::load_test();
@@ -66,6 +66,9 @@ mod tests {
             })
             .await;
 
-        assert!(!result.is_ok(), "result should be ok");
+        let response = result.expect("result should be ok");
+        assert_eq!(response.friend_id, user2.id);
+        assert!(response.friend_user_info.user_details.is_none());
+        assert!(response.chat_messages.unwrap().messages.is_empty());
     }
 }
@@ -0,0 +1,176 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, RecordingMail, SentEmails, TestApp};
+    use commons::generic_errors::GenericError;
+    use credentials::credential_services::CredentialService;
+    use crypto::Crypto;
+    use jwt::JWT;
+    use persistence::{DatabaseInterface, Env, DB};
+    use sessions::services::SessionService;
+    use shaku::{module, HasComponent};
+    use usecases::{LoginOutcome, LoginUseCase, ACCOUNT_MAX_LOGIN_FAILURES, IP_MAX_LOGIN_FAILURES};
+    use users::user_services::UserService;
+
+    module! {
+        TestModule {
+            components = [
+                LoginUseCase,
+                UserService,
+                SessionService,
+                CredentialService,
+                Crypto,
+                RecordingMail,
+                JWT,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    async fn setup() -> (TestApp<TestModule>, SentEmails) {
+        common::setup_with_mail(TestModule::builder()).await
+    }
+
+    // The seconds until logging in is allowed again, `None` for any other outcome
+    fn locked_for(result: anyhow::Result<LoginOutcome>) -> Option<i64> {
+        match result.err()?.downcast_ref::<GenericError>() {
+            Some(GenericError::LoginLocked(seconds, code)) => {
+                assert_eq!(*code, 429);
+                Some(*seconds)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_login_failed(result: anyhow::Result<LoginOutcome>) -> bool {
+        matches!(
+            result
+                .err()
+                .as_ref()
+                .and_then(|e| e.downcast_ref::<GenericError>()),
+            Some(GenericError::LoginFailed(_))
+        )
+    }
+
+    async fn end_lockouts(module: &TestModule) {
+        let db: &dyn DatabaseInterface = module.resolve_ref();
+        sqlx::query("UPDATE login_failures SET locked_until = ?")
+            .bind(chrono::Local::now().naive_local() - chrono::Duration::minutes(1))
+            .execute(&*db.get_pool())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_account_lockout() {
+        let (module, sent) = setup().await;
+        let user = module.create_user_with_credential("lockeduser").await;
+
+        for _ in 1..ACCOUNT_MAX_LOGIN_FAILURES {
+            assert!(is_login_failed(
+                module
+                    .login_from("lockeduser", "wrongpassword", "10.0.0.1")
+                    .await
+            ));
+        }
+        assert!(sent.lock().unwrap().is_empty());
+        assert_eq!(
+            locked_for(
+                module
+                    .login_from("lockeduser", "wrongpassword", "10.0.0.2")
+                    .await
+            ),
+            Some(60)
+        );
+        common::wait_for_emails(&sent, 1).await;
+        {
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent.len(), 1, "the owner should be told about the lockout");
+            assert_eq!(sent[0].0, user.email);
+            assert!(sent[0].1.contains("10.0.0.2"));
+        }
+
+        // Even the right password waits, from any address
+        assert!(locked_for(
+            module
+                .login_from("lockeduser", "password8", "10.0.0.3")
+                .await
+        )
+        .is_some());
+
+        // Each failure after the lockout doubles the wait, without more emails
+        end_lockouts(&module).await;
+        assert_eq!(
+            locked_for(
+                module
+                    .login_from("lockeduser", "wrongpassword", "10.0.0.1")
+                    .await
+            ),
+            Some(120)
+        );
+        end_lockouts(&module).await;
+        assert_eq!(
+            locked_for(
+                module
+                    .login_from("lockeduser", "wrongpassword", "10.0.0.1")
+                    .await
+            ),
+            Some(240)
+        );
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        // Logging in starts the count over
+        end_lockouts(&module).await;
+        module
+            .login_from("lockeduser", "password8", "10.0.0.1")
+            .await
+            .unwrap()
+            .logged_in()
+            .unwrap();
+        for _ in 1..ACCOUNT_MAX_LOGIN_FAILURES {
+            assert!(is_login_failed(
+                module
+                    .login_from("lockeduser", "wrongpassword", "10.0.0.1")
+                    .await
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ip_lockout() {
+        let (module, sent) = setup().await;
+        module.create_user_with_credential("ipuser").await;
+
+        // Unknown usernames count against the address
+        for attempt in 1..IP_MAX_LOGIN_FAILURES {
+            let username = format!("nobody{}", attempt);
+            assert!(is_login_failed(
+                module
+                    .login_from(&username, "wrongpassword", "10.0.0.9")
+                    .await
+            ));
+        }
+        assert!(locked_for(
+            module
+                .login_from("nobody", "wrongpassword", "10.0.0.9")
+                .await
+        )
+        .is_some());
+        assert!(
+            locked_for(module.login_from("ipuser", "password8", "10.0.0.9").await).is_some(),
+            "nothing is checked from a locked out address"
+        );
+
+        // Other addresses and the account itself are not affected
+        module
+            .login_from("ipuser", "password8", "10.0.0.10")
+            .await
+            .unwrap()
+            .logged_in()
+            .unwrap();
+        assert!(sent.lock().unwrap().is_empty());
+    }
+}
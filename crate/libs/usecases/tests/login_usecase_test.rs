@@ -2,7 +2,9 @@
 mod tests {
     use credentials::credential::Credential;
     use credentials::credential_services::{CredentialService, CredentialServiceInterface};
+    use crypto::Crypto;
     use jwt::JWT;
+    use mail::Mail;
     use persistence::db::database::DBParameters;
     use persistence::db::sqlite::create_sqlite_db_pool;
     use persistence::env::myenv::EnvInterface;
@@ -16,7 +18,7 @@ mod tests {
 
     module! {
         TestModule {
-            components = [LoginUseCase, UserService, CredentialService, SessionService, Env, DB, JWT],
+            components = [LoginUseCase, UserService, CredentialService, SessionService, Env, DB, JWT, Crypto, Mail],
             providers = []
         }
     }
@@ -40,20 +42,37 @@ mod tests {
     #[tokio::test]
     async fn test_all_login_usecase() {
         let module = setup().await;
-        let (result_login_usecase, result_login_with_invalid_password) = futures::future::join(
+        let (
+            result_login_usecase,
+            result_login_with_invalid_password,
+            result_logout,
+            result_refresh,
+        ) = futures::future::join4(
             test_login_usecase(&module),
             test_login_usecase_with_invalid_password(&module),
+            test_logout_revokes_sessions(&module),
+            test_refresh_rotates_tokens(&module),
         )
         .await;
         // Process results
         match result_login_usecase {
             Ok(_) => println!("Task Login usecase completed"),
-            Err(e) => assert!(false, "error: {}", e),
+            Err(e) => panic!("error: {}", e),
         }
 
         match result_login_with_invalid_password {
             Ok(_) => println!("Task Login usecase with invalid password completed"),
-            Err(e) => assert!(false, "error: {}", e),
+            Err(e) => panic!("error: {}", e),
+        }
+
+        match result_logout {
+            Ok(_) => println!("Task Logout completed"),
+            Err(e) => panic!("error: {}", e),
+        }
+
+        match result_refresh {
+            Ok(_) => println!("Task Refresh completed"),
+            Err(e) => panic!("error: {}", e),
         }
 
         println!("All tasks completed.");
@@ -87,8 +106,17 @@ mod tests {
             user_agent: "user_agent",
             ip_address: "ip_address",
         };
-        let response = login_usecase.login(request).await.unwrap();
+        let response = login_usecase
+            .login(request)
+            .await
+            .unwrap()
+            .logged_in()
+            .unwrap();
         assert!(!response.token.is_empty(), "token should not be empty");
+        assert!(
+            !response.refresh_token.is_empty(),
+            "refresh token should not be empty"
+        );
         assert!(
             !response.private_key.is_empty(),
             "private key should not be empty"
@@ -144,4 +172,180 @@ mod tests {
         );
         Ok(())
     }
+
+    async fn test_logout_revokes_sessions(module: &TestModule) -> anyhow::Result<()> {
+        println!("test_logout_revokes_sessions");
+        let user_service: &dyn UserServiceInterface = module.resolve_ref();
+        let login_usecase: &dyn LoginUseCaseInterface = module.resolve_ref();
+        let credential_service: &dyn CredentialServiceInterface = module.resolve_ref();
+
+        let mut user = User::new(
+            String::from("logoutuser"),
+            String::from("logoutuser@gmail.com"),
+            String::from("password8"),
+        )?;
+        user.is_active = true;
+        user_service.create_user(&user).await?;
+        credential_service
+            .create_credential(&Credential::new(
+                user.id,
+                "private_key_example",
+                "public_key_example",
+            ))
+            .await?;
+
+        let mut tokens = Vec::new();
+        for user_agent in ["laptop", "phone", "tablet"] {
+            let request = LoginRequest {
+                username: "logoutuser",
+                password: "password8",
+                user_agent,
+                ip_address: "ip_address",
+            };
+            tokens.push(
+                login_usecase
+                    .login(request)
+                    .await?
+                    .logged_in()
+                    .unwrap()
+                    .token,
+            );
+        }
+        let laptop = login_usecase.authorize_current_user(&tokens[0]).await?;
+        let phone = login_usecase.authorize_current_user(&tokens[1]).await?;
+
+        login_usecase.logout(&laptop).await?;
+        assert!(
+            login_usecase
+                .authorize_current_user(&tokens[0])
+                .await
+                .is_err(),
+            "logged out token should be rejected"
+        );
+        assert!(
+            login_usecase
+                .authorize_current_user(&tokens[1])
+                .await
+                .is_ok(),
+            "other sessions should stay valid"
+        );
+
+        login_usecase.logout_everywhere(&phone).await?;
+        for token in &tokens {
+            assert!(
+                login_usecase.authorize_current_user(token).await.is_err(),
+                "every session should be revoked"
+            );
+        }
+        Ok(())
+    }
+
+    async fn test_refresh_rotates_tokens(module: &TestModule) -> anyhow::Result<()> {
+        println!("test_refresh_rotates_tokens");
+        let user_service: &dyn UserServiceInterface = module.resolve_ref();
+        let login_usecase: &dyn LoginUseCaseInterface = module.resolve_ref();
+        let credential_service: &dyn CredentialServiceInterface = module.resolve_ref();
+        let db: &dyn DatabaseInterface = module.resolve_ref();
+
+        let mut user = User::new(
+            String::from("refreshuser"),
+            String::from("refreshuser@gmail.com"),
+            String::from("password8"),
+        )?;
+        user.is_active = true;
+        user_service.create_user(&user).await?;
+        credential_service
+            .create_credential(&Credential::new(
+                user.id,
+                "private_key_example",
+                "public_key_example",
+            ))
+            .await?;
+        let login = || {
+            login_usecase.login(LoginRequest {
+                username: "refreshuser",
+                password: "password8",
+                user_agent: "user_agent",
+                ip_address: "ip_address",
+            })
+        };
+
+        // The other tests share the database, only touch this user's tokens
+        const OF_USER: &str = "session_id IN (SELECT session_id FROM sessions WHERE user_id = ?)";
+
+        let response = login().await?.logged_in().unwrap();
+        let claims = login_usecase
+            .authorize_current_user(&response.token)
+            .await?;
+
+        let refreshed = login_usecase.refresh(&response.refresh_token).await?;
+        assert_eq!(
+            refreshed.claims.jti, claims.jti,
+            "the session should stay the same"
+        );
+        assert_eq!(refreshed.claims.user_id, user.id.to_string());
+        let next_refresh_token = refreshed
+            .refresh_token
+            .expect("refresh token should rotate");
+        assert_ne!(next_refresh_token, response.refresh_token);
+        login_usecase
+            .authorize_current_user(&refreshed.token)
+            .await?;
+
+        // A parallel request right after the rotation only gets a new access token
+        let raced = login_usecase.refresh(&response.refresh_token).await?;
+        assert!(
+            raced.refresh_token.is_none(),
+            "used token should not rotate again"
+        );
+
+        assert!(
+            login_usecase.refresh("not a refresh token").await.is_err(),
+            "unknown refresh token should be rejected"
+        );
+
+        // Long after the rotation the old token can only be a stolen copy
+        sqlx::query(&format!(
+            "UPDATE refresh_tokens SET used_at = ? WHERE used_at IS NOT NULL AND {}",
+            OF_USER
+        ))
+        .bind(chrono::Local::now().naive_local() - chrono::Duration::minutes(5))
+        .bind(user.id.to_string())
+        .execute(&*db.get_pool())
+        .await?;
+        assert!(
+            login_usecase
+                .refresh(&response.refresh_token)
+                .await
+                .is_err(),
+            "reused refresh token should be rejected"
+        );
+        assert!(
+            login_usecase.refresh(&next_refresh_token).await.is_err(),
+            "reuse should revoke the latest refresh token too"
+        );
+        assert!(
+            login_usecase
+                .authorize_current_user(&refreshed.token)
+                .await
+                .is_err(),
+            "reuse should revoke the session"
+        );
+
+        let response = login().await?.logged_in().unwrap();
+        sqlx::query(&format!(
+            "UPDATE refresh_tokens SET expires_at = ? WHERE {}",
+            OF_USER
+        ))
+        .bind(chrono::Local::now().naive_local() - chrono::Duration::seconds(1))
+        .bind(user.id.to_string())
+        .execute(&*db.get_pool())
+        .await?;
+        let expired = login_usecase.refresh(&response.refresh_token).await;
+        assert!(
+            expired.unwrap_err().to_string().contains("expired"),
+            "expired refresh token should be rejected"
+        );
+        Ok(())
+    }
 }
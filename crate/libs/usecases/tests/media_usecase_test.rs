@@ -0,0 +1,362 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, TestApp};
+    use chats::chat_services::{ChatService, ChatServiceInterface};
+    use chats::events::ChatEventBroker;
+    use commons::generic_errors::GenericError;
+    use crypto::Crypto;
+    use futures::TryStreamExt;
+    use image::{DynamicImage, ImageFormat};
+    use persistence::{DatabaseInterface, Env, DB};
+    use shaku::{module, HasComponent};
+    use std::io::Cursor;
+    use storage::{BlobStorage, LocalStorage};
+    use usecases::chat_usecase::{AttachmentUpload, ChatUsecase, ChatUsecaseImpl};
+    use usecases::media_usecase::{MediaUsecase, MediaUsecaseImpl};
+    use usecases::userdetail_usecase::{UserDetailUsecase, UserDetailUsecaseImpl};
+    use usecases::utils;
+    use user_details::entity::UserDetail;
+    use user_details::user_detail_service::{UserDetailService, UserDetailServiceImpl};
+    use users::user_services::UserService;
+
+    module! {
+        TestModule {
+            components = [
+                MediaUsecaseImpl,
+                ChatUsecaseImpl,
+                UserDetailUsecaseImpl,
+                ChatService,
+                ChatEventBroker,
+                UserDetailServiceImpl,
+                UserService,
+                LocalStorage,
+                Crypto,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    const PDF: &[u8] = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n";
+
+    async fn setup() -> TestApp<TestModule> {
+        common::setup(TestModule::builder()).await
+    }
+
+    // Transparent, so every size stays a PNG
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        DynamicImage::new_rgba8(width, height)
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    async fn remove_media(module: &TestModule, media_url: &str) {
+        let storage: &dyn BlobStorage = module.resolve_ref();
+        let (_, key) = utils::media_storage_key(media_url).unwrap();
+        storage.delete(&key).await.unwrap();
+    }
+
+    fn assert_error<T: std::fmt::Debug>(result: anyhow::Result<T>, expected: &str) {
+        let error = result.unwrap_err();
+        let kind = match error.downcast_ref::<GenericError>() {
+            Some(GenericError::InvalidInput(_, _)) => "invalid input",
+            Some(GenericError::PermissionDenied(_, _)) => "permission denied",
+            Some(GenericError::Unauthorized()) => "unauthorized",
+            other => panic!("unexpected error {:?}", other),
+        };
+        assert_eq!(kind, expected);
+    }
+
+    #[tokio::test]
+    async fn test_attachments_are_only_served_to_chat_members() {
+        let module = setup().await;
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let chat_usecase: &dyn ChatUsecase = module.resolve_ref();
+        let media_usecase: &dyn MediaUsecase = module.resolve_ref();
+
+        let sender = module.create_user("mediasender").await;
+        let receiver = module.create_user("mediareceiver").await;
+        let stranger = module.create_user("mediastranger").await;
+        let chat_id = chat_service
+            .initiate_private_chat(&sender.id.to_string(), &receiver.id.to_string())
+            .await
+            .unwrap()
+            .to_string();
+        let message = chat_usecase
+            .send_attachments_to_chat(
+                &chat_id,
+                &sender.id.to_string(),
+                "",
+                &[AttachmentUpload {
+                    file_name: String::from("report.pdf"),
+                    data: PDF.to_vec(),
+                }],
+            )
+            .await
+            .unwrap();
+        let file_url = message.3[0].file_url.clone();
+        assert!(file_url.starts_with(utils::MEDIA_URL_PREFIX));
+
+        let media = media_usecase
+            .open_media(&file_url, Some(&receiver.id.to_string()), None)
+            .await
+            .unwrap();
+        assert_eq!(media.content_type, "application/pdf");
+        assert_eq!(media.file_name.as_deref(), Some("report.pdf"));
+        assert_eq!(media.size, PDF.len() as u64);
+        let content: Vec<_> = media_usecase
+            .read_media(&media, None)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(content.concat(), PDF);
+
+        assert_error(
+            media_usecase
+                .open_media(&file_url, Some(&stranger.id.to_string()), None)
+                .await,
+            "permission denied",
+        );
+        assert_error(
+            media_usecase.open_media(&file_url, None, None).await,
+            "unauthorized",
+        );
+
+        // A signed URL stands in for the session, but only for the file it was made for
+        let signed_url = media_usecase.sign_media_url(&file_url).await.unwrap();
+        let signature = signed_url.split_once("?signature=").unwrap().1;
+        media_usecase
+            .open_media(&file_url, None, Some(signature))
+            .await
+            .unwrap();
+        assert_error(
+            media_usecase
+                .open_media(&file_url, None, Some("AAAAAAAAAAAAAAAAAAAAAAAA"))
+                .await,
+            "unauthorized",
+        );
+        let other_url = format!("{}attachments/other.pdf", utils::MEDIA_URL_PREFIX);
+        assert_error(
+            media_usecase
+                .open_media(&other_url, None, Some(signature))
+                .await,
+            "invalid input",
+        );
+
+        // Thumbnails follow the attachment they were made from
+        let photo = chat_usecase
+            .send_attachments_to_chat(
+                &chat_id,
+                &sender.id.to_string(),
+                "",
+                &[AttachmentUpload {
+                    file_name: String::from("photo.png"),
+                    data: png(640, 480),
+                }],
+            )
+            .await
+            .unwrap();
+        let thumbnail_url = photo.3[0].thumbnail_url.clone().unwrap();
+        let thumbnail = media_usecase
+            .open_media(&thumbnail_url, Some(&receiver.id.to_string()), None)
+            .await
+            .unwrap();
+        assert_eq!(thumbnail.content_type, "image/png");
+        assert_eq!(thumbnail.file_name, None);
+        assert_error(
+            media_usecase
+                .open_media(&thumbnail_url, Some(&stranger.id.to_string()), None)
+                .await,
+            "permission denied",
+        );
+
+        remove_media(&module, &file_url).await;
+        remove_media(&module, &photo.3[0].file_url).await;
+        remove_media(&module, &thumbnail_url).await;
+    }
+
+    #[tokio::test]
+    async fn test_profile_pictures_are_served_to_signed_in_users() {
+        let module = setup().await;
+        let user_detail_usecase: &dyn UserDetailUsecase = module.resolve_ref();
+        let media_usecase: &dyn MediaUsecase = module.resolve_ref();
+
+        let owner = module.create_user("avatarowner").await;
+        let viewer = module.create_user("avatarviewer").await;
+        user_detail_usecase
+            .update_profile(&UserDetail::new(owner.id))
+            .await
+            .unwrap();
+        let profile_picture = user_detail_usecase
+            .upload_profile_picture(&owner.id.to_string(), &png(600, 400))
+            .await
+            .unwrap();
+        let sizes = [
+            (&profile_picture.large, 512),
+            (&profile_picture.medium, 256),
+            (&profile_picture.small, 96),
+        ];
+
+        for (avatar, size) in sizes {
+            let media = media_usecase
+                .open_media(avatar, Some(&viewer.id.to_string()), None)
+                .await
+                .unwrap();
+            assert_eq!(media.content_type, "image/png");
+            assert_eq!(media.file_name, None);
+            let content: Vec<_> = media_usecase
+                .read_media(&media, None)
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            let image = image::load_from_memory(&content.concat()).unwrap();
+            assert_eq!((image.width(), image.height()), (size, size));
+            assert_error(
+                media_usecase.open_media(avatar, None, None).await,
+                "unauthorized",
+            );
+        }
+
+        let viewer_id = viewer.id.to_string();
+        for url in [
+            format!("{}avatars/unknown.png", utils::MEDIA_URL_PREFIX),
+            format!("{}avatars/../../Cargo.toml", utils::MEDIA_URL_PREFIX),
+            format!("{}private/{}", utils::MEDIA_URL_PREFIX, "file.png"),
+            String::from("/assets/index.html"),
+        ] {
+            assert_error(
+                media_usecase.open_media(&url, Some(&viewer_id), None).await,
+                "invalid input",
+            );
+        }
+
+        for (avatar, _) in sizes {
+            remove_media(&module, avatar).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_profile_picture_replaces_the_previous_files() {
+        let module = setup().await;
+        let user_detail_usecase: &dyn UserDetailUsecase = module.resolve_ref();
+        let storage: &dyn BlobStorage = module.resolve_ref();
+
+        let owner = module.create_user("reuploader").await;
+        user_detail_usecase
+            .update_profile(&UserDetail::new(owner.id))
+            .await
+            .unwrap();
+        let mut uploads = Vec::new();
+        for _ in 0..2 {
+            let profile_picture = user_detail_usecase
+                .upload_profile_picture(&owner.id.to_string(), &png(300, 300))
+                .await
+                .unwrap();
+            let keys: Vec<_> = [
+                profile_picture.large,
+                profile_picture.medium,
+                profile_picture.small,
+            ]
+            .iter()
+            .map(|media_url| utils::media_storage_key(media_url).unwrap().1)
+            .collect();
+            uploads.push(keys);
+        }
+
+        for key in &uploads[0] {
+            assert_eq!(storage.size(key).await.unwrap(), None);
+        }
+        for key in &uploads[1] {
+            assert!(storage.size(key).await.unwrap().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_legacy_uploads_are_moved_into_the_blob_storage() {
+        let module = setup().await;
+        let db: &dyn DatabaseInterface = module.resolve_ref();
+        let chat_service: &dyn ChatServiceInterface = module.resolve_ref();
+        let user_detail_service: &dyn UserDetailService = module.resolve_ref();
+        let user_detail_usecase: &dyn UserDetailUsecase = module.resolve_ref();
+        let media_usecase: &dyn MediaUsecase = module.resolve_ref();
+
+        let owner = module.create_user("legacyowner").await;
+        user_detail_usecase
+            .update_profile(&UserDetail::new(owner.id))
+            .await
+            .unwrap();
+        let group = chat_service
+            .create_group_chat("Legacy", &owner.id, &[])
+            .await
+            .unwrap();
+        let legacy_dir = module.storage_root().join("legacy_uploads");
+        std::fs::create_dir_all(&legacy_dir).unwrap();
+        for file_name in ["profile_old.png", "group_old.png", "unused_old.png"] {
+            std::fs::write(legacy_dir.join(file_name), png(8, 8)).unwrap();
+        }
+        // Only the largest size was stored back then
+        sqlx::query("UPDATE user_details SET profile_picture = ? WHERE user_id = ?")
+            .bind("/assets/uploads/profile_old.png")
+            .bind(owner.id.to_string())
+            .execute(&*db.get_pool())
+            .await
+            .unwrap();
+        chat_service
+            .update_chat_avatar(
+                &group.id.to_string(),
+                &owner.id.to_string(),
+                "/assets/uploads/group_old.png",
+            )
+            .await
+            .unwrap();
+
+        let imported = media_usecase
+            .import_legacy_uploads(&legacy_dir)
+            .await
+            .unwrap();
+        assert_eq!(imported, 2);
+        assert!(!legacy_dir.join("profile_old.png").exists());
+        assert!(!legacy_dir.join("group_old.png").exists());
+        assert!(legacy_dir.join("unused_old.png").exists());
+
+        let profile_picture = user_detail_service
+            .get_user_detail_by_user_id(&owner.id.to_string())
+            .await
+            .unwrap()
+            .profile_picture
+            .unwrap();
+        let avatar = chat_service
+            .get_chat(&group.id.to_string())
+            .await
+            .unwrap()
+            .unwrap()
+            .avatar
+            .unwrap();
+        for (media_url, folder) in [(&profile_picture, "avatars"), (&avatar, "groups")] {
+            assert!(media_url.starts_with(&format!("{}{}/", utils::MEDIA_URL_PREFIX, folder)));
+            let media = media_usecase
+                .open_media(media_url, Some(&owner.id.to_string()), None)
+                .await
+                .unwrap();
+            assert_eq!(media.content_type, "image/png");
+        }
+
+        // Nothing is left to move on the next start
+        assert_eq!(
+            media_usecase
+                .import_legacy_uploads(&legacy_dir)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
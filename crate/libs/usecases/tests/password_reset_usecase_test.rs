@@ -0,0 +1,145 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, RecordingMail, SentEmails, TestApp};
+    use credentials::credential_services::CredentialService;
+    use crypto::Crypto;
+    use jwt::JWT;
+    use persistence::{DatabaseInterface, Env, DB};
+    use sessions::services::SessionService;
+    use shaku::{module, HasComponent};
+    use usecases::password_reset_usecase::{PasswordResetUsecase, PasswordResetUsecaseImpl};
+    use usecases::{LoginUseCase, LoginUseCaseInterface};
+    use users::user_services::UserService;
+
+    module! {
+        TestModule {
+            components = [
+                PasswordResetUsecaseImpl,
+                LoginUseCase,
+                UserService,
+                SessionService,
+                CredentialService,
+                Crypto,
+                RecordingMail,
+                JWT,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    async fn setup() -> (TestApp<TestModule>, SentEmails) {
+        common::setup_with_mail(TestModule::builder()).await
+    }
+
+    fn reset_token(body: &str) -> String {
+        let link = body.split("/reset-password/").nth(1).unwrap();
+        link.split('"').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_reset_password_with_emailed_link() {
+        let (module, sent) = setup().await;
+        let reset_usecase: &dyn PasswordResetUsecase = module.resolve_ref();
+        let login_usecase: &dyn LoginUseCaseInterface = module.resolve_ref();
+        let user = module.create_user_with_credential("resetuser").await;
+        let old_token = module
+            .session_token("resetuser", "password8")
+            .await
+            .unwrap();
+
+        // Unknown addresses get the same answer, just no email
+        reset_usecase
+            .request_reset("nobody@gmail.com")
+            .await
+            .unwrap();
+        assert!(sent.lock().unwrap().is_empty());
+        assert!(reset_usecase.request_reset(" ").await.is_err());
+
+        reset_usecase
+            .request_reset("ResetUser@gmail.com")
+            .await
+            .unwrap();
+        common::wait_for_emails(&sent, 1).await;
+        let token = {
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].0, user.email);
+            reset_token(&sent[0].1)
+        };
+
+        reset_usecase.check_reset_token(&token).await.unwrap();
+        assert!(reset_usecase.check_reset_token("unknown").await.is_err());
+
+        // Rejected passwords leave the link usable
+        assert!(reset_usecase
+            .reset_password(&token, "short", "short")
+            .await
+            .is_err());
+        assert!(reset_usecase
+            .reset_password(&token, "newpassword9", "newpassword0")
+            .await
+            .is_err());
+        reset_usecase.check_reset_token(&token).await.unwrap();
+
+        reset_usecase
+            .reset_password(&token, "newpassword9", "newpassword9")
+            .await
+            .unwrap();
+        assert!(module
+            .session_token("resetuser", "password8")
+            .await
+            .is_err());
+        module
+            .session_token("resetuser", "newpassword9")
+            .await
+            .unwrap();
+        assert!(
+            login_usecase
+                .authorize_current_user(&old_token)
+                .await
+                .is_err(),
+            "sessions from before the reset should be revoked"
+        );
+
+        // Single use
+        assert!(reset_usecase.check_reset_token(&token).await.is_err());
+        assert!(reset_usecase
+            .reset_password(&token, "otherpassword9", "otherpassword9")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_reset_link() {
+        let (module, sent) = setup().await;
+        let reset_usecase: &dyn PasswordResetUsecase = module.resolve_ref();
+        let db: &dyn DatabaseInterface = module.resolve_ref();
+        module.create_user_with_credential("resetuser").await;
+
+        reset_usecase
+            .request_reset("resetuser@gmail.com")
+            .await
+            .unwrap();
+        common::wait_for_emails(&sent, 1).await;
+        let token = reset_token(&sent.lock().unwrap()[0].1);
+        sqlx::query("UPDATE password_resets SET expires_at = ?")
+            .bind(chrono::Local::now().naive_local() - chrono::Duration::minutes(1))
+            .execute(&*db.get_pool())
+            .await
+            .unwrap();
+
+        assert!(reset_usecase.check_reset_token(&token).await.is_err());
+        assert!(reset_usecase
+            .reset_password(&token, "newpassword9", "newpassword9")
+            .await
+            .is_err());
+        module
+            .session_token("resetuser", "password8")
+            .await
+            .unwrap();
+    }
+}
@@ -1,12 +1,15 @@
+mod common;
+
 #[cfg(test)]
 mod tests {
+    use crate::common::{self, RecordingMail, SentEmails};
     use credentials::credential_services::{CredentialService, CredentialServiceInterface};
     use crypto::{Crypto, Encrypt};
     use mail::{Mail, SendEmail};
     use persistence::env::myenv::EnvInterface;
     use persistence::{DatabaseInterface, Env};
     use std::sync::Arc;
-    use usecases::utils::setup_db;
+    use usecases::register_usecase::ActivationToken;
     use usecases::{RegisterRequest, RegisterUseCase, RegisterUseCaseInterface};
     use users::user::User;
     use users::user_services::{UserService, UserServiceInterface};
@@ -16,7 +19,7 @@ mod tests {
         dotenv::dotenv().ok();
         // let env: &'static Env = Box::leak(Box::new(Env::new()));
         let env: Arc<dyn EnvInterface> = Arc::new(Env::new());
-        let db = setup_db().await;
+        let db = common::setup_db().await;
         let mail: Arc<dyn SendEmail> = Mail::new_arc(Arc::clone(&env));
         let user_service: Arc<dyn UserServiceInterface> =
             Arc::new(UserService::new(Arc::clone(&db)));
@@ -48,7 +51,7 @@ mod tests {
     async fn test_activate_user() {
         dotenv::dotenv().ok();
         let env: Arc<dyn EnvInterface> = Arc::new(Env::new()); // let env: &'static Env = Arc::new(Env::new());
-        let db: Arc<dyn DatabaseInterface> = setup_db().await;
+        let db: Arc<dyn DatabaseInterface> = common::setup_db().await;
         let mail: Arc<dyn SendEmail> = Mail::new_arc(env.clone());
         let user_service: Arc<dyn UserServiceInterface> = Arc::new(UserService::new(db.clone()));
         let credential_service: Arc<dyn CredentialServiceInterface> =
@@ -74,14 +77,137 @@ mod tests {
         let x = user_service.get_user_by_uuid(user.id).await.unwrap();
         assert!(!x.is_active);
 
-        // test activating user, make user status active
+        // a bare encrypted user id is not an activation token
         let user_id = user.id.to_string();
         let user_id_encrypted = encrypt.encrypt(user_id.as_str()).await.unwrap();
         let response = register_usecase.activate_user(&user_id_encrypted).await;
+        assert!(response.is_err());
+        assert!(register_usecase.activate_user("short").await.is_err());
+
+        // test activating user, make user status active
+        let token = ActivationToken::new(user.id, chrono::Duration::hours(1))
+            .seal(encrypt.as_ref())
+            .await
+            .unwrap();
+        let response = register_usecase.activate_user(&token).await;
         assert!(response.is_ok());
 
         // test activate user, ensure user is active
         let x = user_service.get_user_by_uuid(user.id).await.unwrap();
         assert!(x.is_active);
     }
+
+    #[tokio::test]
+    async fn test_expired_activation_link() {
+        dotenv::dotenv().ok();
+        let env: Arc<dyn EnvInterface> = Arc::new(Env::new());
+        let db: Arc<dyn DatabaseInterface> = common::setup_db().await;
+        let mail: Arc<dyn SendEmail> = Mail::new_arc(env.clone());
+        let user_service: Arc<dyn UserServiceInterface> = Arc::new(UserService::new(db.clone()));
+        let credential_service: Arc<dyn CredentialServiceInterface> =
+            Arc::new(CredentialService::new(Arc::clone(&db)));
+        let encrypt: Arc<dyn Encrypt> = Crypto::new_arc(env.clone());
+        let register_usecase = RegisterUseCase::new(
+            Arc::clone(&user_service),
+            Arc::clone(&credential_service),
+            Arc::clone(&mail),
+            Arc::clone(&env),
+            Arc::clone(&encrypt),
+        );
+
+        let user = User::new(
+            String::from("expired"),
+            String::from("expired@gmail.com"),
+            String::from("password8"),
+        )
+        .unwrap();
+        user_service.create_user(&user).await.unwrap();
+
+        let token = ActivationToken::new(user.id, chrono::Duration::minutes(-1))
+            .seal(encrypt.as_ref())
+            .await
+            .unwrap();
+        let error = register_usecase.activate_user(&token).await.unwrap_err();
+        assert!(error.to_string().contains("expired"));
+        let x = user_service.get_user_by_uuid(user.id).await.unwrap();
+        assert!(!x.is_active);
+    }
+
+    #[tokio::test]
+    async fn test_resend_activation() {
+        dotenv::dotenv().ok();
+        let env: Arc<dyn EnvInterface> = Arc::new(Env::new());
+        let db: Arc<dyn DatabaseInterface> = common::setup_db().await;
+        let sent = SentEmails::default();
+        let mail = Arc::new(RecordingMail::new(sent.clone()));
+        let user_service: Arc<dyn UserServiceInterface> = Arc::new(UserService::new(db.clone()));
+        let credential_service: Arc<dyn CredentialServiceInterface> =
+            Arc::new(CredentialService::new(Arc::clone(&db)));
+        let encrypt: Arc<dyn Encrypt> = Crypto::new_arc(env.clone());
+        let register_usecase = RegisterUseCase::new(
+            Arc::clone(&user_service),
+            Arc::clone(&credential_service),
+            mail.clone(),
+            Arc::clone(&env),
+            Arc::clone(&encrypt),
+        );
+        let sent_count = || sent.lock().unwrap().len();
+
+        let request = RegisterRequest {
+            username: "resender",
+            email: "resender@gmail.com",
+            password: "password8",
+            private_key: "private_key",
+            public_key: "public_key",
+        };
+        register_usecase.register(&request).await.unwrap();
+        assert_eq!(sent_count(), 1);
+
+        // Too soon after sign up, and unknown addresses get the same answer
+        register_usecase
+            .resend_activation("resender@gmail.com")
+            .await
+            .unwrap();
+        register_usecase
+            .resend_activation("nobody@gmail.com")
+            .await
+            .unwrap();
+        assert_eq!(sent_count(), 1);
+        assert!(register_usecase.resend_activation(" ").await.is_err());
+
+        sqlx::query("UPDATE users SET activation_sent_at = ?")
+            .bind(chrono::Utc::now().naive_utc() - chrono::Duration::hours(1))
+            .execute(&*db.get_pool())
+            .await
+            .unwrap();
+        register_usecase
+            .resend_activation("Resender@gmail.com")
+            .await
+            .unwrap();
+        register_usecase
+            .resend_activation("resender@gmail.com")
+            .await
+            .unwrap();
+        common::wait_for_emails(&sent, 2).await;
+        assert_eq!(sent_count(), 2);
+
+        let token = {
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent[1].0, "resender@gmail.com");
+            let link = sent[1].1.split("/activate/").nth(1).unwrap();
+            link.split('"').next().unwrap().to_string()
+        };
+        register_usecase.activate_user(&token).await.unwrap();
+
+        // Active accounts get nothing
+        sqlx::query("UPDATE users SET activation_sent_at = NULL")
+            .execute(&*db.get_pool())
+            .await
+            .unwrap();
+        register_usecase
+            .resend_activation("resender@gmail.com")
+            .await
+            .unwrap();
+        assert_eq!(sent_count(), 2);
+    }
 }
@@ -0,0 +1,127 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, TestApp};
+    use commons::generic_errors::GenericError;
+    use credentials::credential_services::CredentialService;
+    use crypto::Crypto;
+    use jwt::{AccessClaims, JWTInterface, Role, JWT};
+    use mail::Mail;
+    use persistence::{Env, DB};
+    use sessions::entity::Session;
+    use sessions::services::{SessionService, SessionServiceInterface};
+    use shaku::{module, HasComponent};
+    use usecases::session_usecase::{SessionUsecase, SessionUsecaseImpl};
+    use usecases::{LoginUseCase, LoginUseCaseInterface};
+    use users::user::User;
+    use users::user_services::UserService;
+
+    module! {
+        TestModule {
+            components = [
+                SessionUsecaseImpl,
+                LoginUseCase,
+                SessionService,
+                CredentialService,
+                Crypto,
+                Mail,
+                UserService,
+                JWT,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    const FIREFOX_ON_LINUX: &str =
+        "Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:133.0) Gecko/20100101 Firefox/133.0";
+    const SAFARI_ON_IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 18_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.1 Mobile/15E148 Safari/604.1";
+
+    async fn setup() -> TestApp<TestModule> {
+        common::setup(TestModule::builder()).await
+    }
+
+    // Signs the user in like LoginUseCase::login an hour ago, without the password check
+    async fn sign_in(
+        module: &TestModule,
+        user: &User,
+        user_agent: &str,
+    ) -> (AccessClaims, Session) {
+        let session_service: &dyn SessionServiceInterface = module.resolve_ref();
+        let claims = AccessClaims::new(user.id.to_string(), Role::User);
+        let mut session = Session::new(
+            claims.jti.parse().unwrap(),
+            user.id,
+            user_agent.to_string(),
+            String::from("10.0.0.1"),
+        );
+        let an_hour_ago = chrono::Local::now().naive_local() - chrono::Duration::hours(1);
+        session.created_at = Some(an_hour_ago);
+        session.updated_at = Some(an_hour_ago);
+        session_service.create_session(&session).await.unwrap();
+        (claims, session)
+    }
+
+    #[tokio::test]
+    async fn test_list_and_revoke_sessions() {
+        let module = setup().await;
+        let session_usecase: &dyn SessionUsecase = module.resolve_ref();
+        let login_usecase: &dyn LoginUseCaseInterface = module.resolve_ref();
+        let jwt: &dyn JWTInterface = module.resolve_ref();
+
+        let alice = module.create_user("sessionalice").await;
+        let bob = module.create_user("sessionbob").await;
+        let (laptop, laptop_session) = sign_in(&module, &alice, FIREFOX_ON_LINUX).await;
+        let (phone, phone_session) = sign_in(&module, &alice, SAFARI_ON_IPHONE).await;
+        let (_, tablet_session) = sign_in(&module, &alice, FIREFOX_ON_LINUX).await;
+        let (_, bob_session) = sign_in(&module, &bob, FIREFOX_ON_LINUX).await;
+
+        // Authorized requests move the last active time forward, at most once a minute
+        let token = jwt.generate_token(&laptop).await.unwrap().token;
+        login_usecase.authorize_current_user(&token).await.unwrap();
+        let sessions = session_usecase.get_active_sessions(&laptop).await.unwrap();
+        let last_active_at = sessions[0].last_active_at.unwrap();
+        assert!(last_active_at > laptop_session.updated_at.unwrap());
+        assert_eq!(sessions[0].first_seen_at, laptop_session.created_at);
+        login_usecase.authorize_current_user(&token).await.unwrap();
+        let sessions = session_usecase.get_active_sessions(&laptop).await.unwrap();
+        assert_eq!(sessions[0].last_active_at, Some(last_active_at));
+
+        let sessions = session_usecase.get_active_sessions(&phone).await.unwrap();
+        assert_eq!(sessions.len(), 3);
+        assert_eq!(sessions[0].id, phone_session.id);
+        assert!(sessions[0].is_current);
+        assert_eq!(sessions[0].device.browser, "Safari");
+        assert_eq!(sessions[0].device.os, "iOS");
+        assert_eq!(sessions[0].ip_address, "10.0.0.1");
+        assert_eq!(sessions[1].id, laptop_session.id);
+        assert!(!sessions[1].is_current);
+        assert_eq!(sessions[1].device.browser, "Firefox");
+        assert_eq!(sessions[2].id, tablet_session.id);
+
+        for (session_id, error) in [
+            (phone_session.id.to_string(), "Log out to end the session"),
+            (bob_session.id.to_string(), "Session not found"),
+            (String::from("not-a-session"), "Session not found"),
+        ] {
+            let result = session_usecase.revoke_session(&phone, &session_id).await;
+            match result.unwrap_err().downcast_ref::<GenericError>() {
+                Some(GenericError::InvalidInput(message, _)) => {
+                    assert!(message.contains(error), "{}", message)
+                }
+                other => panic!("expected invalid input, got {:?}", other),
+            }
+        }
+
+        session_usecase
+            .revoke_session(&phone, &laptop_session.id.to_string())
+            .await
+            .unwrap();
+        assert!(login_usecase.authorize_current_user(&token).await.is_err());
+        let sessions = session_usecase.get_active_sessions(&phone).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[1].id, tablet_session.id);
+    }
+}
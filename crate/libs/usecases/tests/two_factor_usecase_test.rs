@@ -0,0 +1,248 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{self, TestApp, PASSWORD};
+    use commons::generic_errors::GenericError;
+    use commons::totp::{totp_code, totp_step};
+    use credentials::credential_services::CredentialService;
+    use crypto::Crypto;
+    use jwt::{AccessClaims, JWT};
+    use mail::Mail;
+    use persistence::{DatabaseInterface, Env, DB};
+    use sessions::services::{SessionService, SessionServiceInterface};
+    use shaku::{module, HasComponent};
+    use usecases::two_factor_usecase::{
+        TwoFactorUsecase, TwoFactorUsecaseImpl, RECOVERY_CODE_COUNT,
+    };
+    use usecases::{LoginOutcome, LoginUseCase, LoginUseCaseInterface, TwoFactorLoginRequest};
+    use users::user_services::UserService;
+
+    module! {
+        TestModule {
+            components = [
+                TwoFactorUsecaseImpl,
+                LoginUseCase,
+                UserService,
+                SessionService,
+                CredentialService,
+                Crypto,
+                Mail,
+                JWT,
+                Env,
+                DB
+            ],
+            providers = []
+        }
+    }
+
+    async fn setup() -> TestApp<TestModule> {
+        common::setup(TestModule::builder()).await
+    }
+
+    fn challenge_of(outcome: LoginOutcome) -> String {
+        match outcome {
+            LoginOutcome::TwoFactorRequired { challenge_token } => challenge_token,
+            LoginOutcome::LoggedIn(_) => panic!("expected a two-factor challenge"),
+        }
+    }
+
+    async fn complete(
+        module: &TestModule,
+        challenge_token: &str,
+        code: &str,
+    ) -> anyhow::Result<()> {
+        let login_usecase: &dyn LoginUseCaseInterface = module.resolve_ref();
+        let response = login_usecase
+            .complete_two_factor_login(TwoFactorLoginRequest {
+                challenge_token,
+                code,
+                user_agent: "user_agent",
+                ip_address: "ip_address",
+            })
+            .await?;
+        login_usecase
+            .authorize_current_user(&response.token)
+            .await
+            .map(|_| ())
+    }
+
+    fn code_at(secret: &str, steps_from_now: u64) -> String {
+        let now = chrono::Utc::now().timestamp() as u64;
+        totp_code(secret, totp_step(now) + steps_from_now).unwrap()
+    }
+
+    /// Turns two-factor login on, returns the secret and the recovery codes.
+    async fn enroll(module: &TestModule, claims: &AccessClaims) -> (String, Vec<String>) {
+        let two_factor_usecase: &dyn TwoFactorUsecase = module.resolve_ref();
+        let enrollment = two_factor_usecase.start_enrollment(claims).await.unwrap();
+        let codes = two_factor_usecase
+            .confirm_enrollment(claims, &code_at(&enrollment.secret, 0))
+            .await
+            .unwrap();
+        (enrollment.secret, codes)
+    }
+
+    #[tokio::test]
+    async fn test_login_with_two_factor() {
+        let module = setup().await;
+        let two_factor_usecase: &dyn TwoFactorUsecase = module.resolve_ref();
+        let session_service: &dyn SessionServiceInterface = module.resolve_ref();
+        let user = module.create_user_with_credential("twofactoruser").await;
+        let claims = module.claims("twofactoruser", PASSWORD).await.unwrap();
+
+        let enrollment = two_factor_usecase.start_enrollment(&claims).await.unwrap();
+        assert!(enrollment
+            .otpauth_uri
+            .starts_with("otpauth://totp/Chaty:twofactoruser?secret="));
+        assert!(enrollment.otpauth_uri.contains(&enrollment.secret));
+        // Nothing changes until a code is confirmed
+        assert!(
+            !two_factor_usecase
+                .get_status(&claims)
+                .await
+                .unwrap()
+                .enabled
+        );
+        module
+            .login("twofactoruser", PASSWORD)
+            .await
+            .unwrap()
+            .logged_in()
+            .unwrap();
+        assert!(two_factor_usecase
+            .confirm_enrollment(&claims, "000000x")
+            .await
+            .is_err());
+
+        let codes = two_factor_usecase
+            .confirm_enrollment(&claims, &code_at(&enrollment.secret, 0))
+            .await
+            .unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        let status = two_factor_usecase.get_status(&claims).await.unwrap();
+        assert!(status.enabled);
+        assert_eq!(status.recovery_codes_left, RECOVERY_CODE_COUNT as i64);
+        assert!(two_factor_usecase.start_enrollment(&claims).await.is_err());
+
+        // The password alone no longer opens a session
+        let sessions_before = session_service
+            .get_sessions_by_user(user.id)
+            .await
+            .unwrap()
+            .len();
+        let challenge = challenge_of(module.login("twofactoruser", PASSWORD).await.unwrap());
+        assert_eq!(
+            session_service
+                .get_sessions_by_user(user.id)
+                .await
+                .unwrap()
+                .len(),
+            sessions_before
+        );
+
+        // The code used to confirm the setup cannot be replayed
+        assert!(
+            complete(&module, &challenge, &code_at(&enrollment.secret, 0))
+                .await
+                .is_err()
+        );
+        assert!(
+            complete(&module, "unknown", &code_at(&enrollment.secret, 1))
+                .await
+                .is_err()
+        );
+        complete(&module, &challenge, &code_at(&enrollment.secret, 1))
+            .await
+            .unwrap();
+        // A challenge completes once
+        assert!(complete(&module, &challenge, &codes[0]).await.is_err());
+
+        // Recovery codes work once each, however they are typed
+        let challenge = challenge_of(module.login("twofactoruser", PASSWORD).await.unwrap());
+        complete(
+            &module,
+            &challenge,
+            &format!(" {} ", codes[0].to_uppercase()),
+        )
+        .await
+        .unwrap();
+        let challenge = challenge_of(module.login("twofactoruser", PASSWORD).await.unwrap());
+        assert!(complete(&module, &challenge, &codes[0]).await.is_err());
+        complete(&module, &challenge, &codes[1]).await.unwrap();
+        assert_eq!(
+            two_factor_usecase
+                .get_status(&claims)
+                .await
+                .unwrap()
+                .recovery_codes_left,
+            RECOVERY_CODE_COUNT as i64 - 2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_too_many_wrong_codes_end_the_login() {
+        let module = setup().await;
+        let db: &dyn DatabaseInterface = module.resolve_ref();
+        module.create_user_with_credential("guesseduser").await;
+        let claims = module.claims("guesseduser", PASSWORD).await.unwrap();
+        let (_, codes) = enroll(&module, &claims).await;
+
+        let challenge = challenge_of(module.login("guesseduser", PASSWORD).await.unwrap());
+        for _ in 0..4 {
+            assert!(complete(&module, &challenge, "123456").await.is_err());
+        }
+        // Wrong codes count towards the account lockout like wrong passwords
+        let error = complete(&module, &challenge, "123456").await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<GenericError>(),
+            Some(GenericError::LoginLocked(..))
+        ));
+        assert!(complete(&module, &challenge, &codes[0]).await.is_err());
+
+        // Once the lockout is over, entering the password again starts over
+        sqlx::query("UPDATE login_failures SET locked_until = ?")
+            .bind(chrono::Local::now().naive_local() - chrono::Duration::minutes(1))
+            .execute(&*db.get_pool())
+            .await
+            .unwrap();
+        let challenge = challenge_of(module.login("guesseduser", PASSWORD).await.unwrap());
+        complete(&module, &challenge, &codes[0]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disable_two_factor() {
+        let module = setup().await;
+        let two_factor_usecase: &dyn TwoFactorUsecase = module.resolve_ref();
+        module.create_user_with_credential("disableuser").await;
+        let claims = module.claims("disableuser", PASSWORD).await.unwrap();
+        assert!(two_factor_usecase
+            .disable(&claims, "password8", "123456")
+            .await
+            .is_err());
+        let (_, codes) = enroll(&module, &claims).await;
+
+        assert!(two_factor_usecase
+            .disable(&claims, "wrongpassword8", &codes[0])
+            .await
+            .is_err());
+        assert!(two_factor_usecase
+            .disable(&claims, "password8", "123456")
+            .await
+            .is_err());
+        two_factor_usecase
+            .disable(&claims, "password8", &codes[0])
+            .await
+            .unwrap();
+
+        let status = two_factor_usecase.get_status(&claims).await.unwrap();
+        assert!(!status.enabled);
+        assert_eq!(status.recovery_codes_left, 0);
+        module
+            .login("disableuser", PASSWORD)
+            .await
+            .unwrap()
+            .logged_in()
+            .unwrap();
+    }
+}
@@ -1,12 +1,14 @@
+mod common;
+
 #[cfg(test)]
 mod tests {
-    use usecases::utils::setup_db;
+    use crate::common;
     use users::user::User;
     use users::user_services::{UserService, UserServiceInterface};
 
     #[tokio::test]
     async fn test_create_user() {
-        let db = setup_db().await;
+        let db = common::setup_db().await;
         let user_service = UserService::new(db);
 
         let user = User::new(
@@ -32,7 +34,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_user_by_userid() {
-        let db = setup_db().await;
+        let db = common::setup_db().await;
         let user_service = UserService::new(db);
 
         let user = User::new(
@@ -58,7 +60,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_user_by_username() {
-        let db = setup_db().await;
+        let db = common::setup_db().await;
         let user_service = UserService::new(db);
 
         let mut user = User::new(
@@ -83,7 +85,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_activate_eligible_user() {
-        let db = setup_db().await;
+        let db = common::setup_db().await;
         let user_service = UserService::new(db);
 
         let user = User::new(
@@ -105,7 +107,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_activate_user_id_not_found_should_fail() {
-        let db = setup_db().await;
+        let db = common::setup_db().await;
         let user_service = UserService::new(db);
 
         let nonexistent_id = uuid::Uuid::new_v4();
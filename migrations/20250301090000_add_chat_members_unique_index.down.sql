@@ -0,0 +1 @@
+DROP INDEX IF EXISTS chat_members_chat_id_user_id;
@@ -0,0 +1 @@
+CREATE UNIQUE INDEX chat_members_chat_id_user_id ON chat_members (chat_id, user_id);
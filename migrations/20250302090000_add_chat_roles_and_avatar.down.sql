@@ -0,0 +1,2 @@
+ALTER TABLE chats DROP COLUMN avatar;
+ALTER TABLE chat_members DROP COLUMN role;
@@ -0,0 +1,13 @@
+ALTER TABLE chat_members ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'member';
+ALTER TABLE chats ADD COLUMN avatar VARCHAR(255);
+
+-- Existing groups are handed to whoever joined first
+UPDATE chat_members
+SET role = 'owner'
+WHERE id IN (
+    SELECT cm.id
+    FROM chat_members cm
+    JOIN chats c ON c.id = cm.chat_id
+    WHERE c.is_group = TRUE
+      AND cm.joined_at = (SELECT MIN(joined_at) FROM chat_members WHERE chat_id = cm.chat_id)
+);
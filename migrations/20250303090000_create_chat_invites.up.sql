@@ -0,0 +1,14 @@
+CREATE TABLE chat_invites
+(
+    id         UUID PRIMARY KEY,
+    chat_id    UUID      NOT NULL REFERENCES chats (id),
+    created_by UUID      NOT NULL REFERENCES users (id),
+    -- NULL means the link never expires / can be used any number of times
+    expires_at TIMESTAMP,
+    max_uses   INT,
+    use_count  INT       NOT NULL DEFAULT 0,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_chat_invites_chat_id ON chat_invites (chat_id);
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS message_read_receipts_message_id_user_id;
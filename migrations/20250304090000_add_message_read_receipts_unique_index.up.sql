@@ -0,0 +1,2 @@
+-- A message is read once per user, marking it again is a no-op
+CREATE UNIQUE INDEX message_read_receipts_message_id_user_id ON message_read_receipts (message_id, user_id);
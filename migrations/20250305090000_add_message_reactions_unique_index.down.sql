@@ -0,0 +1 @@
+DROP INDEX IF EXISTS message_reactions_message_id_user_id_reaction;
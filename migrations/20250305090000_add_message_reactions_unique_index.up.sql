@@ -0,0 +1,2 @@
+-- One reaction per emoji per user, several different emojis are fine
+CREATE UNIQUE INDEX message_reactions_message_id_user_id_reaction ON message_reactions (message_id, user_id, reaction);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS attachments_message_id;
+ALTER TABLE attachments DROP COLUMN file_name;
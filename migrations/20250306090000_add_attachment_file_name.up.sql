@@ -0,0 +1,3 @@
+ALTER TABLE attachments ADD COLUMN file_name VARCHAR(255) NOT NULL DEFAULT '';
+
+CREATE INDEX attachments_message_id ON attachments (message_id);
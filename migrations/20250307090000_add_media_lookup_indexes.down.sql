@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS attachments_file_url;
+DROP INDEX IF EXISTS chats_avatar;
+DROP INDEX IF EXISTS user_details_profile_picture;
@@ -0,0 +1,4 @@
+-- Every media request is authorized by looking up who owns the file
+CREATE INDEX attachments_file_url ON attachments (file_url);
+CREATE INDEX chats_avatar ON chats (avatar);
+CREATE INDEX user_details_profile_picture ON user_details (profile_picture);
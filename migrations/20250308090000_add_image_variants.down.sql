@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS attachments_thumbnail_url;
+DROP INDEX IF EXISTS user_details_profile_picture_small;
+DROP INDEX IF EXISTS user_details_profile_picture_medium;
+ALTER TABLE attachments DROP COLUMN thumbnail_url;
+ALTER TABLE user_details DROP COLUMN profile_picture_small;
+ALTER TABLE user_details DROP COLUMN profile_picture_medium;
@@ -0,0 +1,8 @@
+-- Smaller copies of every profile picture, `profile_picture` itself stays the largest
+ALTER TABLE user_details ADD COLUMN profile_picture_medium TEXT;
+ALTER TABLE user_details ADD COLUMN profile_picture_small TEXT;
+ALTER TABLE attachments ADD COLUMN thumbnail_url TEXT;
+
+CREATE INDEX user_details_profile_picture_medium ON user_details (profile_picture_medium);
+CREATE INDEX user_details_profile_picture_small ON user_details (profile_picture_small);
+CREATE INDEX attachments_thumbnail_url ON attachments (thumbnail_url);
@@ -0,0 +1,24 @@
+-- NULL while the message was never edited / is still visible to everyone
+ALTER TABLE messages ADD COLUMN edited_at TIMESTAMP;
+ALTER TABLE messages ADD COLUMN deleted_at TIMESTAMP;
+
+-- The content a message had before each edit
+CREATE TABLE message_revisions
+(
+    id         UUID PRIMARY KEY,
+    message_id UUID      NOT NULL REFERENCES messages (id),
+    content    TEXT      NOT NULL,
+    -- When this content was replaced
+    edited_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_message_revisions_message_id ON message_revisions (message_id);
+
+-- Messages a user deleted for themselves only
+CREATE TABLE hidden_messages
+(
+    message_id UUID      NOT NULL REFERENCES messages (id),
+    user_id    UUID      NOT NULL REFERENCES users (id),
+    hidden_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id)
+);
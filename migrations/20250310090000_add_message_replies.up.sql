@@ -0,0 +1,4 @@
+-- The message this one answers, NULL for messages that are not replies
+ALTER TABLE messages ADD COLUMN reply_to_message_id UUID REFERENCES messages (id);
+
+CREATE INDEX idx_messages_reply_to_message_id ON messages (reply_to_message_id);
@@ -0,0 +1,4 @@
+-- The message a thread reply hangs under, NULL for messages of the main timeline
+ALTER TABLE messages ADD COLUMN thread_root_id UUID REFERENCES messages (id);
+
+CREATE INDEX idx_messages_thread_root_id ON messages (thread_root_id, sent_at);
@@ -0,0 +1,13 @@
+-- Every rotation adds a row, the rows of one session make up its token family
+CREATE TABLE refresh_tokens
+(
+    id         UUID PRIMARY KEY,
+    session_id UUID        NOT NULL,
+    token_hash VARCHAR(64) UNIQUE NOT NULL, -- sha256 of the token, the token itself only lives in the cookie
+    expires_at TIMESTAMP   NOT NULL,
+    used_at    TIMESTAMP,                   -- set once the token was exchanged for a new one
+    created_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (session_id) REFERENCES sessions (session_id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_refresh_tokens_session_id ON refresh_tokens (session_id);
@@ -0,0 +1,12 @@
+-- Emailed "forgot password" links, deleted once one of them was used
+CREATE TABLE password_resets
+(
+    id         UUID PRIMARY KEY,
+    user_id    UUID        NOT NULL,
+    token_hash VARCHAR(64) UNIQUE NOT NULL, -- sha256 of the token, the token itself only lives in the link
+    expires_at TIMESTAMP   NOT NULL,
+    created_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_password_resets_user_id ON password_resets (user_id);
@@ -0,0 +1,13 @@
+-- A new address waits here until the confirmation link sent to it is opened
+CREATE TABLE email_changes
+(
+    id         UUID PRIMARY KEY,
+    user_id    UUID         NOT NULL,
+    new_email  VARCHAR(255) NOT NULL,
+    token_hash VARCHAR(64)  UNIQUE NOT NULL, -- sha256 of the token, the token itself only lives in the link
+    expires_at TIMESTAMP    NOT NULL,
+    created_at TIMESTAMP    NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_email_changes_user_id ON email_changes (user_id);
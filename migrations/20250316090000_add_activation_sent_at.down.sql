@@ -0,0 +1 @@
+ALTER TABLE users DROP COLUMN activation_sent_at;
@@ -0,0 +1,2 @@
+-- When the last activation email went out, resending is throttled on it
+ALTER TABLE users ADD COLUMN activation_sent_at TIMESTAMP;
@@ -0,0 +1,36 @@
+-- The authenticator secret of a user, two-factor login is on once enabled_at is set
+CREATE TABLE user_two_factor
+(
+    user_id        UUID PRIMARY KEY,
+    secret         TEXT      NOT NULL, -- encrypted with APP_KEY_MAIN
+    enabled_at     TIMESTAMP,
+    last_used_step INTEGER,            -- 30 second step of the last accepted code, each code works once
+    created_at     TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE recovery_codes
+(
+    id         UUID PRIMARY KEY,
+    user_id    UUID        NOT NULL,
+    code_hash  VARCHAR(64) NOT NULL,
+    used_at    TIMESTAMP,
+    created_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_recovery_codes_user_id ON recovery_codes (user_id);
+
+-- A password login waiting for its second factor
+CREATE TABLE login_challenges
+(
+    id         UUID PRIMARY KEY,
+    user_id    UUID        NOT NULL,
+    token_hash VARCHAR(64) UNIQUE NOT NULL,
+    attempts   INTEGER     NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP   NOT NULL,
+    created_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_login_challenges_user_id ON login_challenges (user_id);
@@ -0,0 +1,11 @@
+-- Failed logins counted per account and per IP address, a subject is locked out
+-- until locked_until once it failed too often
+CREATE TABLE login_failures
+(
+    scope          VARCHAR(16)  NOT NULL, -- account or ip
+    subject        VARCHAR(255) NOT NULL, -- the user id or the address
+    failures       INTEGER      NOT NULL DEFAULT 0,
+    locked_until   TIMESTAMP,
+    last_failed_at TIMESTAMP    NOT NULL,
+    PRIMARY KEY (scope, subject)
+);